@@ -0,0 +1,11 @@
+// Генерация кода gRPC из proto/orders.proto
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto");
+
+    let file_descriptors = protox::compile(["orders.proto"], ["proto"])?;
+    tonic_build::configure()
+        .build_client(false)
+        .compile_fds(file_descriptors)?;
+
+    Ok(())
+}
@@ -0,0 +1,374 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use bytes::Bytes;
+use chrono::{Datelike, Months, NaiveDate};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures::{pin_mut, SinkExt, TryStreamExt};
+use log::info;
+use tokio::{fs, sync::mpsc, task};
+
+use crate::{
+    db::{Database, DatabaseNode},
+    errors::AppError,
+};
+
+// Таблицы, секционированные по месяцу date_created.
+// Дочерние таблицы идут первыми: их секции ссылаются на секцию orders
+const CHILD_TABLES: &[&str] = &["items", "delivery", "payment"];
+const ORDERS_TABLE: &str = "orders";
+
+// Размер блока при загрузке архива обратно в базу
+const RESTORE_CHUNK_SIZE: usize = 64 * 1024;
+
+// Число блоков COPY в очереди между соединением и потоком сжатия
+const COPY_CHANNEL_SIZE: usize = 16;
+
+// Первое число месяца, в который попадает date
+pub fn month_start(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).expect("every month has a first day")
+}
+
+// Разбирает месяц в формате YYYY-MM
+pub fn parse_month(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(&format!("{value}-01"), "%Y-%m-%d")
+        .map_err(|_| format!("Invalid month {value}, expected YYYY-MM"))
+}
+
+fn partition_name(table: &str, month: NaiveDate) -> String {
+    format!("{table}_{}", month.format("y%Ym%m"))
+}
+
+fn archive_file(archive_dir: &Path, month: NaiveDate, table: &str) -> PathBuf {
+    archive_dir
+        .join(month.format("%Y-%m").to_string())
+        .join(format!("{table}.copy.gz"))
+}
+
+// Создаёт секции на текущий и следующий месяц, чтобы вставка
+// в начале месяца не упала из-за отсутствующей секции
+pub async fn create_partitions(db: &mut Database, today: NaiveDate) -> Result<(), AppError> {
+    let current = month_start(today);
+    let next = current + Months::new(1);
+
+    for month in [current, next] {
+        db.client
+            .execute("SELECT create_order_partitions($1)", &[&month])
+            .await?;
+    }
+
+    Ok(())
+}
+
+// Месяцы с секциями orders раньше before, кроме восстановленных из архива
+async fn months_to_archive(db: &Database, before: NaiveDate) -> Result<Vec<NaiveDate>, AppError> {
+    let rows = db
+        .client
+        .query(
+            "SELECT child.relname AS partition
+             FROM pg_inherits
+             JOIN pg_class parent ON parent.oid = pg_inherits.inhparent
+             JOIN pg_class child ON child.oid = pg_inherits.inhrelid
+             WHERE parent.relname = $1",
+            &[&ORDERS_TABLE],
+        )
+        .await?;
+    let restored_rows = db
+        .client
+        .query(
+            "SELECT month FROM order_archives WHERE restored_at IS NOT NULL",
+            &[],
+        )
+        .await?;
+    let restored: Vec<NaiveDate> = restored_rows.iter().map(|row| row.get("month")).collect();
+
+    let mut months: Vec<NaiveDate> = rows
+        .iter()
+        .filter_map(|row| {
+            let partition: String = row.get("partition");
+            let suffix = partition.strip_prefix(&format!("{ORDERS_TABLE}_"))?;
+            NaiveDate::parse_from_str(&format!("{suffix}d01"), "y%Ym%md%d").ok()
+        })
+        .filter(|month| *month < before && !restored.contains(month))
+        .collect();
+    months.sort();
+
+    Ok(months)
+}
+
+// Выгружает секции за месяцы раньше before в сжатые файлы и удаляет их из базы.
+// Файлы пишутся до удаления секций, поэтому сбой не приводит к потере данных.
+// Соединение занимается только на время запросов, сжатие и запись файлов
+// идут в отдельном потоке
+pub async fn archive_before(
+    node: &DatabaseNode,
+    before: NaiveDate,
+    archive_dir: &Path,
+) -> Result<Vec<NaiveDate>, AppError> {
+    let months = months_to_archive(&*node.db().await?, before).await?;
+
+    for month in &months {
+        archive_month(node, *month, archive_dir).await?;
+    }
+
+    Ok(months)
+}
+
+async fn archive_month(
+    node: &DatabaseNode,
+    month: NaiveDate,
+    archive_dir: &Path,
+) -> Result<(), AppError> {
+    for table in CHILD_TABLES.iter().chain([&ORDERS_TABLE]) {
+        let path = archive_file(archive_dir, month, table);
+        let size = export_partition(node, &partition_name(table, month), &path).await?;
+        info!("Exported {size} bytes of {table} to {}", path.display());
+    }
+
+    // Секции дочерних таблиц удаляются напрямую, секция orders сначала
+    // отсоединяется: на неё ссылаются внешние ключи секционированных таблиц
+    let mut db = node.db().await?;
+    let transaction = db.client.transaction().await?;
+    for table in CHILD_TABLES {
+        transaction
+            .batch_execute(&format!("DROP TABLE {}", partition_name(table, month)))
+            .await?;
+    }
+    let orders_partition = partition_name(ORDERS_TABLE, month);
+    transaction
+        .batch_execute(&format!(
+            "ALTER TABLE {ORDERS_TABLE} DETACH PARTITION {orders_partition};
+             DROP TABLE {orders_partition};"
+        ))
+        .await?;
+    transaction
+        .execute(
+            "INSERT INTO order_archives (month) VALUES ($1)
+             ON CONFLICT (month) DO UPDATE SET
+                archived_at = CURRENT_TIMESTAMP,
+                restored_at = NULL",
+            &[&month],
+        )
+        .await?;
+    transaction.commit().await?;
+    db.statements.clear();
+
+    info!("Archived orders for {}", month.format("%Y-%m"));
+
+    Ok(())
+}
+
+// Пишет секцию в формате COPY, сжатом gzip, возвращает размер несжатых данных
+async fn export_partition(
+    node: &DatabaseNode,
+    partition: &str,
+    path: &Path,
+) -> Result<u64, AppError> {
+    // Сначала пишется временный файл, чтобы не оставить обрезанный архив
+    let tmp_path = path.with_extension("gz.tmp");
+    let (sender, receiver) = mpsc::channel(COPY_CHANNEL_SIZE);
+    let writer = task::spawn_blocking({
+        let tmp_path = tmp_path.clone();
+        move || write_archive(&tmp_path, receiver)
+    });
+
+    let copied = copy_partition(node, partition, sender).await;
+    let written = writer
+        .await
+        .map_err(|err| AppError::IOError(err.into()))
+        .and_then(|written| written);
+
+    // Ошибка записи прерывает чтение COPY, поэтому проверяется первой
+    match written.and(copied) {
+        Ok(size) => {
+            fs::rename(&tmp_path, path).await?;
+            Ok(size)
+        }
+        Err(err) => {
+            let _ = fs::remove_file(&tmp_path).await;
+            Err(err)
+        }
+    }
+}
+
+// Читает секцию через COPY и передаёт блоки в канал. Соединение занято,
+// пока идёт COPY. Чтение прекращается, если запись файла завершилась
+async fn copy_partition(
+    node: &DatabaseNode,
+    partition: &str,
+    sender: mpsc::Sender<Bytes>,
+) -> Result<u64, AppError> {
+    let db = node.db().await?;
+    let stream = db
+        .client
+        .copy_out(&format!("COPY {partition} TO STDOUT"))
+        .await?;
+    pin_mut!(stream);
+
+    let mut copied = 0;
+    while let Some(chunk) = stream.try_next().await? {
+        copied += chunk.len() as u64;
+        if sender.send(chunk).await.is_err() {
+            break;
+        }
+    }
+
+    Ok(copied)
+}
+
+// Сжимает блоки из канала в файл. Выполняется вне runtime: gzip и запись
+// в файл блокирующие
+fn write_archive(path: &Path, mut receiver: mpsc::Receiver<Bytes>) -> Result<(), AppError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut encoder = GzEncoder::new(BufWriter::new(File::create(path)?), Compression::default());
+    while let Some(chunk) = receiver.blocking_recv() {
+        encoder.write_all(&chunk)?;
+    }
+
+    let file = encoder
+        .finish()?
+        .into_inner()
+        .map_err(|err| err.into_error())?;
+    file.sync_all()?;
+
+    Ok(())
+}
+
+// Восстанавливает архивный месяц: создаёт секции заново, загружает
+// в них данные из файлов и присоединяет к секционированным таблицам.
+// Файлы распаковываются в отдельном потоке
+pub async fn restore_month(
+    node: &DatabaseNode,
+    month: NaiveDate,
+    archive_dir: &Path,
+) -> Result<(), AppError> {
+    let month_end = month + Months::new(1);
+
+    // Файлы проверяются до начала транзакции, чтобы не занимать соединение зря
+    for table in [&ORDERS_TABLE].into_iter().chain(CHILD_TABLES) {
+        fs::metadata(archive_file(archive_dir, month, table)).await?;
+    }
+
+    let mut db = node.db().await?;
+
+    // Секция orders присоединяется первой, чтобы внешние ключи
+    // дочерних секций было на что проверять
+    let transaction = db.client.transaction().await?;
+    for table in [&ORDERS_TABLE].into_iter().chain(CHILD_TABLES) {
+        let partition = partition_name(table, month);
+        let path = archive_file(archive_dir, month, table);
+
+        transaction
+            .batch_execute(&format!(
+                "CREATE TABLE {partition} (LIKE {table} INCLUDING DEFAULTS INCLUDING CONSTRAINTS)"
+            ))
+            .await?;
+
+        let sink = transaction
+            .copy_in(&format!("COPY {partition} FROM STDIN"))
+            .await?;
+        pin_mut!(sink);
+
+        let (sender, mut receiver) = mpsc::channel(COPY_CHANNEL_SIZE);
+        let reader = task::spawn_blocking({
+            let path = path.clone();
+            move || read_archive(&path, sender)
+        });
+        while let Some(chunk) = receiver.recv().await {
+            sink.send(chunk).await?;
+        }
+        // Незавершённое чтение файла не должно попасть в базу как полная секция
+        reader
+            .await
+            .map_err(|err| AppError::IOError(err.into()))??;
+        let rows = sink.finish().await?;
+
+        transaction
+            .batch_execute(&format!(
+                "ALTER TABLE {table} ATTACH PARTITION {partition}
+                 FOR VALUES FROM ('{month} 00:00:00+00') TO ('{month_end} 00:00:00+00')"
+            ))
+            .await?;
+
+        info!("Restored {rows} rows of {table} from {}", path.display());
+    }
+    transaction
+        .execute(
+            "UPDATE order_archives SET restored_at = CURRENT_TIMESTAMP WHERE month = $1",
+            &[&month],
+        )
+        .await?;
+    transaction.commit().await?;
+
+    db.statements.clear();
+
+    info!("Restored orders for {}", month.format("%Y-%m"));
+
+    Ok(())
+}
+
+// Распаковывает архив блоками в канал, пока его читает COPY
+fn read_archive(path: &Path, sender: mpsc::Sender<Bytes>) -> Result<(), AppError> {
+    let mut decoder = BufReader::new(GzDecoder::new(File::open(path)?));
+    let mut buffer = vec![0; RESTORE_CHUNK_SIZE];
+    loop {
+        let read = decoder.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+        if sender
+            .blocking_send(Bytes::copy_from_slice(&buffer[..read]))
+            .is_err()
+        {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn archive_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("archive-{}", Uuid::new_v4()))
+            .join("orders.copy.gz");
+        let chunks: Vec<Bytes> = (0..100)
+            .map(|index| Bytes::from(format!("order-{index}\tcustomer-{index}\n")))
+            .collect();
+
+        let (sender, receiver) = mpsc::channel(COPY_CHANNEL_SIZE);
+        let writer = task::spawn_blocking({
+            let path = path.clone();
+            move || write_archive(&path, receiver)
+        });
+        for chunk in &chunks {
+            sender.send(chunk.clone()).await.unwrap();
+        }
+        drop(sender);
+        writer.await.unwrap().unwrap();
+
+        let (sender, mut receiver) = mpsc::channel(COPY_CHANNEL_SIZE);
+        let reader = task::spawn_blocking({
+            let path = path.clone();
+            move || read_archive(&path, sender)
+        });
+        let mut restored = Vec::new();
+        while let Some(chunk) = receiver.recv().await {
+            restored.extend_from_slice(&chunk);
+        }
+        reader.await.unwrap().unwrap();
+
+        assert_eq!(restored, chunks.concat());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
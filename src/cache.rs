@@ -2,8 +2,6 @@ use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-use uuid::Uuid;
-
 #[derive(Clone)]
 pub struct CachedRecord<T> {
     pub data: T,
@@ -11,7 +9,7 @@ pub struct CachedRecord<T> {
     last_accessed: Instant,
 }
 pub struct Cache<T> {
-    records: Arc<Mutex<HashMap<Uuid, CachedRecord<T>>>>,
+    records: Arc<Mutex<HashMap<String, CachedRecord<T>>>>,
 }
 
 impl<T> Cache<T>
@@ -24,24 +22,24 @@ where
         }
     }
 
-    pub fn get_record(&mut self, key: Uuid) -> Option<CachedRecord<T>> {
+    pub fn get_record(&mut self, key: &str) -> Option<CachedRecord<T>> {
         let mut records = self.records.lock().unwrap();
-        if let Some(record) = records.get(&key) {
+        if let Some(record) = records.get(key) {
             let mut mutable_record = record.clone();
             mutable_record.last_accessed = Instant::now();
 
             if Instant::now().duration_since(record.last_accessed) > record.time_to_live {
-                records.remove(&key);
+                records.remove(key);
                 return None;
             }
 
-            return Some(record.clone());
+            Some(record.clone())
         } else {
             None
         }
     }
 
-    pub fn update_record(&self, key: Uuid, new_data: T) {
+    pub fn update_record(&self, key: String, new_data: T) {
         let mut records = self.records.lock().unwrap();
         let record = CachedRecord {
             data: new_data,
@@ -0,0 +1,478 @@
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{Months, NaiveDate, Utc};
+use clap::Subcommand;
+use log::{error, info};
+
+use crate::{
+    archive,
+    csv_orders::{self, CsvOutput, ExportFilter},
+    dead_letters::{self, DeadLetter},
+    errors::AppError,
+    storage::{DeadLetterListQuery, OrderRepository},
+    utils, AppState,
+};
+
+// Сколько записей dead_letters читается за раз при повторе всех
+const REPLAY_PAGE_SIZE: u32 = 100;
+
+// Команды обслуживания, выполняются вместо запуска сервера
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Archive monthly order partitions older than ARCHIVE_RETENTION_MONTHS
+    ArchiveOrders {
+        /// Archive months before this one instead of the retention period, e.g. 2024-06
+        #[arg(long, value_parser = archive::parse_month)]
+        before: Option<NaiveDate>,
+    },
+
+    /// Restore an archived month of orders, e.g. 2024-01
+    RestoreOrders {
+        #[arg(value_parser = archive::parse_month)]
+        month: NaiveDate,
+    },
+
+    /// Check that service queries use indexes on a seeded dataset, fail on a seq scan
+    CheckQueryPlans {
+        /// Number of generated orders, rolled back after the check
+        #[arg(long, default_value_t = 100_000)]
+        rows: u32,
+    },
+
+    /// Report orphaned and duplicated delivery, payment and items rows
+    CheckIntegrity,
+
+    /// List orders that failed to be created, newest first
+    ListDeadLetters {
+        /// Only dead letters from this source: http, grpc, graphql, kafka, file, csv
+        #[arg(long)]
+        source: Option<String>,
+
+        /// Only dead letters that were not replayed successfully
+        #[arg(long, action)]
+        pending: bool,
+
+        #[arg(long, default_value_t = 20)]
+        limit: u32,
+
+        #[arg(long, default_value_t = 0)]
+        offset: u32,
+    },
+
+    /// Show a dead letter with its original payload
+    ShowDeadLetter { id: i64 },
+
+    /// Replay dead letters through the order create path, all pending ones if no ids given
+    ReplayDeadLetters {
+        ids: Vec<i64>,
+
+        /// Replay only pending dead letters from this source
+        #[arg(long)]
+        source: Option<String>,
+    },
+
+    /// Export orders to CSV, one row per item unless items go to a separate file
+    ExportCsv {
+        output: PathBuf,
+
+        /// Write items to this file linked by order_uid, one row per order to the output
+        #[arg(long)]
+        items_output: Option<PathBuf>,
+
+        /// Comma-separated output columns, all by default
+        #[arg(long)]
+        columns: Option<String>,
+
+        #[arg(long)]
+        customer_id: Option<String>,
+
+        /// Orders created on or after this date, e.g. 2024-06-01
+        #[arg(long)]
+        from: Option<NaiveDate>,
+
+        /// Orders created on or before this date
+        #[arg(long)]
+        to: Option<NaiveDate>,
+    },
+
+    /// Export orders, delivery, payment and items created in a date range to Parquet files by day
+    ExportParquet {
+        /// First creation date, e.g. 2024-06-01
+        #[arg(long)]
+        from: NaiveDate,
+
+        /// Last creation date, inclusive
+        #[arg(long)]
+        to: NaiveDate,
+
+        /// Output directory, files go to <table>/date=YYYY-MM-DD/part-0.parquet
+        #[arg(long, default_value = "export")]
+        output: PathBuf,
+    },
+
+    /// Import orders from CSV in the export layout, fail if any order is rejected
+    ImportCsv {
+        input: PathBuf,
+
+        /// Items file written by export-csv --items-output
+        #[arg(long)]
+        items: Option<PathBuf>,
+    },
+}
+
+pub async fn run(command: Command, state: Arc<AppState>) -> Result<(), AppError> {
+    let storage = &state.storage;
+    match command {
+        Command::ArchiveOrders { before } => {
+            let before = match before.or_else(retention_cutoff) {
+                Some(before) => before,
+                None => {
+                    return Err(AppError::ConfigError(
+                        "set ARCHIVE_RETENTION_MONTHS or pass --before".to_string(),
+                    ));
+                }
+            };
+            archive_orders(storage, before).await?;
+        }
+        Command::RestoreOrders { month } => {
+            storage.restore_orders(month, &archive_dir()).await?;
+        }
+        Command::CheckQueryPlans { rows } => {
+            check_query_plans(storage, rows).await?;
+        }
+        Command::CheckIntegrity => {
+            check_integrity(storage).await?;
+        }
+        Command::ListDeadLetters {
+            source,
+            pending,
+            limit,
+            offset,
+        } => {
+            let query = DeadLetterListQuery {
+                source,
+                pending,
+                limit,
+                offset,
+            };
+            for letter in storage.list_dead_letters(&query).await? {
+                info!("{}", dead_letter_summary(&letter));
+            }
+        }
+        Command::ShowDeadLetter { id } => match storage.get_dead_letter(id).await? {
+            Some(letter) => {
+                info!(
+                    "{}\nreference: {}\nreplay error: {}\n{}",
+                    dead_letter_summary(&letter),
+                    letter.reference.as_deref().unwrap_or("-"),
+                    letter.replay_error.as_deref().unwrap_or("-"),
+                    letter.payload
+                );
+            }
+            None => error!("Dead letter {id} not found"),
+        },
+        Command::ReplayDeadLetters { ids, source } => {
+            replay_dead_letters(&state, ids, source).await?;
+        }
+        Command::ExportCsv {
+            output,
+            items_output,
+            columns,
+            customer_id,
+            from,
+            to,
+        } => {
+            let filter = ExportFilter {
+                customer_id,
+                from,
+                to,
+            };
+            export_csv(&state, &output, items_output.as_deref(), columns, &filter).await?;
+        }
+        Command::ExportParquet { from, to, output } => {
+            export_parquet(storage, from, to, &output).await?;
+        }
+        Command::ImportCsv { input, items } => {
+            import_csv(&state, &input, items.as_deref()).await?;
+        }
+    }
+
+    Ok(())
+}
+
+// Первый месяц, который остаётся в базе: текущий месяц и ARCHIVE_RETENTION_MONTHS
+// предыдущих. None, если архивация выключена
+pub fn retention_cutoff() -> Option<NaiveDate> {
+    let retention_months = utils::archive_retention_months();
+    if retention_months == 0 {
+        return None;
+    }
+
+    archive::month_start(Utc::now().date_naive()).checked_sub_months(Months::new(retention_months))
+}
+
+pub async fn archive_orders(
+    storage: &Arc<dyn OrderRepository>,
+    before: NaiveDate,
+) -> Result<(), AppError> {
+    let archived = storage.archive_orders(before, &archive_dir()).await?;
+    info!(
+        "Archived {} months of orders before {}",
+        archived.len(),
+        before.format("%Y-%m")
+    );
+
+    Ok(())
+}
+
+// Выводит планы запросов, с последовательным сканированием — целиком
+async fn check_query_plans(storage: &Arc<dyn OrderRepository>, rows: u32) -> Result<(), AppError> {
+    let plans = storage.check_query_plans(rows).await?;
+
+    let mut failed = Vec::new();
+    for plan in plans {
+        if plan.seq_scans.is_empty() {
+            info!("{}: ok", plan.name);
+        } else {
+            error!(
+                "{}: seq scan on {}\n{}",
+                plan.name,
+                plan.seq_scans.join(", "),
+                plan.plan
+            );
+            failed.push(plan.name);
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(AppError::SeqScanError(failed.join(", ")));
+    }
+
+    Ok(())
+}
+
+async fn check_integrity(storage: &Arc<dyn OrderRepository>) -> Result<(), AppError> {
+    let issues = storage.check_integrity().await?;
+    if issues.is_empty() {
+        info!("No data integrity issues found");
+        return Ok(());
+    }
+
+    for issue in &issues {
+        error!(
+            "{}: {} found, order_uid: {}",
+            issue.check,
+            issue.count,
+            issue.examples.join(", ")
+        );
+    }
+
+    Err(AppError::IntegrityError(
+        issues
+            .iter()
+            .map(|issue| issue.check.as_str())
+            .collect::<Vec<_>>()
+            .join(", "),
+    ))
+}
+
+// Строка списка: id, время, источник, состояние и ошибка
+fn dead_letter_summary(letter: &DeadLetter) -> String {
+    let replay_state = match letter.replayed_at {
+        Some(replayed_at) => format!("replayed at {}", replayed_at.to_rfc3339()),
+        None => "pending".to_string(),
+    };
+
+    format!(
+        "{} {} {} {}: {}",
+        letter.dead_letter_id,
+        letter.created_at.to_rfc3339(),
+        letter.source,
+        replay_state,
+        letter.error
+    )
+}
+
+// Повторяет заказы по одному, без ids — все ожидающие повтора.
+// Завершается ошибкой, если хотя бы один повтор не удался
+async fn replay_dead_letters(
+    state: &AppState,
+    ids: Vec<i64>,
+    source: Option<String>,
+) -> Result<(), AppError> {
+    let ids = if ids.is_empty() {
+        pending_dead_letter_ids(&state.storage, source).await?
+    } else {
+        ids
+    };
+
+    let mut failed = Vec::new();
+    for id in ids {
+        match dead_letters::replay(state, id).await {
+            Ok(None) => {
+                error!("Dead letter {id} not found");
+                failed.push(id.to_string());
+            }
+            Err(AppError::ReplayedError(id)) => {
+                error!("Dead letter {id} is already replayed");
+                failed.push(id.to_string());
+            }
+            Err(err) => return Err(err),
+            Ok(Some(DeadLetter {
+                replay_error: Some(replay_error),
+                ..
+            })) => {
+                error!("Dead letter {id} replay failed: {replay_error}");
+                failed.push(id.to_string());
+            }
+            Ok(Some(_)) => info!("Dead letter {id} replayed"),
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(AppError::ReplayError(failed.join(", ")));
+    }
+
+    Ok(())
+}
+
+// id всех ожидающих повтора записей, старые первыми
+async fn pending_dead_letter_ids(
+    storage: &Arc<dyn OrderRepository>,
+    source: Option<String>,
+) -> Result<Vec<i64>, AppError> {
+    let mut query = DeadLetterListQuery {
+        source,
+        pending: true,
+        limit: REPLAY_PAGE_SIZE,
+        offset: 0,
+    };
+
+    let mut ids = Vec::new();
+    loop {
+        let page = storage.list_dead_letters(&query).await?;
+        if page.is_empty() {
+            break;
+        }
+        ids.extend(page.iter().map(|letter| letter.dead_letter_id));
+        query.offset += REPLAY_PAGE_SIZE;
+    }
+    ids.reverse();
+
+    Ok(ids)
+}
+
+// Выгружает заказы в CSV, с items_output товары пишутся отдельным файлом
+async fn export_csv(
+    state: &AppState,
+    output: &Path,
+    items_output: Option<&Path>,
+    columns: Option<String>,
+    filter: &ExportFilter,
+) -> Result<(), AppError> {
+    let default_columns = match items_output {
+        Some(_) => csv_orders::order_columns(),
+        None => csv_orders::all_columns(),
+    };
+    let columns =
+        csv_orders::parse_columns(columns.as_deref().unwrap_or_default(), default_columns)
+            .map_err(AppError::CsvHeaderError)?;
+
+    let mut outputs = vec![CsvOutput::new(
+        columns,
+        false,
+        BufWriter::new(File::create(output)?),
+    )];
+    if let Some(items_output) = items_output {
+        outputs.push(CsvOutput::new(
+            csv_orders::item_columns(),
+            true,
+            BufWriter::new(File::create(items_output)?),
+        ));
+    }
+
+    let exported = csv_orders::export_orders(state, filter, &mut outputs).await?;
+    info!("Exported {exported} orders to {}", output.display());
+
+    Ok(())
+}
+
+async fn export_parquet(
+    storage: &Arc<dyn OrderRepository>,
+    from: NaiveDate,
+    to: NaiveDate,
+    output: &Path,
+) -> Result<(), AppError> {
+    if from > to {
+        return Err(AppError::ConfigError(
+            "--from must not be later than --to".to_string(),
+        ));
+    }
+
+    let files = storage.export_parquet(from, to, output).await?;
+    for file in &files {
+        info!(
+            "{} {}: {} rows to {}",
+            file.table,
+            file.date,
+            file.rows,
+            file.path.display()
+        );
+    }
+    let rows: usize = files.iter().map(|file| file.rows).sum();
+    info!(
+        "Exported {rows} rows in {} files to {}",
+        files.len(),
+        output.display()
+    );
+
+    Ok(())
+}
+
+// Загружает заказы из CSV, выводит строки отклонённых заказов.
+// Завершается ошибкой, если хотя бы один заказ не загружен
+async fn import_csv(state: &AppState, input: &Path, items: Option<&Path>) -> Result<(), AppError> {
+    let orders_csv = fs::read(input)?;
+    let items_csv = items.map(fs::read).transpose()?;
+    let name = input
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let report = csv_orders::import_orders(state, &name, &orders_csv, items_csv.as_deref()).await?;
+    for rejected in &report.rejected {
+        let rows: Vec<String> = rejected.rows.iter().map(u64::to_string).collect();
+        error!(
+            "Order {} (rows {}) rejected: {}",
+            rejected.order_uid.as_deref().unwrap_or("-"),
+            if rows.is_empty() {
+                "-".to_string()
+            } else {
+                rows.join(", ")
+            },
+            rejected.error
+        );
+    }
+    info!(
+        "Imported {} of {} orders from {} rows",
+        report.imported, report.orders, report.rows
+    );
+
+    if !report.rejected.is_empty() {
+        return Err(AppError::ImportError(format!(
+            "{} orders or rows rejected",
+            report.rejected.len()
+        )));
+    }
+
+    Ok(())
+}
+
+fn archive_dir() -> PathBuf {
+    PathBuf::from(utils::archive_dir())
+}
@@ -0,0 +1,320 @@
+use std::sync::Arc;
+
+use crate::{errors::AppError, AppState};
+
+// Настройки потребителя заказов из Kafka
+#[derive(Debug, Clone)]
+pub struct ConsumerConfig {
+    pub brokers: String,
+    pub topic: String,
+    pub group_id: String,
+    // Топик для сообщений, из которых нельзя создать заказ
+    pub dead_letter_topic: String,
+}
+
+// Запускает фоновое чтение заказов из топика
+#[cfg(feature = "kafka")]
+pub fn spawn(state: Arc<AppState>, config: ConsumerConfig) -> Result<(), AppError> {
+    kafka::spawn(state, config)
+}
+
+#[cfg(not(feature = "kafka"))]
+pub fn spawn(_state: Arc<AppState>, _config: ConsumerConfig) -> Result<(), AppError> {
+    Err(AppError::ConfigError(
+        "KAFKA_ORDERS_TOPIC requires the kafka cargo feature".to_string(),
+    ))
+}
+
+#[cfg(feature = "kafka")]
+mod kafka {
+    use std::{sync::Arc, time::Duration};
+
+    use log::{error, info, warn};
+    use rdkafka::{
+        config::ClientConfig,
+        consumer::{CommitMode, Consumer, StreamConsumer},
+        message::{BorrowedMessage, Header, Headers, OwnedHeaders},
+        producer::{FutureProducer, FutureRecord},
+        Message,
+    };
+
+    use super::ConsumerConfig;
+    use crate::{
+        dead_letters::SOURCE_KAFKA,
+        errors::AppError,
+        ingest::{self, IngestOutcome},
+        AppState,
+    };
+
+    // Сколько ждать подтверждения брокера для сообщения в DLQ
+    const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+    // Пауза после ошибки чтения из топика
+    const RECEIVE_ERROR_PAUSE: Duration = Duration::from_secs(1);
+
+    pub fn spawn(state: Arc<AppState>, config: ConsumerConfig) -> Result<(), AppError> {
+        let kafka_error = |err: rdkafka::error::KafkaError| {
+            AppError::ConfigError(format!("Kafka consumer: {err}"))
+        };
+
+        // Offset фиксируется вручную после обработки сообщения
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("bootstrap.servers", &config.brokers)
+            .set("group.id", &config.group_id)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .map_err(kafka_error)?;
+        consumer
+            .subscribe(&[config.topic.as_str()])
+            .map_err(kafka_error)?;
+
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", &config.brokers)
+            .set("enable.idempotence", "true")
+            .set("acks", "all")
+            .create()
+            .map_err(kafka_error)?;
+
+        tokio::spawn(async move { consume(state, consumer, producer, config).await });
+
+        Ok(())
+    }
+
+    // Сообщения партиции обрабатываются по одному. Offset фиксируется только
+    // после коммита заказа в базе или отправки в DLQ, до этого сообщение
+    // обрабатывается повторно и следующие сообщения ждут
+    async fn consume(
+        state: Arc<AppState>,
+        consumer: StreamConsumer,
+        producer: FutureProducer,
+        config: ConsumerConfig,
+    ) {
+        loop {
+            let message = match consumer.recv().await {
+                Ok(message) => message,
+                Err(err) => {
+                    error!("Kafka receive error: {err}");
+                    tokio::time::sleep(RECEIVE_ERROR_PAUSE).await;
+                    continue;
+                }
+            };
+
+            let mut attempt = 1;
+            let rejected = loop {
+                match ingest_message(&state, &message).await {
+                    IngestOutcome::Accepted => break None,
+                    IngestOutcome::Rejected(reason) => break Some(reason),
+                    IngestOutcome::Failed(err) => {
+                        pause(&state, &message, &err, &mut attempt).await;
+                    }
+                }
+            };
+
+            if let Some(reason) = rejected {
+                warn!(
+                    "Kafka message {} rejected: {reason}, sending to {}",
+                    position(&message),
+                    config.dead_letter_topic
+                );
+                let mut attempt = 1;
+                while let Err(err) = send_dead_letter(&producer, &config, &message, &reason).await {
+                    pause(&state, &message, &err, &mut attempt).await;
+                }
+            }
+
+            if let Err(err) = consumer.commit_message(&message, CommitMode::Async) {
+                error!(
+                    "Failed to commit Kafka message {}: {err}",
+                    position(&message)
+                );
+            }
+        }
+    }
+
+    // Создаёт заказ из сообщения с ключом идемпотентности по положению сообщения:
+    // сообщение, прочитанное повторно после сбоя до фиксации offset, не создаёт
+    // второй заказ
+    async fn ingest_message(state: &AppState, message: &BorrowedMessage<'_>) -> IngestOutcome {
+        let key = format!(
+            "kafka-{}-{}-{}",
+            message.topic(),
+            message.partition(),
+            message.offset()
+        );
+
+        // Ключ без ответа остался от обработки, прерванной перезапуском.
+        // Сообщение обрабатывается только здесь, поэтому ключ освобождается
+        if let Err(err) = state.storage.release_idempotency_key(&key).await {
+            return IngestOutcome::Failed(err.to_string());
+        }
+
+        ingest::ingest_order(
+            state,
+            SOURCE_KAFKA,
+            &position(message),
+            Some(&key),
+            message.payload().unwrap_or_default(),
+        )
+        .await
+    }
+
+    // Пауза перед повторной обработкой сообщения, растёт как у повторов базы
+    async fn pause(state: &AppState, message: &BorrowedMessage<'_>, err: &str, attempt: &mut u32) {
+        let backoff = state.retry_policy.backoff(*attempt);
+        warn!(
+            "Kafka message {}: {err}, retrying in {backoff:?}",
+            position(message)
+        );
+        tokio::time::sleep(backoff).await;
+        *attempt = attempt.saturating_add(1);
+    }
+
+    // Копирует сообщение в DLQ с исходными ключом и заголовками,
+    // причина и положение исходного сообщения добавляются в заголовки
+    async fn send_dead_letter(
+        producer: &FutureProducer,
+        config: &ConsumerConfig,
+        message: &BorrowedMessage<'_>,
+        reason: &str,
+    ) -> Result<(), String> {
+        let partition = message.partition().to_string();
+        let offset = message.offset().to_string();
+
+        let mut headers = OwnedHeaders::new();
+        if let Some(original) = message.headers() {
+            for header in original.iter() {
+                headers = headers.insert(header);
+            }
+        }
+        let headers = headers
+            .insert(Header {
+                key: "error",
+                value: Some(reason),
+            })
+            .insert(Header {
+                key: "source_topic",
+                value: Some(message.topic()),
+            })
+            .insert(Header {
+                key: "source_partition",
+                value: Some(&partition),
+            })
+            .insert(Header {
+                key: "source_offset",
+                value: Some(&offset),
+            });
+
+        let mut record = FutureRecord::to(&config.dead_letter_topic)
+            .payload(message.payload().unwrap_or_default())
+            .headers(headers);
+        if let Some(key) = message.key() {
+            record = record.key(key);
+        }
+
+        producer
+            .send(record, SEND_TIMEOUT)
+            .await
+            .map_err(|(err, _)| format!("Dead letter error: {err}"))?;
+
+        info!(
+            "Kafka message {} sent to {}",
+            position(message),
+            config.dead_letter_topic
+        );
+
+        Ok(())
+    }
+
+    // Положение сообщения для журнала: topic/partition@offset
+    fn position(message: &BorrowedMessage<'_>) -> String {
+        format!(
+            "{}/{}@{}",
+            message.topic(),
+            message.partition(),
+            message.offset()
+        )
+    }
+
+    // Проверка на брокере из docker-compose.yml: TEST_KAFKA_BROKERS=localhost:9092
+    #[cfg(test)]
+    mod tests {
+        use std::time::Instant;
+
+        use rdkafka::message::Headers;
+        use uuid::Uuid;
+
+        use super::*;
+        use crate::{routes::tests::state, storage::memory::MemoryOrderRepository};
+
+        // Сколько ждать обработки сообщений
+        const WAIT_TIMEOUT: Duration = Duration::from_secs(60);
+
+        fn brokers() -> Option<String> {
+            std::env::var("TEST_KAFKA_BROKERS").ok()
+        }
+
+        async fn produce(brokers: &str, topic: &str, payload: &[u8]) {
+            let producer: FutureProducer = ClientConfig::new()
+                .set("bootstrap.servers", brokers)
+                .create()
+                .unwrap();
+            producer
+                .send(
+                    FutureRecord::<(), _>::to(topic).payload(payload),
+                    SEND_TIMEOUT,
+                )
+                .await
+                .unwrap();
+        }
+
+        #[tokio::test]
+        async fn creates_orders_and_sends_rejected_to_dead_letter_topic() {
+            let Some(brokers) = brokers() else {
+                return;
+            };
+            let topic = format!("orders-test-{}", Uuid::new_v4());
+            let config = ConsumerConfig {
+                brokers: brokers.clone(),
+                topic: topic.clone(),
+                group_id: format!("{topic}-group"),
+                dead_letter_topic: format!("{topic}-dlq"),
+            };
+
+            let order_uid = format!("kafka-{}", Uuid::new_v4());
+            let mut order: serde_json::Value =
+                serde_json::from_str(include_str!("test/stubs/order.json")).unwrap();
+            order["order_uid"] = serde_json::json!(order_uid);
+            produce(&brokers, &topic, order.to_string().as_bytes()).await;
+            produce(&brokers, &topic, b"not json").await;
+
+            let state = state(Arc::new(MemoryOrderRepository::new()));
+            spawn(state.clone(), config.clone()).unwrap();
+
+            let started = Instant::now();
+            while state.storage.get_order(&order_uid).await.unwrap().is_none() {
+                assert!(started.elapsed() < WAIT_TIMEOUT, "order is not created");
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+
+            let dead_letters: StreamConsumer = ClientConfig::new()
+                .set("bootstrap.servers", &brokers)
+                .set("group.id", format!("{topic}-dlq-reader"))
+                .set("auto.offset.reset", "earliest")
+                .create()
+                .unwrap();
+            dead_letters
+                .subscribe(&[config.dead_letter_topic.as_str()])
+                .unwrap();
+            let message = tokio::time::timeout(WAIT_TIMEOUT, dead_letters.recv())
+                .await
+                .expect("no message in the dead letter topic")
+                .unwrap();
+            assert_eq!(message.payload(), Some(&b"not json"[..]));
+            let headers = message.headers().unwrap();
+            assert!(headers.iter().any(
+                |header| header.key == "source_topic" && header.value == Some(topic.as_bytes())
+            ));
+        }
+    }
+}
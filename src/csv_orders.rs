@@ -0,0 +1,995 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    future::Future,
+    io::Write,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, NaiveDate, NaiveTime, SecondsFormat, Utc};
+use csv::{ReaderBuilder, StringRecord, Trim, Writer};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    dead_letters::SOURCE_CSV,
+    errors::AppError,
+    ingest::{self, IngestOutcome},
+    money::{Currency, MinorUnits, Money},
+    retry,
+    schema::{CreateOrderDTO, DeliveryDTO, GetOrderDTO, OrderItemDTO, PaymentDTO},
+    storage::{OrderCursor, OrderListQuery},
+    timestamp, AppState,
+};
+
+// Сколько заказов читается из хранилища за раз при выгрузке
+const EXPORT_PAGE_SIZE: u32 = 500;
+
+// Колонки CSV. Поля delivery, payment и товара идут с префиксом
+const ORDER_COLUMNS: &[&str] = &[
+    "order_uid",
+    "track_number",
+    "entry",
+    "locale",
+    "internal_signature",
+    "customer_id",
+    "delivery_service",
+    "sm_id",
+    "date_created",
+    "shardkey",
+    "oof_shard",
+];
+const DELIVERY_COLUMNS: &[&str] = &[
+    "delivery.name",
+    "delivery.phone",
+    "delivery.zip",
+    "delivery.city",
+    "delivery.address",
+    "delivery.region",
+    "delivery.email",
+];
+const PAYMENT_COLUMNS: &[&str] = &[
+    "payment.transaction",
+    "payment.request_id",
+    "payment.currency",
+    "payment.provider",
+    "payment.amount",
+    "payment.payment_dt",
+    "payment.bank",
+    "payment.delivery_cost",
+    "payment.goods_total",
+    "payment.custom_fee",
+];
+const ITEM_COLUMNS: &[&str] = &[
+    "item.chrt_id",
+    "item.track_number",
+    "item.price",
+    "item.rid",
+    "item.name",
+    "item.sale",
+    "item.size",
+    "item.total_price",
+    "item.nm_id",
+    "item.brand",
+    "item.status",
+];
+
+// Колонки, которые при загрузке можно не передавать
+const OPTIONAL_COLUMNS: &[&str] = &["order_uid", "date_created"];
+
+// Символы, с которых табличные редакторы начинают формулу
+const FORMULA_CHARS: &[char] = &['=', '+', '-', '@'];
+
+// Фильтр выгрузки, даты создания включительно
+#[derive(Debug, Clone, Default)]
+pub struct ExportFilter {
+    pub customer_id: Option<String>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+// Файл выгрузки. С колонками товара в файле строка на каждый товар
+// и колонки заказа повторяются, без них — строка на заказ.
+// В файле товаров items_only заказы без товаров пропускаются
+pub struct CsvOutput<W: Write> {
+    pub columns: Vec<String>,
+    pub items_only: bool,
+    pub writer: Writer<W>,
+}
+
+// Результат загрузки CSV
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    // Строки данных в файле заказов
+    pub rows: usize,
+    pub orders: usize,
+    pub imported: usize,
+    pub rejected: Vec<RejectedOrder>,
+}
+
+// Заказ, который не удалось загрузить. rows — номера строк файла заказов,
+// заголовок — строка 1
+#[derive(Debug, Serialize)]
+pub struct RejectedOrder {
+    pub rows: Vec<u64>,
+    pub order_uid: Option<String>,
+    pub error: String,
+}
+
+// Заказ, собранный из строк CSV
+struct PendingOrder {
+    rows: Vec<u64>,
+    order_uid: Option<String>,
+    order: Result<CreateOrderDTO, String>,
+}
+
+// Буфер выгрузки, общий с тем, кто забирает записанное по страницам
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    // Забирает записанное с прошлого вызова
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write> CsvOutput<W> {
+    pub fn new(columns: Vec<String>, items_only: bool, writer: W) -> Self {
+        CsvOutput {
+            columns,
+            items_only,
+            writer: Writer::from_writer(writer),
+        }
+    }
+}
+
+// Все колонки в порядке выгрузки
+pub fn all_columns() -> Vec<String> {
+    [
+        ORDER_COLUMNS,
+        DELIVERY_COLUMNS,
+        PAYMENT_COLUMNS,
+        ITEM_COLUMNS,
+    ]
+    .concat()
+    .into_iter()
+    .map(str::to_string)
+    .collect()
+}
+
+// Колонки заказа без товаров, для выгрузки товаров отдельным файлом
+pub fn order_columns() -> Vec<String> {
+    [ORDER_COLUMNS, DELIVERY_COLUMNS, PAYMENT_COLUMNS]
+        .concat()
+        .into_iter()
+        .map(str::to_string)
+        .collect()
+}
+
+// Колонки файла товаров: заказ и поля товара
+pub fn item_columns() -> Vec<String> {
+    ["order_uid"]
+        .iter()
+        .chain(ITEM_COLUMNS)
+        .map(|column| column.to_string())
+        .collect()
+}
+
+// Разбирает список колонок через запятую, пустой список — default
+pub fn parse_columns(value: &str, default: Vec<String>) -> Result<Vec<String>, String> {
+    let columns: Vec<String> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|column| !column.is_empty())
+        .map(str::to_string)
+        .collect();
+    if columns.is_empty() {
+        return Ok(default);
+    }
+
+    validate_columns(&columns)?;
+    Ok(columns)
+}
+
+fn validate_columns(columns: &[String]) -> Result<(), String> {
+    let known = all_columns();
+    let unknown: Vec<&str> = columns
+        .iter()
+        .filter(|column| !known.contains(column))
+        .map(String::as_str)
+        .collect();
+    if !unknown.is_empty() {
+        return Err(format!("Unknown columns: {}", unknown.join(", ")));
+    }
+
+    Ok(())
+}
+
+// Выгружает заказы, новые первыми, во все файлы за один проход.
+// Возвращает число выгруженных заказов
+pub async fn export_orders<W: Write>(
+    state: &AppState,
+    filter: &ExportFilter,
+    outputs: &mut [CsvOutput<W>],
+) -> Result<usize, AppError> {
+    export_pages(state, filter, outputs, || async { Ok(()) }).await
+}
+
+// Выгрузка по страницам: после каждой страницы и в конце файлы сбрасываются
+// и вызывается page_written, например чтобы отправить записанное клиенту.
+// Даты фильтра применяются в запросе к хранилищу
+pub async fn export_pages<W, F, Fut>(
+    state: &AppState,
+    filter: &ExportFilter,
+    outputs: &mut [CsvOutput<W>],
+    mut page_written: F,
+) -> Result<usize, AppError>
+where
+    W: Write,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), AppError>>,
+{
+    for output in outputs.iter_mut() {
+        output.writer.write_record(&output.columns)?;
+    }
+
+    let mut query = OrderListQuery {
+        customer_id: filter.customer_id.clone(),
+        limit: EXPORT_PAGE_SIZE,
+        offset: 0,
+        after: None,
+        created_from: filter.from.map(start_of_day),
+        created_before: filter.to.and_then(|to| to.succ_opt()).map(start_of_day),
+    };
+    let mut exported = 0;
+    loop {
+        let page = retry::with_retry(&state.retry_policy, "Export orders", || {
+            state.storage.list_orders(&query)
+        })
+        .await?;
+        if page.is_empty() {
+            break;
+        }
+
+        for order in &page {
+            for output in outputs.iter_mut() {
+                write_order(output, order)?;
+            }
+            exported += 1;
+        }
+        for output in outputs.iter_mut() {
+            output.writer.flush()?;
+        }
+        page_written().await?;
+        // Следующая страница по позиции последнего заказа, а не по offset
+        query.after = page.last().map(OrderCursor::after);
+    }
+
+    for output in outputs.iter_mut() {
+        output.writer.flush()?;
+    }
+    page_written().await?;
+
+    Ok(exported)
+}
+
+fn start_of_day(date: NaiveDate) -> DateTime<Utc> {
+    date.and_time(NaiveTime::MIN).and_utc()
+}
+
+fn write_order<W: Write>(output: &mut CsvOutput<W>, order: &GetOrderDTO) -> Result<(), AppError> {
+    let per_item = output
+        .columns
+        .iter()
+        .any(|column| column.starts_with("item."));
+
+    if per_item && !order.items.is_empty() {
+        for item in &order.items {
+            let record = output
+                .columns
+                .iter()
+                .map(|column| column_value(order, Some(item), column));
+            output.writer.write_record(record)?;
+        }
+    } else if !output.items_only {
+        let record = output
+            .columns
+            .iter()
+            .map(|column| column_value(order, None, column));
+        output.writer.write_record(record)?;
+    }
+
+    Ok(())
+}
+
+// Значение колонки. Суммы в минимальных единицах, метки времени в RFC 3339.
+// Текст, который табличный редактор прочитал бы как формулу, экранируется
+fn column_value(order: &GetOrderDTO, item: Option<&OrderItemDTO>, column: &str) -> String {
+    let value = raw_column_value(order, item, column);
+    if needs_escape(&value) {
+        format!("'{value}")
+    } else {
+        value
+    }
+}
+
+// Значение начинается с символа формулы, в том числе после кавычек
+// экранирования: такое значение тоже экранируется, чтобы загрузка
+// сняла ровно одну кавычку
+fn needs_escape(value: &str) -> bool {
+    value.trim_start_matches('\'').starts_with(FORMULA_CHARS)
+}
+
+// Снимает экранирование формулы, добавленное выгрузкой
+fn unescape(value: &str) -> &str {
+    match value.strip_prefix('\'') {
+        Some(rest) if needs_escape(rest) => rest,
+        _ => value,
+    }
+}
+
+fn raw_column_value(order: &GetOrderDTO, item: Option<&OrderItemDTO>, column: &str) -> String {
+    let delivery = &order.delivery;
+    let payment = &order.payment;
+    let timestamp =
+        |date: &chrono::DateTime<chrono::Utc>| date.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+
+    if let Some(field) = column.strip_prefix("item.") {
+        let Some(item) = item else {
+            return String::new();
+        };
+        return match field {
+            "chrt_id" => item.chrt_id.to_string(),
+            "track_number" => item.track_number.clone(),
+            "price" => item.price.amount.0.to_string(),
+            "rid" => item.rid.clone(),
+            "name" => item.name.clone(),
+            "sale" => item.sale.to_string(),
+            "size" => item.size.clone(),
+            "total_price" => item.total_price.amount.0.to_string(),
+            "nm_id" => item.nm_id.to_string(),
+            "brand" => item.brand.clone(),
+            "status" => item.status.to_string(),
+            _ => String::new(),
+        };
+    }
+
+    match column {
+        "order_uid" => order.order_uid.clone(),
+        "track_number" => order.track_number.clone(),
+        "entry" => order.entry.clone(),
+        "locale" => order.locale.clone(),
+        "internal_signature" => order.internal_signature.clone(),
+        "customer_id" => order.customer_id.clone(),
+        "delivery_service" => order.delivery_service.clone(),
+        "sm_id" => order.sm_id.to_string(),
+        "date_created" => timestamp(&order.date_created),
+        "shardkey" => order.shardkey.clone(),
+        "oof_shard" => order.oof_shard.clone(),
+        "delivery.name" => delivery.name.clone(),
+        "delivery.phone" => delivery.phone.clone(),
+        "delivery.zip" => delivery.zip.clone(),
+        "delivery.city" => delivery.city.clone(),
+        "delivery.address" => delivery.address.clone(),
+        "delivery.region" => delivery.region.clone(),
+        "delivery.email" => delivery.email.clone(),
+        "payment.transaction" => payment.transaction.clone(),
+        "payment.request_id" => payment.request_id.clone(),
+        "payment.currency" => payment.currency().to_string(),
+        "payment.provider" => payment.provider.clone(),
+        "payment.amount" => payment.amount.amount.0.to_string(),
+        "payment.payment_dt" => timestamp(&payment.payment_dt),
+        "payment.bank" => payment.bank.clone(),
+        "payment.delivery_cost" => payment.delivery_cost.amount.0.to_string(),
+        "payment.goods_total" => payment.goods_total.amount.0.to_string(),
+        "payment.custom_fee" => payment.custom_fee.amount.0.to_string(),
+        _ => String::new(),
+    }
+}
+
+// Загружает заказы из CSV в раскладке выгрузки. Без файла товаров
+// соседние строки с одинаковыми колонками заказа собираются в один заказ,
+// с файлом товаров товары привязываются к заказам по order_uid.
+// Ошибка возвращается только для неверного заголовка, ошибки строк
+// попадают в отчёт. name — имя файла для dead_letters
+pub async fn import_orders(
+    state: &AppState,
+    name: &str,
+    orders_csv: &[u8],
+    items_csv: Option<&[u8]>,
+) -> Result<ImportReport, AppError> {
+    let mut report = ImportReport {
+        rows: 0,
+        orders: 0,
+        imported: 0,
+        rejected: Vec::new(),
+    };
+
+    let mut pending = match items_csv {
+        None => read_order_rows(orders_csv, &mut report)?,
+        Some(items_csv) => read_separate_items(orders_csv, items_csv, &mut report)?,
+    };
+    report.orders = pending.len();
+
+    // Ключ идемпотентности из содержимого файлов и первой строки заказа:
+    // повторная загрузка тех же файлов не создаёт заказы второй раз
+    let mut hasher = Sha256::new();
+    hasher.update(orders_csv);
+    hasher.update(items_csv.unwrap_or_default());
+    let file_hash = hex::encode(hasher.finalize());
+
+    for PendingOrder {
+        rows,
+        order_uid,
+        order,
+    } in pending.drain(..)
+    {
+        let first_row = rows.first().copied().unwrap_or_default();
+        let order = match order {
+            Ok(order) => order,
+            Err(error) => {
+                report.rejected.push(RejectedOrder {
+                    rows,
+                    order_uid,
+                    error,
+                });
+                continue;
+            }
+        };
+
+        let key = format!("csv-{}-{first_row}", &file_hash[..16]);
+        let reference = format!("{name}:{first_row}");
+        let payload = serde_json::to_vec(&order).unwrap_or_default();
+        let outcome =
+            ingest::ingest_order(state, SOURCE_CSV, &reference, Some(&key), &payload).await;
+        let error = match outcome {
+            IngestOutcome::Accepted => {
+                report.imported += 1;
+                continue;
+            }
+            IngestOutcome::Rejected(error) | IngestOutcome::Failed(error) => error,
+        };
+        report.rejected.push(RejectedOrder {
+            rows,
+            order_uid,
+            error,
+        });
+    }
+    report
+        .rejected
+        .sort_by_key(|rejected| rejected.rows.first().copied());
+
+    Ok(report)
+}
+
+// Заказы с товарами в тех же строках
+fn read_order_rows(
+    orders_csv: &[u8],
+    report: &mut ImportReport,
+) -> Result<Vec<PendingOrder>, AppError> {
+    let mut reader = csv_reader(orders_csv);
+    let headers = reader.headers()?.clone();
+    let columns = header_columns(&headers)?;
+    let has_items = ITEM_COLUMNS
+        .iter()
+        .any(|column| columns.contains_key(*column));
+    require_columns(&columns, &required_columns(has_items))?;
+
+    let order_indexes: Vec<usize> = columns
+        .iter()
+        .filter(|(column, _)| !column.starts_with("item."))
+        .map(|(_, index)| *index)
+        .collect();
+
+    let mut pending: Vec<PendingOrder> = Vec::new();
+    let mut last_key: Option<Vec<String>> = None;
+    for result in reader.records() {
+        report.rows += 1;
+        let record = match result {
+            Ok(record) => record,
+            Err(err) => {
+                report.rejected.push(rejected_record(err));
+                last_key = None;
+                continue;
+            }
+        };
+        let line = record_line(&record);
+        let row = Row {
+            columns: &columns,
+            record: &record,
+        };
+
+        let key: Vec<String> = order_indexes
+            .iter()
+            .map(|index| record[*index].to_string())
+            .collect();
+        let context = format!("row {line}");
+
+        // Строка того же заказа, что и предыдущая: добавляется товар
+        if last_key.as_ref() == Some(&key) {
+            if let Some(last) = pending.last_mut() {
+                last.rows.push(line);
+                add_item(&mut last.order, &row, &context);
+                continue;
+            }
+        }
+
+        let mut order = row.order().map_err(|err| format!("{context}: {err}"));
+        add_item(&mut order, &row, &context);
+        pending.push(PendingOrder {
+            rows: vec![line],
+            order_uid: row.optional("order_uid"),
+            order,
+        });
+        last_key = Some(key);
+    }
+
+    Ok(pending)
+}
+
+// Заказы без товаров и товары отдельным файлом, связанные по order_uid
+fn read_separate_items(
+    orders_csv: &[u8],
+    items_csv: &[u8],
+    report: &mut ImportReport,
+) -> Result<Vec<PendingOrder>, AppError> {
+    let mut reader = csv_reader(orders_csv);
+    let headers = reader.headers()?.clone();
+    let columns = header_columns(&headers)?;
+    require_columns(&columns, &required_columns(false))?;
+    require_columns(&columns, &["order_uid"])?;
+
+    let mut pending: Vec<PendingOrder> = Vec::new();
+    let mut by_uid: HashMap<String, usize> = HashMap::new();
+    for result in reader.records() {
+        report.rows += 1;
+        let record = match result {
+            Ok(record) => record,
+            Err(err) => {
+                report.rejected.push(rejected_record(err));
+                continue;
+            }
+        };
+        let line = record_line(&record);
+        let row = Row {
+            columns: &columns,
+            record: &record,
+        };
+
+        let order = row.order().map_err(|err| format!("row {line}: {err}"));
+        let order_uid = row.text("order_uid").unwrap_or_default();
+        if order_uid.is_empty() {
+            report.rejected.push(RejectedOrder {
+                rows: vec![line],
+                order_uid: None,
+                error: format!("row {line}: order_uid is required with a separate items file"),
+            });
+            continue;
+        }
+        if by_uid.contains_key(&order_uid) {
+            report.rejected.push(RejectedOrder {
+                rows: vec![line],
+                order_uid: Some(order_uid),
+                error: format!("row {line}: order_uid is repeated in the orders file"),
+            });
+            continue;
+        }
+
+        by_uid.insert(order_uid.clone(), pending.len());
+        pending.push(PendingOrder {
+            rows: vec![line],
+            order_uid: Some(order_uid),
+            order,
+        });
+    }
+
+    let mut reader = csv_reader(items_csv);
+    let headers = reader.headers()?.clone();
+    let columns = header_columns(&headers)?;
+    require_columns(
+        &columns,
+        &item_columns()
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>(),
+    )?;
+
+    for result in reader.records() {
+        let record = match result {
+            Ok(record) => record,
+            Err(err) => {
+                let mut rejected = rejected_record(err);
+                rejected.error = format!("items file: {}", rejected.error);
+                rejected.rows.clear();
+                report.rejected.push(rejected);
+                continue;
+            }
+        };
+        let line = record_line(&record);
+        let row = Row {
+            columns: &columns,
+            record: &record,
+        };
+
+        let order_uid = row.text("order_uid").unwrap_or_default();
+        let Some(index) = by_uid.get(&order_uid) else {
+            report.rejected.push(RejectedOrder {
+                rows: Vec::new(),
+                order_uid: Some(order_uid.clone()),
+                error: format!("items row {line}: order {order_uid} is not in the orders file"),
+            });
+            continue;
+        };
+        add_item(
+            &mut pending[*index].order,
+            &row,
+            &format!("items row {line}"),
+        );
+    }
+
+    Ok(pending)
+}
+
+fn csv_reader(content: &[u8]) -> csv::Reader<&[u8]> {
+    ReaderBuilder::new().trim(Trim::All).from_reader(content)
+}
+
+// Номера колонок по заголовку, неизвестные колонки — ошибка
+fn header_columns(headers: &StringRecord) -> Result<HashMap<String, usize>, AppError> {
+    let columns: Vec<String> = headers.iter().map(str::to_string).collect();
+    validate_columns(&columns).map_err(AppError::CsvHeaderError)?;
+
+    Ok(columns
+        .into_iter()
+        .enumerate()
+        .map(|(index, column)| (column, index))
+        .collect())
+}
+
+// Колонки, без которых заказ не собрать
+fn required_columns(with_items: bool) -> Vec<&'static str> {
+    let mut required: Vec<&str> = [ORDER_COLUMNS, DELIVERY_COLUMNS, PAYMENT_COLUMNS]
+        .concat()
+        .into_iter()
+        .filter(|column| !OPTIONAL_COLUMNS.contains(column))
+        .collect();
+    if with_items {
+        required.extend(ITEM_COLUMNS);
+    }
+    required
+}
+
+fn require_columns(columns: &HashMap<String, usize>, required: &[&str]) -> Result<(), AppError> {
+    let missing: Vec<&str> = required
+        .iter()
+        .filter(|column| !columns.contains_key(**column))
+        .copied()
+        .collect();
+    if !missing.is_empty() {
+        return Err(AppError::CsvHeaderError(format!(
+            "Missing columns: {}",
+            missing.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+// Добавляет товар строки к заказу, цены товара в валюте платежа заказа
+fn add_item(order: &mut Result<CreateOrderDTO, String>, row: &Row, context: &str) {
+    if let Ok(current) = order {
+        match row.item(current.payment.currency()) {
+            Ok(Some(item)) => current.items.push(item),
+            Ok(None) => {}
+            Err(error) => *order = Err(format!("{context}: {error}")),
+        }
+    }
+}
+
+// Строка, которую не удалось прочитать: неверное число полей или не UTF-8
+fn rejected_record(err: csv::Error) -> RejectedOrder {
+    let line = err.position().map(|position| position.line());
+    RejectedOrder {
+        rows: line.into_iter().collect(),
+        order_uid: None,
+        error: match line {
+            Some(line) => format!("row {line}: {err}"),
+            None => err.to_string(),
+        },
+    }
+}
+
+fn record_line(record: &StringRecord) -> u64 {
+    record
+        .position()
+        .map(|position| position.line())
+        .unwrap_or_default()
+}
+
+// Строка CSV с доступом к значениям по имени колонки
+struct Row<'a> {
+    columns: &'a HashMap<String, usize>,
+    record: &'a StringRecord,
+}
+
+impl Row<'_> {
+    fn get(&self, column: &str) -> Option<&str> {
+        self.columns
+            .get(column)
+            .and_then(|index| self.record.get(*index))
+    }
+
+    // Непустое значение необязательной колонки
+    fn optional(&self, column: &str) -> Option<String> {
+        self.get(column)
+            .filter(|value| !value.is_empty())
+            .map(|value| unescape(value).to_string())
+    }
+
+    fn text(&self, column: &str) -> Result<String, String> {
+        self.get(column)
+            .map(|value| unescape(value).to_string())
+            .ok_or_else(|| format!("{column} is missing"))
+    }
+
+    fn parse<T>(&self, column: &str) -> Result<T, String>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = self.text(column)?;
+        value
+            .parse()
+            .map_err(|err| format!("{column}: invalid value {value:?}: {err}"))
+    }
+
+    fn order(&self) -> Result<CreateOrderDTO, String> {
+        let currency = Currency::new(&self.text("payment.currency")?)
+            .map_err(|err| format!("payment.currency: {err}"))?;
+        let money = |column| -> Result<Money, String> {
+            Ok(Money::new(
+                MinorUnits(self.parse(column)?),
+                currency.clone(),
+            ))
+        };
+        let payment_dt = self.text("payment.payment_dt")?;
+
+        Ok(CreateOrderDTO {
+            order_uid: self.optional("order_uid"),
+            track_number: self.text("track_number")?,
+            entry: self.text("entry")?,
+            delivery: DeliveryDTO {
+                name: self.text("delivery.name")?,
+                phone: self.text("delivery.phone")?,
+                zip: self.text("delivery.zip")?,
+                city: self.text("delivery.city")?,
+                address: self.text("delivery.address")?,
+                region: self.text("delivery.region")?,
+                email: self.text("delivery.email")?,
+            },
+            payment: PaymentDTO {
+                transaction: self.text("payment.transaction")?,
+                request_id: self.text("payment.request_id")?,
+                provider: self.text("payment.provider")?,
+                amount: money("payment.amount")?,
+                payment_dt: timestamp::parse(&payment_dt)
+                    .map_err(|err| format!("payment.payment_dt: {err}"))?,
+                bank: self.text("payment.bank")?,
+                delivery_cost: money("payment.delivery_cost")?,
+                goods_total: money("payment.goods_total")?,
+                custom_fee: money("payment.custom_fee")?,
+            },
+            items: Vec::new(),
+            locale: self.text("locale")?,
+            internal_signature: self.text("internal_signature")?,
+            customer_id: self.text("customer_id")?,
+            delivery_service: self.text("delivery_service")?,
+            sm_id: self.parse("sm_id")?,
+            shardkey: self.text("shardkey")?,
+            oof_shard: self.text("oof_shard")?,
+        })
+    }
+
+    // Товар строки, None — все колонки товара пусты или их нет
+    fn item(&self, currency: &Currency) -> Result<Option<OrderItemDTO>, String> {
+        if ITEM_COLUMNS
+            .iter()
+            .all(|column| self.get(column).unwrap_or_default().is_empty())
+        {
+            return Ok(None);
+        }
+
+        Ok(Some(OrderItemDTO {
+            chrt_id: self.parse("item.chrt_id")?,
+            track_number: self.text("item.track_number")?,
+            price: Money::new(MinorUnits(self.parse("item.price")?), currency.clone()),
+            rid: self.text("item.rid")?,
+            name: self.text("item.name")?,
+            sale: self.parse("item.sale")?,
+            size: self.text("item.size")?,
+            total_price: Money::new(
+                MinorUnits(self.parse("item.total_price")?),
+                currency.clone(),
+            ),
+            nm_id: self.parse("item.nm_id")?,
+            brand: self.text("item.brand")?,
+            status: self.parse("item.status")?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{routes, routes::tests::state, storage::memory::MemoryOrderRepository};
+
+    fn order(order_uid: &str) -> CreateOrderDTO {
+        let mut order: serde_json::Value =
+            serde_json::from_str(include_str!("test/stubs/order.json")).unwrap();
+        order["order_uid"] = json!(order_uid);
+        serde_json::from_value(order).unwrap()
+    }
+
+    fn new_state() -> Arc<AppState> {
+        state(Arc::new(MemoryOrderRepository::new()))
+    }
+
+    // Заказы без date_created, который назначается при создании
+    async fn stored(state: &AppState, order_uids: &[&str]) -> Vec<serde_json::Value> {
+        let mut orders = Vec::new();
+        for order_uid in order_uids {
+            let order = state.storage.get_order(order_uid).await.unwrap().unwrap();
+            let mut order = serde_json::to_value(order).unwrap();
+            order.as_object_mut().unwrap().remove("date_created");
+            orders.push(order);
+        }
+        orders
+    }
+
+    async fn export(state: &AppState, outputs: Vec<(Vec<String>, bool)>) -> Vec<String> {
+        let buffers: Vec<SharedBuffer> = outputs.iter().map(|_| SharedBuffer::default()).collect();
+        let mut outputs: Vec<CsvOutput<SharedBuffer>> = outputs
+            .into_iter()
+            .zip(&buffers)
+            .map(|((columns, items_only), buffer)| {
+                CsvOutput::new(columns, items_only, buffer.clone())
+            })
+            .collect();
+        export_orders(state, &ExportFilter::default(), &mut outputs)
+            .await
+            .unwrap();
+        buffers
+            .iter()
+            .map(|buffer| String::from_utf8(buffer.take()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn escapes_formulas() {
+        for value in ["=SUM(A1)", "+972", "-1", "@cmd", "'=x", "''+x"] {
+            assert!(needs_escape(value), "{value}");
+            assert_eq!(unescape(&format!("'{value}")), value);
+        }
+        for value in ["text", "'quoted", "1637907727", ""] {
+            assert!(!needs_escape(value), "{value}");
+            assert_eq!(unescape(value), value);
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trips_export_and_import() {
+        let source = new_state();
+        let mut first = order("order-1");
+        first.delivery.name = "=HYPERLINK(\"http://example.com\")".to_string();
+        let mut second_item = first.items[0].clone();
+        second_item.chrt_id += 1;
+        second_item.rid = "@rid".to_string();
+        first.items.push(second_item);
+        first.payment.goods_total.amount = MinorUnits(634);
+        first.payment.amount.amount = MinorUnits(2134);
+        for body in [first, order("order-2")] {
+            let (status, _) = routes::create_order(&source, &body, None).await.unwrap();
+            assert!(status.is_success());
+        }
+        let expected = stored(&source, &["order-1", "order-2"]).await;
+
+        let [orders_csv] = export(&source, vec![(all_columns(), false)])
+            .await
+            .try_into()
+            .unwrap();
+        assert!(orders_csv.contains("\"'=HYPERLINK(\"\"http://example.com\"\")\""));
+        assert!(orders_csv.contains("'+9720000000"));
+        assert!(orders_csv.contains("'@rid"));
+
+        let target = new_state();
+        let report = import_orders(&target, "orders.csv", orders_csv.as_bytes(), None)
+            .await
+            .unwrap();
+        assert_eq!((report.rows, report.orders, report.imported), (3, 2, 2));
+        assert!(report.rejected.is_empty(), "{report:?}");
+        assert_eq!(stored(&target, &["order-1", "order-2"]).await, expected);
+
+        // Заказы и товары отдельными файлами
+        let [orders_csv, items_csv] = export(
+            &source,
+            vec![(order_columns(), false), (item_columns(), true)],
+        )
+        .await
+        .try_into()
+        .unwrap();
+        let target = new_state();
+        let report = import_orders(
+            &target,
+            "orders.csv",
+            orders_csv.as_bytes(),
+            Some(items_csv.as_bytes()),
+        )
+        .await
+        .unwrap();
+        assert_eq!((report.rows, report.orders, report.imported), (2, 2, 2));
+        assert_eq!(stored(&target, &["order-1", "order-2"]).await, expected);
+    }
+
+    #[tokio::test]
+    async fn reports_rejected_rows() {
+        let source = new_state();
+        for order_uid in ["order-1", "order-2", "order-3"] {
+            let (status, _) = routes::create_order(&source, &order(order_uid), None)
+                .await
+                .unwrap();
+            assert!(status.is_success());
+        }
+        let [orders_csv] = export(&source, vec![(all_columns(), false)])
+            .await
+            .try_into()
+            .unwrap();
+
+        // Строки 2–4 — заказы в порядке выгрузки: во второй неверный sm_id,
+        // в третьей не хватает полей
+        let mut lines: Vec<String> = orders_csv.lines().map(str::to_string).collect();
+        let sm_id_column = all_columns().iter().position(|c| c == "sm_id").unwrap();
+        let mut fields: Vec<String> = lines[2].split(',').map(str::to_string).collect();
+        fields[sm_id_column] = "many".to_string();
+        lines[2] = fields.join(",");
+        lines[3] = "order-x,TRACK".to_string();
+        let csv = lines.join("\n");
+
+        let target = new_state();
+        let report = import_orders(&target, "orders.csv", csv.as_bytes(), None)
+            .await
+            .unwrap();
+        assert_eq!((report.rows, report.imported), (3, 1));
+        let rejected: Vec<(Vec<u64>, &str)> = report
+            .rejected
+            .iter()
+            .map(|rejected| (rejected.rows.clone(), rejected.error.as_str()))
+            .collect();
+        assert_eq!(rejected.len(), 2, "{rejected:?}");
+        assert_eq!(rejected[0].0, [3]);
+        assert!(rejected[0].1.contains("sm_id"), "{}", rejected[0].1);
+        assert_eq!(rejected[1].0, [4]);
+
+        let header = "order_uid,unknown\n";
+        assert!(matches!(
+            import_orders(&target, "orders.csv", header.as_bytes(), None).await,
+            Err(AppError::CsvHeaderError(_))
+        ));
+    }
+}
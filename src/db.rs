@@ -0,0 +1,326 @@
+use std::{
+    collections::HashMap,
+    error::Error as _,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex as StdMutex,
+    },
+    time::{Duration, Instant},
+};
+
+use log::{error, info, warn};
+use postgres_native_tls::MakeTlsConnector;
+use tokio::sync::{MappedMutexGuard, Mutex, MutexGuard};
+use tokio_postgres::{error::SqlState, Client, Error as PgError, GenericClient, Statement};
+
+use crate::errors::AppError;
+
+// Кеш подготовленных выражений, привязанный к конкретному соединению.
+// Statement в Postgres живёт только в рамках соединения, поэтому кеш
+// хранится рядом с клиентом и уничтожается вместе с ним.
+#[derive(Default)]
+pub struct StatementCache {
+    statements: HashMap<String, Statement>,
+}
+
+impl StatementCache {
+    pub fn new() -> Self {
+        StatementCache {
+            statements: HashMap::new(),
+        }
+    }
+
+    // Возвращает подготовленное выражение по тексту запроса,
+    // при промахе готовит его через переданный клиент или транзакцию
+    pub async fn prepare<C>(&mut self, client: &C, query: &str) -> Result<Statement, PgError>
+    where
+        C: GenericClient + Sync,
+    {
+        if let Some(statement) = self.statements.get(query) {
+            return Ok(statement.clone());
+        }
+
+        let statement = client.prepare(query).await?;
+        self.statements.insert(query.to_string(), statement.clone());
+
+        Ok(statement)
+    }
+
+    // Сбрасывает кеш, например после миграции, меняющей схему таблиц
+    pub fn clear(&mut self) {
+        self.statements.clear();
+    }
+}
+
+// Соединение с базой данных вместе с его кешем выражений
+pub struct Database {
+    pub client: Client,
+    pub statements: StatementCache,
+}
+
+impl Database {
+    pub fn new(client: Client) -> Self {
+        Database {
+            client,
+            statements: StatementCache::new(),
+        }
+    }
+
+    // Открывает новое соединение, фоновая задача соединения
+    // завершается вместе с ним, после чего клиент считается закрытым
+    pub async fn connect(connection_string: &str, tls: MakeTlsConnector) -> Result<Self, PgError> {
+        let (client, connection) = tokio_postgres::connect(connection_string, tls).await?;
+
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                error!("Database connection error: {e}");
+            }
+        });
+
+        Ok(Database::new(client))
+    }
+
+    // Соединение разорвано и требует переподключения
+    pub fn is_closed(&self) -> bool {
+        self.client.is_closed()
+    }
+}
+
+// Сервер базы данных, к которому сервис переподключается сам.
+// Соединение открывается при первом обращении или заново после разрыва
+pub struct DatabaseNode {
+    name: String,
+    connection_string: String,
+    tls: MakeTlsConnector,
+    db: Mutex<Option<Database>>,
+    // Подключается одна задача за раз, соединение на это время не блокируется
+    reconnect: Mutex<()>,
+    // Число завершённых попыток подключения
+    connect_attempts: AtomicU64,
+}
+
+impl DatabaseNode {
+    pub fn new(name: &str, connection_string: String, tls: MakeTlsConnector) -> Self {
+        DatabaseNode {
+            name: name.to_string(),
+            connection_string,
+            tls,
+            db: Mutex::new(None),
+            reconnect: Mutex::new(()),
+            connect_attempts: AtomicU64::new(0),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Возвращает рабочее соединение, переподключаясь, если прежнее разорвано.
+    // Задержку между попытками задаёт вызывающий код через retry::with_retry.
+    // Запросы, дождавшиеся чужой попытки подключения, получают её результат
+    // и не подключаются заново друг за другом
+    pub async fn db(&self) -> Result<MappedMutexGuard<'_, Database>, AppError> {
+        if let Some(db) = self.open_db().await {
+            return Ok(db);
+        }
+
+        let seen_attempts = self.connect_attempts.load(Ordering::Acquire);
+        let _reconnect = self.reconnect.lock().await;
+        if let Some(db) = self.open_db().await {
+            return Ok(db);
+        }
+        if self.connect_attempts.load(Ordering::Acquire) != seen_attempts {
+            return Err(AppError::ConnectionError(format!(
+                "Failed to connect to {}",
+                self.name
+            )));
+        }
+
+        if self.db.lock().await.is_some() {
+            warn!("Connection to {} is closed, reconnecting", self.name);
+        }
+        let connected = Database::connect(&self.connection_string, self.tls.clone()).await;
+        self.connect_attempts.fetch_add(1, Ordering::Release);
+        let new_db = connected.map_err(|err| AppError::ConnectionError(err.to_string()))?;
+        info!("Connected to {}", self.name);
+
+        // Подготовленные выражения старого соединения недействительны
+        let mut db = self.db.lock().await;
+        *db = Some(new_db);
+
+        Ok(MutexGuard::map(db, |db| {
+            db.as_mut().expect("connection is established above")
+        }))
+    }
+
+    // Текущее соединение, если оно открыто
+    async fn open_db(&self) -> Option<MappedMutexGuard<'_, Database>> {
+        let db = self.db.lock().await;
+        if db.as_ref().is_none_or(Database::is_closed) {
+            return None;
+        }
+
+        Some(MutexGuard::map(db, |db| {
+            db.as_mut().expect("connection is checked above")
+        }))
+    }
+}
+
+// Реплики для чтения. Запросы распределяются по кругу, реплика с ошибкой
+// соединения исключается на время cooldown, после чего пробуется снова
+pub struct ReplicaSet {
+    replicas: Vec<Replica>,
+    next: AtomicUsize,
+    cooldown: Duration,
+}
+
+struct Replica {
+    node: DatabaseNode,
+    unhealthy_until: StdMutex<Option<Instant>>,
+}
+
+impl ReplicaSet {
+    pub fn new(replicas: Vec<DatabaseNode>, cooldown: Duration) -> Self {
+        ReplicaSet {
+            replicas: replicas
+                .into_iter()
+                .map(|node| Replica {
+                    node,
+                    unhealthy_until: StdMutex::new(None),
+                })
+                .collect(),
+            next: AtomicUsize::new(0),
+            cooldown,
+        }
+    }
+
+    // Здоровые реплики в порядке, в котором их стоит опрашивать
+    pub fn healthy(&self) -> Vec<&DatabaseNode> {
+        if self.replicas.is_empty() {
+            return Vec::new();
+        }
+
+        let now = Instant::now();
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % self.replicas.len();
+
+        (0..self.replicas.len())
+            .map(|offset| &self.replicas[(start + offset) % self.replicas.len()])
+            .filter(|replica| {
+                replica
+                    .unhealthy_until
+                    .lock()
+                    .unwrap()
+                    .is_none_or(|until| until <= now)
+            })
+            .map(|replica| &replica.node)
+            .collect()
+    }
+
+    // Исключает реплику из чтения на время cooldown
+    pub fn mark_unhealthy(&self, node: &DatabaseNode) {
+        if let Some(replica) = self
+            .replicas
+            .iter()
+            .find(|replica| std::ptr::eq(&replica.node, node))
+        {
+            *replica.unhealthy_until.lock().unwrap() = Some(Instant::now() + self.cooldown);
+            warn!(
+                "{} is unavailable, excluded from reads for {:?}",
+                node.name(),
+                self.cooldown
+            );
+        }
+    }
+}
+
+// Временная ошибка Postgres: конфликт сериализации, взаимоблокировка
+// или обрыв соединения. Такую транзакцию можно повторить целиком
+pub fn is_transient(err: &PgError) -> bool {
+    if err.is_closed() {
+        return true;
+    }
+
+    if let Some(code) = err.code() {
+        return [
+            SqlState::T_R_SERIALIZATION_FAILURE,
+            SqlState::T_R_DEADLOCK_DETECTED,
+            SqlState::CONNECTION_EXCEPTION,
+            SqlState::CONNECTION_DOES_NOT_EXIST,
+            SqlState::CONNECTION_FAILURE,
+            SqlState::SQLCLIENT_UNABLE_TO_ESTABLISH_SQLCONNECTION,
+            SqlState::ADMIN_SHUTDOWN,
+            SqlState::CRASH_SHUTDOWN,
+            SqlState::CANNOT_CONNECT_NOW,
+        ]
+        .contains(code);
+    }
+
+    // Ошибки ввода-вывода на уровне сокета: сброс соединения, таймаут
+    err.source()
+        .is_some_and(|source| source.downcast_ref::<std::io::Error>().is_some())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Arc;
+
+    use futures::future::join_all;
+
+    use super::*;
+    use crate::{tls, utils};
+
+    // Тесты с PostgreSQL запускаются с TEST_POSTGRES=1 на базе из .env,
+    // к которой применены миграции (-m up)
+    pub fn connection() -> Option<(String, MakeTlsConnector)> {
+        if std::env::var("TEST_POSTGRES").is_err() {
+            eprintln!("TEST_POSTGRES is not set, skipping PostgreSQL test");
+            return None;
+        }
+
+        let tls = tls::make_connector(&utils::postgres_tls_config()).unwrap();
+        Some((utils::build_connection_string(), tls))
+    }
+
+    #[tokio::test]
+    async fn reconnects_once_for_concurrent_requests() {
+        let Some((connection_string, tls)) = connection() else {
+            return;
+        };
+        let node = Arc::new(DatabaseNode::new(
+            "test",
+            connection_string.clone(),
+            tls.clone(),
+        ));
+
+        let pid: i32 = {
+            let db = node.db().await.unwrap();
+            db.client
+                .query_one("SELECT pg_backend_pid()", &[])
+                .await
+                .unwrap()
+                .get(0)
+        };
+        let killer = Database::connect(&connection_string, tls).await.unwrap();
+        killer
+            .client
+            .execute("SELECT pg_terminate_backend($1)", &[&pid])
+            .await
+            .unwrap();
+        while !node.db.lock().await.as_ref().unwrap().is_closed() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let results = join_all((0..10).map(|_| {
+            let node = node.clone();
+            async move {
+                let db = node.db().await?;
+                db.client.query_one("SELECT 1", &[]).await?;
+                Ok::<_, AppError>(())
+            }
+        }))
+        .await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(node.connect_attempts.load(Ordering::Acquire), 2);
+    }
+}
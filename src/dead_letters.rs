@@ -0,0 +1,174 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use axum::Json;
+use chrono::{DateTime, Utc};
+use log::{error, warn};
+use serde::Serialize;
+
+use crate::{
+    errors::{self, AppError},
+    routes,
+    schema::CreateOrderDTO,
+    AppState,
+};
+
+// Источники заказов
+pub const SOURCE_HTTP: &str = "http";
+pub const SOURCE_FILE: &str = "file";
+pub const SOURCE_CSV: &str = "csv";
+pub const SOURCE_GRPC: &str = "grpc";
+pub const SOURCE_GRAPHQL: &str = "graphql";
+#[cfg(feature = "kafka")]
+pub const SOURCE_KAFKA: &str = "kafka";
+
+// Заказ, который не удалось создать
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetter {
+    pub dead_letter_id: i64,
+    pub source: String,
+    // Уточнение источника: ключ идемпотентности, положение сообщения в топике
+    pub reference: Option<String>,
+    // Тело запроса или сообщения как есть
+    pub payload: String,
+    pub error: String,
+    #[serde(serialize_with = "crate::timestamp::serialize")]
+    pub created_at: DateTime<Utc>,
+    #[serde(serialize_with = "crate::timestamp::serialize_option")]
+    pub replayed_at: Option<DateTime<Utc>>,
+    pub replay_error: Option<String>,
+}
+
+// Число записей источника для метрик
+#[derive(Debug)]
+pub struct DeadLetterCount {
+    pub source: String,
+    pub pending: i64,
+    pub replayed: i64,
+}
+
+// Счётчики с момента запуска процесса
+#[derive(Default)]
+pub struct DeadLetterMetrics {
+    rejected: Mutex<BTreeMap<String, u64>>,
+    replays_succeeded: AtomicU64,
+    replays_failed: AtomicU64,
+}
+
+// Сохраняет отклонённый заказ. Ошибка записи только журналируется,
+// чтобы не подменить собой исходную ошибку заказа
+pub async fn record(
+    state: &AppState,
+    source: &str,
+    reference: Option<&str>,
+    payload: &[u8],
+    error: &str,
+) {
+    *state
+        .dead_letter_metrics
+        .rejected
+        .lock()
+        .unwrap()
+        .entry(source.to_string())
+        .or_default() += 1;
+
+    let payload = String::from_utf8_lossy(payload);
+    match state
+        .storage
+        .add_dead_letter(source, reference, &payload, error)
+        .await
+    {
+        Ok(id) => warn!("Order from {source} rejected, saved as dead letter {id}: {error}"),
+        Err(err) => {
+            error!("Failed to save dead letter from {source}: {err}, error: {error}, payload: {payload}")
+        }
+    }
+}
+
+// Повторно создаёт заказ из записи тем же путём, что и POST /api/orders.
+// Возвращает обновлённую запись, None — записи нет. Успешно повторённая
+// запись второй раз не повторяется, а ключ идемпотентности записи не даёт
+// создать заказ дважды при одновременных повторах
+pub async fn replay(state: &AppState, id: i64) -> Result<Option<DeadLetter>, AppError> {
+    let Some(letter) = state.storage.get_dead_letter(id).await? else {
+        return Ok(None);
+    };
+    if letter.replayed_at.is_some() {
+        return Err(AppError::ReplayedError(id));
+    }
+
+    let key = format!("dead-letter-{id}");
+    let replay_error = match serde_json::from_str::<CreateOrderDTO>(&letter.payload) {
+        Ok(body) => match routes::resubmit_order(state, &body, &key).await {
+            Ok((status, _)) if status.is_success() => None,
+            Ok((_, Json(response))) | Err((_, Json(response))) => {
+                Some(errors::response_message(&response))
+            }
+        },
+        Err(err) => Some(format!("Invalid order JSON: {err}")),
+    };
+
+    let counter = match replay_error {
+        None => &state.dead_letter_metrics.replays_succeeded,
+        Some(_) => &state.dead_letter_metrics.replays_failed,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+
+    state
+        .storage
+        .complete_dead_letter_replay(id, replay_error.as_deref())
+        .await?;
+
+    state.storage.get_dead_letter(id).await
+}
+
+// Метрики в текстовом формате Prometheus
+pub async fn render_metrics(state: &AppState) -> Result<String, AppError> {
+    let counts = state.storage.count_dead_letters().await?;
+    let metrics = &state.dead_letter_metrics;
+
+    let mut lines = vec![
+        "# HELP orders_rejected_total Orders rejected since start, by source".to_string(),
+        "# TYPE orders_rejected_total counter".to_string(),
+    ];
+    for (source, count) in metrics.rejected.lock().unwrap().iter() {
+        lines.push(format!(
+            "orders_rejected_total{{source=\"{source}\"}} {count}"
+        ));
+    }
+
+    lines.push("# HELP orders_dead_letters Stored dead letters, by source and state".to_string());
+    lines.push("# TYPE orders_dead_letters gauge".to_string());
+    for count in &counts {
+        for (dead_letter_state, value) in [("pending", count.pending), ("replayed", count.replayed)]
+        {
+            lines.push(format!(
+                "orders_dead_letters{{source=\"{}\",state=\"{dead_letter_state}\"}} {value}",
+                count.source
+            ));
+        }
+    }
+
+    lines.push(
+        "# HELP orders_dead_letter_replays_total Dead letter replays since start, by result"
+            .to_string(),
+    );
+    lines.push("# TYPE orders_dead_letter_replays_total counter".to_string());
+    for (result, counter) in [
+        ("ok", &metrics.replays_succeeded),
+        ("failed", &metrics.replays_failed),
+    ] {
+        lines.push(format!(
+            "orders_dead_letter_replays_total{{result=\"{result}\"}} {}",
+            counter.load(Ordering::Relaxed)
+        ));
+    }
+
+    lines.push(String::new());
+    Ok(lines.join("\n"))
+}
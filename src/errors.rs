@@ -2,42 +2,111 @@ use axum::{http::StatusCode, Json};
 use log::error;
 use serde_json::json;
 use thiserror::Error;
-use tokio_postgres::{Error as PgError, Transaction};
+use tokio_postgres::Error as PgError;
 
+use crate::db;
+
+#[allow(clippy::enum_variant_names)]
 #[derive(Error, Debug)]
 pub enum AppError {
     #[error("Postgres error: {0}")]
     PostgresError(#[from] PgError),
 
+    #[error("SQLite error: {0}")]
+    SqliteError(#[from] rusqlite::Error),
+
+    #[error("TLS error: {0}")]
+    TlsError(#[from] native_tls::Error),
+
     #[error("IO error: {0}")]
     IOError(#[from] std::io::Error),
 
     #[error("UID parse error: {0}")]
     UIDError(#[from] uuid::Error),
+
+    #[error("Order {0} already exists")]
+    OrderExistsError(String),
+
+    #[error("Idempotency key {0} is not held by this request")]
+    IdempotencyKeyError(String),
+
+    #[error("Order data is incomplete: {0}")]
+    IncompleteOrderError(String),
+
+    #[error("Operation is not supported by this storage: {0}")]
+    UnsupportedError(String),
+
+    #[error("Shard {0} is not configured")]
+    ShardNotFoundError(usize),
+
+    #[error("Database is unavailable: {0}")]
+    ConnectionError(String),
+
+    #[error("Configuration error: {0}")]
+    ConfigError(String),
+
+    #[error("Sequential scan planned for: {0}")]
+    SeqScanError(String),
+
+    #[error("Data integrity issues found: {0}")]
+    IntegrityError(String),
+
+    #[error("Event sink error: {0}")]
+    SinkError(String),
+
+    #[error("Dead letter {0} is already replayed")]
+    ReplayedError(i64),
+
+    #[error("Dead letters replay failed: {0}")]
+    ReplayError(String),
+
+    #[error("CSV error: {0}")]
+    CsvError(#[from] csv::Error),
+
+    #[error("Invalid CSV header: {0}")]
+    CsvHeaderError(String),
+
+    #[error("Orders import failed: {0}")]
+    ImportError(String),
+
+    #[error("Parquet error: {0}")]
+    ParquetError(#[from] parquet::errors::ParquetError),
+
+    #[error("Arrow error: {0}")]
+    ArrowError(#[from] arrow_schema::ArrowError),
+
+    #[error("gRPC server error: {0}")]
+    GrpcError(#[from] tonic::transport::Error),
 }
 
-pub fn handle_db_error(err: PgError) -> (StatusCode, Json<serde_json::Value>) {
-    let error_response = json!({
-        "status": "error",
-        "message": format!("Database error: {:?}", err),
-    });
-    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+impl AppError {
+    // Временная ошибка, после которой транзакцию можно повторить целиком
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            AppError::PostgresError(err) => db::is_transient(err),
+            AppError::SqliteError(rusqlite::Error::SqliteFailure(err, _)) => matches!(
+                err.code,
+                rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked
+            ),
+            AppError::ConnectionError(_) => true,
+            _ => false,
+        }
+    }
 }
 
-// Функция для обработки ошибок транзакции
-pub async fn handle_transaction_error<E>(
-    err: E,
-    transaction: Transaction<'_>,
-    message: &str,
-) -> (StatusCode, Json<serde_json::Value>)
-where
-    E: std::fmt::Debug + std::fmt::Display,
-{
-    // Пытаемся откатить транзакцию
-    if let Err(rollback_err) = transaction.rollback().await {
-        error!("Failed to rollback transaction: {:?}", rollback_err);
+// Статус ответа для ошибки хранилища: временная ошибка, оставшаяся после
+// повторов, — 503, операция, которую выбранное хранилище не поддерживает, — 501,
+// остальные — 500
+fn storage_error_status(err: &AppError) -> StatusCode {
+    match err {
+        err if err.is_retryable() => StatusCode::SERVICE_UNAVAILABLE,
+        AppError::UnsupportedError(_) => StatusCode::NOT_IMPLEMENTED,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
     }
+}
 
+// Функция для обработки ошибок хранилища при изменении данных
+pub fn handle_storage_error(err: AppError, message: &str) -> (StatusCode, Json<serde_json::Value>) {
     error!("{}: {}", message, err);
 
     let error_response = json!({
@@ -46,24 +115,30 @@ where
         "details": err.to_string()
     });
 
-    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    (storage_error_status(&err), Json(error_response))
+}
+
+// Текст ошибки из ответа с ошибкой: message и details, если есть
+pub fn response_message(response: &serde_json::Value) -> String {
+    let message = response["message"].as_str().unwrap_or("Unknown error");
+    match response["details"].as_str() {
+        Some(details) => format!("{message}: {details}"),
+        None => message.to_string(),
+    }
 }
 
 // Функция для обработки ошибок получения элементов
-pub async fn handle_get_request_error<E>(
-    err: E,
+pub async fn handle_get_request_error(
+    err: AppError,
     message: &str,
-) -> (StatusCode, Json<serde_json::Value>)
-where
-    E: std::fmt::Debug + std::fmt::Display,
-{
+) -> (StatusCode, Json<serde_json::Value>) {
     error!("{}: {}", message, err);
 
     let error_response = json!({
         "error": message,
     });
 
-    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    (storage_error_status(&err), Json(error_response))
 }
 
 pub async fn api_fallback() -> (StatusCode, Json<serde_json::Value>) {
@@ -0,0 +1,419 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use chrono::Utc;
+use log::{error, info, warn};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::fs;
+
+use crate::{
+    dead_letters::{self, SOURCE_FILE},
+    errors::AppError,
+    ingest::{self, IngestOutcome},
+    AppState,
+};
+
+// Подкаталоги каталога приёма
+const PROCESSING_DIR: &str = "processing";
+const DONE_DIR: &str = "done";
+const FAILED_DIR: &str = "failed";
+
+// Файл, изменённый недавно, может ещё дописываться
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+// Настройки приёма заказов из файлов
+#[derive(Debug, Clone)]
+pub struct IngestConfig {
+    pub dir: PathBuf,
+    pub poll_interval: Duration,
+    // Попытки записи при временной ошибке базы, после них файл остаётся
+    // в processing/ до следующего опроса
+    pub max_attempts: u32,
+}
+
+// Отчёт об обработке файла, кладётся рядом с файлом в failed/
+#[derive(Debug, Serialize)]
+struct FileReport {
+    file: String,
+    records: usize,
+    imported: usize,
+    // Ошибка файла целиком, например неверный JSON
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    rejected: Vec<RejectedRecord>,
+}
+
+// Отклонённая запись: номер строки NDJSON или элемента массива JSON, с 1
+#[derive(Debug, Serialize)]
+struct RejectedRecord {
+    record: usize,
+    error: String,
+}
+
+// Запускает фоновый опрос каталога. Каталог опрашивается, а не отслеживается
+// уведомлениями файловой системы: на сетевых томах они не приходят
+pub async fn spawn(state: Arc<AppState>, config: IngestConfig) -> Result<(), AppError> {
+    for subdir in [PROCESSING_DIR, DONE_DIR, FAILED_DIR] {
+        fs::create_dir_all(config.dir.join(subdir)).await?;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(config.poll_interval);
+        loop {
+            interval.tick().await;
+            if let Err(err) = poll(&state, &config).await {
+                error!("File ingestion error: {err}");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+// Сначала дочитываются файлы, обработка которых прервалась, затем
+// забираются новые. Переименование в processing/ атомарно, поэтому
+// файл не попадёт в обработку дважды
+async fn poll(state: &AppState, config: &IngestConfig) -> Result<(), AppError> {
+    let processing_dir = config.dir.join(PROCESSING_DIR);
+
+    for path in list_files(&processing_dir, Duration::ZERO).await? {
+        process_file(state, config, &path).await?;
+    }
+
+    for path in list_files(&config.dir, SETTLE_TIME).await? {
+        let Some(file_name) = path.file_name() else {
+            continue;
+        };
+        let claimed = processing_dir.join(file_name);
+        fs::rename(&path, &claimed).await?;
+        process_file(state, config, &claimed).await?;
+    }
+
+    Ok(())
+}
+
+// Файлы каталога по имени. Скрытые файлы и файлы с расширением .tmp
+// или .part ещё записываются и пропускаются
+async fn list_files(dir: &Path, settle_time: Duration) -> Result<Vec<PathBuf>, AppError> {
+    let mut files = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') || name.ends_with(".tmp") || name.ends_with(".part") {
+            continue;
+        }
+
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .unwrap_or_default();
+        if age < settle_time {
+            continue;
+        }
+
+        files.push(entry.path());
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+// Импортирует записи файла и переносит его в done/ или, если хотя бы одна
+// запись отклонена, в failed/ с отчётом
+async fn process_file(
+    state: &AppState,
+    config: &IngestConfig,
+    path: &Path,
+) -> Result<(), AppError> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let content = fs::read(path).await?;
+
+    let report = match parse_records(&name, &content) {
+        Ok(records) => import_records(state, config, &name, &content, records).await?,
+        Err(error) => {
+            dead_letters::record(state, SOURCE_FILE, Some(&name), &content, &error).await;
+            FileReport {
+                file: name.clone(),
+                records: 0,
+                imported: 0,
+                error: Some(error),
+                rejected: Vec::new(),
+            }
+        }
+    };
+
+    let failed = report.error.is_some() || !report.rejected.is_empty();
+    let target_dir = config.dir.join(if failed { FAILED_DIR } else { DONE_DIR });
+    let target = free_path(&target_dir, &name).await;
+
+    // Отчёт пишется до переноса: если перенос прервётся, файл обработается
+    // заново и отчёт будет перезаписан тем же содержимым
+    if failed {
+        let report_path = target.with_file_name(format!(
+            "{}.report.json",
+            target.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        let report_json = serde_json::to_vec_pretty(&report)
+            .map_err(|err| AppError::IOError(std::io::Error::other(err)))?;
+        fs::write(&report_path, report_json).await?;
+    }
+    fs::rename(path, &target).await?;
+
+    if failed {
+        warn!(
+            "File {name}: {} of {} records imported, moved to {}",
+            report.imported,
+            report.records,
+            target.display()
+        );
+    } else {
+        info!("File {name}: {} records imported", report.imported);
+    }
+
+    Ok(())
+}
+
+// Записи файла: строки .ndjson и .jsonl, элементы массива или один объект .json
+fn parse_records(name: &str, content: &[u8]) -> Result<Vec<(usize, Vec<u8>)>, String> {
+    let extension = Path::new(name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("ndjson" | "jsonl") => Ok(content
+            .split(|byte| *byte == b'\n')
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim_ascii()))
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(record, line)| (record, line.to_vec()))
+            .collect()),
+        Some("json") => match serde_json::from_slice(content) {
+            Ok(serde_json::Value::Array(values)) => Ok(values
+                .iter()
+                .enumerate()
+                .map(|(index, value)| (index + 1, value.to_string().into_bytes()))
+                .collect()),
+            Ok(_) => Ok(vec![(1, content.to_vec())]),
+            Err(err) => Err(format!("Invalid JSON file: {err}")),
+        },
+        _ => Err("Unsupported file type, expected .json, .ndjson or .jsonl".to_string()),
+    }
+}
+
+// Каждая запись создаётся с ключом идемпотентности из хеша содержимого
+// файла и номера записи. После перезапуска или повторной выкладки того же
+// файла уже созданные заказы не создаются второй раз. Если временная ошибка
+// не прошла за max_attempts попыток, обработка файла прерывается
+async fn import_records(
+    state: &AppState,
+    config: &IngestConfig,
+    name: &str,
+    content: &[u8],
+    records: Vec<(usize, Vec<u8>)>,
+) -> Result<FileReport, AppError> {
+    let file_hash = hex::encode(Sha256::digest(content));
+    let mut report = FileReport {
+        file: name.to_string(),
+        records: records.len(),
+        imported: 0,
+        error: None,
+        rejected: Vec::new(),
+    };
+
+    for (record, payload) in records {
+        let key = format!("file-{}-{record}", &file_hash[..16]);
+        let reference = format!("{name}:{record}");
+
+        let mut attempt = 1;
+        loop {
+            // Ключ без ответа остался от обработки, прерванной перезапуском.
+            // Файл обрабатывается только здесь, поэтому ключ освобождается
+            let outcome = match state.storage.release_idempotency_key(&key).await {
+                Ok(()) => {
+                    ingest::ingest_order(state, SOURCE_FILE, &reference, Some(&key), &payload).await
+                }
+                Err(err) if err.is_retryable() => IngestOutcome::Failed(err.to_string()),
+                Err(err) => {
+                    let error = err.to_string();
+                    dead_letters::record(state, SOURCE_FILE, Some(&reference), &payload, &error)
+                        .await;
+                    IngestOutcome::Rejected(error)
+                }
+            };
+
+            match outcome {
+                IngestOutcome::Accepted => {
+                    report.imported += 1;
+                    break;
+                }
+                IngestOutcome::Rejected(error) => {
+                    report.rejected.push(RejectedRecord { record, error });
+                    break;
+                }
+                IngestOutcome::Failed(err) if attempt >= config.max_attempts => {
+                    return Err(AppError::ConnectionError(format!(
+                        "file {reference} failed after {attempt} attempts: {err}"
+                    )));
+                }
+                IngestOutcome::Failed(err) => {
+                    let backoff = state.retry_policy.backoff(attempt);
+                    warn!("File {reference}: {err}, retrying in {backoff:?}");
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+// Путь для файла в каталоге назначения. Если файл с таким именем уже есть,
+// к имени добавляется время переноса
+async fn free_path(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    if !fs::try_exists(&path).await.unwrap_or(false) {
+        return path;
+    }
+
+    dir.join(format!("{}-{name}", Utc::now().format("%Y%m%dT%H%M%S%3f")))
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{routes::tests::state, storage::memory::MemoryOrderRepository};
+
+    fn order(order_uid: &str) -> serde_json::Value {
+        let mut order: serde_json::Value =
+            serde_json::from_str(include_str!("test/stubs/order.json")).unwrap();
+        order["order_uid"] = serde_json::json!(order_uid);
+        order
+    }
+
+    async fn config() -> IngestConfig {
+        let config = IngestConfig {
+            dir: std::env::temp_dir().join(format!("ingest-{}", Uuid::new_v4())),
+            poll_interval: Duration::from_secs(1),
+            max_attempts: 2,
+        };
+        for subdir in [PROCESSING_DIR, DONE_DIR, FAILED_DIR] {
+            fs::create_dir_all(config.dir.join(subdir)).await.unwrap();
+        }
+        config
+    }
+
+    // Кладёт файл в processing/, как после переноса из каталога приёма
+    async fn claim(config: &IngestConfig, name: &str, content: &str) -> PathBuf {
+        let path = config.dir.join(PROCESSING_DIR).join(name);
+        fs::write(&path, content).await.unwrap();
+        path
+    }
+
+    #[test]
+    fn parses_records_by_extension() {
+        let records = parse_records("orders.ndjson", b"{\"a\":1}\n\n  {\"b\":2}  \n").unwrap();
+        assert_eq!(
+            records,
+            [(1, b"{\"a\":1}".to_vec()), (3, b"{\"b\":2}".to_vec())]
+        );
+        assert_eq!(parse_records("orders.JSONL", b"{}").unwrap().len(), 1);
+
+        let records = parse_records("orders.json", b"[{\"a\":1}, {\"b\":2}]").unwrap();
+        assert_eq!(
+            records,
+            [(1, b"{\"a\":1}".to_vec()), (2, b"{\"b\":2}".to_vec())]
+        );
+        let records = parse_records("order.json", b"{\"a\":1}").unwrap();
+        assert_eq!(records, [(1, b"{\"a\":1}".to_vec())]);
+
+        assert!(parse_records("orders.json", b"[{").is_err());
+        assert!(parse_records("orders.csv", b"{}").is_err());
+        assert!(parse_records("orders", b"{}").is_err());
+    }
+
+    #[tokio::test]
+    async fn moves_files_to_done_or_failed_with_report() {
+        let state = state(Arc::new(MemoryOrderRepository::new()));
+        let config = config().await;
+
+        let content = format!("{}\n{}\n", order("order-1"), order("order-2"));
+        let path = claim(&config, "orders.ndjson", &content).await;
+        process_file(&state, &config, &path).await.unwrap();
+        assert!(
+            fs::try_exists(config.dir.join(DONE_DIR).join("orders.ndjson"))
+                .await
+                .unwrap()
+        );
+        assert!(!fs::try_exists(&path).await.unwrap());
+
+        let content = format!("{}\nnot json\n", order("order-3"));
+        let path = claim(&config, "mixed.ndjson", &content).await;
+        process_file(&state, &config, &path).await.unwrap();
+        let failed_dir = config.dir.join(FAILED_DIR);
+        assert!(fs::try_exists(failed_dir.join("mixed.ndjson"))
+            .await
+            .unwrap());
+        let report: serde_json::Value = serde_json::from_slice(
+            &fs::read(failed_dir.join("mixed.ndjson.report.json"))
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(report["records"], 2);
+        assert_eq!(report["imported"], 1);
+        assert_eq!(report["rejected"][0]["record"], 2);
+
+        let path = claim(&config, "orders.txt", "{}").await;
+        process_file(&state, &config, &path).await.unwrap();
+        let report = fs::read_to_string(failed_dir.join("orders.txt.report.json"))
+            .await
+            .unwrap();
+        assert!(report.contains("Unsupported file type"));
+
+        for order_uid in ["order-1", "order-2", "order-3"] {
+            assert!(state.storage.get_order(order_uid).await.unwrap().is_some());
+        }
+        fs::remove_dir_all(&config.dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_file_in_processing_after_max_attempts() {
+        let storage = Arc::new(MemoryOrderRepository::new());
+        let state = state(storage.clone());
+        let config = config().await;
+
+        // Временная ошибка на каждой попытке каждого повтора
+        storage.fail_writes(u32::MAX);
+        let path = claim(&config, "orders.json", &order("order-1").to_string()).await;
+        let err = process_file(&state, &config, &path).await.unwrap_err();
+        assert!(err.is_retryable(), "{err}");
+        assert!(fs::try_exists(&path).await.unwrap());
+
+        // Следующий опрос дочитывает файл из processing/
+        storage.fail_writes(0);
+        poll(&state, &config).await.unwrap();
+        assert!(
+            fs::try_exists(config.dir.join(DONE_DIR).join("orders.json"))
+                .await
+                .unwrap()
+        );
+        assert!(state.storage.get_order("order-1").await.unwrap().is_some());
+        fs::remove_dir_all(&config.dir).await.unwrap();
+    }
+}
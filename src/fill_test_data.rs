@@ -1,3 +1,4 @@
+use chrono::DateTime;
 use log::info;
 use reqwest::Client;
 use std::{sync::Arc, time::Duration};
@@ -5,7 +6,10 @@ use uuid::Uuid;
 
 use tokio_postgres::Error;
 
-use crate::schema::{CreateOrderDTO, DeliveryDTO, OrderItemDTO, PaymentDTO};
+use crate::{
+    money::{Currency, MinorUnits, Money},
+    schema::{CreateOrderDTO, DeliveryDTO, OrderItemDTO, PaymentDTO},
+};
 
 // Создание единичного заказа
 async fn create_order(port: u16, client: &Client, order: &CreateOrderDTO) {
@@ -33,8 +37,12 @@ async fn create_order(port: u16, client: &Client, order: &CreateOrderDTO) {
 
 async fn bulk_create_orders(args: Arc<crate::Args>) {
     let client = Client::new();
+    let currency = Currency::new("USD").expect("USD is a valid currency code");
+    let usd = |amount| Money::new(MinorUnits(amount), currency.clone());
+
     for _ in 0..args.count {
         let order = CreateOrderDTO {
+            order_uid: None,
             track_number: "TN123456789".to_string(),
             entry: "warehouse".to_string(),
             locale: "en_US".to_string(),
@@ -56,24 +64,23 @@ async fn bulk_create_orders(args: Arc<crate::Args>) {
             payment: PaymentDTO {
                 transaction: "tx12345".to_string(),
                 request_id: "rq12345".to_string(),
-                currency: "USD".to_string(),
                 provider: "Visa".to_string(),
-                amount: 100,
-                payment_dt: 1637924400, // Unix timestamp
+                amount: usd(95),
+                payment_dt: DateTime::from_timestamp(1637924400, 0).unwrap_or_default(),
                 bank: "Sample Bank".to_string(),
-                delivery_cost: 5,
-                goods_total: 95,
-                custom_fee: 0,
+                delivery_cost: usd(5),
+                goods_total: usd(90),
+                custom_fee: usd(0),
             },
             items: vec![OrderItemDTO {
                 chrt_id: 123456789,
                 track_number: "TN123456789".to_string(),
-                price: 100,
+                price: usd(100),
                 rid: "RID12345".to_string(),
                 name: "Sample Item".to_string(),
                 sale: 10,
                 size: "M".to_string(),
-                total_price: 90,
+                total_price: usd(90),
                 nm_id: 987654321,
                 brand: "Sample Brand".to_string(),
                 status: 1,
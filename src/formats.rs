@@ -0,0 +1,125 @@
+use std::fmt;
+
+use axum::http::{header, HeaderMap};
+use serde::{de::DeserializeOwned, Serialize};
+
+// Поддерживаемые типы для сообщений об ошибках 406 и 415
+pub const SUPPORTED_TYPES: &str =
+    "application/json, application/msgpack, application/cbor, application/xml";
+
+// Формат тела запроса или ответа
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    MessagePack,
+    Cbor,
+    Xml,
+}
+
+impl Format {
+    // Формат по типу без параметров. Кроме основных типов принимаются
+    // application/*+json, application/*+xml и распространённые синонимы
+    pub fn from_mime(mime: &str) -> Option<Format> {
+        match mime {
+            "application/json" => Some(Format::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Format::MessagePack)
+            }
+            "application/cbor" => Some(Format::Cbor),
+            "application/xml" | "text/xml" => Some(Format::Xml),
+            mime if mime.starts_with("application/") && mime.ends_with("+json") => {
+                Some(Format::Json)
+            }
+            mime if mime.starts_with("application/") && mime.ends_with("+xml") => Some(Format::Xml),
+            _ => None,
+        }
+    }
+
+    // Формат ответа по заголовку Accept: поддерживаемый тип с наибольшим q,
+    // при равных q — первый в заголовке. Без заголовка и для */* — JSON.
+    // None — ни один из перечисленных типов не поддерживается
+    pub fn from_accept(headers: &HeaderMap) -> Option<Format> {
+        let Some(accept) = headers
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.trim().is_empty())
+        else {
+            return Some(Format::Json);
+        };
+
+        let mut best: Option<(Format, f32)> = None;
+        for range in accept.split(',') {
+            let mut parts = range.split(';');
+            let mime = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|quality| quality.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if quality <= 0.0 {
+                continue;
+            }
+
+            let format = match mime.as_str() {
+                "*/*" | "application/*" => Some(Format::Json),
+                "text/*" => Some(Format::Xml),
+                mime => Format::from_mime(mime),
+            };
+            if let Some(format) = format {
+                if best.is_none_or(|(_, best_quality)| quality > best_quality) {
+                    best = Some((format, quality));
+                }
+            }
+        }
+
+        best.map(|(format, _)| format)
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MessagePack => "application/msgpack",
+            Format::Cbor => "application/cbor",
+            Format::Xml => "application/xml",
+        }
+    }
+
+    // Кодирует значение. root — имя корневого элемента XML
+    pub fn encode<T: Serialize>(self, root: &str, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Json => serde_json::to_vec(value).map_err(|err| err.to_string()),
+            // Структуры пишутся картами с именами полей, как в JSON
+            Format::MessagePack => rmp_serde::to_vec_named(value).map_err(|err| err.to_string()),
+            Format::Cbor => {
+                let mut buffer = Vec::new();
+                ciborium::into_writer(value, &mut buffer).map_err(|err| err.to_string())?;
+                Ok(buffer)
+            }
+            Format::Xml => quick_xml::se::to_string_with_root(root, value)
+                .map(String::into_bytes)
+                .map_err(|err| err.to_string()),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            Format::Json => serde_json::from_slice(bytes).map_err(|err| err.to_string()),
+            Format::MessagePack => rmp_serde::from_slice(bytes).map_err(|err| err.to_string()),
+            Format::Cbor => ciborium::from_reader(bytes).map_err(|err| err.to_string()),
+            Format::Xml => {
+                let text = std::str::from_utf8(bytes).map_err(|err| err.to_string())?;
+                quick_xml::de::from_str(text).map_err(|err| err.to_string())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Json => write!(f, "JSON"),
+            Format::MessagePack => write!(f, "MessagePack"),
+            Format::Cbor => write!(f, "CBOR"),
+            Format::Xml => write!(f, "XML"),
+        }
+    }
+}
@@ -0,0 +1,694 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_graphql::{
+    dataloader::{DataLoader, Loader},
+    http::GraphiQLSource,
+    Context, EmptySubscription, ErrorExtensions, InputObject, Object, Schema, SimpleObject,
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{Html, IntoResponse},
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use log::error;
+
+use crate::{
+    dead_letters::SOURCE_GRAPHQL,
+    errors::{response_message, AppError},
+    idempotency,
+    money::{Currency, MinorUnits, Money},
+    retry,
+    routes::{self, DEFAULT_LIST_LIMIT, MAX_LIST_LIMIT, MAX_LIST_OFFSET},
+    schema::{CreateOrderDTO, DeliveryDTO, GetOrderDTO, OrderItemDTO, PaymentDTO},
+    storage::OrderListQuery,
+    AppState,
+};
+
+// Максимум идентификаторов в одном запросе ordersByUids
+const MAX_BATCH_SIZE: usize = 100;
+
+// Ограничения сложности запросов GraphQL
+#[derive(Debug, Clone)]
+pub struct GraphqlConfig {
+    // Наибольшая вложенность полей
+    pub max_depth: usize,
+    // Наибольшая сложность: каждое поле стоит 1, поля-списки умножают
+    // стоимость вложенных полей на число элементов
+    pub max_complexity: usize,
+}
+
+pub type OrdersSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+#[derive(Clone)]
+struct GraphqlState {
+    schema: OrdersSchema,
+    app_state: Arc<AppState>,
+}
+
+// POST /graphql — запросы, GET /graphql — GraphiQL
+pub fn router<S>(app_state: Arc<AppState>, config: &GraphqlConfig) -> Router<S> {
+    let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .data(app_state.clone())
+        .limit_depth(config.max_depth)
+        .limit_complexity(config.max_complexity)
+        .finish();
+
+    Router::new()
+        .route("/graphql", get(graphiql_handler).post(graphql_handler))
+        .with_state(GraphqlState { schema, app_state })
+}
+
+// Загрузчик создаётся на каждый запрос: заказы, запрошенные разными полями
+// одного запроса, загружаются одним обращением к хранилищу
+async fn graphql_handler(
+    State(graphql): State<GraphqlState>,
+    Json(request): Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    let loader = DataLoader::new(
+        OrderLoader {
+            state: graphql.app_state,
+        },
+        tokio::spawn,
+    );
+
+    Json(graphql.schema.execute(request.data(loader)).await)
+}
+
+async fn graphiql_handler() -> impl IntoResponse {
+    Html(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+// Заказы по order_uid тем же путём, что и пакетное чтение gRPC:
+// кеш, затем одно обращение к хранилищу
+struct OrderLoader {
+    state: Arc<AppState>,
+}
+
+impl Loader<String> for OrderLoader {
+    type Value = GetOrderDTO;
+    type Error = Arc<AppError>;
+
+    async fn load(&self, keys: &[String]) -> Result<HashMap<String, GetOrderDTO>, Self::Error> {
+        routes::find_orders(&self.state, keys)
+            .await
+            .map_err(Arc::new)
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    // Заказ по order_uid, null — если заказа нет
+    async fn order(
+        &self,
+        ctx: &Context<'_>,
+        order_uid: String,
+    ) -> async_graphql::Result<Option<Order>> {
+        let loader = ctx.data_unchecked::<DataLoader<OrderLoader>>();
+        let order = loader
+            .load_one(order_uid)
+            .await
+            .map_err(|err| storage_error(&err, "Get order error"))?;
+
+        Ok(order.map(Into::into))
+    }
+
+    // Заказы по списку order_uid в порядке списка, null на месте ненайденных
+    #[graphql(complexity = "order_uids.len().saturating_mul(child_complexity)")]
+    async fn orders_by_uids(
+        &self,
+        ctx: &Context<'_>,
+        order_uids: Vec<String>,
+    ) -> async_graphql::Result<Vec<Option<Order>>> {
+        if order_uids.len() > MAX_BATCH_SIZE {
+            return Err(user_error(format!(
+                "At most {MAX_BATCH_SIZE} order_uids are allowed"
+            )));
+        }
+
+        let loader = ctx.data_unchecked::<DataLoader<OrderLoader>>();
+        let orders = loader
+            .load_many(order_uids.iter().cloned())
+            .await
+            .map_err(|err| storage_error(&err, "Get orders error"))?;
+
+        Ok(order_uids
+            .iter()
+            .map(|order_uid| orders.get(order_uid).cloned().map(Into::into))
+            .collect())
+    }
+
+    // Страница заказов, новые первыми, как GET /api/orders
+    #[graphql(complexity = "(limit as usize).saturating_mul(child_complexity)")]
+    async fn orders(
+        &self,
+        ctx: &Context<'_>,
+        customer_id: Option<String>,
+        #[graphql(default_with = "DEFAULT_LIST_LIMIT")] limit: u32,
+        #[graphql(default)] offset: u32,
+    ) -> async_graphql::Result<Vec<Order>> {
+        if limit == 0 || limit > MAX_LIST_LIMIT {
+            return Err(user_error(format!(
+                "limit must be between 1 and {MAX_LIST_LIMIT}"
+            )));
+        }
+        if offset > MAX_LIST_OFFSET {
+            return Err(user_error(format!(
+                "offset must not exceed {MAX_LIST_OFFSET}"
+            )));
+        }
+
+        let state = ctx.data_unchecked::<Arc<AppState>>();
+        let query = OrderListQuery {
+            customer_id,
+            limit,
+            offset,
+            after: None,
+            created_from: None,
+            created_before: None,
+        };
+        let orders = retry::with_retry(&state.retry_policy, "List orders", || {
+            state.storage.list_orders(&query)
+        })
+        .await
+        .map_err(|err| storage_error(&err, "List orders error"))?;
+
+        Ok(orders.into_iter().map(Into::into).collect())
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    // Создание заказа тем же путём, что и POST /api/orders
+    async fn create_order(
+        &self,
+        ctx: &Context<'_>,
+        input: CreateOrderInput,
+        idempotency_key: Option<String>,
+    ) -> async_graphql::Result<CreateOrderResult> {
+        let state = ctx.data_unchecked::<Arc<AppState>>();
+        if let Some(key) = &idempotency_key {
+            idempotency::validate_key(key).map_err(user_error)?;
+        }
+
+        let body = CreateOrderDTO::try_from(input).map_err(user_error)?;
+        let payload = serde_json::to_vec(&body).unwrap_or_default();
+
+        let result = routes::submit_order(
+            state,
+            &body,
+            idempotency_key.as_deref(),
+            SOURCE_GRAPHQL,
+            idempotency_key.as_deref(),
+            &payload,
+        )
+        .await;
+        match result {
+            Ok((status, Json(response))) if status.is_success() => Ok(CreateOrderResult {
+                order_uid: response["order_uid"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                created: status == StatusCode::CREATED,
+            }),
+            // Сохранённый по ключу ответ с ошибкой возвращается так же, как ошибка
+            Ok((status, Json(response))) | Err((status, Json(response))) => {
+                Err(async_graphql::Error::new(response_message(&response))
+                    .extend_with(|_, extensions| extensions.set("status", status.as_u16())))
+            }
+        }
+    }
+}
+
+// Ошибка в запросе клиента
+fn user_error(message: impl Into<String>) -> async_graphql::Error {
+    async_graphql::Error::new(message)
+        .extend_with(|_, extensions| extensions.set("status", StatusCode::BAD_REQUEST.as_u16()))
+}
+
+// Ошибка хранилища: подробности пишутся в лог, клиенту уходит только message
+fn storage_error(err: &AppError, message: &str) -> async_graphql::Error {
+    error!("{message}: {err}");
+
+    let status = if err.is_retryable() {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else if matches!(err, AppError::UnsupportedError(_)) {
+        StatusCode::NOT_IMPLEMENTED
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    async_graphql::Error::new(message)
+        .extend_with(|_, extensions| extensions.set("status", status.as_u16()))
+}
+
+#[derive(SimpleObject)]
+struct CreateOrderResult {
+    order_uid: String,
+    // false — заказ с тем же содержимым уже существовал
+    created: bool,
+}
+
+#[derive(SimpleObject)]
+struct Order {
+    order_uid: String,
+    track_number: String,
+    entry: String,
+    delivery: Delivery,
+    payment: Payment,
+    items: Vec<Item>,
+    locale: String,
+    internal_signature: String,
+    customer_id: String,
+    delivery_service: String,
+    sm_id: i32,
+    date_created: DateTime<Utc>,
+    shardkey: String,
+    oof_shard: String,
+}
+
+#[derive(SimpleObject, InputObject)]
+#[graphql(input_name = "DeliveryInput")]
+struct Delivery {
+    name: String,
+    phone: String,
+    zip: String,
+    city: String,
+    address: String,
+    region: String,
+    email: String,
+}
+
+// Суммы в минимальных единицах валюты currency
+#[derive(SimpleObject, InputObject)]
+#[graphql(input_name = "PaymentInput")]
+struct Payment {
+    transaction: String,
+    request_id: String,
+    currency: String,
+    provider: String,
+    amount: i64,
+    payment_dt: DateTime<Utc>,
+    bank: String,
+    delivery_cost: i64,
+    goods_total: i64,
+    custom_fee: i64,
+}
+
+// Цены в минимальных единицах валюты платежа
+#[derive(SimpleObject, InputObject)]
+#[graphql(input_name = "ItemInput")]
+struct Item {
+    chrt_id: i64,
+    track_number: String,
+    price: i64,
+    rid: String,
+    name: String,
+    sale: i32,
+    size: String,
+    total_price: i64,
+    nm_id: i64,
+    brand: String,
+    status: i32,
+}
+
+#[derive(InputObject)]
+struct CreateOrderInput {
+    // Если не передан, идентификатор генерируется базой данных
+    order_uid: Option<String>,
+    track_number: String,
+    entry: String,
+    delivery: Delivery,
+    payment: Payment,
+    items: Vec<Item>,
+    locale: String,
+    internal_signature: String,
+    customer_id: String,
+    delivery_service: String,
+    sm_id: i32,
+    shardkey: String,
+    oof_shard: String,
+}
+
+impl TryFrom<CreateOrderInput> for CreateOrderDTO {
+    type Error = String;
+
+    fn try_from(value: CreateOrderInput) -> Result<Self, Self::Error> {
+        let payment: PaymentDTO = value.payment.try_into()?;
+
+        Ok(CreateOrderDTO {
+            order_uid: value.order_uid,
+            track_number: value.track_number,
+            entry: value.entry,
+            delivery: value.delivery.into(),
+            items: value
+                .items
+                .into_iter()
+                .map(|item| order_item(item, payment.currency()))
+                .collect(),
+            payment,
+            locale: value.locale,
+            internal_signature: value.internal_signature,
+            customer_id: value.customer_id,
+            delivery_service: value.delivery_service,
+            sm_id: value.sm_id,
+            shardkey: value.shardkey,
+            oof_shard: value.oof_shard,
+        })
+    }
+}
+
+impl From<GetOrderDTO> for Order {
+    fn from(value: GetOrderDTO) -> Self {
+        Order {
+            order_uid: value.order_uid,
+            track_number: value.track_number,
+            entry: value.entry,
+            delivery: value.delivery.into(),
+            payment: value.payment.into(),
+            items: value.items.into_iter().map(Into::into).collect(),
+            locale: value.locale,
+            internal_signature: value.internal_signature,
+            customer_id: value.customer_id,
+            delivery_service: value.delivery_service,
+            sm_id: value.sm_id,
+            date_created: value.date_created,
+            shardkey: value.shardkey,
+            oof_shard: value.oof_shard,
+        }
+    }
+}
+
+impl From<Delivery> for DeliveryDTO {
+    fn from(value: Delivery) -> Self {
+        DeliveryDTO {
+            name: value.name,
+            phone: value.phone,
+            zip: value.zip,
+            city: value.city,
+            address: value.address,
+            region: value.region,
+            email: value.email,
+        }
+    }
+}
+
+impl From<DeliveryDTO> for Delivery {
+    fn from(value: DeliveryDTO) -> Self {
+        Delivery {
+            name: value.name,
+            phone: value.phone,
+            zip: value.zip,
+            city: value.city,
+            address: value.address,
+            region: value.region,
+            email: value.email,
+        }
+    }
+}
+
+impl TryFrom<Payment> for PaymentDTO {
+    type Error = String;
+
+    fn try_from(value: Payment) -> Result<Self, Self::Error> {
+        let currency = Currency::new(&value.currency).map_err(|err| err.to_string())?;
+        let money = |amount| Money::new(MinorUnits(amount), currency.clone());
+
+        Ok(PaymentDTO {
+            transaction: value.transaction,
+            request_id: value.request_id,
+            provider: value.provider,
+            amount: money(value.amount),
+            payment_dt: value.payment_dt,
+            bank: value.bank,
+            delivery_cost: money(value.delivery_cost),
+            goods_total: money(value.goods_total),
+            custom_fee: money(value.custom_fee),
+        })
+    }
+}
+
+impl From<PaymentDTO> for Payment {
+    fn from(value: PaymentDTO) -> Self {
+        Payment {
+            transaction: value.transaction,
+            request_id: value.request_id,
+            currency: value.amount.currency.into(),
+            provider: value.provider,
+            amount: value.amount.amount.0,
+            payment_dt: value.payment_dt,
+            bank: value.bank,
+            delivery_cost: value.delivery_cost.amount.0,
+            goods_total: value.goods_total.amount.0,
+            custom_fee: value.custom_fee.amount.0,
+        }
+    }
+}
+
+// Цены товара в валюте платежа заказа
+fn order_item(value: Item, currency: &Currency) -> OrderItemDTO {
+    OrderItemDTO {
+        chrt_id: value.chrt_id,
+        track_number: value.track_number,
+        price: Money::new(MinorUnits(value.price), currency.clone()),
+        rid: value.rid,
+        name: value.name,
+        sale: value.sale,
+        size: value.size,
+        total_price: Money::new(MinorUnits(value.total_price), currency.clone()),
+        nm_id: value.nm_id,
+        brand: value.brand,
+        status: value.status,
+    }
+}
+
+impl From<OrderItemDTO> for Item {
+    fn from(value: OrderItemDTO) -> Self {
+        Item {
+            chrt_id: value.chrt_id,
+            track_number: value.track_number,
+            price: value.price.amount.0,
+            rid: value.rid,
+            name: value.name,
+            sale: value.sale,
+            size: value.size,
+            total_price: value.total_price.amount.0,
+            nm_id: value.nm_id,
+            brand: value.brand,
+            status: value.status,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{routes::tests::state, storage::memory::MemoryOrderRepository};
+
+    fn app(state: Arc<AppState>, max_depth: usize, max_complexity: usize) -> Router {
+        let config = GraphqlConfig {
+            max_depth,
+            max_complexity,
+        };
+        router(state, &config)
+    }
+
+    async fn execute(app: &Router, query: &str, variables: Value) -> Value {
+        let body = json!({"query": query, "variables": variables});
+        let request = Request::post("/graphql")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn order_input(order_uid: &str) -> Value {
+        json!({
+            "orderUid": order_uid,
+            "trackNumber": "WBILMTESTTRACK",
+            "entry": "WBIL",
+            "delivery": {
+                "name": "Test Testov",
+                "phone": "+9720000000",
+                "zip": "2639809",
+                "city": "Kiryat Mozkin",
+                "address": "Ploshad Mira 15",
+                "region": "Kraiot",
+                "email": "test@gmail.com"
+            },
+            "payment": {
+                "transaction": order_uid,
+                "requestId": "",
+                "currency": "USD",
+                "provider": "wbpay",
+                "amount": 1817,
+                "paymentDt": "2021-11-26T06:22:07Z",
+                "bank": "alpha",
+                "deliveryCost": 1500,
+                "goodsTotal": 317,
+                "customFee": 0
+            },
+            "items": [{
+                "chrtId": 9934930,
+                "trackNumber": "WBILMTESTTRACK",
+                "price": 453,
+                "rid": "ab4219087a764ae0btest",
+                "name": "Mascaras",
+                "sale": 30,
+                "size": "0",
+                "totalPrice": 317,
+                "nmId": 2389212,
+                "brand": "Vivienne Sabo",
+                "status": 202
+            }],
+            "locale": "en",
+            "internalSignature": "",
+            "customerId": "test",
+            "deliveryService": "meest",
+            "smId": 99,
+            "shardkey": "9",
+            "oofShard": "1"
+        })
+    }
+
+    async fn store(state: &AppState, order_uid: &str) {
+        let mut order: CreateOrderDTO =
+            serde_json::from_str(include_str!("test/stubs/order.json")).unwrap();
+        order.order_uid = Some(order_uid.to_string());
+        state.storage.create_order(&order, None).await.unwrap();
+    }
+
+    fn error_message(response: &Value) -> &str {
+        response["errors"][0]["message"]
+            .as_str()
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn rejects_deep_and_complex_queries() {
+        let app = app(state(Arc::new(MemoryOrderRepository::new())), 2, 10);
+
+        let response = execute(&app, r#"{ order(orderUid: "x") { orderUid } }"#, json!({})).await;
+        assert!(response.get("errors").is_none(), "{response}");
+        assert_eq!(response["data"]["order"], Value::Null);
+
+        let deep = r#"{ order(orderUid: "x") { delivery { name } } }"#;
+        let response = execute(&app, deep, json!({})).await;
+        assert!(
+            error_message(&response).contains("nested too deep"),
+            "{response}"
+        );
+
+        // Каждый идентификатор умножает стоимость вложенных полей
+        let complex = r#"{ ordersByUids(orderUids: ["1", "2", "3", "4", "5"]) {
+            orderUid trackNumber entry
+        } }"#;
+        let response = execute(&app, complex, json!({})).await;
+        assert!(
+            error_message(&response).contains("too complex"),
+            "{response}"
+        );
+
+        let response = execute(&app, "{ orders(limit: 11) { orderUid } }", json!({})).await;
+        assert!(
+            error_message(&response).contains("too complex"),
+            "{response}"
+        );
+    }
+
+    #[tokio::test]
+    async fn gets_orders_by_uids_in_request_order() {
+        let state = state(Arc::new(MemoryOrderRepository::new()));
+        store(&state, "order-1").await;
+        store(&state, "order-2").await;
+        let app = app(state.clone(), 16, 5000);
+
+        let query = r#"query($uids: [String!]!) {
+            ordersByUids(orderUids: $uids) { orderUid items { rid } }
+        }"#;
+        let uids = json!(["order-2", "missing", "order-1", "order-2"]);
+        let response = execute(&app, query, json!({"uids": uids})).await;
+
+        let orders = &response["data"]["ordersByUids"];
+        let order_uids: Vec<Value> = orders
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|order| order["orderUid"].clone())
+            .collect();
+        assert_eq!(
+            order_uids,
+            [
+                json!("order-2"),
+                Value::Null,
+                json!("order-1"),
+                json!("order-2")
+            ]
+        );
+        assert_eq!(orders[1], Value::Null);
+        assert_eq!(orders[0]["items"][0]["rid"], "ab4219087a764ae0btest");
+
+        // Загруженные заказы остаются в кеше
+        let mut cache = state.cache.lock().await;
+        assert!(cache.get_record("order-1").is_some());
+        assert!(cache.get_record("order-2").is_some());
+        assert!(cache.get_record("missing").is_none());
+    }
+
+    #[tokio::test]
+    async fn creates_order() {
+        let state = state(Arc::new(MemoryOrderRepository::new()));
+        let app = app(state.clone(), 16, 5000);
+        let mutation = r#"mutation($input: CreateOrderInput!) {
+            createOrder(input: $input) { orderUid created }
+        }"#;
+
+        let response = execute(&app, mutation, json!({"input": order_input("order-1")})).await;
+        assert_eq!(
+            response["data"]["createOrder"],
+            json!({"orderUid": "order-1", "created": true}),
+            "{response}"
+        );
+        let stored = state.storage.get_order("order-1").await.unwrap().unwrap();
+        assert_eq!(stored.payment.amount.to_string(), "18.17 USD");
+
+        // Тот же заказ повторно не создаётся
+        let response = execute(&app, mutation, json!({"input": order_input("order-1")})).await;
+        assert_eq!(
+            response["data"]["createOrder"],
+            json!({"orderUid": "order-1", "created": false}),
+            "{response}"
+        );
+
+        let mut input = order_input("order-2");
+        input["payment"]["currency"] = json!("usd");
+        let response = execute(&app, mutation, json!({"input": input})).await;
+        assert_eq!(
+            response["errors"][0]["extensions"]["status"], 400,
+            "{response}"
+        );
+
+        let mut input = order_input("order-2");
+        input["payment"]["goodsTotal"] = json!(1);
+        let response = execute(&app, mutation, json!({"input": input})).await;
+        assert_eq!(
+            response["errors"][0]["extensions"]["status"], 400,
+            "{response}"
+        );
+        assert!(state.storage.get_order("order-2").await.unwrap().is_none());
+    }
+}
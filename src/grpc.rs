@@ -0,0 +1,703 @@
+use std::{net::SocketAddr, pin::Pin, sync::Arc};
+
+use axum::{http::StatusCode, Json};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use log::error;
+use serde_json::json;
+use tonic::{transport::Server, Code, Request, Response, Status};
+
+use crate::{
+    dead_letters::{self, SOURCE_GRPC},
+    errors::{response_message, AppError},
+    idempotency::{self, IDEMPOTENCY_KEY_HEADER},
+    money::{Currency, MinorUnits, Money},
+    retry, routes,
+    schema::{CreateOrderDTO, DeliveryDTO, GetOrderDTO, OrderItemDTO, PaymentDTO},
+    storage::{OrderCursor, OrderListQuery},
+    AppState,
+};
+
+pub mod proto {
+    tonic::include_proto!("orders.v1");
+}
+
+use proto::orders_server::{Orders, OrdersServer};
+
+// Максимум идентификаторов в одном BatchGetOrders
+const MAX_BATCH_SIZE: usize = 100;
+
+// Размер страницы, которой ListOrders читает заказы из хранилища
+const LIST_PAGE_SIZE: u32 = 100;
+
+// Запускает gRPC-сервер. Хранилище и кеш общие с HTTP API
+pub async fn serve(state: Arc<AppState>, port: u16) -> Result<(), AppError> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+
+    Server::builder()
+        .add_service(OrdersServer::new(OrdersService { state }))
+        .serve(addr)
+        .await?;
+
+    Ok(())
+}
+
+struct OrdersService {
+    state: Arc<AppState>,
+}
+
+#[tonic::async_trait]
+impl Orders for OrdersService {
+    // Заказ создаётся тем же путём, что и POST /api/orders. В dead_letters
+    // тело сохраняется в JSON в формате тела POST /api/orders, чтобы его
+    // можно было прочитать и повторить
+    async fn create_order(
+        &self,
+        request: Request<proto::CreateOrderRequest>,
+    ) -> Result<Response<proto::CreateOrderResponse>, Status> {
+        let idempotency_key = match request.metadata().get(IDEMPOTENCY_KEY_HEADER) {
+            Some(value) => {
+                let key = value
+                    .to_str()
+                    .map_err(|err| Status::invalid_argument(err.to_string()))?;
+                idempotency::validate_key(key).map_err(Status::invalid_argument)?;
+                Some(key.to_string())
+            }
+            None => None,
+        };
+
+        let message = request.into_inner();
+        let body = match CreateOrderDTO::try_from(message.clone()) {
+            Ok(body) => body,
+            Err(error) => {
+                dead_letters::record(
+                    &self.state,
+                    SOURCE_GRPC,
+                    idempotency_key.as_deref(),
+                    request_json(&message).to_string().as_bytes(),
+                    &error,
+                )
+                .await;
+                return Err(Status::invalid_argument(error));
+            }
+        };
+        let payload = serde_json::to_vec(&body).unwrap_or_default();
+
+        let result = routes::submit_order(
+            &self.state,
+            &body,
+            idempotency_key.as_deref(),
+            SOURCE_GRPC,
+            idempotency_key.as_deref(),
+            &payload,
+        )
+        .await;
+        match result {
+            Ok((status, Json(response))) if status.is_success() => {
+                Ok(Response::new(proto::CreateOrderResponse {
+                    order_uid: response["order_uid"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    created: status == StatusCode::CREATED,
+                }))
+            }
+            // Сохранённый по ключу ответ с ошибкой возвращается так же, как ошибка
+            Ok((status, Json(response))) | Err((status, Json(response))) => Err(Status::new(
+                status_code(status),
+                response_message(&response),
+            )),
+        }
+    }
+
+    async fn get_order(
+        &self,
+        request: Request<proto::GetOrderRequest>,
+    ) -> Result<Response<proto::Order>, Status> {
+        let order_uid = request.into_inner().order_uid;
+        match routes::find_order(&self.state, &order_uid).await {
+            Ok(Some(order)) => Ok(Response::new(order.into())),
+            Ok(None) => Err(Status::not_found("Order not found!")),
+            Err(err) => Err(storage_status(err, "Get order error")),
+        }
+    }
+
+    // Заказы возвращаются в порядке запроса, повторы идентификаторов
+    // не схлопываются. Сначала читается кеш, остальные заказы загружаются
+    // из хранилища одним запросом
+    async fn batch_get_orders(
+        &self,
+        request: Request<proto::BatchGetOrdersRequest>,
+    ) -> Result<Response<proto::BatchGetOrdersResponse>, Status> {
+        let order_uids = request.into_inner().order_uids;
+        if order_uids.len() > MAX_BATCH_SIZE {
+            return Err(Status::invalid_argument(format!(
+                "At most {MAX_BATCH_SIZE} order_uids are allowed"
+            )));
+        }
+
+        let fetched = routes::find_orders(&self.state, &order_uids)
+            .await
+            .map_err(|err| storage_status(err, "Batch get orders error"))?;
+
+        let mut response = proto::BatchGetOrdersResponse::default();
+        for order_uid in order_uids {
+            match fetched.get(&order_uid) {
+                Some(order) => response.orders.push(order.clone().into()),
+                None => response.missing.push(order_uid),
+            }
+        }
+
+        Ok(Response::new(response))
+    }
+
+    type ListOrdersStream = Pin<Box<dyn Stream<Item = Result<proto::Order, Status>> + Send>>;
+
+    // Заказы читаются страницами по мере отправки клиенту.
+    // Тип ошибки потока задан tonic
+    #[allow(clippy::result_large_err)]
+    async fn list_orders(
+        &self,
+        request: Request<proto::ListOrdersRequest>,
+    ) -> Result<Response<Self::ListOrdersStream>, Status> {
+        let proto::ListOrdersRequest { customer_id, limit } = request.into_inner();
+        let state = self.state.clone();
+
+        // Страницы читаются по позиции последнего заказа, а не по offset,
+        // чтобы обход всего списка не дорожал к концу
+        let pages = stream::try_unfold(Some(None), move |after| {
+            let state = state.clone();
+            let customer_id = customer_id.clone();
+            async move {
+                let Some(after) = after else {
+                    return Ok::<_, Status>(None);
+                };
+
+                let query = OrderListQuery {
+                    customer_id,
+                    limit: LIST_PAGE_SIZE,
+                    offset: 0,
+                    after,
+                    created_from: None,
+                    created_before: None,
+                };
+                let orders = retry::with_retry(&state.retry_policy, "List orders", || {
+                    state.storage.list_orders(&query)
+                })
+                .await
+                .map_err(|err| storage_status(err, "List orders error"))?;
+
+                let next = (orders.len() == LIST_PAGE_SIZE as usize)
+                    .then(|| orders.last().map(OrderCursor::after));
+                Ok(Some((orders, next)))
+            }
+        });
+        let orders = pages
+            .map_ok(|orders| {
+                stream::iter(
+                    orders
+                        .into_iter()
+                        .map(|order| Ok(proto::Order::from(order))),
+                )
+            })
+            .try_flatten();
+
+        let stream: Self::ListOrdersStream = match limit {
+            0 => Box::pin(orders),
+            limit => Box::pin(orders.take(limit as usize)),
+        };
+
+        Ok(Response::new(stream))
+    }
+}
+
+// Код gRPC для статуса ответа HTTP API
+fn status_code(status: StatusCode) -> Code {
+    match status {
+        StatusCode::BAD_REQUEST => Code::InvalidArgument,
+        StatusCode::NOT_FOUND => Code::NotFound,
+        StatusCode::CONFLICT => Code::AlreadyExists,
+        StatusCode::UNPROCESSABLE_ENTITY => Code::FailedPrecondition,
+        StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+        StatusCode::NOT_IMPLEMENTED => Code::Unimplemented,
+        status if status.is_server_error() => Code::Internal,
+        _ => Code::Unknown,
+    }
+}
+
+// Ошибка хранилища: подробности пишутся в лог, клиенту уходит только message
+fn storage_status(err: AppError, message: &str) -> Status {
+    error!("{message}: {err}");
+
+    if err.is_retryable() {
+        Status::unavailable(message)
+    } else if matches!(err, AppError::UnsupportedError(_)) {
+        Status::unimplemented(message)
+    } else {
+        Status::internal(message)
+    }
+}
+
+// Тело запроса в формате JSON тела POST /api/orders для dead_letters.
+// Поля копируются как есть, даже если заказ из них не собирается
+fn request_json(message: &proto::CreateOrderRequest) -> serde_json::Value {
+    let delivery = message.delivery.as_ref().map(|delivery| {
+        json!({
+            "name": delivery.name,
+            "phone": delivery.phone,
+            "zip": delivery.zip,
+            "city": delivery.city,
+            "address": delivery.address,
+            "region": delivery.region,
+            "email": delivery.email,
+        })
+    });
+    let payment = message.payment.as_ref().map(|payment| {
+        let payment_dt = payment.payment_dt.map(|value| {
+            match from_timestamp("payment.payment_dt", Some(value)) {
+                Ok(date) => json!(date.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+                Err(_) => json!({"seconds": value.seconds, "nanos": value.nanos}),
+            }
+        });
+        json!({
+            "transaction": payment.transaction,
+            "request_id": payment.request_id,
+            "currency": payment.currency,
+            "provider": payment.provider,
+            "amount": payment.amount,
+            "payment_dt": payment_dt,
+            "bank": payment.bank,
+            "delivery_cost": payment.delivery_cost,
+            "goods_total": payment.goods_total,
+            "custom_fee": payment.custom_fee,
+        })
+    });
+    let items: Vec<serde_json::Value> = message
+        .items
+        .iter()
+        .map(|item| {
+            json!({
+                "chrt_id": item.chrt_id,
+                "track_number": item.track_number,
+                "price": item.price,
+                "rid": item.rid,
+                "name": item.name,
+                "sale": item.sale,
+                "size": item.size,
+                "total_price": item.total_price,
+                "nm_id": item.nm_id,
+                "brand": item.brand,
+                "status": item.status,
+            })
+        })
+        .collect();
+
+    let mut body = json!({
+        "track_number": message.track_number,
+        "entry": message.entry,
+        "delivery": delivery,
+        "payment": payment,
+        "items": items,
+        "locale": message.locale,
+        "internal_signature": message.internal_signature,
+        "customer_id": message.customer_id,
+        "delivery_service": message.delivery_service,
+        "sm_id": message.sm_id,
+        "shardkey": message.shardkey,
+        "oof_shard": message.oof_shard,
+    });
+    if let Some(order_uid) = &message.order_uid {
+        body["order_uid"] = json!(order_uid);
+    }
+    body
+}
+
+fn timestamp(date: DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: date.timestamp(),
+        nanos: date.timestamp_subsec_nanos() as i32,
+    }
+}
+
+fn from_timestamp(
+    field: &str,
+    value: Option<prost_types::Timestamp>,
+) -> Result<DateTime<Utc>, String> {
+    let value = value.ok_or_else(|| format!("{field} is required"))?;
+    u32::try_from(value.nanos)
+        .ok()
+        .and_then(|nanos| DateTime::from_timestamp(value.seconds, nanos))
+        .ok_or_else(|| format!("{field} is out of range"))
+}
+
+impl TryFrom<proto::CreateOrderRequest> for CreateOrderDTO {
+    type Error = String;
+
+    fn try_from(value: proto::CreateOrderRequest) -> Result<Self, Self::Error> {
+        let delivery = value.delivery.ok_or("delivery is required")?;
+        let payment = value.payment.ok_or("payment is required")?;
+        let payment: PaymentDTO = payment.try_into()?;
+
+        Ok(CreateOrderDTO {
+            order_uid: value.order_uid,
+            track_number: value.track_number,
+            entry: value.entry,
+            delivery: delivery.into(),
+            items: value
+                .items
+                .into_iter()
+                .map(|item| order_item(item, payment.currency()))
+                .collect(),
+            payment,
+            locale: value.locale,
+            internal_signature: value.internal_signature,
+            customer_id: value.customer_id,
+            delivery_service: value.delivery_service,
+            sm_id: value.sm_id,
+            shardkey: value.shardkey,
+            oof_shard: value.oof_shard,
+        })
+    }
+}
+
+impl From<GetOrderDTO> for proto::Order {
+    fn from(value: GetOrderDTO) -> Self {
+        proto::Order {
+            order_uid: value.order_uid,
+            track_number: value.track_number,
+            entry: value.entry,
+            delivery: Some(value.delivery.into()),
+            payment: Some(value.payment.into()),
+            items: value.items.into_iter().map(Into::into).collect(),
+            locale: value.locale,
+            internal_signature: value.internal_signature,
+            customer_id: value.customer_id,
+            delivery_service: value.delivery_service,
+            sm_id: value.sm_id,
+            date_created: Some(timestamp(value.date_created)),
+            shardkey: value.shardkey,
+            oof_shard: value.oof_shard,
+        }
+    }
+}
+
+impl From<proto::Delivery> for DeliveryDTO {
+    fn from(value: proto::Delivery) -> Self {
+        DeliveryDTO {
+            name: value.name,
+            phone: value.phone,
+            zip: value.zip,
+            city: value.city,
+            address: value.address,
+            region: value.region,
+            email: value.email,
+        }
+    }
+}
+
+impl From<DeliveryDTO> for proto::Delivery {
+    fn from(value: DeliveryDTO) -> Self {
+        proto::Delivery {
+            name: value.name,
+            phone: value.phone,
+            zip: value.zip,
+            city: value.city,
+            address: value.address,
+            region: value.region,
+            email: value.email,
+        }
+    }
+}
+
+impl TryFrom<proto::Payment> for PaymentDTO {
+    type Error = String;
+
+    fn try_from(value: proto::Payment) -> Result<Self, Self::Error> {
+        let currency = Currency::new(&value.currency).map_err(|err| err.to_string())?;
+        let money = |amount| Money::new(MinorUnits(amount), currency.clone());
+
+        Ok(PaymentDTO {
+            transaction: value.transaction,
+            request_id: value.request_id,
+            provider: value.provider,
+            amount: money(value.amount),
+            payment_dt: from_timestamp("payment.payment_dt", value.payment_dt)?,
+            bank: value.bank,
+            delivery_cost: money(value.delivery_cost),
+            goods_total: money(value.goods_total),
+            custom_fee: money(value.custom_fee),
+        })
+    }
+}
+
+impl From<PaymentDTO> for proto::Payment {
+    fn from(value: PaymentDTO) -> Self {
+        proto::Payment {
+            transaction: value.transaction,
+            request_id: value.request_id,
+            currency: value.amount.currency.into(),
+            provider: value.provider,
+            amount: value.amount.amount.0,
+            payment_dt: Some(timestamp(value.payment_dt)),
+            bank: value.bank,
+            delivery_cost: value.delivery_cost.amount.0,
+            goods_total: value.goods_total.amount.0,
+            custom_fee: value.custom_fee.amount.0,
+        }
+    }
+}
+
+// Цены товара в валюте платежа заказа
+fn order_item(value: proto::Item, currency: &Currency) -> OrderItemDTO {
+    OrderItemDTO {
+        chrt_id: value.chrt_id,
+        track_number: value.track_number,
+        price: Money::new(MinorUnits(value.price), currency.clone()),
+        rid: value.rid,
+        name: value.name,
+        sale: value.sale,
+        size: value.size,
+        total_price: Money::new(MinorUnits(value.total_price), currency.clone()),
+        nm_id: value.nm_id,
+        brand: value.brand,
+        status: value.status,
+    }
+}
+
+impl From<OrderItemDTO> for proto::Item {
+    fn from(value: OrderItemDTO) -> Self {
+        proto::Item {
+            chrt_id: value.chrt_id,
+            track_number: value.track_number,
+            price: value.price.amount.0,
+            rid: value.rid,
+            name: value.name,
+            sale: value.sale,
+            size: value.size,
+            total_price: value.total_price.amount.0,
+            nm_id: value.nm_id,
+            brand: value.brand,
+            status: value.status,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        routes::tests::state,
+        storage::{memory::MemoryOrderRepository, DeadLetterListQuery},
+    };
+
+    fn order_json() -> serde_json::Value {
+        serde_json::from_str(include_str!("test/stubs/order.json")).unwrap()
+    }
+
+    fn request() -> proto::CreateOrderRequest {
+        proto::CreateOrderRequest {
+            order_uid: Some("b563feb7b2b84b6test".to_string()),
+            track_number: "WBILMTESTTRACK".to_string(),
+            entry: "WBIL".to_string(),
+            delivery: Some(proto::Delivery {
+                name: "Test Testov".to_string(),
+                phone: "+9720000000".to_string(),
+                zip: "2639809".to_string(),
+                city: "Kiryat Mozkin".to_string(),
+                address: "Ploshad Mira 15".to_string(),
+                region: "Kraiot".to_string(),
+                email: "test@gmail.com".to_string(),
+            }),
+            payment: Some(proto::Payment {
+                transaction: "b563feb7b2b84b6test".to_string(),
+                request_id: String::new(),
+                currency: "USD".to_string(),
+                provider: "wbpay".to_string(),
+                amount: 1817,
+                payment_dt: Some(prost_types::Timestamp {
+                    seconds: 1637907727,
+                    nanos: 0,
+                }),
+                bank: "alpha".to_string(),
+                delivery_cost: 1500,
+                goods_total: 317,
+                custom_fee: 0,
+            }),
+            items: vec![proto::Item {
+                chrt_id: 9934930,
+                track_number: "WBILMTESTTRACK".to_string(),
+                price: 453,
+                rid: "ab4219087a764ae0btest".to_string(),
+                name: "Mascaras".to_string(),
+                sale: 30,
+                size: "0".to_string(),
+                total_price: 317,
+                nm_id: 2389212,
+                brand: "Vivienne Sabo".to_string(),
+                status: 202,
+            }],
+            locale: "en".to_string(),
+            internal_signature: String::new(),
+            customer_id: "test".to_string(),
+            delivery_service: "meest".to_string(),
+            sm_id: 99,
+            shardkey: "9".to_string(),
+            oof_shard: "1".to_string(),
+        }
+    }
+
+    fn service() -> OrdersService {
+        OrdersService {
+            state: state(Arc::new(MemoryOrderRepository::new())),
+        }
+    }
+
+    #[test]
+    fn converts_create_request_to_dto() {
+        let expected: CreateOrderDTO = serde_json::from_value(order_json()).unwrap();
+        let expected = serde_json::to_value(expected).unwrap();
+
+        let body = CreateOrderDTO::try_from(request()).unwrap();
+        assert_eq!(serde_json::to_value(&body).unwrap(), expected);
+
+        // Тело для dead_letters читается как тело POST /api/orders
+        let stored: CreateOrderDTO = serde_json::from_value(request_json(&request())).unwrap();
+        assert_eq!(serde_json::to_value(stored).unwrap(), expected);
+    }
+
+    #[test]
+    fn rejects_incomplete_create_request() {
+        let mut message = request();
+        message.delivery = None;
+        let error = CreateOrderDTO::try_from(message.clone()).err().unwrap();
+        assert_eq!(error, "delivery is required");
+        assert_eq!(request_json(&message)["delivery"], serde_json::Value::Null);
+
+        let mut message = request();
+        message.payment.as_mut().unwrap().currency = "usd".to_string();
+        assert!(CreateOrderDTO::try_from(message).is_err());
+
+        let mut message = request();
+        message.payment.as_mut().unwrap().payment_dt = Some(prost_types::Timestamp {
+            seconds: 0,
+            nanos: -1,
+        });
+        let error = CreateOrderDTO::try_from(message.clone()).err().unwrap();
+        assert_eq!(error, "payment.payment_dt is out of range");
+        assert_eq!(
+            request_json(&message)["payment"]["payment_dt"],
+            json!({"seconds": 0, "nanos": -1})
+        );
+    }
+
+    #[test]
+    fn converts_order_to_proto() {
+        let order: GetOrderDTO = serde_json::from_value(order_json()).unwrap();
+        let message = proto::Order::from(order);
+
+        assert_eq!(message.order_uid, "b563feb7b2b84b6test");
+        assert_eq!(
+            message.date_created,
+            Some(prost_types::Timestamp {
+                seconds: 1637907739,
+                nanos: 0,
+            })
+        );
+        assert_eq!(message.delivery, request().delivery);
+        assert_eq!(message.payment, request().payment);
+        assert_eq!(message.items, request().items);
+    }
+
+    #[test]
+    fn maps_http_status_to_code() {
+        let cases = [
+            (StatusCode::BAD_REQUEST, Code::InvalidArgument),
+            (StatusCode::NOT_FOUND, Code::NotFound),
+            (StatusCode::CONFLICT, Code::AlreadyExists),
+            (StatusCode::UNPROCESSABLE_ENTITY, Code::FailedPrecondition),
+            (StatusCode::SERVICE_UNAVAILABLE, Code::Unavailable),
+            (StatusCode::NOT_IMPLEMENTED, Code::Unimplemented),
+            (StatusCode::INTERNAL_SERVER_ERROR, Code::Internal),
+            (StatusCode::IM_A_TEAPOT, Code::Unknown),
+        ];
+        for (status, code) in cases {
+            assert_eq!(status_code(status), code, "{status}");
+        }
+
+        let status = storage_status(AppError::ConnectionError("down".to_string()), "Error");
+        assert_eq!(status.code(), Code::Unavailable);
+        let status = storage_status(AppError::UnsupportedError("op".to_string()), "Error");
+        assert_eq!(status.code(), Code::Unimplemented);
+    }
+
+    #[tokio::test]
+    async fn batch_gets_orders_in_request_order() {
+        let service = service();
+        service.create_order(Request::new(request())).await.unwrap();
+        // Второй заказ есть только в хранилище, не в кеше
+        let mut body = CreateOrderDTO::try_from(request()).unwrap();
+        body.order_uid = Some("order-2".to_string());
+        service
+            .state
+            .storage
+            .create_order(&body, None)
+            .await
+            .unwrap();
+
+        let order_uids = [
+            "b563feb7b2b84b6test",
+            "missing",
+            "order-2",
+            "b563feb7b2b84b6test",
+        ];
+        let response = service
+            .batch_get_orders(Request::new(proto::BatchGetOrdersRequest {
+                order_uids: order_uids.iter().map(|uid| uid.to_string()).collect(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let found: Vec<&str> = response
+            .orders
+            .iter()
+            .map(|order| order.order_uid.as_str())
+            .collect();
+        assert_eq!(
+            found,
+            ["b563feb7b2b84b6test", "order-2", "b563feb7b2b84b6test"]
+        );
+        assert_eq!(response.missing, ["missing"]);
+    }
+
+    #[tokio::test]
+    async fn stores_rejected_request_as_json() {
+        let service = service();
+        let mut message = request();
+        message.delivery = None;
+
+        let status = service
+            .create_order(Request::new(message))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let query = DeadLetterListQuery {
+            source: Some(SOURCE_GRPC.to_string()),
+            pending: false,
+            limit: 10,
+            offset: 0,
+        };
+        let letters = service
+            .state
+            .storage
+            .list_dead_letters(&query)
+            .await
+            .unwrap();
+        assert_eq!(letters.len(), 1);
+        let payload: serde_json::Value = serde_json::from_str(&letters[0].payload).unwrap();
+        assert_eq!(payload["track_number"], "WBILMTESTTRACK");
+    }
+}
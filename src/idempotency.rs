@@ -0,0 +1,88 @@
+use axum::{http::StatusCode, Json};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+// Максимальная длина ключа идемпотентности
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+// Результат попытки занять ключ идемпотентности
+pub enum IdempotencyClaim {
+    // Ключ новый (или истёк), запрос нужно выполнить
+    Claimed,
+    // Запрос с этим ключом уже выполнен, возвращаем сохранённый ответ
+    Replay(StatusCode, serde_json::Value),
+    // Запрос с этим ключом ещё выполняется
+    InProgress,
+    // Ключ уже использован с другим телом запроса
+    Mismatch,
+}
+
+// Проверяет значение заголовка Idempotency-Key
+pub fn validate_key(key: &str) -> Result<(), String> {
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+        return Err(format!(
+            "{IDEMPOTENCY_KEY_HEADER} must be between 1 and {MAX_IDEMPOTENCY_KEY_LENGTH} characters long"
+        ));
+    }
+
+    Ok(())
+}
+
+// Хеш запроса: метод, путь и тело в каноничном JSON-представлении
+pub fn request_hash<T: Serialize>(method: &str, path: &str, body: &T) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_bytes());
+    hasher.update(b" ");
+    hasher.update(path.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(serde_json::to_vec(body).unwrap_or_default());
+
+    hex::encode(hasher.finalize())
+}
+
+// order_uid для заказа без своего. С ключом идемпотентности он выводится
+// из ключа: повтор с тем же ключом находит уже созданный заказ
+pub fn new_order_uid(key: Option<&str>) -> String {
+    let Some(key) = key else {
+        return Uuid::new_v4().to_string();
+    };
+
+    let digest = Sha256::digest(key.as_bytes());
+    let bytes: [u8; 16] = digest[..16].try_into().expect("digest is 32 bytes long");
+    uuid::Builder::from_random_bytes(bytes)
+        .into_uuid()
+        .to_string()
+}
+
+// Ответ на успешное создание заказа. Сохраняется хранилищем вместе с заказом,
+// чтобы повтор запроса с тем же ключом вернул ровно его
+pub fn created_response(order_uid: &str) -> (StatusCode, serde_json::Value) {
+    (
+        StatusCode::CREATED,
+        serde_json::json!({
+            "order_uid": order_uid,
+        }),
+    )
+}
+
+// Ответ для запросов, которые нельзя выполнить из-за состояния ключа
+pub fn claim_error_response(claim: &IdempotencyClaim) -> (StatusCode, Json<serde_json::Value>) {
+    let (status, message) = match claim {
+        IdempotencyClaim::InProgress => (
+            StatusCode::CONFLICT,
+            "A request with this Idempotency-Key is already in progress",
+        ),
+        _ => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Idempotency-Key has already been used with a different request",
+        ),
+    };
+
+    (
+        status,
+        Json(serde_json::json!({"status": "error", "message": message})),
+    )
+}
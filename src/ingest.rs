@@ -0,0 +1,121 @@
+use axum::{http::StatusCode, Json};
+use log::info;
+
+use crate::{dead_letters, errors::response_message, routes, schema::CreateOrderDTO, AppState};
+
+// Результат обработки заказа из очереди или файла
+#[derive(Debug)]
+pub enum IngestOutcome {
+    // Заказ создан или уже существует с тем же содержимым
+    Accepted,
+    // Заказ не может быть создан, повтор не поможет. Сохранён в dead_letters
+    Rejected(String),
+    // Временная ошибка базы (503), обработку нужно повторить позже
+    Failed(String),
+}
+
+// Создаёт заказ из JSON тем же путём, что и POST /api/orders:
+// проверки, транзакция с повторами, ключ идемпотентности и обновление кеша
+pub async fn ingest_order(
+    state: &AppState,
+    source: &str,
+    reference: &str,
+    idempotency_key: Option<&str>,
+    payload: &[u8],
+) -> IngestOutcome {
+    let body: CreateOrderDTO = match serde_json::from_slice(payload) {
+        Ok(body) => body,
+        Err(err) => {
+            let error = format!("Invalid order JSON: {err}");
+            dead_letters::record(state, source, Some(reference), payload, &error).await;
+            return IngestOutcome::Rejected(error);
+        }
+    };
+
+    let result = routes::submit_order(
+        state,
+        &body,
+        idempotency_key,
+        source,
+        Some(reference),
+        payload,
+    )
+    .await;
+    match result {
+        Ok((status, _)) if status.is_success() => {
+            info!(
+                "Ingested order {} from {source} {reference} ({status})",
+                body.order_uid.unwrap_or_default()
+            );
+            IngestOutcome::Accepted
+        }
+        // Сохранённый по ключу ответ на ранее отклонённый заказ
+        Ok((_, Json(response))) => IngestOutcome::Rejected(response_message(&response)),
+        // Остальные ошибки сервера повторятся так же, поэтому заказ отклоняется
+        Err((StatusCode::SERVICE_UNAVAILABLE, Json(response))) => {
+            IngestOutcome::Failed(response_message(&response))
+        }
+        Err((_, Json(response))) => IngestOutcome::Rejected(response_message(&response)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        routes::tests::state,
+        storage::{memory::MemoryOrderRepository, postgres, DeadLetterListQuery},
+    };
+
+    fn payload(order_uid: &str) -> serde_json::Value {
+        let mut order: serde_json::Value =
+            serde_json::from_str(include_str!("test/stubs/order.json")).unwrap();
+        order["order_uid"] = serde_json::json!(order_uid);
+        order
+    }
+
+    #[tokio::test]
+    async fn accepts_repeated_order_and_rejects_invalid_json() {
+        let state = state(Arc::new(MemoryOrderRepository::new()));
+        let order = payload("ingest-1").to_string();
+
+        for _ in 0..2 {
+            let outcome = ingest_order(&state, "test", "ref", None, order.as_bytes()).await;
+            assert!(matches!(outcome, IngestOutcome::Accepted), "{outcome:?}");
+        }
+
+        let outcome = ingest_order(&state, "test", "ref", None, b"not json").await;
+        assert!(matches!(outcome, IngestOutcome::Rejected(_)), "{outcome:?}");
+    }
+
+    // Ошибка базы, которая не пройдёт и при повторе, отклоняет заказ,
+    // а не останавливает обработку очереди
+    #[tokio::test]
+    async fn rejects_order_on_permanent_database_error() {
+        let Some(repository) = postgres::tests::repository().await else {
+            return;
+        };
+        let state = state(Arc::new(repository));
+        let mut order = payload(&format!("ingest-{}", uuid::Uuid::new_v4()));
+        order["delivery"]["name"] = serde_json::json!("Test\u{0}Testov");
+
+        let outcome = ingest_order(&state, "test", "ref", None, order.to_string().as_bytes()).await;
+        assert!(matches!(outcome, IngestOutcome::Rejected(_)), "{outcome:?}");
+
+        let letters = state
+            .storage
+            .list_dead_letters(&DeadLetterListQuery {
+                source: Some("test".to_string()),
+                pending: true,
+                limit: 100,
+                offset: 0,
+            })
+            .await
+            .unwrap();
+        assert!(letters.iter().any(|letter| letter
+            .payload
+            .contains(order["order_uid"].as_str().unwrap())));
+    }
+}
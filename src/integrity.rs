@@ -0,0 +1,95 @@
+use crate::{db::Database, errors::AppError};
+
+// Сколько order_uid с нарушением выводится в отчёт
+pub const EXAMPLES_LIMIT: usize = 10;
+
+// Проверки целостности: запрос возвращает order_uid строк с нарушением.
+// Запросы общие для PostgreSQL и SQLite
+pub const CHECKS: &[(&str, &str)] = &[
+    (
+        "delivery without order",
+        "SELECT COALESCE(d.order_uid, 'NULL') AS order_uid FROM delivery d
+         LEFT JOIN orders o ON o.order_uid = d.order_uid
+         WHERE o.order_uid IS NULL",
+    ),
+    (
+        "payment without order",
+        "SELECT COALESCE(p.order_uid, 'NULL') AS order_uid FROM payment p
+         LEFT JOIN orders o ON o.order_uid = p.order_uid
+         WHERE o.order_uid IS NULL",
+    ),
+    (
+        "items without order",
+        "SELECT COALESCE(i.order_uid, 'NULL') AS order_uid FROM items i
+         LEFT JOIN orders o ON o.order_uid = i.order_uid
+         WHERE o.order_uid IS NULL",
+    ),
+    (
+        "duplicated delivery",
+        "SELECT order_uid FROM delivery
+         WHERE order_uid IS NOT NULL
+         GROUP BY order_uid HAVING COUNT(*) > 1",
+    ),
+    (
+        "duplicated payment",
+        "SELECT order_uid FROM payment
+         WHERE order_uid IS NOT NULL
+         GROUP BY order_uid HAVING COUNT(*) > 1",
+    ),
+    (
+        "order without delivery",
+        "SELECT o.order_uid FROM orders o
+         LEFT JOIN delivery d ON d.order_uid = o.order_uid
+         WHERE d.order_uid IS NULL",
+    ),
+    (
+        "order without payment",
+        "SELECT o.order_uid FROM orders o
+         LEFT JOIN payment p ON p.order_uid = o.order_uid
+         WHERE p.order_uid IS NULL",
+    ),
+];
+
+// Найденное нарушение: число строк и несколько order_uid для примера
+#[derive(Debug)]
+pub struct IntegrityIssue {
+    pub check: String,
+    pub count: i64,
+    pub examples: Vec<String>,
+}
+
+pub fn count_query(query: &str) -> String {
+    format!("SELECT COUNT(*) FROM ({query}) AS issues")
+}
+
+pub fn examples_query(query: &str) -> String {
+    format!("{query} ORDER BY order_uid LIMIT {EXAMPLES_LIMIT}")
+}
+
+// Выполняет проверки в PostgreSQL, возвращает только найденные нарушения
+pub async fn check(db: &Database) -> Result<Vec<IntegrityIssue>, AppError> {
+    let mut issues = Vec::new();
+
+    for (check, query) in CHECKS {
+        let count: i64 = db.client.query_one(&count_query(query), &[]).await?.get(0);
+        if count == 0 {
+            continue;
+        }
+
+        let examples = db
+            .client
+            .query(&examples_query(query), &[])
+            .await?
+            .iter()
+            .map(|row| row.get("order_uid"))
+            .collect();
+
+        issues.push(IntegrityIssue {
+            check: check.to_string(),
+            count,
+            examples,
+        });
+    }
+
+    Ok(issues)
+}
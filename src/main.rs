@@ -5,22 +5,62 @@ use axum::{
     Router,
 };
 use cache::Cache;
+use commands::Command;
+use db::{DatabaseNode, ReplicaSet};
+use dead_letters::DeadLetterMetrics;
 use errors::{api_fallback, AppError};
+use graphql::GraphqlConfig;
 use migrate::Migration;
+use order_events::EventHub;
+use retry::RetryPolicy;
 use schema::GetOrderDTO;
+use storage::{
+    memory::MemoryOrderRepository, postgres::PostgresOrderRepository,
+    sharded::ShardedOrderRepository, sqlite::SqliteOrderRepository, OrderRepository,
+    StorageBackend,
+};
 use tokio::sync::Mutex;
-use tokio_postgres::{Client, NoTls};
 
+mod archive;
 mod cache;
+mod commands;
+mod consumer;
+mod csv_orders;
+mod db;
+mod dead_letters;
 mod errors;
+mod file_ingest;
 mod fill_test_data;
+mod formats;
+mod graphql;
+mod grpc;
+mod idempotency;
+mod ingest;
+mod integrity;
 mod migrate;
+mod money;
+mod order_events;
+mod outbox;
+mod parquet_export;
+mod query_plans;
+mod retry;
 mod routes;
 mod schema;
+mod storage;
+mod timestamp;
+mod tls;
 mod utils;
+mod webhooks;
 use clap::Parser;
 
-use crate::routes::{create_order_handler, get_order_handler};
+use crate::routes::{
+    create_order_handler, create_webhook_handler, delete_webhook_handler, export_items_csv_handler,
+    export_orders_csv_handler, get_dead_letter_handler, get_order_handler, get_webhook_handler,
+    import_orders_csv_handler, list_dead_letters_handler, list_orders_handler,
+    list_webhook_deliveries_handler, list_webhooks_handler, metrics_handler,
+    redeliver_webhook_handler, replay_dead_letter_handler, stream_orders_handler,
+    stream_orders_ws_handler,
+};
 use log::{error, info, warn};
 
 /// Orders service
@@ -43,6 +83,10 @@ pub struct Args {
     #[arg(short, long, default_value_t = 8000)]
     port: u16,
 
+    /// gRPC server port
+    #[arg(long, default_value_t = 50051)]
+    grpc_port: u16,
+
     /// Migraion script
     #[arg(short, long, value_enum)]
     migration: Option<Migration>,
@@ -50,55 +94,197 @@ pub struct Args {
     /// Run test data script
     #[clap(long, action)]
     test_run: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
 pub struct AppState {
-    db: Arc<Mutex<Client>>,
+    storage: Arc<dyn OrderRepository>,
     cache: Arc<Mutex<Cache<GetOrderDTO>>>,
+    idempotency_key_ttl: Duration,
+    retry_policy: RetryPolicy,
+    dead_letter_metrics: DeadLetterMetrics,
+    order_events: Arc<EventHub>,
 }
 
-// Создание роутера
-fn create_router(app_state: Arc<AppState>) -> Router {
-    return Router::new()
+// Создание роутера. API вебхуков подключается, только если они включены
+fn create_router(
+    app_state: Arc<AppState>,
+    graphql_config: &GraphqlConfig,
+    webhooks_enabled: bool,
+) -> Router {
+    let router = Router::new()
+        .route("/api/orders/stream", get(stream_orders_handler))
+        .route("/api/orders/stream/ws", get(stream_orders_ws_handler))
         .route("/api/orders/:id", get(get_order_handler))
-        .route("/api/orders", post(create_order_handler))
+        .route(
+            "/api/orders",
+            get(list_orders_handler).post(create_order_handler),
+        )
+        .route("/api/export/orders.csv", get(export_orders_csv_handler))
+        .route("/api/export/items.csv", get(export_items_csv_handler))
+        .route("/api/import/orders.csv", post(import_orders_csv_handler))
+        .route("/api/dead-letters", get(list_dead_letters_handler))
+        .route("/api/dead-letters/:id", get(get_dead_letter_handler))
+        .route(
+            "/api/dead-letters/:id/replay",
+            post(replay_dead_letter_handler),
+        )
+        .route("/metrics", get(metrics_handler));
+    let router = if webhooks_enabled {
+        router.merge(webhook_router())
+    } else {
+        router
+    };
+
+    router
+        .merge(graphql::router(app_state.clone(), graphql_config))
         .fallback(api_fallback)
-        .with_state(app_state);
+        .with_state(app_state)
+}
+
+fn webhook_router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route(
+            "/api/webhooks",
+            get(list_webhooks_handler).post(create_webhook_handler),
+        )
+        .route(
+            "/api/webhooks/:id",
+            get(get_webhook_handler).delete(delete_webhook_handler),
+        )
+        .route(
+            "/api/webhooks/:id/deliveries",
+            get(list_webhook_deliveries_handler),
+        )
+        .route(
+            "/api/webhooks/:id/deliveries/:delivery_id/redeliver",
+            post(redeliver_webhook_handler),
+        )
+}
+
+// Создание хранилища заказов выбранного типа
+async fn create_storage(
+    backend: StorageBackend,
+    webhooks_enabled: bool,
+) -> Result<Arc<dyn OrderRepository>, AppError> {
+    info!("Using {backend} storage");
+
+    match backend {
+        StorageBackend::Postgres => {
+            let tls_config = utils::postgres_tls_config();
+            info!("Postgres TLS mode: {}", tls_config.mode);
+
+            let tls = tls::make_connector(&tls_config)?;
+
+            let replica_strings = utils::postgres_replicas();
+            if !replica_strings.is_empty() {
+                info!("Using {} read replicas", replica_strings.len());
+            }
+            let replicas = replica_strings
+                .into_iter()
+                .enumerate()
+                .map(|(index, connection_string)| {
+                    DatabaseNode::new(
+                        &format!("replica {}", index + 1),
+                        connection_string,
+                        tls.clone(),
+                    )
+                })
+                .collect();
+
+            let repository = PostgresOrderRepository::connect(
+                "primary",
+                utils::build_connection_string(),
+                tls.clone(),
+                ReplicaSet::new(replicas, utils::replica_cooldown()),
+                utils::read_your_writes_window(),
+            )
+            .await?;
+
+            // С шардами основная база хранит справочник шардов и ключи идемпотентности
+            let shard_strings = utils::postgres_shards();
+            if shard_strings.is_empty() {
+                return Ok(Arc::new(repository));
+            }
+
+            // Доставки вебхуков пишутся в транзакции заказа, а подписок в шардах нет
+            if webhooks_enabled {
+                return Err(AppError::ConfigError(
+                    "webhooks are not supported with POSTGRES_SHARDS, set WEBHOOKS_ENABLED=false"
+                        .to_string(),
+                ));
+            }
+
+            info!("Using {} shards", shard_strings.len());
+            let mut shards = Vec::with_capacity(shard_strings.len());
+            for (index, connection_string) in shard_strings.into_iter().enumerate() {
+                let shard = PostgresOrderRepository::connect(
+                    &format!("shard {index}"),
+                    connection_string,
+                    tls.clone(),
+                    ReplicaSet::new(Vec::new(), utils::replica_cooldown()),
+                    utils::read_your_writes_window(),
+                )
+                .await?;
+                shards.push(shard);
+            }
+
+            Ok(Arc::new(ShardedOrderRepository::new(repository, shards)))
+        }
+        StorageBackend::Sqlite => Ok(Arc::new(
+            SqliteOrderRepository::open(&utils::sqlite_path())?,
+        )),
+        StorageBackend::Memory => Ok(Arc::new(MemoryOrderRepository::new())),
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), AppError> {
     utils::init_logger();
+    timestamp::init(utils::timestamp_format());
 
     let args_arc = Arc::new(Args::parse());
-    let (client, connection) =
-        tokio_postgres::connect(&utils::build_connection_string(), NoTls).await?;
-
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            error!("Database connection error: {e}");
-        }
-    });
+    let webhook_config = utils::webhook_config();
+    let storage = create_storage(utils::storage_backend(), webhook_config.enabled).await?;
 
     let cache: Cache<GetOrderDTO> = cache::Cache::new();
+    let event_stream_config = utils::event_stream_config();
     let app_state = Arc::new(AppState {
-        db: Arc::new(Mutex::new(client)),
+        storage,
         cache: Arc::new(Mutex::new(cache)),
+        idempotency_key_ttl: utils::idempotency_key_ttl(),
+        retry_policy: utils::retry_policy(),
+        dead_letter_metrics: DeadLetterMetrics::default(),
+        order_events: Arc::new(EventHub::new(event_stream_config.buffer)),
     });
 
+    // Команда обслуживания выполняется после миграции, сервер не запускается
+    if let Some(command) = args_arc.command.clone() {
+        if let Some(migration) = args_arc.migration.clone() {
+            app_state.storage.migrate(migration).await?;
+        }
+        return commands::run(command, app_state).await;
+    }
+
     match args_arc.migration.clone().unwrap_or(Migration::None) {
         Migration::None => {}
         migration => {
-            let app_state_clone: Arc<AppState> = app_state.clone();
+            let storage_clone = app_state.storage.clone();
             tokio::spawn(async move {
-                if let Err(e) = migrate::migrate(app_state_clone, migration.clone()).await {
+                if let Err(e) = storage_clone.migrate(migration.clone()).await {
                     error!("Migration error: {e}");
                 }
             });
         }
     }
 
-    let router = create_router(app_state.clone());
+    let router = create_router(
+        app_state.clone(),
+        &utils::graphql_config(),
+        webhook_config.enabled,
+    );
     let port_connection = args_arc.port;
     let socket_addr = format!("0.0.0.0:{}", port_connection);
 
@@ -127,6 +313,97 @@ async fn main() -> Result<(), AppError> {
             }
         });
     }
+    {
+        let storage_clone = app_state.storage.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(3600));
+            // Удаляем истёкшие ключи идемпотентности раз в час
+            loop {
+                interval.tick().await;
+                match storage_clone.cleanup_expired_idempotency_keys().await {
+                    Ok(removed) => info!("Removed {removed} expired idempotency keys"),
+                    Err(e) => error!("Idempotency keys cleanup error: {e}"),
+                }
+            }
+        });
+    }
+
+    {
+        let storage_clone = app_state.storage.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(86400));
+            // Раз в сутки создаём секции на следующий месяц и архивируем старые
+            loop {
+                interval.tick().await;
+                if let Err(e) = storage_clone.create_partitions().await {
+                    error!("Partitions creation error: {e}");
+                }
+                if let Some(before) = commands::retention_cutoff() {
+                    if let Err(e) = commands::archive_orders(&storage_clone, before).await {
+                        error!("Orders archivation error: {e}");
+                    }
+                }
+            }
+        });
+    }
+
+    // Relay событий outbox, без приёмника события копятся в таблице
+    let relay_config = utils::outbox_relay_config();
+    match outbox::make_sink(&relay_config).await? {
+        Some(sink) => {
+            info!(
+                "Outbox relay publishes to {} {}",
+                relay_config.sink,
+                relay_config.destination()
+            );
+            outbox::spawn_relay(app_state.storage.clone(), sink, relay_config);
+        }
+        None => warn!("OUTBOX_SINK is not set, outbox events are not published"),
+    }
+
+    // Отправка вебхуков подписчикам
+    if webhook_config.enabled {
+        webhooks::spawn_dispatcher(app_state.storage.clone(), webhook_config);
+    } else {
+        info!("Webhooks are disabled");
+    }
+
+    // Поток событий о заказах для SSE и WebSocket
+    order_events::spawn_feed(app_state.clone(), event_stream_config);
+
+    // Потребитель заказов из Kafka включается заданием KAFKA_ORDERS_TOPIC
+    if let Some(consumer_config) = utils::kafka_consumer_config() {
+        info!(
+            "Consuming orders from Kafka topic {} at {} as group {}, dead letters go to {}",
+            consumer_config.topic,
+            consumer_config.brokers,
+            consumer_config.group_id,
+            consumer_config.dead_letter_topic
+        );
+        consumer::spawn(app_state.clone(), consumer_config)?;
+    }
+
+    // Приём заказов из файлов включается заданием INGEST_DIR
+    if let Some(ingest_config) = utils::file_ingest_config() {
+        info!(
+            "Watching {} for order files every {:?}",
+            ingest_config.dir.display(),
+            ingest_config.poll_interval
+        );
+        file_ingest::spawn(app_state.clone(), ingest_config).await?;
+    }
+
+    // gRPC API на отдельном порту с общими хранилищем и кешем
+    {
+        let state_clone = app_state.clone();
+        let grpc_port = args_arc.grpc_port;
+        tokio::spawn(async move {
+            if let Err(e) = grpc::serve(state_clone, grpc_port).await {
+                error!("gRPC server error: {e}");
+            }
+        });
+        info!("gRPC server started on port: {grpc_port}");
+    }
 
     axum::serve(listener, router).await.unwrap();
 
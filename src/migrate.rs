@@ -1,9 +1,8 @@
-use std::{env, fmt, fs, path::Path, str::FromStr, sync::Arc};
+use std::{env, fmt, fs, path::Path, str::FromStr};
 
 use log::{error, info};
-use tokio_postgres::GenericClient;
 
-use crate::{errors::AppError, AppState};
+use crate::{db::Database, errors::AppError};
 
 // Типы миграций
 #[derive(Debug, Clone)]
@@ -36,35 +35,81 @@ impl FromStr for Migration {
     }
 }
 
-// Применяет миграции в зависимости от переданных аргументов
-pub async fn migrate(app_state: Arc<AppState>, migration: Migration) -> Result<(), AppError> {
-    let migration_name = match migration {
-        Migration::Up => "init_migration.sql",
-        Migration::Down => "down_migration.sql",
-        Migration::None => "",
-    };
+// Скрипты миграции вверх в порядке применения.
+// Новые миграции добавляются только в конец списка
+const UP_MIGRATIONS: &[&str] = &[
+    "init_migration.sql",
+    "002_client_order_uid.sql",
+    "003_idempotency_keys.sql",
+    "004_timestamptz.sql",
+    "005_money_bigint.sql",
+    "006_order_shards.sql",
+    "007_partition_by_month.sql",
+    "008_indexes.sql",
+    "009_one_to_one.sql",
+    "010_outbox.sql",
+    "011_dead_letters.sql",
+    "012_webhooks.sql",
+];
+
+const DOWN_MIGRATION: &str = "down_migration.sql";
 
-    let resolved_migration_script_string = match load_migration_script_as_string(migration_name) {
-        Ok(migration) => migration,
-        Err(err) => {
-            return Err(err)?;
+// Применяет миграции в зависимости от переданных аргументов
+pub async fn migrate(client_db: &mut Database, migration: Migration) -> Result<(), AppError> {
+    match migration {
+        Migration::Up => {
+            client_db
+                .client
+                .batch_execute(
+                    "CREATE TABLE IF NOT EXISTS schema_migrations (
+                        name VARCHAR PRIMARY KEY,
+                        applied_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+                    )",
+                )
+                .await?;
+
+            for migration_name in UP_MIGRATIONS {
+                let applied = client_db
+                    .client
+                    .query_opt(
+                        "SELECT name FROM schema_migrations WHERE name = $1",
+                        &[migration_name],
+                    )
+                    .await?;
+                if applied.is_some() {
+                    continue;
+                }
+
+                let migration_script = load_migration_script_as_string(migration_name)?;
+
+                // Каждая миграция применяется в своей транзакции вместе с отметкой о ней
+                let transaction = client_db.client.transaction().await?;
+                transaction.batch_execute(&migration_script).await?;
+                transaction
+                    .execute(
+                        "INSERT INTO schema_migrations (name) VALUES ($1)",
+                        &[migration_name],
+                    )
+                    .await?;
+                transaction.commit().await?;
+
+                info!("Applied migration {migration_name}");
+            }
         }
-    };
-
-    let client_db = app_state.db.lock().await;
-    for migration_script in resolved_migration_script_string
-        .split(";")
-        .collect::<Vec<&str>>()
-    {
-        client_db.client().execute(migration_script, &[]).await?;
+        Migration::Down => {
+            let migration_script = load_migration_script_as_string(DOWN_MIGRATION)?;
+            client_db.client.batch_execute(&migration_script).await?;
+        }
+        Migration::None => return Ok(()),
     }
 
-    info!("Succefully migrated!");
+    // Подготовленные выражения могли ссылаться на изменённые таблицы
+    client_db.statements.clear();
 
     Ok(())
 }
 
-fn load_migration_script_as_string(migration_name: &str) -> Result<String, std::io::Error> {
+pub fn load_migration_script_as_string(migration_name: &str) -> Result<String, std::io::Error> {
     let migration_script_path_string = format!("./src/migrations/{}", &migration_name);
 
     let migration_script_path = match Path::new(&migration_script_path_string).canonicalize() {
@@ -0,0 +1,20 @@
+-- order_uid может приходить от клиента в виде UUID или произвольной строки,
+-- поэтому хранится как VARCHAR; UUID генерируется, только если клиент его не передал
+ALTER TABLE delivery DROP CONSTRAINT IF EXISTS delivery_order_uid_fkey;
+ALTER TABLE payment DROP CONSTRAINT IF EXISTS payment_order_uid_fkey;
+ALTER TABLE items DROP CONSTRAINT IF EXISTS items_order_uid_fkey;
+
+ALTER TABLE orders ALTER COLUMN order_uid DROP DEFAULT;
+ALTER TABLE orders ALTER COLUMN order_uid TYPE VARCHAR USING order_uid::VARCHAR;
+ALTER TABLE orders ALTER COLUMN order_uid SET DEFAULT public.uuid_generate_v4()::VARCHAR;
+
+ALTER TABLE delivery ALTER COLUMN order_uid TYPE VARCHAR USING order_uid::VARCHAR;
+ALTER TABLE payment ALTER COLUMN order_uid TYPE VARCHAR USING order_uid::VARCHAR;
+ALTER TABLE items ALTER COLUMN order_uid TYPE VARCHAR USING order_uid::VARCHAR;
+
+ALTER TABLE delivery ADD CONSTRAINT delivery_order_uid_fkey
+    FOREIGN KEY (order_uid) REFERENCES orders(order_uid) ON DELETE CASCADE;
+ALTER TABLE payment ADD CONSTRAINT payment_order_uid_fkey
+    FOREIGN KEY (order_uid) REFERENCES orders(order_uid) ON DELETE CASCADE;
+ALTER TABLE items ADD CONSTRAINT items_order_uid_fkey
+    FOREIGN KEY (order_uid) REFERENCES orders(order_uid) ON DELETE CASCADE;
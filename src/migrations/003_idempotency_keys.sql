@@ -0,0 +1,11 @@
+-- Ключи идемпотентности из заголовка Idempotency-Key.
+-- response_status и response_body заполняются после выполнения запроса,
+-- пока они пустые, запрос с этим ключом считается выполняющимся
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    idempotency_key VARCHAR PRIMARY KEY,
+    request_hash VARCHAR NOT NULL,
+    response_status INTEGER,
+    response_body JSONB,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,14 @@
+-- Все метки времени хранятся как TIMESTAMPTZ.
+-- Существующие значения TIMESTAMP считаются записанными в UTC
+ALTER TABLE orders
+    ALTER COLUMN date_created TYPE TIMESTAMPTZ USING date_created AT TIME ZONE 'UTC';
+
+ALTER TABLE payment
+    ALTER COLUMN payment_dt TYPE TIMESTAMPTZ USING payment_dt AT TIME ZONE 'UTC';
+
+ALTER TABLE idempotency_keys
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'UTC',
+    ALTER COLUMN expires_at TYPE TIMESTAMPTZ USING expires_at AT TIME ZONE 'UTC';
+
+ALTER TABLE schema_migrations
+    ALTER COLUMN applied_at TYPE TIMESTAMPTZ USING applied_at AT TIME ZONE 'UTC';
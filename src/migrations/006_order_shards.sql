@@ -0,0 +1,7 @@
+-- Справочник шардов: в какой базе хранится заказ.
+-- Заполняется только в режиме шардирования и только в основной базе
+CREATE TABLE IF NOT EXISTS order_shards (
+    order_uid VARCHAR PRIMARY KEY,
+    shard INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,161 @@
+-- Секционирование orders, items, delivery и payment по месяцу date_created.
+-- В дочерние таблицы добавляется date_created заказа, чтобы строки заказа
+-- попадали в секции одного месяца и архивировались вместе.
+-- Уникальность order_uid между секциями обеспечивает таблица order_uids:
+-- она не секционируется и не архивируется, поэтому идентификатор
+-- архивного заказа не может быть использован повторно
+
+CREATE TABLE IF NOT EXISTS order_uids (
+    order_uid VARCHAR PRIMARY KEY,
+    date_created TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO order_uids (order_uid, date_created)
+SELECT order_uid, date_created FROM orders
+ON CONFLICT (order_uid) DO NOTHING;
+
+-- Последовательности идентификаторов переходят к новым таблицам
+ALTER SEQUENCE items_item_id_seq OWNED BY NONE;
+ALTER SEQUENCE delivery_delivery_id_seq OWNED BY NONE;
+ALTER SEQUENCE payment_payment_id_seq OWNED BY NONE;
+
+ALTER TABLE items RENAME TO items_unpartitioned;
+ALTER TABLE delivery RENAME TO delivery_unpartitioned;
+ALTER TABLE payment RENAME TO payment_unpartitioned;
+ALTER TABLE orders RENAME TO orders_unpartitioned;
+
+CREATE TABLE orders (
+    order_uid VARCHAR NOT NULL,
+    track_number VARCHAR NOT NULL,
+    entry VARCHAR NOT NULL,
+    locale VARCHAR,
+    internal_signature VARCHAR,
+    customer_id VARCHAR NOT NULL,
+    delivery_service VARCHAR,
+    shardkey VARCHAR,
+    sm_id INTEGER,
+    date_created TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    oof_shard VARCHAR,
+    PRIMARY KEY (order_uid, date_created)
+) PARTITION BY RANGE (date_created);
+
+CREATE TABLE delivery (
+    delivery_id INTEGER NOT NULL DEFAULT nextval('delivery_delivery_id_seq'),
+    order_uid VARCHAR NOT NULL,
+    date_created TIMESTAMPTZ NOT NULL,
+    name VARCHAR NOT NULL,
+    phone VARCHAR NOT NULL,
+    zip VARCHAR NOT NULL,
+    city VARCHAR NOT NULL,
+    address VARCHAR NOT NULL,
+    region VARCHAR NOT NULL,
+    email VARCHAR NOT NULL,
+    PRIMARY KEY (delivery_id, date_created),
+    FOREIGN KEY (order_uid, date_created)
+        REFERENCES orders (order_uid, date_created) ON DELETE CASCADE
+) PARTITION BY RANGE (date_created);
+
+CREATE TABLE payment (
+    payment_id INTEGER NOT NULL DEFAULT nextval('payment_payment_id_seq'),
+    order_uid VARCHAR NOT NULL,
+    date_created TIMESTAMPTZ NOT NULL,
+    transaction VARCHAR NOT NULL,
+    request_id VARCHAR,
+    currency VARCHAR NOT NULL,
+    provider VARCHAR NOT NULL,
+    amount BIGINT NOT NULL,
+    payment_dt TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    bank VARCHAR NOT NULL,
+    delivery_cost BIGINT NOT NULL,
+    goods_total BIGINT NOT NULL,
+    custom_fee BIGINT NOT NULL,
+    PRIMARY KEY (payment_id, date_created),
+    FOREIGN KEY (order_uid, date_created)
+        REFERENCES orders (order_uid, date_created) ON DELETE CASCADE
+) PARTITION BY RANGE (date_created);
+
+CREATE TABLE items (
+    item_id INTEGER NOT NULL DEFAULT nextval('items_item_id_seq'),
+    order_uid VARCHAR NOT NULL,
+    date_created TIMESTAMPTZ NOT NULL,
+    chrt_id BIGINT NOT NULL,
+    track_number VARCHAR NOT NULL,
+    price BIGINT NOT NULL,
+    rid VARCHAR NOT NULL,
+    name VARCHAR NOT NULL,
+    sale INTEGER NOT NULL,
+    size VARCHAR NOT NULL,
+    total_price BIGINT NOT NULL,
+    nm_id BIGINT NOT NULL,
+    brand VARCHAR NOT NULL,
+    status INTEGER NOT NULL,
+    PRIMARY KEY (item_id, date_created),
+    FOREIGN KEY (order_uid, date_created)
+        REFERENCES orders (order_uid, date_created) ON DELETE CASCADE
+) PARTITION BY RANGE (date_created);
+
+ALTER SEQUENCE items_item_id_seq OWNED BY items.item_id;
+ALTER SEQUENCE delivery_delivery_id_seq OWNED BY delivery.delivery_id;
+ALTER SEQUENCE payment_payment_id_seq OWNED BY payment.payment_id;
+
+-- Создаёт секции всех четырёх таблиц за месяц, в который попадает month.
+-- Границы месяца считаются в UTC независимо от часового пояса сессии.
+-- Вызывается миграцией и фоновой задачей сервиса, повторный вызов ничего не меняет
+CREATE OR REPLACE FUNCTION create_order_partitions(month DATE) RETURNS VOID AS $$
+DECLARE
+    month_start DATE := date_trunc('month', month)::DATE;
+    month_end DATE := (date_trunc('month', month) + INTERVAL '1 month')::DATE;
+    suffix TEXT := to_char(month_start, '"y"YYYY"m"MM');
+    table_name TEXT;
+BEGIN
+    FOREACH table_name IN ARRAY ARRAY['orders', 'delivery', 'payment', 'items'] LOOP
+        EXECUTE format(
+            'CREATE TABLE IF NOT EXISTS %I PARTITION OF %I FOR VALUES FROM (%L) TO (%L)',
+            table_name || '_' || suffix,
+            table_name,
+            month_start::TIMESTAMP AT TIME ZONE 'UTC',
+            month_end::TIMESTAMP AT TIME ZONE 'UTC'
+        );
+    END LOOP;
+END;
+$$ LANGUAGE plpgsql;
+
+SELECT create_order_partitions(month::DATE)
+FROM (
+    SELECT DISTINCT date_trunc('month', date_created AT TIME ZONE 'UTC') AS month
+    FROM orders_unpartitioned
+    UNION
+    SELECT date_trunc('month', CURRENT_TIMESTAMP AT TIME ZONE 'UTC')
+    UNION
+    SELECT date_trunc('month', CURRENT_TIMESTAMP AT TIME ZONE 'UTC' + INTERVAL '1 month')
+) months;
+
+INSERT INTO orders SELECT
+    order_uid, track_number, entry, locale, internal_signature, customer_id,
+    delivery_service, shardkey, sm_id, date_created, oof_shard
+FROM orders_unpartitioned;
+
+INSERT INTO delivery SELECT
+    d.delivery_id, d.order_uid, o.date_created, d.name, d.phone, d.zip,
+    d.city, d.address, d.region, d.email
+FROM delivery_unpartitioned d JOIN orders_unpartitioned o USING (order_uid);
+
+INSERT INTO payment SELECT
+    p.payment_id, p.order_uid, o.date_created, p.transaction, p.request_id,
+    p.currency, p.provider, p.amount, p.payment_dt, p.bank, p.delivery_cost,
+    p.goods_total, p.custom_fee
+FROM payment_unpartitioned p JOIN orders_unpartitioned o USING (order_uid);
+
+INSERT INTO items SELECT
+    i.item_id, i.order_uid, o.date_created, i.chrt_id, i.track_number, i.price,
+    i.rid, i.name, i.sale, i.size, i.total_price, i.nm_id, i.brand, i.status
+FROM items_unpartitioned i JOIN orders_unpartitioned o USING (order_uid);
+
+DROP TABLE items_unpartitioned, delivery_unpartitioned, payment_unpartitioned, orders_unpartitioned;
+
+-- Архивированные месяцы. restored_at заполняется при восстановлении месяца из архива
+CREATE TABLE IF NOT EXISTS order_archives (
+    month DATE PRIMARY KEY,
+    archived_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    restored_at TIMESTAMPTZ
+);
@@ -0,0 +1,17 @@
+-- Индексы для основных запросов сервиса. Индекс секционированной таблицы
+-- создаётся во всех её секциях, в том числе в новых и восстановленных из архива
+
+-- Загрузка доставки, оплаты и товаров заказа, а также каскадное удаление
+CREATE INDEX IF NOT EXISTS delivery_order_uid_idx ON delivery (order_uid, date_created);
+CREATE INDEX IF NOT EXISTS payment_order_uid_idx ON payment (order_uid, date_created);
+CREATE INDEX IF NOT EXISTS items_order_uid_idx ON items (order_uid, date_created);
+
+-- Список заказов покупателя и общий список, новые первыми
+CREATE INDEX IF NOT EXISTS orders_customer_id_idx ON orders (customer_id, date_created DESC, order_uid);
+CREATE INDEX IF NOT EXISTS orders_date_created_idx ON orders (date_created DESC, order_uid);
+
+-- Поиск заказа по трек-номеру
+CREATE INDEX IF NOT EXISTS orders_track_number_idx ON orders (track_number);
+
+-- Очистка просроченных ключей идемпотентности
+CREATE INDEX IF NOT EXISTS idempotency_keys_expires_at_idx ON idempotency_keys (expires_at);
@@ -0,0 +1,25 @@
+-- У заказа ровно одна доставка и одна оплата: модель заказа содержит
+-- по одному объекту delivery и payment, частичные оплаты не поддерживаются.
+-- order_uid дочерних таблиц уже NOT NULL после 007_partition_by_month.sql.
+-- Миграция прерывается, если в базе уже есть повторы, найти их можно
+-- командой check-integrity
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM delivery GROUP BY order_uid HAVING COUNT(*) > 1) THEN
+        RAISE EXCEPTION 'delivery has several rows for one order, run check-integrity';
+    END IF;
+    IF EXISTS (SELECT 1 FROM payment GROUP BY order_uid HAVING COUNT(*) > 1) THEN
+        RAISE EXCEPTION 'payment has several rows for one order, run check-integrity';
+    END IF;
+END
+$$;
+
+-- Уникальность на секционированной таблице должна включать ключ секционирования.
+-- date_created дочерней строки равна дате заказа, поэтому пара
+-- (order_uid, date_created) задаёт одну строку на заказ.
+-- Индексы из 008_indexes.sql заменяются индексами ограничений
+DROP INDEX IF EXISTS delivery_order_uid_idx;
+DROP INDEX IF EXISTS payment_order_uid_idx;
+
+ALTER TABLE delivery ADD CONSTRAINT delivery_order_uid_key UNIQUE (order_uid, date_created);
+ALTER TABLE payment ADD CONSTRAINT payment_order_uid_key UNIQUE (order_uid, date_created);
@@ -0,0 +1,17 @@
+-- События о заказах для внешних систем. Запись идёт в одной транзакции
+-- с заказом, отправку выполняет relay сервиса в порядке event_id.
+-- delivered_at заполняется после успешной отправки, такие строки
+-- удаляются по истечении OUTBOX_RETENTION_HOURS
+CREATE TABLE IF NOT EXISTS outbox (
+    event_id BIGSERIAL PRIMARY KEY,
+    order_uid VARCHAR NOT NULL,
+    event_type VARCHAR NOT NULL,
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    delivered_at TIMESTAMPTZ,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error VARCHAR
+);
+
+CREATE INDEX IF NOT EXISTS outbox_pending_idx ON outbox (event_id) WHERE delivered_at IS NULL;
+CREATE INDEX IF NOT EXISTS outbox_delivered_at_idx ON outbox (delivered_at) WHERE delivered_at IS NOT NULL;
@@ -0,0 +1,16 @@
+-- Заказы, которые не удалось создать: тело запроса или сообщения как есть,
+-- источник и ошибка. replayed_at заполняется после успешного повтора,
+-- replay_error хранит ошибку последнего неудачного повтора
+CREATE TABLE IF NOT EXISTS dead_letters (
+    dead_letter_id BIGSERIAL PRIMARY KEY,
+    source VARCHAR NOT NULL,
+    reference VARCHAR,
+    payload TEXT NOT NULL,
+    error VARCHAR NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    replayed_at TIMESTAMPTZ,
+    replay_error VARCHAR
+);
+
+CREATE INDEX IF NOT EXISTS dead_letters_source_idx ON dead_letters (source, dead_letter_id DESC);
+CREATE INDEX IF NOT EXISTS dead_letters_pending_idx ON dead_letters (dead_letter_id DESC) WHERE replayed_at IS NULL;
@@ -0,0 +1,42 @@
+-- Подписки партнёров на события о заказах. Партнёр — служба доставки,
+-- подписка получает события только о заказах с её delivery_service.
+-- Тело каждой доставки подписывается HMAC-SHA256 с секретом подписки
+CREATE TABLE IF NOT EXISTS webhook_subscriptions (
+    subscription_id BIGSERIAL PRIMARY KEY,
+    delivery_service VARCHAR NOT NULL,
+    url VARCHAR NOT NULL,
+    event_types VARCHAR[] NOT NULL,
+    secret VARCHAR NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Доставки события подписчику. Строки добавляются в транзакции заказа
+-- вместе с событием outbox. pending ждёт попытки в next_attempt_at,
+-- delivered и failed (попытки исчерпаны) — итог, повтор вручную
+-- возвращает строку в pending
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    delivery_id BIGSERIAL PRIMARY KEY,
+    subscription_id BIGINT NOT NULL
+        REFERENCES webhook_subscriptions (subscription_id) ON DELETE CASCADE,
+    event_id BIGINT NOT NULL,
+    order_uid VARCHAR NOT NULL,
+    event_type VARCHAR NOT NULL,
+    payload JSONB NOT NULL,
+    status VARCHAR NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    last_status_code INTEGER,
+    last_error VARCHAR,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_attempt_at TIMESTAMPTZ,
+    delivered_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS webhook_subscriptions_delivery_service_idx
+    ON webhook_subscriptions (delivery_service);
+CREATE INDEX IF NOT EXISTS webhook_deliveries_subscription_idx
+    ON webhook_deliveries (subscription_id, delivery_id DESC);
+CREATE INDEX IF NOT EXISTS webhook_deliveries_due_idx
+    ON webhook_deliveries (next_attempt_at) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS webhook_deliveries_created_at_idx
+    ON webhook_deliveries (created_at) WHERE status <> 'pending';
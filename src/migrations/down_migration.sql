@@ -4,4 +4,24 @@ DROP TABLE IF EXISTS delivery CASCADE;
 
 DROP TABLE IF EXISTS payment CASCADE;
 
-DROP TABLE IF EXISTS items CASCADE;
\ No newline at end of file
+DROP TABLE IF EXISTS items CASCADE;
+
+DROP TABLE IF EXISTS schema_migrations CASCADE;
+
+DROP TABLE IF EXISTS idempotency_keys CASCADE;
+
+DROP TABLE IF EXISTS order_shards CASCADE;
+
+DROP TABLE IF EXISTS order_uids CASCADE;
+
+DROP TABLE IF EXISTS order_archives CASCADE;
+
+DROP FUNCTION IF EXISTS create_order_partitions(DATE);
+
+DROP TABLE IF EXISTS webhook_deliveries CASCADE;
+
+DROP TABLE IF EXISTS webhook_subscriptions CASCADE;
+
+DROP TABLE IF EXISTS outbox CASCADE;
+
+DROP TABLE IF EXISTS dead_letters CASCADE;
@@ -1,7 +1,5 @@
 SET search_path TO public;
-DROP EXTENSION IF EXISTS "uuid-ossp";
-
-CREATE EXTENSION "uuid-ossp" SCHEMA public;
+CREATE EXTENSION IF NOT EXISTS "uuid-ossp" SCHEMA public;
 
 CREATE TABLE IF NOT EXISTS orders (
     order_uid UUID PRIMARY KEY DEFAULT public.uuid_generate_v4() NOT NULL,
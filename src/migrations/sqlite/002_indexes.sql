@@ -0,0 +1,10 @@
+-- Индексы для основных запросов сервиса, как в 008_indexes.sql для PostgreSQL
+CREATE INDEX IF NOT EXISTS delivery_order_uid_idx ON delivery (order_uid);
+CREATE INDEX IF NOT EXISTS payment_order_uid_idx ON payment (order_uid);
+CREATE INDEX IF NOT EXISTS items_order_uid_idx ON items (order_uid);
+
+CREATE INDEX IF NOT EXISTS orders_customer_id_idx ON orders (customer_id, date_created DESC, order_uid);
+CREATE INDEX IF NOT EXISTS orders_date_created_idx ON orders (date_created DESC, order_uid);
+CREATE INDEX IF NOT EXISTS orders_track_number_idx ON orders (track_number);
+
+CREATE INDEX IF NOT EXISTS idempotency_keys_expires_at_idx ON idempotency_keys (expires_at);
@@ -0,0 +1,57 @@
+-- Одна доставка и одна оплата на заказ, order_uid дочерних таблиц NOT NULL.
+-- SQLite не меняет ограничения существующих столбцов, поэтому таблицы
+-- пересоздаются. При повторах вставка упадёт на UNIQUE, найти их можно
+-- командой check-integrity
+CREATE TABLE delivery_new (
+    delivery_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    order_uid TEXT NOT NULL UNIQUE REFERENCES orders(order_uid) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    phone TEXT NOT NULL,
+    zip TEXT NOT NULL,
+    city TEXT NOT NULL,
+    address TEXT NOT NULL,
+    region TEXT NOT NULL,
+    email TEXT NOT NULL
+);
+INSERT INTO delivery_new SELECT * FROM delivery;
+DROP TABLE delivery;
+ALTER TABLE delivery_new RENAME TO delivery;
+
+CREATE TABLE payment_new (
+    payment_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    order_uid TEXT NOT NULL UNIQUE REFERENCES orders(order_uid) ON DELETE CASCADE,
+    "transaction" TEXT NOT NULL,
+    request_id TEXT,
+    currency TEXT NOT NULL,
+    provider TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    payment_dt TEXT NOT NULL,
+    bank TEXT NOT NULL,
+    delivery_cost INTEGER NOT NULL,
+    goods_total INTEGER NOT NULL,
+    custom_fee INTEGER NOT NULL
+);
+INSERT INTO payment_new SELECT * FROM payment;
+DROP TABLE payment;
+ALTER TABLE payment_new RENAME TO payment;
+
+CREATE TABLE items_new (
+    item_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    order_uid TEXT NOT NULL REFERENCES orders(order_uid) ON DELETE CASCADE,
+    chrt_id INTEGER NOT NULL,
+    track_number TEXT NOT NULL,
+    price INTEGER NOT NULL,
+    rid TEXT NOT NULL,
+    name TEXT NOT NULL,
+    sale INTEGER NOT NULL,
+    size TEXT NOT NULL,
+    total_price INTEGER NOT NULL,
+    nm_id INTEGER NOT NULL,
+    brand TEXT NOT NULL,
+    status INTEGER NOT NULL
+);
+INSERT INTO items_new SELECT * FROM items;
+DROP TABLE items;
+ALTER TABLE items_new RENAME TO items;
+
+CREATE INDEX IF NOT EXISTS items_order_uid_idx ON items (order_uid);
@@ -0,0 +1,14 @@
+-- Заказы, которые не удалось создать, как в 011_dead_letters.sql для PostgreSQL
+CREATE TABLE IF NOT EXISTS dead_letters (
+    dead_letter_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    source TEXT NOT NULL,
+    reference TEXT,
+    payload TEXT NOT NULL,
+    error TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    replayed_at TEXT,
+    replay_error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS dead_letters_source_idx ON dead_letters (source, dead_letter_id DESC);
+CREATE INDEX IF NOT EXISTS dead_letters_pending_idx ON dead_letters (dead_letter_id DESC) WHERE replayed_at IS NULL;
@@ -0,0 +1,15 @@
+DROP TABLE IF EXISTS items;
+
+DROP TABLE IF EXISTS payment;
+
+DROP TABLE IF EXISTS delivery;
+
+DROP TABLE IF EXISTS orders;
+
+DROP TABLE IF EXISTS idempotency_keys;
+
+DROP TABLE IF EXISTS schema_migrations;
+
+DROP TABLE IF EXISTS outbox;
+
+DROP TABLE IF EXISTS dead_letters;
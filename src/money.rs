@@ -0,0 +1,225 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum MoneyError {
+    #[error("Invalid currency code: {0}")]
+    InvalidCurrency(String),
+
+    #[error("Currency mismatch: {0} and {1}")]
+    CurrencyMismatch(Currency, Currency),
+
+    #[error("Amount overflow")]
+    Overflow,
+}
+
+// Сумма в минимальных единицах валюты (центы, копейки, иены).
+// В JSON передаётся как целое число, в базе хранится как BIGINT
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(transparent)]
+pub struct MinorUnits(pub i64);
+
+impl From<i64> for MinorUnits {
+    fn from(value: i64) -> Self {
+        MinorUnits(value)
+    }
+}
+
+// Действующие коды валют ISO 4217 в алфавитном порядке. Коды без минимальной
+// единицы (драгоценные металлы, расчётные единицы, XTS и XXX) не принимаются
+const ISO_4217_CODES: &[&str] = &[
+    "AED", "AFN", "ALL", "AMD", "ANG", "AOA", "ARS", "AUD", "AWG", "AZN", "BAM", "BBD", "BDT",
+    "BGN", "BHD", "BIF", "BMD", "BND", "BOB", "BOV", "BRL", "BSD", "BTN", "BWP", "BYN", "BZD",
+    "CAD", "CDF", "CHE", "CHF", "CHW", "CLF", "CLP", "CNY", "COP", "COU", "CRC", "CUC", "CUP",
+    "CVE", "CZK", "DJF", "DKK", "DOP", "DZD", "EGP", "ERN", "ETB", "EUR", "FJD", "FKP", "GBP",
+    "GEL", "GHS", "GIP", "GMD", "GNF", "GTQ", "GYD", "HKD", "HNL", "HTG", "HUF", "IDR", "ILS",
+    "INR", "IQD", "IRR", "ISK", "JMD", "JOD", "JPY", "KES", "KGS", "KHR", "KMF", "KPW", "KRW",
+    "KWD", "KYD", "KZT", "LAK", "LBP", "LKR", "LRD", "LSL", "LYD", "MAD", "MDL", "MGA", "MKD",
+    "MMK", "MNT", "MOP", "MRU", "MUR", "MVR", "MWK", "MXN", "MXV", "MYR", "MZN", "NAD", "NGN",
+    "NIO", "NOK", "NPR", "NZD", "OMR", "PAB", "PEN", "PGK", "PHP", "PKR", "PLN", "PYG", "QAR",
+    "RON", "RSD", "RUB", "RWF", "SAR", "SBD", "SCR", "SDG", "SEK", "SGD", "SHP", "SLE", "SLL",
+    "SOS", "SRD", "SSP", "STN", "SVC", "SYP", "SZL", "THB", "TJS", "TMT", "TND", "TOP", "TRY",
+    "TTD", "TWD", "TZS", "UAH", "UGX", "USD", "USN", "UYI", "UYU", "UYW", "UZS", "VED", "VES",
+    "VND", "VUV", "WST", "XAF", "XCD", "XCG", "XOF", "XPF", "YER", "ZAR", "ZMW", "ZWG", "ZWL",
+];
+
+// Код валюты ISO 4217, например USD
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Currency(String);
+
+impl Currency {
+    pub fn new(code: &str) -> Result<Self, MoneyError> {
+        if ISO_4217_CODES.binary_search(&code).is_err() {
+            return Err(MoneyError::InvalidCurrency(code.to_string()));
+        }
+
+        Ok(Currency(code.to_string()))
+    }
+
+    // Код валюты из базы данных, записанный до появления проверки, берётся как есть
+    pub fn from_stored(code: String) -> Self {
+        Currency(code)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    // Количество знаков после запятой в основной единице валюты
+    pub fn exponent(&self) -> u32 {
+        match self.0.as_str() {
+            "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF"
+            | "UGX" | "UYI" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+            "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+            "CLF" | "UYW" => 4,
+            _ => 2,
+        }
+    }
+}
+
+impl TryFrom<String> for Currency {
+    type Error = MoneyError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Currency::new(&value)
+    }
+}
+
+impl From<Currency> for String {
+    fn from(value: Currency) -> Self {
+        value.0
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// Денежная сумма в конкретной валюте
+#[derive(Clone, Debug, PartialEq)]
+pub struct Money {
+    pub amount: MinorUnits,
+    pub currency: Currency,
+}
+
+impl Money {
+    pub fn new(amount: MinorUnits, currency: Currency) -> Self {
+        Money { amount, currency }
+    }
+
+    // Сложение без переполнения, суммы должны быть в одной валюте
+    pub fn checked_add(&self, other: &Money) -> Result<Money, MoneyError> {
+        if self.currency != other.currency {
+            return Err(MoneyError::CurrencyMismatch(
+                self.currency.clone(),
+                other.currency.clone(),
+            ));
+        }
+
+        let amount = self
+            .amount
+            .0
+            .checked_add(other.amount.0)
+            .ok_or(MoneyError::Overflow)?;
+
+        Ok(Money::new(MinorUnits(amount), self.currency.clone()))
+    }
+}
+
+// Сумма в основных единицах с учётом экспоненты валюты: 1817 USD -> 18.17 USD
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let exponent = self.currency.exponent();
+        let sign = if self.amount.0 < 0 { "-" } else { "" };
+        let minor = self.amount.0.unsigned_abs();
+
+        if exponent == 0 {
+            return write!(f, "{sign}{minor} {}", self.currency);
+        }
+
+        let divisor = 10u64.pow(exponent);
+        write!(
+            f,
+            "{sign}{}.{:0width$} {}",
+            minor / divisor,
+            minor % divisor,
+            self.currency,
+            width = exponent as usize
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn money(amount: i64, currency: &str) -> Money {
+        Money::new(MinorUnits(amount), Currency::new(currency).unwrap())
+    }
+
+    #[test]
+    fn adds_same_currency() {
+        assert_eq!(
+            money(1500, "USD").checked_add(&money(317, "USD")),
+            Ok(money(1817, "USD"))
+        );
+    }
+
+    #[test]
+    fn add_overflow_is_error() {
+        assert_eq!(
+            money(i64::MAX, "USD").checked_add(&money(1, "USD")),
+            Err(MoneyError::Overflow)
+        );
+        assert_eq!(
+            money(i64::MIN, "USD").checked_add(&money(-1, "USD")),
+            Err(MoneyError::Overflow)
+        );
+    }
+
+    #[test]
+    fn add_different_currencies_is_error() {
+        assert_eq!(
+            money(100, "USD").checked_add(&money(100, "EUR")),
+            Err(MoneyError::CurrencyMismatch(
+                Currency::new("USD").unwrap(),
+                Currency::new("EUR").unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn displays_with_currency_exponent() {
+        assert_eq!(money(1817, "USD").to_string(), "18.17 USD");
+        assert_eq!(money(5, "USD").to_string(), "0.05 USD");
+        assert_eq!(money(-1817, "USD").to_string(), "-18.17 USD");
+        assert_eq!(money(1817, "JPY").to_string(), "1817 JPY");
+        assert_eq!(money(1817, "KWD").to_string(), "1.817 KWD");
+        assert_eq!(money(7, "KWD").to_string(), "0.007 KWD");
+    }
+
+    #[test]
+    fn rejects_invalid_currency_codes() {
+        assert!(Currency::new("usd").is_err());
+        assert!(Currency::new("US").is_err());
+        assert!(Currency::new("USDT").is_err());
+        assert!(Currency::new("ABC").is_err());
+        assert!(Currency::new("XAU").is_err());
+        assert!(Currency::new("JPY").is_ok());
+        assert!(serde_json::from_str::<Currency>("\"rub\"").is_err());
+        assert_eq!(
+            serde_json::from_str::<Currency>("\"RUB\"").unwrap(),
+            Currency::new("RUB").unwrap()
+        );
+    }
+
+    #[test]
+    fn currency_codes_are_sorted() {
+        assert!(ISO_4217_CODES.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -0,0 +1,478 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use log::{error, info, warn};
+use serde::Deserialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{errors::AppError, outbox::OutboxEvent, storage::OrderRepository, AppState};
+
+// Сколько событий читается из outbox за раз
+const EVENTS_PAGE_SIZE: u32 = 100;
+
+// Через сколько ожидания пропущенного номера события предупредить в логе
+const GAP_WARNING: Duration = Duration::from_secs(60);
+
+// Номер потока, который хранилище не поддерживает
+const DISABLED: i64 = -2;
+
+// Настройки потока событий о заказах
+#[derive(Debug, Clone)]
+pub struct EventStreamConfig {
+    pub poll_interval: Duration,
+    // Сколько событий канал держит для подписчиков. Клиент, отставший
+    // больше чем на столько событий, дочитывает пропущенные из outbox
+    pub buffer: usize,
+}
+
+// Рассылает клиентам события outbox по мере их фиксации в базе
+pub struct EventHub {
+    sender: broadcast::Sender<Arc<OutboxEvent>>,
+    // Номер последнего разосланного события, -1 — поток не запущен,
+    // DISABLED — хранилище поток не поддерживает
+    position: AtomicI64,
+}
+
+impl EventHub {
+    pub fn new(buffer: usize) -> Self {
+        let (sender, _) = broadcast::channel(buffer);
+        EventHub {
+            sender,
+            position: AtomicI64::new(-1),
+        }
+    }
+
+    // Номер сохраняется до отправки: подписчик, прочитавший номер после
+    // подписки, получит событие из канала или из хранилища
+    fn publish(&self, event: OutboxEvent) {
+        self.position.store(event.event_id, Ordering::Release);
+        // Ошибка означает только отсутствие подписчиков
+        let _ = self.sender.send(Arc::new(event));
+    }
+
+    fn position(&self) -> i64 {
+        self.position.load(Ordering::Acquire)
+    }
+}
+
+// Фильтр событий по полям заказа и место, с которого продолжить поток
+#[derive(Debug, Default, Deserialize)]
+pub struct EventFilter {
+    pub customer_id: Option<String>,
+    pub entry: Option<String>,
+    pub delivery_service: Option<String>,
+    // Для клиентов, которые не могут передать заголовок Last-Event-ID
+    pub last_event_id: Option<i64>,
+}
+
+impl EventFilter {
+    fn matches(&self, event: &OutboxEvent) -> bool {
+        let field_matches = |field: &str, expected: &Option<String>| {
+            expected
+                .as_deref()
+                .is_none_or(|expected| event.payload[field].as_str() == Some(expected))
+        };
+
+        field_matches("customer_id", &self.customer_id)
+            && field_matches("entry", &self.entry)
+            && field_matches("delivery_service", &self.delivery_service)
+    }
+}
+
+// Почему поток клиента закончился
+#[derive(Debug)]
+pub enum StreamEnd {
+    Closed,
+    Storage(AppError),
+}
+
+// Поток событий одного клиента: сначала пропущенные события из outbox
+// после last_event_id, затем новые из канала. Медленный клиент не держит
+// события в памяти: отстав от канала, он снова читает outbox в своём темпе
+pub struct Subscription {
+    storage: Arc<dyn OrderRepository>,
+    hub: Arc<EventHub>,
+    receiver: broadcast::Receiver<Arc<OutboxEvent>>,
+    filter: EventFilter,
+    // Последнее отданное клиенту или пропущенное фильтром событие
+    last_id: i64,
+    // До этого номера события читаются из хранилища, дальше — из канала
+    replay_until: i64,
+    replayed: VecDeque<OutboxEvent>,
+}
+
+impl Subscription {
+    // Без last_event_id отдаются только события, зафиксированные после подключения
+    pub fn new(
+        data: &AppState,
+        filter: EventFilter,
+        last_event_id: Option<i64>,
+    ) -> Result<Subscription, AppError> {
+        let receiver = data.order_events.sender.subscribe();
+        let position = data.order_events.position();
+        if position == DISABLED {
+            return Err(AppError::UnsupportedError("order event stream".to_string()));
+        }
+        if position < 0 {
+            return Err(AppError::ConnectionError(
+                "order event stream is not started".to_string(),
+            ));
+        }
+
+        Ok(Subscription {
+            storage: data.storage.clone(),
+            hub: data.order_events.clone(),
+            receiver,
+            filter,
+            last_id: last_event_id.unwrap_or(position),
+            replay_until: position,
+            replayed: VecDeque::new(),
+        })
+    }
+
+    pub async fn next(&mut self) -> Result<OutboxEvent, StreamEnd> {
+        loop {
+            let event = match self.next_unfiltered().await? {
+                Some(event) => event,
+                None => continue,
+            };
+            self.last_id = event.event_id;
+            if self.filter.matches(&event) {
+                return Ok(event);
+            }
+        }
+    }
+
+    // None — событие уже было отдано из хранилища
+    async fn next_unfiltered(&mut self) -> Result<Option<OutboxEvent>, StreamEnd> {
+        if self.last_id < self.replay_until {
+            if self.replayed.is_empty() {
+                let events = self
+                    .storage
+                    .events_after(self.last_id, EVENTS_PAGE_SIZE)
+                    .await
+                    .map_err(StreamEnd::Storage)?;
+                self.replayed.extend(
+                    events
+                        .into_iter()
+                        .filter(|event| event.event_id <= self.replay_until),
+                );
+            }
+
+            match self.replayed.pop_front() {
+                Some(event) => return Ok(Some(event)),
+                // Старые события уже удалены из outbox
+                None => self.last_id = self.replay_until,
+            }
+        }
+
+        match self.receiver.recv().await {
+            Ok(event) if event.event_id > self.last_id => Ok(Some(event.as_ref().clone())),
+            Ok(_) => Ok(None),
+            // Пропущенные каналом события дочитываются из outbox
+            // до последнего разосланного номера
+            Err(RecvError::Lagged(skipped)) => {
+                info!(
+                    "Order events client lagged by {skipped} events after event {}, reading outbox",
+                    self.last_id
+                );
+                self.replay_until = self.hub.position();
+                Ok(None)
+            }
+            Err(RecvError::Closed) => Err(StreamEnd::Closed),
+        }
+    }
+}
+
+// Пропуск в номерах событий, который ждёт завершения транзакций
+struct Gap {
+    // Номер последнего разосланного события перед пропуском
+    position: i64,
+    // Транзакции с меньшими номерами шли, когда пропуск был замечен
+    xmax: i64,
+    // Все они завершились до последнего чтения outbox
+    settled: bool,
+    since: Instant,
+    warned: bool,
+}
+
+// Читает новые события outbox и рассылает их подписчикам по порядку номеров.
+// Пропущенный номер достался транзакции, которая ещё не завершилась или
+// откатилась. Поток ждёт, пока завершатся все транзакции, шедшие в момент
+// обнаружения пропуска, перечитывает outbox и только потом пропускает номер
+pub fn spawn_feed(app_state: Arc<AppState>, config: EventStreamConfig) {
+    tokio::spawn(async move {
+        let storage = &app_state.storage;
+        let hub = &app_state.order_events;
+        let mut interval = tokio::time::interval(config.poll_interval);
+
+        let mut position = loop {
+            interval.tick().await;
+            match storage.last_event_id().await {
+                Ok(position) => break position,
+                Err(AppError::UnsupportedError(operation)) => {
+                    warn!("Order event stream is disabled: {operation} is not supported");
+                    hub.position.store(DISABLED, Ordering::Release);
+                    return;
+                }
+                Err(e) => error!("Order event stream start error: {e}"),
+            }
+        };
+        hub.position.store(position, Ordering::Release);
+        info!("Order event stream started after event {position}");
+
+        let mut gap: Option<Gap> = None;
+        loop {
+            interval.tick().await;
+            'pages: loop {
+                let events = match storage.events_after(position, EVENTS_PAGE_SIZE).await {
+                    Ok(events) => events,
+                    Err(e) => {
+                        error!("Order event stream error: {e}");
+                        break;
+                    }
+                };
+                let full_page = events.len() == EVENTS_PAGE_SIZE as usize;
+
+                for event in events {
+                    let settled = gap
+                        .as_ref()
+                        .is_some_and(|gap| gap.position == position && gap.settled);
+                    if event.event_id != position + 1 && !settled {
+                        let snapshot = match storage.transaction_snapshot().await {
+                            Ok(snapshot) => snapshot,
+                            Err(e) => {
+                                error!("Order event stream error: {e}");
+                                break 'pages;
+                            }
+                        };
+                        // Без снимка пропуск окончателен
+                        if let Some(snapshot) = snapshot {
+                            if gap.as_ref().is_some_and(|gap| gap.position != position) {
+                                gap = None;
+                            }
+                            let current = gap.get_or_insert(Gap {
+                                position,
+                                xmax: snapshot.xmax,
+                                settled: false,
+                                since: Instant::now(),
+                                warned: false,
+                            });
+                            if snapshot.xmin >= current.xmax {
+                                current.settled = true;
+                                continue 'pages;
+                            }
+                            if !current.warned && current.since.elapsed() >= GAP_WARNING {
+                                warn!(
+                                    "Order event {} is not committed for {:?}, waiting for transactions before {}",
+                                    position + 1,
+                                    current.since.elapsed(),
+                                    current.xmax
+                                );
+                                current.warned = true;
+                            }
+                            break 'pages;
+                        }
+                    }
+                    if event.event_id != position + 1 {
+                        warn!(
+                            "Order events {}..{} are missing from outbox, skipping",
+                            position + 1,
+                            event.event_id - 1
+                        );
+                    }
+                    gap = None;
+                    position = event.event_id;
+                    hub.publish(event);
+                }
+
+                if !full_page {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        routes::tests::state, schema::CreateOrderDTO, storage::memory::MemoryOrderRepository,
+    };
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn config() -> EventStreamConfig {
+        EventStreamConfig {
+            poll_interval: Duration::from_millis(10),
+            buffer: 16,
+        }
+    }
+
+    async fn create(state: &AppState, order_uid: &str, customer_id: &str) {
+        let mut order: serde_json::Value =
+            serde_json::from_str(include_str!("test/stubs/order.json")).unwrap();
+        order["order_uid"] = json!(order_uid);
+        order["customer_id"] = json!(customer_id);
+        let order: CreateOrderDTO = serde_json::from_value(order).unwrap();
+        state.storage.create_order(&order, None).await.unwrap();
+    }
+
+    // Ждёт, пока поток разошлёт событие с номером не меньше заданного
+    async fn wait_for_position(state: &AppState, position: i64) {
+        tokio::time::timeout(WAIT, async {
+            while state.order_events.position() < position {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    async fn next_uids(subscription: &mut Subscription, count: usize) -> Vec<String> {
+        let mut order_uids = Vec::new();
+        for _ in 0..count {
+            let event = tokio::time::timeout(WAIT, subscription.next())
+                .await
+                .unwrap()
+                .unwrap();
+            order_uids.push(event.order_uid);
+        }
+        order_uids
+    }
+
+    #[tokio::test]
+    async fn resumes_after_last_event_id() {
+        let state = state(Arc::new(MemoryOrderRepository::new()));
+        assert!(matches!(
+            Subscription::new(&state, EventFilter::default(), None),
+            Err(AppError::ConnectionError(_))
+        ));
+
+        create(&state, "order-1", "test").await;
+        create(&state, "order-2", "test").await;
+        spawn_feed(state.clone(), config());
+        wait_for_position(&state, 2).await;
+
+        let mut resumed = Subscription::new(&state, EventFilter::default(), Some(1)).unwrap();
+        let mut new_only = Subscription::new(&state, EventFilter::default(), None).unwrap();
+        create(&state, "order-3", "test").await;
+
+        assert_eq!(next_uids(&mut resumed, 2).await, ["order-2", "order-3"]);
+        assert_eq!(next_uids(&mut new_only, 1).await, ["order-3"]);
+    }
+
+    #[tokio::test]
+    async fn catches_up_after_lag() {
+        let mut state = state(Arc::new(MemoryOrderRepository::new()));
+        Arc::get_mut(&mut state).unwrap().order_events = Arc::new(EventHub::new(2));
+        spawn_feed(state.clone(), config());
+        wait_for_position(&state, 0).await;
+
+        let mut subscription = Subscription::new(&state, EventFilter::default(), None).unwrap();
+        let order_uids: Vec<String> = (1..=6).map(|i| format!("order-{i}")).collect();
+        for order_uid in &order_uids {
+            create(&state, order_uid, "test").await;
+        }
+        wait_for_position(&state, 6).await;
+
+        // В канале остались два последних события, остальные читаются из outbox
+        assert_eq!(next_uids(&mut subscription, 6).await, order_uids);
+    }
+
+    #[tokio::test]
+    async fn filters_events() {
+        let state = state(Arc::new(MemoryOrderRepository::new()));
+        spawn_feed(state.clone(), config());
+        wait_for_position(&state, 0).await;
+
+        let filter = EventFilter {
+            customer_id: Some("test".to_string()),
+            ..EventFilter::default()
+        };
+        let mut subscription = Subscription::new(&state, filter, None).unwrap();
+        create(&state, "order-1", "test").await;
+        create(&state, "order-2", "other").await;
+        create(&state, "order-3", "test").await;
+
+        assert_eq!(
+            next_uids(&mut subscription, 2).await,
+            ["order-1", "order-3"]
+        );
+
+        let filter = EventFilter {
+            customer_id: Some("test".to_string()),
+            delivery_service: Some("unknown".to_string()),
+            ..EventFilter::default()
+        };
+        let event = OutboxEvent {
+            event_id: 1,
+            order_uid: "order-1".to_string(),
+            event_type: "order.created".to_string(),
+            payload: json!({"customer_id": "test", "delivery_service": "meest"}),
+            created_at: chrono::Utc::now(),
+        };
+        assert!(!filter.matches(&event));
+    }
+
+    async fn connect() -> tokio_postgres::Client {
+        let (connection_string, tls) = crate::db::tests::connection().unwrap();
+        let (client, connection) = tokio_postgres::connect(&connection_string, tls)
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        client
+    }
+
+    // Событие с меньшим номером, зафиксированное позже следующего, не теряется
+    #[tokio::test]
+    async fn waits_for_late_commit() {
+        let Some(repository) = crate::storage::postgres::tests::repository().await else {
+            return;
+        };
+        let state = state(Arc::new(repository));
+        spawn_feed(state.clone(), config());
+        wait_for_position(&state, 0).await;
+
+        let customer_id = format!("late-{}", uuid::Uuid::new_v4().simple());
+        let filter = EventFilter {
+            customer_id: Some(customer_id.clone()),
+            ..EventFilter::default()
+        };
+        let mut subscription = Subscription::new(&state, filter, None).unwrap();
+        let insert = "INSERT INTO outbox (order_uid, event_type, payload)
+            VALUES ($1, 'order.created', jsonb_build_object('customer_id', $2::TEXT))";
+
+        // Номер транзакции выдаётся до номера события, как при записи заказа
+        let mut client = connect().await;
+        let transaction = client.transaction().await.unwrap();
+        transaction
+            .execute("SELECT txid_current()", &[])
+            .await
+            .unwrap();
+        transaction
+            .execute(insert, &[&"late", &customer_id])
+            .await
+            .unwrap();
+        connect()
+            .await
+            .execute(insert, &[&"early", &customer_id])
+            .await
+            .unwrap();
+
+        let waiting = tokio::time::timeout(Duration::from_millis(300), subscription.next()).await;
+        assert!(waiting.is_err(), "event was published before the gap");
+
+        transaction.commit().await.unwrap();
+        assert_eq!(next_uids(&mut subscription, 2).await, ["late", "early"]);
+    }
+}
@@ -0,0 +1,420 @@
+use std::{collections::HashSet, fmt, str::FromStr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::Serialize;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt, sync::Mutex};
+
+use crate::{errors::AppError, storage::OrderRepository};
+
+// Тип события о создании заказа, в payload — заказ целиком
+pub const ORDER_CREATED_EVENT: &str = "order.created";
+
+// Событие, записанное в outbox в одной транзакции с изменением заказа
+#[derive(Debug, Clone, Serialize)]
+pub struct OutboxEvent {
+    pub event_id: i64,
+    pub order_uid: String,
+    pub event_type: String,
+    pub payload: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+// Куда relay отправляет события
+#[derive(Debug, Clone, PartialEq)]
+pub enum SinkKind {
+    None,
+    File,
+    Webhook,
+    Nats,
+    Kafka,
+}
+
+impl fmt::Display for SinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkKind::None => write!(f, "none"),
+            SinkKind::File => write!(f, "file"),
+            SinkKind::Webhook => write!(f, "webhook"),
+            SinkKind::Nats => write!(f, "nats"),
+            SinkKind::Kafka => write!(f, "kafka"),
+        }
+    }
+}
+
+impl FromStr for SinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(SinkKind::None),
+            "file" => Ok(SinkKind::File),
+            "webhook" => Ok(SinkKind::Webhook),
+            "nats" => Ok(SinkKind::Nats),
+            "kafka" => Ok(SinkKind::Kafka),
+            other => Err(format!("Unknown outbox sink: {other}")),
+        }
+    }
+}
+
+// Настройки relay и приёмника событий
+#[derive(Debug, Clone)]
+pub struct RelayConfig {
+    pub sink: SinkKind,
+    // Файл для приёмника file, события дописываются по строке JSON
+    pub file_path: String,
+    pub webhook_url: Option<String>,
+    pub nats_url: String,
+    pub nats_subject: String,
+    pub kafka_brokers: String,
+    pub kafka_topic: String,
+    pub interval: Duration,
+    pub batch_size: u32,
+    // Сколько хранятся доставленные события
+    pub retention: Duration,
+}
+
+impl RelayConfig {
+    // Куда уходят события выбранного приёмника, для журнала
+    pub fn destination(&self) -> String {
+        match self.sink {
+            SinkKind::None => "nowhere".to_string(),
+            SinkKind::File => self.file_path.clone(),
+            SinkKind::Webhook => self.webhook_url.clone().unwrap_or_default(),
+            SinkKind::Nats => format!("{} subject {}", self.nats_url, self.nats_subject),
+            SinkKind::Kafka => format!("{} topic {}", self.kafka_brokers, self.kafka_topic),
+        }
+    }
+}
+
+// Приёмник событий. Ошибка публикации оставляет событие в outbox до следующей попытки
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), AppError>;
+}
+
+// Создаёт приёмник по настройкам, None — relay выключен
+pub async fn make_sink(config: &RelayConfig) -> Result<Option<Arc<dyn EventSink>>, AppError> {
+    let sink: Arc<dyn EventSink> = match config.sink {
+        SinkKind::None => return Ok(None),
+        SinkKind::File => Arc::new(FileSink {
+            path: config.file_path.clone(),
+            lock: Mutex::new(()),
+        }),
+        SinkKind::Webhook => {
+            let Some(url) = config.webhook_url.clone() else {
+                return Err(AppError::ConfigError(
+                    "OUTBOX_WEBHOOK_URL is required for the webhook sink".to_string(),
+                ));
+            };
+            Arc::new(WebhookSink {
+                client: reqwest::Client::new(),
+                url,
+            })
+        }
+        #[cfg(feature = "nats")]
+        SinkKind::Nats => Arc::new(nats::NatsSink::connect(config).await?),
+        #[cfg(feature = "kafka")]
+        SinkKind::Kafka => Arc::new(kafka::KafkaSink::new(config)?),
+        #[allow(unreachable_patterns)]
+        ref sink => {
+            return Err(AppError::ConfigError(format!(
+                "outbox sink {sink} requires the {sink} cargo feature"
+            )));
+        }
+    };
+
+    Ok(Some(sink))
+}
+
+// Публикует события по порядку. После ошибки остальные события того же
+// заказа в пачке пропускаются, чтобы не обогнать неотправленное.
+// Возвращает результат для каждого опубликованного или неудачного события
+pub async fn publish_in_order(
+    sink: &dyn EventSink,
+    events: &[OutboxEvent],
+) -> Vec<(i64, Result<(), String>)> {
+    let mut blocked = HashSet::new();
+    let mut results = Vec::with_capacity(events.len());
+
+    for event in events {
+        if blocked.contains(&event.order_uid) {
+            continue;
+        }
+
+        match sink.publish(event).await {
+            Ok(()) => results.push((event.event_id, Ok(()))),
+            Err(err) => {
+                warn!(
+                    "Failed to publish event {} of order {}: {err}",
+                    event.event_id, event.order_uid
+                );
+                blocked.insert(event.order_uid.clone());
+                results.push((event.event_id, Err(err.to_string())));
+            }
+        }
+    }
+
+    results
+}
+
+// Фоновая задача: отправляет неотправленные события, пока очередь не опустеет,
+// затем ждёт interval. Доставленные события удаляются раз в час
+pub fn spawn_relay(
+    storage: Arc<dyn OrderRepository>,
+    sink: Arc<dyn EventSink>,
+    config: RelayConfig,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(config.interval);
+        let mut cleanup = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => loop {
+                    match storage.relay_outbox(sink.as_ref(), config.batch_size).await {
+                        Ok(delivered) if delivered > 0 => {
+                            info!("Published {delivered} outbox events");
+                        }
+                        Ok(_) => break,
+                        Err(e) => {
+                            error!("Outbox relay error: {e}");
+                            break;
+                        }
+                    }
+                },
+                _ = cleanup.tick() => {
+                    match storage.cleanup_delivered_events(config.retention).await {
+                        Ok(count) => info!("Removed {count} delivered outbox events"),
+                        Err(e) => error!("Outbox cleanup error: {e}"),
+                    }
+                }
+            }
+        }
+    });
+}
+
+// Дописывает события в файл по строке JSON
+struct FileSink {
+    path: String,
+    lock: Mutex<()>,
+}
+
+#[async_trait]
+impl EventSink for FileSink {
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), AppError> {
+        let _guard = self.lock.lock().await;
+
+        let mut line =
+            serde_json::to_vec(event).map_err(|err| AppError::SinkError(err.to_string()))?;
+        line.push(b'\n');
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&line).await?;
+        file.sync_data().await?;
+
+        Ok(())
+    }
+}
+
+// Отправляет событие POST-запросом, успешным считается ответ 2xx
+struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+}
+
+#[async_trait]
+impl EventSink for WebhookSink {
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), AppError> {
+        self.client
+            .post(&self.url)
+            .header("X-Event-Id", event.event_id.to_string())
+            .json(event)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| AppError::SinkError(err.to_string()))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "nats")]
+mod nats {
+    use async_trait::async_trait;
+
+    use super::{EventSink, OutboxEvent, RelayConfig};
+    use crate::errors::AppError;
+
+    // Публикует события в subject NATS. Заголовок Nats-Msg-Id позволяет
+    // JetStream отбросить повтор после переотправки
+    pub struct NatsSink {
+        client: async_nats::Client,
+        subject: String,
+    }
+
+    impl NatsSink {
+        pub async fn connect(config: &RelayConfig) -> Result<Self, AppError> {
+            let client = async_nats::connect(&config.nats_url)
+                .await
+                .map_err(|err| AppError::SinkError(err.to_string()))?;
+
+            Ok(NatsSink {
+                client,
+                subject: config.nats_subject.clone(),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl EventSink for NatsSink {
+        async fn publish(&self, event: &OutboxEvent) -> Result<(), AppError> {
+            let payload =
+                serde_json::to_vec(event).map_err(|err| AppError::SinkError(err.to_string()))?;
+            let mut headers = async_nats::HeaderMap::new();
+            headers.insert("Nats-Msg-Id", event.event_id.to_string().as_str());
+
+            self.client
+                .publish_with_headers(self.subject.clone(), headers, payload.into())
+                .await
+                .map_err(|err| AppError::SinkError(err.to_string()))?;
+            // Событие считается доставленным, только когда сервер его получил
+            self.client
+                .flush()
+                .await
+                .map_err(|err| AppError::SinkError(err.to_string()))?;
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+mod kafka {
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use rdkafka::{
+        config::ClientConfig,
+        message::{Header, OwnedHeaders},
+        producer::{FutureProducer, FutureRecord},
+    };
+
+    use super::{EventSink, OutboxEvent, RelayConfig};
+    use crate::errors::AppError;
+
+    // Сколько ждать подтверждения брокера
+    const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+    // Публикует события в топик Kafka. Ключ сообщения — order_uid,
+    // поэтому события одного заказа попадают в одну партицию по порядку
+    pub struct KafkaSink {
+        producer: FutureProducer,
+        topic: String,
+    }
+
+    impl KafkaSink {
+        pub fn new(config: &RelayConfig) -> Result<Self, AppError> {
+            let producer = ClientConfig::new()
+                .set("bootstrap.servers", &config.kafka_brokers)
+                .set("enable.idempotence", "true")
+                .set("acks", "all")
+                .create()
+                .map_err(|err| AppError::SinkError(err.to_string()))?;
+
+            Ok(KafkaSink {
+                producer,
+                topic: config.kafka_topic.clone(),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl EventSink for KafkaSink {
+        async fn publish(&self, event: &OutboxEvent) -> Result<(), AppError> {
+            let payload =
+                serde_json::to_vec(event).map_err(|err| AppError::SinkError(err.to_string()))?;
+            let event_id = event.event_id.to_string();
+            let record = FutureRecord::to(&self.topic)
+                .key(&event.order_uid)
+                .payload(&payload)
+                .headers(OwnedHeaders::new().insert(Header {
+                    key: "event_id",
+                    value: Some(&event_id),
+                }));
+
+            self.producer
+                .send(record, SEND_TIMEOUT)
+                .await
+                .map_err(|(err, _)| AppError::SinkError(err.to_string()))?;
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    // Запоминает опубликованные события, события с заданными номерами не публикует
+    struct FakeSink {
+        failing: HashSet<i64>,
+        published: std::sync::Mutex<Vec<i64>>,
+    }
+
+    #[async_trait]
+    impl EventSink for FakeSink {
+        async fn publish(&self, event: &OutboxEvent) -> Result<(), AppError> {
+            if self.failing.contains(&event.event_id) {
+                return Err(AppError::SinkError("broker is unavailable".to_string()));
+            }
+            self.published.lock().unwrap().push(event.event_id);
+            Ok(())
+        }
+    }
+
+    fn event(event_id: i64, order_uid: &str) -> OutboxEvent {
+        OutboxEvent {
+            event_id,
+            order_uid: order_uid.to_string(),
+            event_type: ORDER_CREATED_EVENT.to_string(),
+            payload: json!({"order_uid": order_uid}),
+            created_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_later_events_of_failed_order() {
+        let sink = FakeSink {
+            failing: HashSet::from([3]),
+            published: std::sync::Mutex::new(Vec::new()),
+        };
+        let events = [
+            event(1, "order-a"),
+            event(2, "order-b"),
+            event(3, "order-a"),
+            event(4, "order-b"),
+            event(5, "order-a"),
+            event(6, "order-c"),
+        ];
+
+        let results = publish_in_order(&sink, &events).await;
+
+        let ids: Vec<i64> = results.iter().map(|(event_id, _)| *event_id).collect();
+        assert_eq!(ids, [1, 2, 3, 4, 6]);
+        let error = results[2].1.as_ref().unwrap_err();
+        assert!(error.contains("broker is unavailable"), "{error}");
+        assert!(results
+            .iter()
+            .filter(|(event_id, _)| *event_id != 3)
+            .all(|(_, result)| result.is_ok()));
+        assert_eq!(*sink.published.lock().unwrap(), [1, 2, 4, 6]);
+    }
+}
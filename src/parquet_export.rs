@@ -0,0 +1,342 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use arrow_array::{
+    builder::{
+        Decimal128Builder, Int32Builder, Int64Builder, StringBuilder, TimestampMicrosecondBuilder,
+    },
+    ArrayRef, RecordBatch,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use tokio_postgres::{IsolationLevel, Row};
+
+use crate::{db::Database, errors::AppError, money::Currency};
+
+// Сколько строк читается из базы за раз и пишется одним пакетом
+const BATCH_ROWS: i32 = 10_000;
+
+// Размер группы строк в файле. Группа копится в памяти до записи
+const ROW_GROUP_ROWS: usize = 100_000;
+
+// Суммы выгружаются как decimal с четырьмя знаками после запятой:
+// этого хватает для всех валют, i64 минимальных единиц помещается в 23 знака
+const MONEY_PRECISION: u8 = 23;
+const MONEY_SCALE: i8 = 4;
+
+// Выгруженный файл
+#[derive(Debug)]
+pub struct ParquetFile {
+    pub table: &'static str,
+    pub date: NaiveDate,
+    pub rows: usize,
+    pub path: PathBuf,
+}
+
+// Тип колонки в Parquet
+#[derive(Clone, Copy)]
+enum ColumnType {
+    Text,
+    Int32,
+    Int64,
+    Timestamp,
+    // Сумма в минимальных единицах валюты из колонки currency запроса
+    Money,
+}
+
+struct Column {
+    name: &'static str,
+    column_type: ColumnType,
+    nullable: bool,
+}
+
+// Таблица выгрузки: запрос за сутки [$1, $2) и его колонки по порядку
+struct Table {
+    name: &'static str,
+    query: &'static str,
+    columns: &'static [Column],
+}
+
+const fn column(name: &'static str, column_type: ColumnType, nullable: bool) -> Column {
+    Column {
+        name,
+        column_type,
+        nullable,
+    }
+}
+
+const TABLES: &[Table] = &[
+    Table {
+        name: "orders",
+        query: "SELECT order_uid, track_number, entry, locale, internal_signature,
+                customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard
+            FROM orders
+            WHERE date_created >= $1 AND date_created < $2
+            ORDER BY date_created, order_uid",
+        columns: &[
+            column("order_uid", ColumnType::Text, false),
+            column("track_number", ColumnType::Text, false),
+            column("entry", ColumnType::Text, false),
+            column("locale", ColumnType::Text, true),
+            column("internal_signature", ColumnType::Text, true),
+            column("customer_id", ColumnType::Text, false),
+            column("delivery_service", ColumnType::Text, true),
+            column("shardkey", ColumnType::Text, true),
+            column("sm_id", ColumnType::Int32, true),
+            column("date_created", ColumnType::Timestamp, false),
+            column("oof_shard", ColumnType::Text, true),
+        ],
+    },
+    Table {
+        name: "delivery",
+        query: "SELECT order_uid, date_created, name, phone, zip, city, address, region, email
+            FROM delivery
+            WHERE date_created >= $1 AND date_created < $2
+            ORDER BY date_created, order_uid",
+        columns: &[
+            column("order_uid", ColumnType::Text, false),
+            column("date_created", ColumnType::Timestamp, false),
+            column("name", ColumnType::Text, false),
+            column("phone", ColumnType::Text, false),
+            column("zip", ColumnType::Text, false),
+            column("city", ColumnType::Text, false),
+            column("address", ColumnType::Text, false),
+            column("region", ColumnType::Text, false),
+            column("email", ColumnType::Text, false),
+        ],
+    },
+    Table {
+        name: "payment",
+        query: "SELECT order_uid, date_created, transaction, request_id, currency, provider,
+                amount, payment_dt, bank, delivery_cost, goods_total, custom_fee
+            FROM payment
+            WHERE date_created >= $1 AND date_created < $2
+            ORDER BY date_created, order_uid",
+        columns: &[
+            column("order_uid", ColumnType::Text, false),
+            column("date_created", ColumnType::Timestamp, false),
+            column("transaction", ColumnType::Text, false),
+            column("request_id", ColumnType::Text, true),
+            column("currency", ColumnType::Text, false),
+            column("provider", ColumnType::Text, false),
+            column("amount", ColumnType::Money, false),
+            column("payment_dt", ColumnType::Timestamp, false),
+            column("bank", ColumnType::Text, false),
+            column("delivery_cost", ColumnType::Money, false),
+            column("goods_total", ColumnType::Money, false),
+            column("custom_fee", ColumnType::Money, false),
+        ],
+    },
+    // Цены товаров в валюте платежа заказа, без платежа суммы пусты
+    Table {
+        name: "items",
+        query: "SELECT i.order_uid, i.date_created, i.chrt_id, i.track_number, i.price,
+                i.rid, i.name, i.sale, i.size, i.total_price, i.nm_id, i.brand, i.status,
+                p.currency
+            FROM items i
+            LEFT JOIN payment p
+                ON p.order_uid = i.order_uid AND p.date_created = i.date_created
+            WHERE i.date_created >= $1 AND i.date_created < $2
+            ORDER BY i.date_created, i.order_uid, i.item_id",
+        columns: &[
+            column("order_uid", ColumnType::Text, false),
+            column("date_created", ColumnType::Timestamp, false),
+            column("chrt_id", ColumnType::Int64, false),
+            column("track_number", ColumnType::Text, false),
+            column("price", ColumnType::Money, true),
+            column("rid", ColumnType::Text, false),
+            column("name", ColumnType::Text, false),
+            column("sale", ColumnType::Int32, false),
+            column("size", ColumnType::Text, false),
+            column("total_price", ColumnType::Money, true),
+            column("nm_id", ColumnType::Int64, false),
+            column("brand", ColumnType::Text, false),
+            column("status", ColumnType::Int32, false),
+            column("currency", ColumnType::Text, true),
+        ],
+    },
+];
+
+// Выгружает заказы, созданные с from по to включительно, в файлы
+// <таблица>/date=YYYY-MM-DD/part-0.parquet. Все таблицы читаются
+// в одном снимке базы, строки идут курсором пакетами по BATCH_ROWS
+pub async fn export(
+    db: &mut Database,
+    from: NaiveDate,
+    to: NaiveDate,
+    output_dir: &Path,
+) -> Result<Vec<ParquetFile>, AppError> {
+    let transaction = db
+        .client
+        .build_transaction()
+        .isolation_level(IsolationLevel::RepeatableRead)
+        .read_only(true)
+        .start()
+        .await?;
+
+    let mut files = Vec::new();
+    for table in TABLES {
+        let statement = transaction.prepare(table.query).await?;
+        let schema = table_schema(table);
+
+        let mut date = from;
+        while date <= to {
+            let next = date + Days::new(1);
+            let portal = transaction
+                .bind(&statement, &[&day_start(date), &day_start(next)])
+                .await?;
+
+            let path = output_dir
+                .join(table.name)
+                .join(format!("date={date}"))
+                .join("part-0.parquet");
+            let tmp_path = path.with_extension("parquet.tmp");
+
+            let mut writer: Option<ArrowWriter<File>> = None;
+            let mut rows = 0;
+            loop {
+                let batch = transaction.query_portal(&portal, BATCH_ROWS).await?;
+                if batch.is_empty() {
+                    break;
+                }
+
+                let writer = match &mut writer {
+                    Some(writer) => writer,
+                    None => writer.insert(create_writer(&tmp_path, schema.clone())?),
+                };
+                writer.write(&record_batch(table, &schema, &batch)?)?;
+                rows += batch.len();
+            }
+
+            // Файл дня без строк удаляется, чтобы повторная выгрузка
+            // не оставила строки, которых уже нет в базе
+            match writer {
+                Some(writer) => {
+                    writer.close()?;
+                    fs::rename(&tmp_path, &path)?;
+                    files.push(ParquetFile {
+                        table: table.name,
+                        date,
+                        rows,
+                        path,
+                    });
+                }
+                None => {
+                    if path.exists() {
+                        fs::remove_file(&path)?;
+                    }
+                }
+            }
+
+            date = next;
+        }
+    }
+
+    transaction.commit().await?;
+
+    Ok(files)
+}
+
+fn day_start(date: NaiveDate) -> DateTime<Utc> {
+    date.and_hms_opt(0, 0, 0)
+        .expect("midnight is a valid time")
+        .and_utc()
+}
+
+fn table_schema(table: &Table) -> SchemaRef {
+    let fields: Vec<Field> = table
+        .columns
+        .iter()
+        .map(|column| {
+            let data_type = match column.column_type {
+                ColumnType::Text => DataType::Utf8,
+                ColumnType::Int32 => DataType::Int32,
+                ColumnType::Int64 => DataType::Int64,
+                ColumnType::Timestamp => {
+                    DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()))
+                }
+                ColumnType::Money => DataType::Decimal128(MONEY_PRECISION, MONEY_SCALE),
+            };
+            Field::new(column.name, data_type, column.nullable)
+        })
+        .collect();
+
+    Arc::new(Schema::new(fields))
+}
+
+fn create_writer(path: &Path, schema: SchemaRef) -> Result<ArrowWriter<File>, AppError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_max_row_group_size(ROW_GROUP_ROWS)
+        .build();
+
+    Ok(ArrowWriter::try_new(
+        File::create(path)?,
+        schema,
+        Some(properties),
+    )?)
+}
+
+// Пакет строк запроса в колонках Arrow
+fn record_batch(table: &Table, schema: &SchemaRef, rows: &[Row]) -> Result<RecordBatch, AppError> {
+    let mut arrays: Vec<ArrayRef> = Vec::with_capacity(table.columns.len());
+    for (index, column) in table.columns.iter().enumerate() {
+        let array: ArrayRef = match column.column_type {
+            ColumnType::Text => {
+                let mut builder = StringBuilder::new();
+                for row in rows {
+                    builder.append_option(row.get::<_, Option<String>>(index));
+                }
+                Arc::new(builder.finish())
+            }
+            ColumnType::Int32 => {
+                let mut builder = Int32Builder::with_capacity(rows.len());
+                for row in rows {
+                    builder.append_option(row.get::<_, Option<i32>>(index));
+                }
+                Arc::new(builder.finish())
+            }
+            ColumnType::Int64 => {
+                let mut builder = Int64Builder::with_capacity(rows.len());
+                for row in rows {
+                    builder.append_option(row.get::<_, Option<i64>>(index));
+                }
+                Arc::new(builder.finish())
+            }
+            ColumnType::Timestamp => {
+                let mut builder =
+                    TimestampMicrosecondBuilder::with_capacity(rows.len()).with_timezone("UTC");
+                for row in rows {
+                    let value = row.get::<_, Option<DateTime<Utc>>>(index);
+                    builder.append_option(value.map(|date| date.timestamp_micros()));
+                }
+                Arc::new(builder.finish())
+            }
+            ColumnType::Money => {
+                let mut builder = Decimal128Builder::with_capacity(rows.len())
+                    .with_precision_and_scale(MONEY_PRECISION, MONEY_SCALE)?;
+                for row in rows {
+                    let amount = row.get::<_, Option<i64>>(index);
+                    let currency = row.get::<_, Option<String>>("currency");
+                    builder.append_option(amount.zip(currency).map(|(amount, currency)| {
+                        let exponent = Currency::from_stored(currency).exponent();
+                        let factor = 10i128.pow(MONEY_SCALE as u32 - exponent);
+                        amount as i128 * factor
+                    }));
+                }
+                Arc::new(builder.finish())
+            }
+        };
+        arrays.push(array);
+    }
+
+    Ok(RecordBatch::try_new(schema.clone(), arrays)?)
+}
@@ -0,0 +1,356 @@
+use chrono::{DateTime, Utc};
+use tokio_postgres::types::ToSql;
+
+use crate::{
+    db::Database,
+    errors::AppError,
+    storage::postgres::{
+        CLAIM_WEBHOOK_DELIVERIES_QUERY, CLEANUP_IDEMPOTENCY_KEYS_QUERY,
+        CLEANUP_WEBHOOK_DELIVERIES_QUERY, COUNT_DEAD_LETTERS_QUERY, EVENTS_AFTER_QUERY,
+        FIND_SHARDS_QUERY, FIND_SHARD_QUERY, GET_DEAD_LETTER_QUERY, GET_DELIVERIES_QUERY,
+        GET_DELIVERY_QUERY, GET_IDEMPOTENCY_KEY_QUERY, GET_ITEMS_QUERY, GET_ORDERS_ITEMS_QUERY,
+        GET_ORDERS_QUERY, GET_ORDER_QUERY, GET_PAYMENTS_QUERY, GET_PAYMENT_QUERY,
+        LAST_EVENT_ID_QUERY, LIST_CUSTOMER_ORDERS_AFTER_QUERY, LIST_CUSTOMER_ORDERS_QUERY,
+        LIST_DEAD_LETTERS_QUERY, LIST_ORDERS_AFTER_QUERY, LIST_ORDERS_QUERY,
+        LIST_WEBHOOK_DELIVERIES_QUERY, PENDING_EVENTS_QUERY, REDELIVER_WEBHOOK_QUERY,
+    },
+    webhooks::DELIVERY_FAILED,
+};
+
+// Число покупателей в сгенерированных данных
+const SEED_CUSTOMERS: u32 = 1000;
+
+// Число подписок на вебхуки в сгенерированных данных
+const SEED_WEBHOOKS: u32 = 100;
+
+// Запросы, которые читают таблицу целиком: последовательное сканирование
+// для них ожидаемо, план только выводится
+const FULL_SCAN_QUERIES: [&str; 1] = ["count dead letters"];
+
+// План запроса сервиса и найденные в нём последовательные сканирования
+#[derive(Debug)]
+pub struct QueryPlan {
+    pub name: String,
+    pub plan: String,
+    pub seq_scans: Vec<String>,
+}
+
+// Заполняет базу сгенерированными заказами и проверяет планы запросов чтения.
+// Всё выполняется в транзакции, которая откатывается, поэтому данные
+// и собранная статистика в базе не остаются
+pub async fn check(db: &mut Database, seed_rows: u32) -> Result<Vec<QueryPlan>, AppError> {
+    let transaction = db.client.transaction().await?;
+
+    transaction.batch_execute(&seed_script(seed_rows)).await?;
+
+    let order_uid = format!("plan-check-{}", seed_rows / 2 + 1);
+    let order_uids: Vec<String> = (1..=20)
+        .map(|index| format!("plan-check-{}", seed_rows / 2 + index))
+        .collect();
+    let customer_id = "customer-1".to_string();
+    let after_date_created: DateTime<Utc> = transaction
+        .query_one(
+            "SELECT date_created FROM plan_check_orders WHERE order_uid = $1",
+            &[&order_uid],
+        )
+        .await?
+        .get(0);
+    let idempotency_key = format!("plan-check-key-{}", seed_rows / 2 + 1);
+    let limit: i64 = 20;
+    let offset: i64 = 0;
+    let batch_size: i64 = 100;
+    let after_event_id: i64 = seed_rows as i64 / 2;
+    let no_bound: Option<DateTime<Utc>> = None;
+    let created_from = Some(after_date_created - chrono::Duration::days(1));
+    let created_before = Some(after_date_created);
+    let dead_letter_source: Option<&str> = Some("kafka");
+    let dead_letter_id: i64 = seed_rows as i64 / 2;
+    let pending = true;
+    let (subscription_id, delivery_id): (i64, i64) = {
+        let row = transaction
+            .query_one(
+                "SELECT subscription_id, delivery_id FROM webhook_deliveries
+                 WHERE order_uid = $1",
+                &[&order_uid],
+            )
+            .await?;
+        (row.get(0), row.get(1))
+    };
+    let no_status: Option<&str> = None;
+    let failed_status = Some(DELIVERY_FAILED);
+    let lease_millis: f64 = 20_000.0;
+    let webhook_cutoff = Utc::now() - chrono::Duration::days(7);
+
+    let queries: [(&str, &str, Vec<&(dyn ToSql + Sync)>); 30] = [
+        ("get order", GET_ORDER_QUERY, vec![&order_uid]),
+        ("get delivery", GET_DELIVERY_QUERY, vec![&order_uid]),
+        ("get payment", GET_PAYMENT_QUERY, vec![&order_uid]),
+        ("get items", GET_ITEMS_QUERY, vec![&order_uid]),
+        ("get orders", GET_ORDERS_QUERY, vec![&order_uids]),
+        ("get deliveries", GET_DELIVERIES_QUERY, vec![&order_uids]),
+        ("get payments", GET_PAYMENTS_QUERY, vec![&order_uids]),
+        (
+            "get orders items",
+            GET_ORDERS_ITEMS_QUERY,
+            vec![&order_uids],
+        ),
+        (
+            "list orders",
+            LIST_ORDERS_QUERY,
+            vec![&limit, &offset, &no_bound, &no_bound],
+        ),
+        (
+            "list orders by date",
+            LIST_ORDERS_QUERY,
+            vec![&limit, &offset, &created_from, &created_before],
+        ),
+        (
+            "list customer orders",
+            LIST_CUSTOMER_ORDERS_QUERY,
+            vec![&customer_id, &limit, &offset, &no_bound, &no_bound],
+        ),
+        (
+            "list customer orders by date",
+            LIST_CUSTOMER_ORDERS_QUERY,
+            vec![
+                &customer_id,
+                &limit,
+                &offset,
+                &created_from,
+                &created_before,
+            ],
+        ),
+        (
+            "list orders after",
+            LIST_ORDERS_AFTER_QUERY,
+            vec![
+                &after_date_created,
+                &order_uid,
+                &limit,
+                &offset,
+                &created_from,
+                &no_bound,
+            ],
+        ),
+        (
+            "list customer orders after",
+            LIST_CUSTOMER_ORDERS_AFTER_QUERY,
+            vec![
+                &customer_id,
+                &after_date_created,
+                &order_uid,
+                &limit,
+                &offset,
+                &created_from,
+                &no_bound,
+            ],
+        ),
+        ("find shard", FIND_SHARD_QUERY, vec![&order_uid]),
+        ("find shards", FIND_SHARDS_QUERY, vec![&order_uids]),
+        (
+            "get idempotency key",
+            GET_IDEMPOTENCY_KEY_QUERY,
+            vec![&idempotency_key],
+        ),
+        (
+            "cleanup idempotency keys",
+            CLEANUP_IDEMPOTENCY_KEYS_QUERY,
+            vec![],
+        ),
+        (
+            "pending outbox events",
+            PENDING_EVENTS_QUERY,
+            vec![&batch_size],
+        ),
+        (
+            "outbox events after",
+            EVENTS_AFTER_QUERY,
+            vec![&after_event_id, &batch_size],
+        ),
+        ("last outbox event", LAST_EVENT_ID_QUERY, vec![]),
+        (
+            "list dead letters",
+            LIST_DEAD_LETTERS_QUERY,
+            vec![&None::<&str>, &false, &limit, &offset],
+        ),
+        (
+            "list pending source dead letters",
+            LIST_DEAD_LETTERS_QUERY,
+            vec![&dead_letter_source, &pending, &limit, &offset],
+        ),
+        (
+            "get dead letter",
+            GET_DEAD_LETTER_QUERY,
+            vec![&dead_letter_id],
+        ),
+        ("count dead letters", COUNT_DEAD_LETTERS_QUERY, vec![]),
+        (
+            "list webhook deliveries",
+            LIST_WEBHOOK_DELIVERIES_QUERY,
+            vec![&subscription_id, &no_status, &limit, &offset],
+        ),
+        (
+            "list failed webhook deliveries",
+            LIST_WEBHOOK_DELIVERIES_QUERY,
+            vec![&subscription_id, &failed_status, &limit, &offset],
+        ),
+        (
+            "redeliver webhook",
+            REDELIVER_WEBHOOK_QUERY,
+            vec![&delivery_id, &subscription_id],
+        ),
+        (
+            "claim webhook deliveries",
+            CLAIM_WEBHOOK_DELIVERIES_QUERY,
+            vec![&batch_size, &lease_millis],
+        ),
+        (
+            "cleanup webhook deliveries",
+            CLEANUP_WEBHOOK_DELIVERIES_QUERY,
+            vec![&webhook_cutoff],
+        ),
+    ];
+
+    let mut plans = Vec::with_capacity(queries.len());
+    for (name, query, params) in queries {
+        let rows = transaction
+            .query(&format!("EXPLAIN {query}"), &params)
+            .await?;
+        let lines: Vec<String> = rows.iter().map(|row| row.get(0)).collect();
+
+        // Строки плана вида "Seq Scan on orders_y2024m01 orders  (cost=...)".
+        // Сканирование дешевле чтения одной страницы — пустая секция, например
+        // на следующий месяц, индекс для неё планировщик не выбирает.
+        // С условием = ANY стоимость такого сканирования не нулевая
+        let seq_scans = lines
+            .iter()
+            .filter(|_| !FULL_SCAN_QUERIES.contains(&name))
+            .filter_map(|line| line.split_once("Seq Scan on ").map(|(_, rest)| rest))
+            .filter(|rest| !is_empty_relation_scan(rest))
+            .filter_map(|rest| rest.split_whitespace().next())
+            .map(str::to_string)
+            .collect();
+
+        plans.push(QueryPlan {
+            name: name.to_string(),
+            plan: lines.join("\n"),
+            seq_scans,
+        });
+    }
+
+    transaction.rollback().await?;
+
+    Ok(plans)
+}
+
+// Полная стоимость сканирования из "(cost=0.00..1.01 rows=...)" меньше
+// стоимости чтения одной страницы (seq_page_cost = 1)
+fn is_empty_relation_scan(plan_line: &str) -> bool {
+    plan_line
+        .split_once("(cost=")
+        .and_then(|(_, cost)| cost.split_once(".."))
+        .and_then(|(_, total)| total.split_whitespace().next())
+        .and_then(|total| total.parse::<f64>().ok())
+        .is_some_and(|total| total < 1.0)
+}
+
+// Заказы создаются с шагом в секунду назад от текущего момента, секции
+// создаются на каждый месяц этого диапазона. У каждого заказа доставка,
+// оплата, два товара, запись справочника шардов, ключ идемпотентности
+// и событие outbox, неотправленное у каждого сотого. Отклонённые заказы
+// записываются у каждого десятого, повторён каждый второй из них. Доставка
+// вебхука есть у каждого заказа, ждёт отправки у каждого сотого, не удалась
+// у каждого сотого со сдвигом
+fn seed_script(seed_rows: u32) -> String {
+    format!(
+        "CREATE TEMP TABLE plan_check_orders ON COMMIT DROP AS
+         SELECT i, 'plan-check-' || i AS order_uid,
+                CURRENT_TIMESTAMP - i * INTERVAL '1 second' AS date_created
+         FROM generate_series(1, {seed_rows}) AS i;
+
+         SELECT create_order_partitions(month::DATE)
+         FROM (
+            SELECT generate_series(
+                date_trunc('month', MIN(date_created) AT TIME ZONE 'UTC'),
+                date_trunc('month', MAX(date_created) AT TIME ZONE 'UTC'),
+                INTERVAL '1 month'
+            ) AS month
+            FROM plan_check_orders
+         ) months;
+
+         INSERT INTO order_uids (order_uid, date_created)
+         SELECT order_uid, date_created FROM plan_check_orders;
+
+         INSERT INTO orders (order_uid, track_number, entry, customer_id,
+                             shardkey, sm_id, date_created)
+         SELECT order_uid, 'TRACK' || i, 'WBIL', 'customer-' || i % {SEED_CUSTOMERS},
+                (i % 10)::VARCHAR, i, date_created
+         FROM plan_check_orders;
+
+         INSERT INTO delivery (order_uid, date_created, name, phone, zip,
+                               city, address, region, email)
+         SELECT order_uid, date_created, 'Test Testov', '+9720000000', '2639809',
+                'Kiryat Mozkin', 'Ploshad Mira 15', 'Kraiot', 'test@gmail.com'
+         FROM plan_check_orders;
+
+         INSERT INTO payment (order_uid, date_created, transaction, currency,
+                              provider, amount, bank, delivery_cost,
+                              goods_total, custom_fee)
+         SELECT order_uid, date_created, order_uid, 'USD', 'wbpay', 1817,
+                'alpha', 1500, 317, 0
+         FROM plan_check_orders;
+
+         INSERT INTO items (order_uid, date_created, chrt_id, track_number,
+                            price, rid, name, sale, size, total_price,
+                            nm_id, brand, status)
+         SELECT order_uid, date_created, 9934930 + item, 'TRACK' || i, 453,
+                'rid-' || i || '-' || item, 'Mascaras', 30, '0', 317,
+                2389212, 'Vivienne Sabo', 202
+         FROM plan_check_orders, generate_series(1, 2) AS item;
+
+         INSERT INTO order_shards (order_uid, shard)
+         SELECT order_uid, i % 2 FROM plan_check_orders;
+
+         INSERT INTO idempotency_keys (idempotency_key, request_hash, expires_at)
+         SELECT 'plan-check-key-' || i, 'hash', CURRENT_TIMESTAMP + INTERVAL '1 day'
+         FROM plan_check_orders;
+
+         INSERT INTO outbox (order_uid, event_type, payload, created_at,
+                             delivered_at, attempts)
+         SELECT order_uid, 'order.created', '{{}}', date_created,
+                CASE WHEN i % 100 = 0 THEN NULL ELSE date_created END, 1
+         FROM plan_check_orders;
+
+         INSERT INTO dead_letters (source, reference, payload, error, created_at,
+                                   replayed_at)
+         SELECT CASE WHEN i % 20 = 0 THEN 'kafka' ELSE 'http' END, order_uid, '{{}}',
+                'Invalid order', date_created,
+                CASE WHEN i % 20 = 10 THEN date_created END
+         FROM plan_check_orders WHERE i % 10 = 0;
+
+         INSERT INTO webhook_subscriptions (delivery_service, url, event_types, secret)
+         SELECT 'plan-check-' || i, 'http://localhost/hooks/' || i,
+                ARRAY['order.created'], 'plan-check-secret'
+         FROM generate_series(1, {SEED_WEBHOOKS}) AS i;
+
+         INSERT INTO webhook_deliveries (subscription_id, event_id, order_uid, event_type,
+                                         payload, status, attempts, next_attempt_at,
+                                         created_at, delivered_at)
+         SELECT subscription.subscription_id, i, order_uid, 'order.created', '{{}}',
+                CASE WHEN i % 100 = 0 THEN 'pending'
+                     WHEN i % 100 = 50 THEN 'failed'
+                     ELSE 'delivered' END,
+                1, CASE WHEN i % 100 = 0 THEN date_created END, date_created,
+                CASE WHEN i % 100 NOT IN (0, 50) THEN date_created END
+         FROM plan_check_orders
+         JOIN (
+            SELECT subscription_id,
+                   row_number() OVER (ORDER BY subscription_id) - 1 AS position
+            FROM webhook_subscriptions WHERE delivery_service LIKE 'plan-check-%'
+         ) subscription ON subscription.position = i % {SEED_WEBHOOKS};
+
+         ANALYZE orders, delivery, payment, items, order_uids, order_shards,
+                 idempotency_keys, outbox, dead_letters, webhook_subscriptions,
+                 webhook_deliveries;"
+    )
+}
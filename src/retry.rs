@@ -0,0 +1,64 @@
+use std::{future::Future, time::Duration};
+
+use log::warn;
+
+use crate::errors::AppError;
+
+// Параметры повторов при временных сбоях базы данных
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    // Общее число попыток, включая первую
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    // Экспоненциальная задержка перед попыткой с номером attempt (с 1),
+    // ограниченная max_backoff
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+// Выполняет операцию заново, пока она завершается временной ошибкой
+// и не исчерпано число попыток. Операция должна быть целой транзакцией,
+// иначе повтор может применить только часть изменений
+pub async fn with_retry<T, F, Fut>(
+    policy: &RetryPolicy,
+    operation: &str,
+    mut f: F,
+) -> Result<T, AppError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, AppError>>,
+{
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Err(err) if err.is_retryable() && attempt < policy.max_attempts => {
+                let backoff = policy.backoff(attempt);
+                warn!(
+                    "{operation} failed on attempt {attempt}/{}: {err}, retrying in {backoff:?}",
+                    policy.max_attempts
+                );
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
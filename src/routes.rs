@@ -1,464 +1,1494 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use crate::{
-    errors::{handle_db_error, handle_get_request_error, handle_transaction_error, AppError},
-    schema::{DeliveryDTO, GetOrderDTO, Order, OrderItemDTO, PaymentDTO},
+    csv_orders::{self, CsvOutput, ExportFilter, ImportReport, SharedBuffer},
+    dead_letters::{self, DeadLetter, SOURCE_HTTP},
+    errors::{handle_get_request_error, handle_storage_error, response_message, AppError},
+    formats::{Format, SUPPORTED_TYPES},
+    idempotency::{self, IdempotencyClaim, IDEMPOTENCY_KEY_HEADER},
+    order_events::{EventFilter, StreamEnd, Subscription},
+    retry,
+    schema::{
+        ExportCsvParams, GetOrderDTO, ListDeadLettersParams, ListOrdersParams,
+        ListWebhookDeliveriesParams,
+    },
+    storage::{CreateOrderOutcome, DeadLetterListQuery, OrderListQuery, WebhookDeliveryListQuery},
+    webhooks::{
+        NewWebhook, Webhook, WebhookDelivery, DELIVERY_DELIVERED, DELIVERY_FAILED, DELIVERY_PENDING,
+    },
 };
 
+// Имя загруженного по HTTP файла в dead_letters
+const CSV_UPLOAD_NAME: &str = "upload";
+
+// Размер страницы списка заказов по умолчанию и максимальный
+pub const DEFAULT_LIST_LIMIT: u32 = 20;
+pub const MAX_LIST_LIMIT: u32 = 100;
+// Больший offset при нескольких шардах стоит слишком дорого
+pub const MAX_LIST_OFFSET: u32 = 10_000;
+
+// Сколько страниц выгрузки CSV ждут отправки клиенту
+const EXPORT_CHANNEL_SIZE: usize = 4;
+
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::IntoResponse,
+    body::{Body, Bytes},
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     Json,
 };
+use futures::{stream, Stream, StreamExt};
 use log::{error, info};
 use serde_json::json;
-use tokio_postgres::{types::ToSql, Client, Error as PostgresError, Transaction};
-use uuid::Uuid;
+use tokio::sync::mpsc;
 
 use crate::{schema::CreateOrderDTO, AppState};
 
 // POST /api/orders/
-// Endpoint для создания заказа
+// Endpoint для создания заказа. Тело читается как есть, чтобы сохранить его
+// в dead_letters, если заказ не удастся создать
 pub async fn create_order_handler(
     State(data): State<Arc<AppState>>,
-    Json(body): Json<CreateOrderDTO>,
+    headers: HeaderMap,
+    payload: Bytes,
 ) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let mut client_db = data.db.lock().await;
+    let Some(format) = Format::from_mime(&request_mime(&headers)) else {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Json(json!({
+                "status": "error",
+                "message": format!("Expected request with `Content-Type` one of: {SUPPORTED_TYPES}"),
+            })),
+        ));
+    };
 
-    let mut transaction = match client_db.transaction().await {
-        Ok(tx) => tx,
-        Err(err) => return Err(handle_db_error(err)),
+    let idempotency_key = match headers.get(IDEMPOTENCY_KEY_HEADER) {
+        Some(value) => {
+            let key = value
+                .to_str()
+                .map_err(|err| err.to_string())
+                .and_then(|key| {
+                    idempotency::validate_key(key)?;
+                    Ok(key.to_string())
+                });
+            match key {
+                Ok(key) => Some(key),
+                Err(message) => {
+                    return Err((
+                        StatusCode::BAD_REQUEST,
+                        Json(json!({"status": "error", "message": message})),
+                    ));
+                }
+            }
+        }
+        None => None,
     };
 
-    // Создание order
-    let created_order = match OrderService::create_one(&mut transaction, &body, &[]).await {
-        Ok(order) => order,
-        Err(err) => {
-            return Err(handle_transaction_error(err, transaction, "Create order error").await);
+    let parsed = match format {
+        Format::Json => Json::<CreateOrderDTO>::from_bytes(&payload)
+            .map(|Json(body)| body)
+            .map_err(|rejection| (rejection.status(), rejection.body_text())),
+        format => format.decode::<CreateOrderDTO>(&payload).map_err(|err| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Failed to parse the request body as {format}: {err}"),
+            )
+        }),
+    };
+    let body = match parsed {
+        Ok(body) => body,
+        Err((status, message)) => {
+            dead_letters::record(
+                &data,
+                SOURCE_HTTP,
+                idempotency_key.as_deref(),
+                &payload,
+                &message,
+            )
+            .await;
+            return Err((status, Json(json!({"status": "error", "message": message}))));
         }
     };
-    let created_order_uuid = created_order.order_uid;
 
-    // Создание delivery
-    let created_delivery =
-        match DeliveryService::create_one(&mut transaction, &body.delivery, &[&created_order_uuid])
-            .await
-        {
-            Ok(delivery) => delivery,
-            Err(err) => {
-                return Err(
-                    handle_transaction_error(err, transaction, "Create delivery error").await,
-                );
-            }
-        };
+    // Заказ в другом формате сохраняется в dead_letters как JSON,
+    // чтобы его можно было повторить
+    let payload = match format {
+        Format::Json => payload.to_vec(),
+        _ => serde_json::to_vec(&body).unwrap_or_else(|_| payload.to_vec()),
+    };
 
-    // Создание payment
-    let created_payment =
-        match PaymentService::create_one(&mut transaction, &body.payment, &[&created_order_uuid])
-            .await
-        {
-            Ok(payment) => payment,
-            Err(err) => {
-                return Err(
-                    handle_transaction_error(err, transaction, "Create payment error").await,
-                );
-            }
-        };
+    submit_order(
+        &data,
+        &body,
+        idempotency_key.as_deref(),
+        SOURCE_HTTP,
+        idempotency_key.as_deref(),
+        &payload,
+    )
+    .await
+}
 
-    // Создание items
-    let created_order_items =
-        match OrderItemsService::create_many(&mut transaction, &body.items, &[&created_order_uuid])
-            .await
-        {
-            Ok(items) => items,
-            Err(err) => {
-                return Err(handle_transaction_error(err, transaction, "Create items error").await);
-            }
-        };
+// Создание заказа из любого источника. С ключом идемпотентности ключ
+// занимается до создания заказа, а ответ сохраняется. Тело заказа, который
+// не удалось создать, сохраняется в dead_letters с источником и уточнением
+pub async fn submit_order(
+    data: &AppState,
+    body: &CreateOrderDTO,
+    idempotency_key: Option<&str>,
+    source: &str,
+    reference: Option<&str>,
+    payload: &[u8],
+) -> Result<(StatusCode, Json<serde_json::Value>), (StatusCode, Json<serde_json::Value>)> {
+    submit(
+        data,
+        body,
+        idempotency_key,
+        Some((source, reference, payload)),
+    )
+    .await
+}
 
-    let order = GetOrderDTO::from_order(
-        created_order,
-        created_payment,
-        created_delivery,
-        created_order_items,
-    );
+// Повтор заказа из dead_letters: тот же путь, но отклонённый заказ
+// не сохраняется в dead_letters второй раз
+pub async fn resubmit_order(
+    data: &AppState,
+    body: &CreateOrderDTO,
+    idempotency_key: &str,
+) -> Result<(StatusCode, Json<serde_json::Value>), (StatusCode, Json<serde_json::Value>)> {
+    submit(data, body, Some(idempotency_key), None).await
+}
+
+// Источник, уточнение и исходное тело заказа для записи в dead_letters
+type RejectedSource<'a> = (&'a str, Option<&'a str>, &'a [u8]);
+
+async fn submit(
+    data: &AppState,
+    body: &CreateOrderDTO,
+    idempotency_key: Option<&str>,
+    rejected_source: Option<RejectedSource<'_>>,
+) -> Result<(StatusCode, Json<serde_json::Value>), (StatusCode, Json<serde_json::Value>)> {
+    let Some(key) = idempotency_key else {
+        let result = create_order(data, body, None).await;
+        record_rejected(data, rejected_source, result.as_ref().err()).await;
+        return result;
+    };
+
+    // Занимаем ключ до выполнения запроса, чтобы повторы и конкурентные
+    // запросы с тем же ключом не создали заказ второй раз
+    let request_hash = idempotency::request_hash("POST", "/api/orders", body);
+    match data
+        .storage
+        .claim_idempotency_key(key, &request_hash, data.idempotency_key_ttl)
+        .await
+    {
+        Ok(IdempotencyClaim::Claimed) => {}
+        Ok(IdempotencyClaim::Replay(status, response)) => {
+            info!("Replaying response for {IDEMPOTENCY_KEY_HEADER} {key}");
+            return Ok((status, Json(response)));
+        }
+        Ok(claim) => return Err(idempotency::claim_error_response(&claim)),
+        Err(err) => return Err(handle_storage_error(err, "Idempotency key error")),
+    }
+
+    let result = create_order(data, body, Some(key)).await;
+    record_rejected(data, rejected_source, result.as_ref().err()).await;
+
+    // Ошибки сервера освобождают ключ для повтора, остальные ответы сохраняются
+    let stored = match &result {
+        Err((status, _)) if status.is_server_error() => {
+            data.storage.release_idempotency_key(key).await
+        }
+        Ok((status, Json(response))) | Err((status, Json(response))) => {
+            data.storage
+                .complete_idempotency_key(key, *status, response)
+                .await
+        }
+    };
+    if let Err(err) = stored {
+        error!("Failed to store {IDEMPOTENCY_KEY_HEADER} {key}: {err}");
+    }
+
+    result
+}
+
+// Тип тела запроса из Content-Type без параметров, пустой без заголовка
+fn request_mime(headers: &HeaderMap) -> String {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+// Сохраняет в dead_letters тело, по которому заказ не создан. Временная
+// ошибка (503) не сохраняется: отправитель повторит заказ, а очереди
+// и файлы повторяют его сами
+async fn record_rejected(
+    data: &AppState,
+    rejected_source: Option<RejectedSource<'_>>,
+    rejected: Option<&(StatusCode, Json<serde_json::Value>)>,
+) {
+    let (Some((source, reference, payload)), Some((status, Json(response)))) =
+        (rejected_source, rejected)
+    else {
+        return;
+    };
+    if *status != StatusCode::SERVICE_UNAVAILABLE {
+        dead_letters::record(
+            data,
+            source,
+            reference,
+            payload,
+            &response_message(response),
+        )
+        .await;
+    }
+}
+
+// Создание заказа со всеми связанными сущностями. Общий путь для HTTP
+// и других источников заказов
+pub async fn create_order(
+    data: &AppState,
+    body: &CreateOrderDTO,
+    idempotency_key: Option<&str>,
+) -> Result<(StatusCode, Json<serde_json::Value>), (StatusCode, Json<serde_json::Value>)> {
+    let payment_total = match body
+        .validate_order_uid()
+        .and_then(|_| body.validate_amounts())
+    {
+        Ok(total) => total,
+        Err(message) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(json!({"status": "error", "message": message})),
+            ));
+        }
+    };
+
+    // order_uid назначается до повторов: если commit прошёл, но ответ
+    // базы потерялся, повтор найдёт созданный заказ, а не создаст второй
+    let mut body = body.clone();
+    body.order_uid
+        .get_or_insert_with(|| idempotency::new_order_uid(idempotency_key));
+
+    // Создание заказа выполняется одной транзакцией, поэтому при временном
+    // сбое базы она повторяется целиком
+    let created = retry::with_retry(&data.retry_policy, "Create order", || {
+        data.storage.create_order(&body, idempotency_key)
+    })
+    .await;
+    let order = match created {
+        Ok(CreateOrderOutcome::Created(order)) => order,
+        // Заказ с таким order_uid уже создан: повтор запроса или конфликт
+        Ok(CreateOrderOutcome::Exists(existing_order)) => {
+            return replay_existing_order(existing_order, &body);
+        }
+        // order_uid занят заказом, который уже перенесён в архив
+        Err(AppError::OrderExistsError(order_uid)) => {
+            return Err((
+                StatusCode::CONFLICT,
+                Json(json!({
+                    "status": "error",
+                    "message": format!("Order {order_uid} already exists"),
+                })),
+            ));
+        }
+        Err(err) => return Err(handle_storage_error(err, "Create order error")),
+    };
+    let created_order_uid = order.order_uid.clone();
 
     data.cache
         .lock()
         .await
-        .update_record(created_order_uuid, order);
+        .update_record(created_order_uid.clone(), order);
 
-    // Commit транзакции
-    transaction.commit().await.map_err(|err| {
-        error!("Failed to commit transaction: {:?}", err);
+    info!(
+        "Order {} created, payment total {}",
+        created_order_uid, payment_total
+    );
 
-        let error_response = serde_json::json!({
-            "status": "error",
-            "message": "Failed to commit transaction"
-        });
-        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-    })?;
+    let (status, response) = idempotency::created_response(&created_order_uid);
+    Ok((status, Json(response)))
+}
 
-    info!("Order {} created", created_order_uuid);
+// Повторный запрос на создание уже существующего заказа: идентичное тело
+// возвращает сохранённый заказ, отличающееся считается конфликтом
+fn replay_existing_order(
+    existing_order: GetOrderDTO,
+    body: &CreateOrderDTO,
+) -> Result<(StatusCode, Json<serde_json::Value>), (StatusCode, Json<serde_json::Value>)> {
+    let order_uid = &existing_order.order_uid;
+    if !existing_order.matches(body) {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(json!({
+                "status": "error",
+                "message": format!("Order {order_uid} already exists with different content"),
+            })),
+        ));
+    }
+
+    info!("Order {} already exists, returning stored order", order_uid);
 
-    return Ok((
-        StatusCode::CREATED,
-        Json(serde_json::json!({
-            "order_uid": &created_order_uuid,
-        })),
-    ));
+    Ok((StatusCode::OK, Json(json!(existing_order))))
 }
 
 // GET /api/orders/:id
 // Endpoint для получения заказа по id
 pub async fn get_order_handler(
-    Path(id): Path<uuid::Uuid>,
+    Path(id): Path<String>,
     State(data): State<Arc<AppState>>,
-) -> Result<(StatusCode, Json<GetOrderDTO>), (StatusCode, Json<serde_json::Value>)> {
-    let mut client_db = data.db.lock().await;
-    if let Some(cached_item) = data.cache.lock().await.get_record(id) {
-        return Ok((StatusCode::OK, Json(cached_item.data)));
-    }
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let Some(format) = Format::from_accept(&headers) else {
+        return Err((
+            StatusCode::NOT_ACCEPTABLE,
+            Json(json!({
+                "status": "error",
+                "message": format!("Supported response types: {SUPPORTED_TYPES}"),
+            })),
+        ));
+    };
 
-    // Получение order
-    let order_row = match OrderService::get_one_by_id(&mut client_db, id).await {
-        Ok(row) => row,
+    let order = match find_order(&data, &id).await {
+        Ok(Some(order)) => order,
+        Ok(None) => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                Json(json!({"error": "Order not found!"})),
+            ));
+        }
         Err(err) => {
             return Err(handle_get_request_error(err, "Get order error").await);
         }
     };
-    if order_row.is_empty() {
+
+    info!("Get order {}", &id);
+
+    order_response(format, &order)
+}
+
+// Заказ из кеша или хранилища, общий для HTTP и gRPC
+pub async fn find_order(data: &AppState, id: &str) -> Result<Option<GetOrderDTO>, AppError> {
+    if let Some(cached_item) = data.cache.lock().await.get_record(id) {
+        return Ok(Some(cached_item.data));
+    }
+
+    retry::with_retry(&data.retry_policy, "Get order", || {
+        data.storage.get_order(id)
+    })
+    .await
+}
+
+// Заказы по списку идентификаторов: сначала из кеша, остальные одним
+// запросом к хранилищу, загруженные кладутся в кеш. Не найденных заказов
+// в результате нет
+pub async fn find_orders(
+    data: &AppState,
+    order_uids: &[String],
+) -> Result<HashMap<String, GetOrderDTO>, AppError> {
+    let mut orders = HashMap::with_capacity(order_uids.len());
+    let mut missing = Vec::new();
+    {
+        let mut cache = data.cache.lock().await;
+        for order_uid in order_uids {
+            match cache.get_record(order_uid) {
+                Some(cached_item) => {
+                    orders.insert(order_uid.clone(), cached_item.data);
+                }
+                None if !missing.contains(order_uid) => missing.push(order_uid.clone()),
+                None => {}
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        let loaded = retry::with_retry(&data.retry_policy, "Get orders", || {
+            data.storage.get_orders(&missing)
+        })
+        .await?;
+
+        let cache = data.cache.lock().await;
+        for order in loaded {
+            cache.update_record(order.order_uid.clone(), order.clone());
+            orders.insert(order.order_uid.clone(), order);
+        }
+    }
+
+    Ok(orders)
+}
+
+// Заказ в выбранном по Accept формате. Ответ зависит от Accept,
+// поэтому кэши должны различать его по этому заголовку
+fn order_response(
+    format: Format,
+    order: &GetOrderDTO,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    match format.encode("order", order) {
+        Ok(body) => Ok((
+            [
+                (header::CONTENT_TYPE, format.mime()),
+                (header::VARY, "accept"),
+            ],
+            body,
+        )
+            .into_response()),
+        Err(err) => {
+            error!("Failed to encode order as {format}: {err}");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"status": "error", "message": "Failed to encode order"})),
+            ))
+        }
+    }
+}
+
+// GET /api/orders?customer_id=&limit=&offset=
+// Endpoint для получения списка заказов, новые первыми
+pub async fn list_orders_handler(
+    Query(params): Query<ListOrdersParams>,
+    State(data): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<GetOrderDTO>>), (StatusCode, Json<serde_json::Value>)> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIST_LIMIT);
+    if limit == 0 || limit > MAX_LIST_LIMIT {
         return Err((
-            StatusCode::NOT_FOUND,
-            Json(json!({"error": "Order not found!"})),
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "status": "error",
+                "message": format!("limit must be between 1 and {MAX_LIST_LIMIT}"),
+            })),
+        ));
+    }
+    let offset = params.offset.unwrap_or(0);
+    if offset > MAX_LIST_OFFSET {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "status": "error",
+                "message": format!("offset must not exceed {MAX_LIST_OFFSET}"),
+            })),
         ));
     }
 
-    // Получение payment
-    let payment_row = match PaymentService::get_one_by_id(&mut client_db, id).await {
-        Ok(row) => row,
+    let query = OrderListQuery {
+        customer_id: params.customer_id,
+        limit,
+        offset,
+        after: None,
+        created_from: None,
+        created_before: None,
+    };
+    let orders = match retry::with_retry(&data.retry_policy, "List orders", || {
+        data.storage.list_orders(&query)
+    })
+    .await
+    {
+        Ok(orders) => orders,
         Err(err) => {
-            return Err(handle_get_request_error(err, "Payment query error").await);
+            return Err(handle_get_request_error(err, "List orders error").await);
         }
     };
 
-    // Получение delivery
-    let delivery_row = match DeliveryService::get_one_by_id(&mut client_db, id).await {
-        Ok(row) => row,
-        Err(err) => {
-            return Err(handle_get_request_error(err, "Delivery query error").await);
+    info!("List orders: {} found", orders.len());
+
+    Ok((StatusCode::OK, Json(orders)))
+}
+
+// GET /api/orders/stream?customer_id=&entry=&delivery_service=&last_event_id=
+// Server-Sent Events с событиями о заказах. При переподключении браузер
+// передаёт Last-Event-ID, и поток продолжается с пропущенных событий
+pub async fn stream_orders_handler(
+    Query(filter): Query<EventFilter>,
+    State(data): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<
+    Sse<impl Stream<Item = Result<Event, axum::Error>>>,
+    (StatusCode, Json<serde_json::Value>),
+> {
+    let subscription = subscribe_order_events(&data, filter, &headers)?;
+
+    let events = stream::unfold(subscription, |mut subscription| async move {
+        match subscription.next().await {
+            Ok(event) => {
+                let sse_event = Event::default()
+                    .id(event.event_id.to_string())
+                    .event(&event.event_type)
+                    .json_data(&event);
+                Some((sse_event, subscription))
+            }
+            Err(end) => {
+                log_stream_end(&end);
+                None
+            }
         }
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+// GET /api/orders/stream/ws?customer_id=&entry=&delivery_service=&last_event_id=
+// Те же события через WebSocket, по одному JSON в текстовом сообщении
+pub async fn stream_orders_ws_handler(
+    ws: WebSocketUpgrade,
+    Query(filter): Query<EventFilter>,
+    State(data): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let subscription = subscribe_order_events(&data, filter, &headers)?;
+
+    Ok(ws.on_upgrade(move |socket| send_order_events(socket, subscription)))
+}
+
+// Подписка с места, заданного заголовком Last-Event-ID или параметром last_event_id
+fn subscribe_order_events(
+    data: &AppState,
+    filter: EventFilter,
+    headers: &HeaderMap,
+) -> Result<Subscription, (StatusCode, Json<serde_json::Value>)> {
+    let last_event_id = match headers.get("last-event-id") {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<i64>().ok())
+                .unwrap_or(-1),
+        ),
+        None => filter.last_event_id,
     };
+    if last_event_id.is_some_and(|id| id < 0) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "status": "error",
+                "message": "Last event id must be a non-negative integer",
+            })),
+        ));
+    }
 
-    // Получение items
-    let order_item_rows = match OrderItemsService::get_many_by_id(&mut client_db, id).await {
-        Ok(rows) => rows,
-        Err(err) => {
-            return Err(handle_get_request_error(err, "Order items query error").await);
+    Subscription::new(data, filter, last_event_id)
+        .map_err(|err| handle_storage_error(err, "Order event stream is not available"))
+}
+
+// Пока клиент читает медленно, отправка ждёт, а отставший клиент
+// дочитывает события из outbox
+async fn send_order_events(mut socket: WebSocket, mut subscription: Subscription) {
+    let end = loop {
+        tokio::select! {
+            event = subscription.next() => match event {
+                Ok(event) => {
+                    let text = json!(event).to_string();
+                    if socket.send(Message::Text(text)).await.is_err() {
+                        return;
+                    }
+                }
+                Err(end) => break end,
+            },
+            // Клиент сообщений не присылает, ждём только закрытия
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
         }
     };
 
-    let payment = PaymentDTO::from(payment_row);
-    let delivery = DeliveryDTO::from(delivery_row);
-    let order_items: Vec<OrderItemDTO> = order_item_rows
-        .iter()
-        .map(|item| OrderItemDTO::from(item))
-        .collect();
-    let order = GetOrderDTO::from_row(order_row, payment, delivery, order_items);
-
-    info!("Get order {}", &id.to_string());
-
-    return Ok((StatusCode::OK, Json(order)));
-}
-
-// Типаж описывающий структуру запроса на получение элмента
-trait GetOneById {
-    async fn get_one_by_id(
-        client: &mut Client,
-        id: Uuid,
-    ) -> Result<tokio_postgres::Row, PostgresError>;
-}
-
-// Типаж описывающий структуру запроса на получение множества элементов
-trait GetManyById {
-    async fn get_many_by_id(
-        client: &mut Client,
-        id: Uuid,
-    ) -> Result<Vec<tokio_postgres::Row>, PostgresError>;
-}
-
-// Типаж описывающий структуру запроса на создание элемента
-trait CreateOne<T, R>
-where
-    R: From<tokio_postgres::Row>,
-{
-    async fn create_one(
-        transaction: &mut Transaction<'_>,
-        body: &T,
-        params: &[&(dyn ToSql + Sync)],
-    ) -> Result<R, AppError>;
-}
-
-// Типаж описывающий структуру запроса на создание множества элемента
-trait CreateMany<T, R>
-where
-    R: From<tokio_postgres::Row>,
-{
-    async fn create_many(
-        transaction: &mut Transaction<'_>,
-        body: &T,
-        params: &[&(dyn ToSql + Sync)],
-    ) -> Result<Vec<R>, AppError>;
-}
-
-struct PaymentService();
-impl GetOneById for PaymentService {
-    async fn get_one_by_id(
-        client: &mut Client,
-        id: Uuid,
-    ) -> Result<tokio_postgres::Row, PostgresError> {
-        return client
-            .query_one(
-                "SELECT transaction, request_id, currency,
-                             provider, amount, payment_dt,
-                             bank, delivery_cost, goods_total, custom_fee
-                           FROM payment WHERE order_uid = $1",
-                &[&id],
-            )
-            .await;
+    log_stream_end(&end);
+    let (code, reason) = match end {
+        StreamEnd::Closed => (close_code::AWAY, "Server is shutting down"),
+        StreamEnd::Storage(_) => (close_code::ERROR, "Failed to read order events"),
+    };
+    let _ = socket
+        .send(Message::Close(Some(CloseFrame {
+            code,
+            reason: reason.into(),
+        })))
+        .await;
+}
+
+fn log_stream_end(end: &StreamEnd) {
+    if let StreamEnd::Storage(err) = end {
+        error!("Order events stream error: {err}");
     }
 }
-impl CreateOne<PaymentDTO, PaymentDTO> for PaymentService {
-    async fn create_one(
-        transaction: &mut Transaction<'_>,
-        body: &PaymentDTO,
-        params: &[&(dyn ToSql + Sync)],
-    ) -> Result<PaymentDTO, AppError> {
-        let create_payment_stmt = transaction
-            .prepare(
-                "INSERT INTO payment (
-                        order_uid, transaction, request_id,
-                        currency, provider, amount,
-                        payment_dt, bank, delivery_cost,
-                        goods_total, custom_fee
-                 ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) 
-                  RETURNING 
-                        transaction, request_id, currency, provider, amount,
-                        payment_dt, bank, delivery_cost,
-                        goods_total, custom_fee",
-            )
-            .await?;
-
-        let payment_row = transaction
-            .query_one(
-                &create_payment_stmt,
-                &[
-                    params[0],
-                    &body.transaction,
-                    &body.request_id,
-                    &body.currency,
-                    &body.provider,
-                    &body.amount,
-                    &body.payment_dt,
-                    &body.bank,
-                    &body.delivery_cost,
-                    &body.goods_total,
-                    &body.custom_fee,
-                ],
-            )
-            .await?;
 
-        Ok(PaymentDTO::from(payment_row))
+// GET /api/export/orders.csv?columns=&customer_id=&from=&to=
+// Endpoint для выгрузки заказов в CSV. Если выбраны колонки товара,
+// строка на каждый товар, иначе строка на заказ
+pub async fn export_orders_csv_handler(
+    Query(params): Query<ExportCsvParams>,
+    State(data): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    export_csv(data, params, csv_orders::all_columns(), false, "orders.csv").await
+}
+
+// GET /api/export/items.csv?columns=&customer_id=&from=&to=
+// Endpoint для выгрузки товаров заказов отдельным файлом, связь по order_uid
+pub async fn export_items_csv_handler(
+    Query(params): Query<ExportCsvParams>,
+    State(data): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    export_csv(data, params, csv_orders::item_columns(), true, "items.csv").await
+}
+
+// Выгрузка отправляется клиенту по страницам, не собираясь целиком в памяти.
+// Ошибка до первой страницы возвращается статусом ответа, после неё —
+// обрывом ответа
+async fn export_csv(
+    data: Arc<AppState>,
+    params: ExportCsvParams,
+    default_columns: Vec<String>,
+    items_only: bool,
+    file_name: &'static str,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let columns = match csv_orders::parse_columns(
+        params.columns.as_deref().unwrap_or_default(),
+        default_columns,
+    ) {
+        Ok(columns) => columns,
+        Err(message) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(json!({"status": "error", "message": message})),
+            ));
+        }
+    };
+
+    let filter = ExportFilter {
+        customer_id: params.customer_id,
+        from: params.from,
+        to: params.to,
+    };
+    let (sender, mut receiver) = mpsc::channel::<Result<Bytes, AppError>>(EXPORT_CHANNEL_SIZE);
+    tokio::spawn(async move {
+        let buffer = SharedBuffer::default();
+        let mut outputs = [CsvOutput::new(columns, items_only, buffer.clone())];
+        let page_written = || {
+            let chunk = Bytes::from(buffer.take());
+            let sender = sender.clone();
+            async move {
+                if chunk.is_empty() {
+                    return Ok(());
+                }
+                sender.send(Ok(chunk)).await.map_err(|_| {
+                    AppError::IOError(std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+                })
+            }
+        };
+
+        match csv_orders::export_pages(&data, &filter, &mut outputs, page_written).await {
+            Ok(exported) => info!("Export {file_name}: {exported} orders"),
+            Err(err) => {
+                let _ = sender.send(Err(err)).await;
+            }
+        }
+    });
+
+    let first = match receiver.recv().await {
+        Some(Ok(chunk)) => chunk,
+        Some(Err(err)) => return Err(handle_get_request_error(err, "Export orders error").await),
+        None => Bytes::new(),
+    };
+    let rest = stream::unfold(receiver, move |mut receiver| async move {
+        let chunk = receiver.recv().await?.map_err(|err| {
+            error!("Export {file_name} interrupted: {err}");
+            std::io::Error::other(err.to_string())
+        });
+        Some((chunk, receiver))
+    });
+    let body = Body::from_stream(stream::once(async { Ok(first) }).chain(rest));
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{file_name}\""),
+            ),
+        ],
+        body,
+    ))
+}
+
+// POST /api/import/orders.csv
+// Endpoint для загрузки заказов из CSV в раскладке выгрузки, товары в строках.
+// Если часть заказов не загружена, отчёт возвращается с кодом 422
+pub async fn import_orders_csv_handler(
+    State(data): State<Arc<AppState>>,
+    headers: HeaderMap,
+    payload: Bytes,
+) -> Result<(StatusCode, Json<ImportReport>), (StatusCode, Json<serde_json::Value>)> {
+    if request_mime(&headers) != "text/csv" {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Json(json!({
+                "status": "error",
+                "message": "Expected request with `Content-Type: text/csv`",
+            })),
+        ));
     }
+
+    let report = match csv_orders::import_orders(&data, CSV_UPLOAD_NAME, &payload, None).await {
+        Ok(report) => report,
+        Err(err @ (AppError::CsvError(_) | AppError::CsvHeaderError(_))) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(json!({"status": "error", "message": err.to_string()})),
+            ));
+        }
+        Err(err) => return Err(handle_storage_error(err, "Import orders error")),
+    };
+
+    info!(
+        "Import orders.csv: {} of {} orders imported",
+        report.imported, report.orders
+    );
+
+    let status = if report.rejected.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::UNPROCESSABLE_ENTITY
+    };
+    Ok((status, Json(report)))
 }
 
-struct OrderService();
-impl GetOneById for OrderService {
-    async fn get_one_by_id(
-        client: &mut Client,
-        id: Uuid,
-    ) -> Result<tokio_postgres::Row, PostgresError> {
-        return client
-            .query_one(
-                "SELECT order_uid, track_number, entry, locale,
-                        internal_signature, customer_id, delivery_service,
-                        shardkey, sm_id, date_created, oof_shard
-                        FROM orders WHERE order_uid = $1",
-                &[&id],
-            )
-            .await;
+// GET /api/dead-letters?source=&pending=&limit=&offset=
+// Endpoint для получения списка заказов, которые не удалось создать, новые первыми
+pub async fn list_dead_letters_handler(
+    Query(params): Query<ListDeadLettersParams>,
+    State(data): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<DeadLetter>>), (StatusCode, Json<serde_json::Value>)> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIST_LIMIT);
+    if limit == 0 || limit > MAX_LIST_LIMIT {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "status": "error",
+                "message": format!("limit must be between 1 and {MAX_LIST_LIMIT}"),
+            })),
+        ));
+    }
+
+    let query = DeadLetterListQuery {
+        source: params.source,
+        pending: params.pending,
+        limit,
+        offset: params.offset.unwrap_or(0),
+    };
+    match data.storage.list_dead_letters(&query).await {
+        Ok(letters) => Ok((StatusCode::OK, Json(letters))),
+        Err(err) => Err(handle_get_request_error(err, "List dead letters error").await),
     }
 }
-impl CreateOne<CreateOrderDTO, Order> for OrderService {
-    async fn create_one(
-        transaction: &mut Transaction<'_>,
-        body: &CreateOrderDTO,
-        _params: &[&(dyn ToSql + Sync)],
-    ) -> Result<Order, AppError> {
-        let create_order_stmt = transaction
-            .prepare(
-                "INSERT INTO orders (
-              track_number, entry, locale,
-              internal_signature, customer_id, delivery_service,
-              shardkey, sm_id, oof_shard
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING 
-              order_uid, track_number, entry, locale,
-              internal_signature, customer_id, delivery_service,
-              sm_id, date_created, shardkey, oof_shard",
-            )
-            .await?;
-
-        let create_order_row = transaction
-            .query_one(
-                &create_order_stmt,
-                &[
-                    &body.track_number,
-                    &body.entry,
-                    &body.locale,
-                    &body.internal_signature,
-                    &body.customer_id,
-                    &body.delivery_service,
-                    &body.shardkey,
-                    &body.sm_id,
-                    &body.oof_shard,
-                ],
-            )
-            .await?;
 
-        Ok(Order::from(create_order_row))
+// GET /api/dead-letters/:id
+// Endpoint для получения записи с исходным телом и ошибкой
+pub async fn get_dead_letter_handler(
+    Path(id): Path<i64>,
+    State(data): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<DeadLetter>), (StatusCode, Json<serde_json::Value>)> {
+    match data.storage.get_dead_letter(id).await {
+        Ok(Some(letter)) => Ok((StatusCode::OK, Json(letter))),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": "Dead letter not found!"})),
+        )),
+        Err(err) => Err(handle_get_request_error(err, "Get dead letter error").await),
     }
 }
 
-struct OrderItemsService();
-impl GetManyById for OrderItemsService {
-    async fn get_many_by_id(
-        client: &mut Client,
-        id: Uuid,
-    ) -> Result<Vec<tokio_postgres::Row>, PostgresError> {
-        return client
-            .query(
-                "SELECT chrt_id, track_number, price,
-                            rid, name, sale, size,
-                            total_price, nm_id, brand, status
-                           FROM items WHERE order_uid = $1",
-                &[&id],
-            )
-            .await;
+// POST /api/dead-letters/:id/replay
+// Endpoint для повторного создания заказа из записи. Успешный повтор
+// возвращает обновлённую запись, неудачный — 422 с ошибкой
+pub async fn replay_dead_letter_handler(
+    Path(id): Path<i64>,
+    State(data): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<DeadLetter>), (StatusCode, Json<serde_json::Value>)> {
+    let letter = match dead_letters::replay(&data, id).await {
+        Ok(Some(letter)) => letter,
+        Ok(None) => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                Json(json!({"error": "Dead letter not found!"})),
+            ));
+        }
+        Err(AppError::ReplayedError(id)) => {
+            return Err((
+                StatusCode::CONFLICT,
+                Json(json!({
+                    "status": "error",
+                    "message": format!("Dead letter {id} is already replayed"),
+                })),
+            ));
+        }
+        Err(err) => return Err(handle_storage_error(err, "Replay dead letter error")),
+    };
+
+    match &letter.replay_error {
+        None => Ok((StatusCode::OK, Json(letter))),
+        Some(message) => Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({"status": "error", "message": message, "dead_letter": letter})),
+        )),
     }
 }
-impl CreateMany<Vec<OrderItemDTO>, OrderItemDTO> for OrderItemsService {
-    async fn create_many(
-        transaction: &mut Transaction<'_>,
-        body: &Vec<OrderItemDTO>,
-        _params: &[&(dyn ToSql + Sync)],
-    ) -> Result<Vec<OrderItemDTO>, AppError> {
-        let mut query = String::from(
-            "INSERT INTO items (order_uid,
-            chrt_id, track_number, price,
-            rid, name, sale, size, total_price,
-            nm_id, brand, status
-        ) VALUES ",
-        );
-        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
-        for (i, item) in body.iter().enumerate() {
-            let param_start = i * 12 + 1;
-            query.push_str(&format!(
-                "(${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}),",
-                param_start,      // order_uid
-                param_start + 1,  // chrt_id
-                param_start + 2,  // track_number
-                param_start + 3,  // price
-                param_start + 4,  // rid
-                param_start + 5,  // name
-                param_start + 6,  // sale
-                param_start + 7,  // size
-                param_start + 8,  // total_price
-                param_start + 9,  // nm_id
-                param_start + 10, // brand
-                param_start + 11, // status
+
+// POST /api/webhooks
+// Endpoint для создания подписки на события о заказах: url, event_types, secret
+pub async fn create_webhook_handler(
+    State(data): State<Arc<AppState>>,
+    payload: Bytes,
+) -> Result<(StatusCode, Json<Webhook>), (StatusCode, Json<serde_json::Value>)> {
+    let webhook = match Json::<NewWebhook>::from_bytes(&payload) {
+        Ok(Json(webhook)) => webhook,
+        Err(rejection) => {
+            return Err((
+                rejection.status(),
+                Json(json!({"status": "error", "message": rejection.body_text()})),
             ));
+        }
+    };
+    if let Err(message) = webhook.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({"status": "error", "message": message})),
+        ));
+    }
 
-            params.push(_params[0]);
-            params.push(&item.chrt_id);
-            params.push(&item.track_number);
-            params.push(&item.price);
-            params.push(&item.rid);
-            params.push(&item.name);
-            params.push(&item.sale);
-            params.push(&item.size);
-            params.push(&item.total_price);
-            params.push(&item.nm_id);
-            params.push(&item.brand);
-            params.push(&item.status);
-        }
-        query.pop();
-        query.push_str(
-            " RETURNING 
-                    chrt_id, track_number, price,
-                    rid, name, sale, size, total_price,
-                    nm_id, brand, status
-            ",
-        );
+    match data.storage.create_webhook(&webhook).await {
+        Ok(webhook) => {
+            info!(
+                "Webhook {} created for {} of {}",
+                webhook.subscription_id, webhook.url, webhook.delivery_service
+            );
+            Ok((StatusCode::CREATED, Json(webhook)))
+        }
+        Err(err) => Err(handle_storage_error(err, "Create webhook error")),
+    }
+}
 
-        let rows = transaction.query(&query, &params).await?;
-        let order_items: Vec<OrderItemDTO> =
-            rows.iter().map(|item| OrderItemDTO::from(item)).collect();
+// GET /api/webhooks
+// Endpoint для получения списка подписок без секретов
+pub async fn list_webhooks_handler(
+    State(data): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<Webhook>>), (StatusCode, Json<serde_json::Value>)> {
+    match data.storage.list_webhooks().await {
+        Ok(webhooks) => Ok((StatusCode::OK, Json(webhooks))),
+        Err(err) => Err(handle_get_request_error(err, "List webhooks error").await),
+    }
+}
 
-        Ok(order_items)
+// GET /api/webhooks/:id
+pub async fn get_webhook_handler(
+    Path(id): Path<i64>,
+    State(data): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Webhook>), (StatusCode, Json<serde_json::Value>)> {
+    match data.storage.get_webhook(id).await {
+        Ok(Some(webhook)) => Ok((StatusCode::OK, Json(webhook))),
+        Ok(None) => Err(webhook_not_found()),
+        Err(err) => Err(handle_get_request_error(err, "Get webhook error").await),
     }
 }
 
-struct DeliveryService();
-impl GetOneById for DeliveryService {
-    async fn get_one_by_id(
-        client: &mut Client,
-        id: Uuid,
-    ) -> Result<tokio_postgres::Row, PostgresError> {
-        return client
-            .query_one(
-                "SELECT name, phone, zip, city, address, region, email
-                            FROM delivery WHERE order_uid = $1",
-                &[&id],
-            )
-            .await;
+// DELETE /api/webhooks/:id
+// Endpoint для удаления подписки вместе с журналом доставок
+pub async fn delete_webhook_handler(
+    Path(id): Path<i64>,
+    State(data): State<Arc<AppState>>,
+) -> Result<StatusCode, (StatusCode, Json<serde_json::Value>)> {
+    match data.storage.delete_webhook(id).await {
+        Ok(true) => {
+            info!("Webhook {id} deleted");
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Ok(false) => Err(webhook_not_found()),
+        Err(err) => Err(handle_storage_error(err, "Delete webhook error")),
     }
 }
-impl CreateOne<DeliveryDTO, DeliveryDTO> for DeliveryService {
-    async fn create_one(
-        transaction: &mut Transaction<'_>,
-        body: &DeliveryDTO,
-        params: &[&(dyn ToSql + Sync)],
-    ) -> Result<DeliveryDTO, AppError> {
-        let create_delivery_stmt = transaction
-            .prepare(
-                "INSERT INTO delivery (
-              order_uid, name, phone,
-              zip, city, address,
-              region, email
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING
-              name, phone,
-              zip, city, address,
-              region, email",
-            )
-            .await?;
-
-        let create_delivery_row = transaction
-            .query_one(
-                &create_delivery_stmt,
-                &[
-                    params[0],
-                    &body.name,
-                    &body.phone,
-                    &body.zip,
-                    &body.city,
-                    &body.address,
-                    &body.region,
-                    &body.email,
-                ],
-            )
-            .await?;
 
-        Ok(DeliveryDTO::from(create_delivery_row))
+// GET /api/webhooks/:id/deliveries?status=&limit=&offset=
+// Endpoint для журнала доставок подписки, новые первыми
+pub async fn list_webhook_deliveries_handler(
+    Path(id): Path<i64>,
+    Query(params): Query<ListWebhookDeliveriesParams>,
+    State(data): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<WebhookDelivery>>), (StatusCode, Json<serde_json::Value>)> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIST_LIMIT);
+    if limit == 0 || limit > MAX_LIST_LIMIT {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "status": "error",
+                "message": format!("limit must be between 1 and {MAX_LIST_LIMIT}"),
+            })),
+        ));
+    }
+    if let Some(status) = &params.status {
+        if ![DELIVERY_PENDING, DELIVERY_DELIVERED, DELIVERY_FAILED].contains(&status.as_str()) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "status": "error",
+                    "message": format!(
+                        "status must be {DELIVERY_PENDING}, {DELIVERY_DELIVERED} or {DELIVERY_FAILED}"
+                    ),
+                })),
+            ));
+        }
+    }
+
+    match data.storage.get_webhook(id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err(webhook_not_found()),
+        Err(err) => return Err(handle_get_request_error(err, "Get webhook error").await),
+    }
+
+    let query = WebhookDeliveryListQuery {
+        subscription_id: id,
+        status: params.status,
+        limit,
+        offset: params.offset.unwrap_or(0),
+    };
+    match data.storage.list_webhook_deliveries(&query).await {
+        Ok(deliveries) => Ok((StatusCode::OK, Json(deliveries))),
+        Err(err) => Err(handle_get_request_error(err, "List webhook deliveries error").await),
+    }
+}
+
+// POST /api/webhooks/:id/deliveries/:delivery_id/redeliver
+// Endpoint для повторной отправки: доставка уходит при следующем опросе
+// с полным числом попыток, возвращается обновлённая запись
+pub async fn redeliver_webhook_handler(
+    Path((id, delivery_id)): Path<(i64, i64)>,
+    State(data): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<WebhookDelivery>), (StatusCode, Json<serde_json::Value>)> {
+    match data.storage.redeliver_webhook(id, delivery_id).await {
+        Ok(Some(delivery)) => {
+            info!("Webhook {id} delivery {delivery_id} scheduled for redelivery");
+            Ok((StatusCode::OK, Json(delivery)))
+        }
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": "Webhook delivery not found!"})),
+        )),
+        Err(err) => Err(handle_storage_error(err, "Redeliver webhook error")),
+    }
+}
+
+fn webhook_not_found() -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({"error": "Webhook not found!"})),
+    )
+}
+
+// GET /metrics
+// Метрики в формате Prometheus
+pub async fn metrics_handler(
+    State(data): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    match dead_letters::render_metrics(&data).await {
+        Ok(text) => Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text)),
+        Err(err) => Err(handle_get_request_error(err, "Metrics error").await),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::time::Duration;
+
+    use axum::{body::Body, http::Request, Router};
+    use tokio::sync::Mutex;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        cache::Cache, create_router, dead_letters::DeadLetterMetrics, graphql::GraphqlConfig,
+        order_events::EventHub, retry::RetryPolicy, storage::memory::MemoryOrderRepository,
+        storage::OrderRepository, timestamp, timestamp::TimestampFormat,
+    };
+
+    fn app() -> Router {
+        router(Arc::new(MemoryOrderRepository::new()))
+    }
+
+    fn router(storage: Arc<MemoryOrderRepository>) -> Router {
+        let graphql_config = GraphqlConfig {
+            max_depth: 16,
+            max_complexity: 5000,
+        };
+
+        create_router(state(storage), &graphql_config, true)
+    }
+
+    // Состояние сервиса поверх заданного хранилища, повторы без пауз
+    pub fn state(storage: Arc<dyn OrderRepository>) -> Arc<AppState> {
+        Arc::new(AppState {
+            storage,
+            cache: Arc::new(Mutex::new(Cache::new())),
+            idempotency_key_ttl: Duration::from_secs(60),
+            retry_policy: RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::ZERO,
+                max_backoff: Duration::ZERO,
+            },
+            dead_letter_metrics: DeadLetterMetrics::default(),
+            order_events: Arc::new(EventHub::new(16)),
+        })
+    }
+
+    fn order(order_uid: &str) -> serde_json::Value {
+        let mut order: serde_json::Value =
+            serde_json::from_str(include_str!("test/stubs/order.json")).unwrap();
+        order["order_uid"] = json!(order_uid);
+        order
+    }
+
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = if body.is_empty() {
+            serde_json::Value::Null
+        } else {
+            serde_json::from_slice(&body).unwrap()
+        };
+
+        (status, body)
+    }
+
+    async fn post(
+        app: &Router,
+        body: &serde_json::Value,
+        idempotency_key: Option<&str>,
+    ) -> (StatusCode, serde_json::Value) {
+        let mut request =
+            Request::post("/api/orders").header(header::CONTENT_TYPE, "application/json");
+        if let Some(key) = idempotency_key {
+            request = request.header(IDEMPOTENCY_KEY_HEADER, key);
+        }
+
+        send(app, request.body(Body::from(body.to_string())).unwrap()).await
+    }
+
+    async fn get(app: &Router, uri: &str) -> (StatusCode, serde_json::Value) {
+        send(app, Request::get(uri).body(Body::empty()).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn creates_and_gets_order() {
+        let app = app();
+        let body = order("order-1");
+
+        let (status, created) = post(&app, &body, None).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(created, json!({"order_uid": "order-1"}));
+
+        let (status, stored) = get(&app, "/api/orders/order-1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(stored["order_uid"], "order-1");
+        // Unix-время на входе, RFC 3339 на выходе
+        let mut payment = body["payment"].clone();
+        payment["payment_dt"] = json!("2021-11-26T06:22:07Z");
+        assert_eq!(stored["payment"], payment);
+        assert_eq!(stored["items"], body["items"]);
+        assert_eq!(stored["delivery"], body["delivery"]);
+    }
+
+    #[tokio::test]
+    async fn missing_order_is_not_found() {
+        let (status, _) = get(&app(), "/api/orders/missing").await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn retries_lost_commit_without_duplicate() {
+        let storage = Arc::new(MemoryOrderRepository::new());
+        let app = router(storage.clone());
+
+        for (customer_id, key) in [("lost-commit", None), ("lost-commit-key", Some("key-1"))] {
+            let mut body = order("unused");
+            body.as_object_mut().unwrap().remove("order_uid");
+            body["customer_id"] = json!(customer_id);
+
+            storage.lose_commits(1);
+            let (status, _) = post(&app, &body, key).await;
+            assert!(status.is_success(), "{status}");
+
+            let (_, orders) = get(&app, &format!("/api/orders?customer_id={customer_id}")).await;
+            assert_eq!(orders.as_array().unwrap().len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_unsupported_operations() {
+        let app = app();
+        let webhook = json!({
+            "delivery_service": "meest",
+            "url": "http://localhost:9000/hooks",
+            "event_types": ["order.created"],
+            "secret": "0123456789abcdef",
+        });
+        let request = Request::post("/api/webhooks")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(webhook.to_string()))
+            .unwrap();
+        let (status, _) = send(&app, request).await;
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+        let (status, _) = get(&app, "/api/webhooks").await;
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+
+        // Событий, кроме order.created, сервис не отправляет
+        let mut webhook = webhook;
+        webhook["event_types"] = json!(["order.status_changed"]);
+        let request = Request::post("/api/webhooks")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(webhook.to_string()))
+            .unwrap();
+        assert_eq!(send(&app, request).await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn lists_orders_by_customer() {
+        let app = app();
+        for order_uid in ["order-1", "order-2", "order-3"] {
+            let mut body = order(order_uid);
+            if order_uid == "order-3" {
+                body["customer_id"] = json!("other");
+            }
+            assert_eq!(post(&app, &body, None).await.0, StatusCode::CREATED);
+        }
+
+        let (status, orders) = get(&app, "/api/orders?customer_id=test").await;
+        assert_eq!(status, StatusCode::OK);
+        let mut order_uids: Vec<&str> = orders
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|order| order["order_uid"].as_str().unwrap())
+            .collect();
+        order_uids.sort();
+        assert_eq!(order_uids, ["order-1", "order-2"]);
+
+        let (status, orders) = get(&app, "/api/orders?limit=1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(orders.as_array().unwrap().len(), 1);
+
+        let (status, _) = get(&app, "/api/orders?limit=0").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = get(&app, &format!("/api/orders?offset={}", MAX_LIST_OFFSET + 1)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn replays_identical_order() {
+        let app = app();
+        let body = order("order-1");
+        assert_eq!(post(&app, &body, None).await.0, StatusCode::CREATED);
+
+        let (status, replayed) = post(&app, &body, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(replayed["order_uid"], "order-1");
+
+        let mut changed = body.clone();
+        changed["track_number"] = json!("OTHER");
+        let (status, _) = post(&app, &changed, None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn replays_response_for_idempotency_key() {
+        let app = app();
+        let mut body = order("order-1");
+        body.as_object_mut().unwrap().remove("order_uid");
+
+        let (status, created) = post(&app, &body, Some("key-1")).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        // Без ключа тот же запрос создал бы второй заказ
+        let (status, replayed) = post(&app, &body, Some("key-1")).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(replayed, created);
+
+        let mut changed = body.clone();
+        changed["track_number"] = json!("OTHER");
+        let (status, _) = post(&app, &changed, Some("key-1")).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn exports_csv_by_date() {
+        let app = app();
+        for order_uid in ["order-1", "order-2"] {
+            assert_eq!(
+                post(&app, &order(order_uid), None).await.0,
+                StatusCode::CREATED
+            );
+        }
+        let export = |uri: String| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        let today = chrono::Utc::now().date_naive();
+        let (status, csv) = export(format!("/api/export/orders.csv?from={today}&to={today}")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(csv.lines().count(), 3);
+        assert!(csv.starts_with("order_uid,"));
+
+        let tomorrow = today.succ_opt().unwrap();
+        let (status, csv) = export(format!("/api/export/orders.csv?from={tomorrow}")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(csv.lines().count(), 1);
+        let yesterday = today.pred_opt().unwrap();
+        let (_, csv) = export(format!("/api/export/items.csv?to={yesterday}")).await;
+        assert_eq!(csv.lines().count(), 1);
+
+        let (status, _) = export("/api/export/orders.csv?columns=unknown".to_string()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn replays_dead_letter_once() {
+        let storage = Arc::new(MemoryOrderRepository::new());
+        let app = router(storage.clone());
+        let body = order("order-1");
+
+        // Временная ошибка после всех повторов в dead_letters не попадает
+        storage.fail_writes(3);
+        let (status, _) = post(&app, &body, None).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let (_, letters) = get(&app, "/api/dead-letters").await;
+        assert_eq!(letters, json!([]));
+
+        let id = storage
+            .add_dead_letter(SOURCE_HTTP, None, &body.to_string(), "database unavailable")
+            .await
+            .unwrap();
+        let replay = || {
+            let request = Request::post(format!("/api/dead-letters/{id}/replay"))
+                .body(Body::empty())
+                .unwrap();
+            send(&app, request)
+        };
+
+        let (status, letter) = replay().await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(letter["replayed_at"].is_string());
+        let (status, _) = replay().await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (_, orders) = get(&app, "/api/orders?customer_id=test").await;
+        assert_eq!(orders.as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_orders() {
+        let app = app();
+
+        let (status, response) = post(&app, &order("bad uid"), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(response["status"], "error");
+
+        let mut body = order("order-1");
+        body["payment"]["goods_total"] = json!(1);
+        assert_eq!(post(&app, &body, None).await.0, StatusCode::BAD_REQUEST);
+
+        let mut body = order("order-1");
+        body["payment"]["currency"] = json!("usd");
+        assert_eq!(
+            post(&app, &body, None).await.0,
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+
+        let (status, _) = post(&app, &json!({"order_uid": "order-1"}), None).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let request = Request::post("/api/orders")
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body(Body::from(order("order-1").to_string()))
+            .unwrap();
+        assert_eq!(
+            send(&app, request).await.0,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+
+        let (status, _) = get(&app, "/api/orders/order-1").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    fn accept(value: Option<&str>) -> Option<Format> {
+        let mut headers = HeaderMap::new();
+        if let Some(value) = value {
+            headers.insert(header::ACCEPT, value.parse().unwrap());
+        }
+        Format::from_accept(&headers)
+    }
+
+    #[test]
+    fn chooses_response_format_from_accept() {
+        let cases = [
+            (None, Some(Format::Json)),
+            (Some(" "), Some(Format::Json)),
+            (Some("*/*"), Some(Format::Json)),
+            (Some("application/*"), Some(Format::Json)),
+            (Some("text/*"), Some(Format::Xml)),
+            (Some("text/html, */*;q=0.1"), Some(Format::Json)),
+            (Some("application/vnd.api+json"), Some(Format::Json)),
+            (Some("application/x-msgpack"), Some(Format::MessagePack)),
+            // Наибольший q, при равных — первый в заголовке
+            (
+                Some("application/xml;q=0.5, application/cbor"),
+                Some(Format::Cbor),
+            ),
+            (
+                Some("application/json; q=0.2, application/msgpack; q=0.8"),
+                Some(Format::MessagePack),
+            ),
+            (
+                Some("application/cbor, application/xml"),
+                Some(Format::Cbor),
+            ),
+            (Some("*/*;q=0.1, text/xml;q=0.9"), Some(Format::Xml)),
+            // q=0 исключает тип, неразборчивый q считается равным 1
+            (
+                Some("application/json;q=0, text/xml;q=0.1"),
+                Some(Format::Xml),
+            ),
+            (
+                Some("application/cbor;q=abc, application/xml;q=0.9"),
+                Some(Format::Cbor),
+            ),
+            (Some("application/json;q=0"), None),
+            (Some("text/html, image/png"), None),
+        ];
+
+        for (header, expected) in cases {
+            assert_eq!(accept(header), expected, "{header:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unacceptable_response_type() {
+        let app = app();
+        post(&app, &order("order-1"), None).await;
+
+        let request = Request::get("/api/orders/order-1")
+            .header(header::ACCEPT, "text/html")
+            .body(Body::empty())
+            .unwrap();
+        let (status, response) = send(&app, request).await;
+        assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+        assert!(response["message"]
+            .as_str()
+            .unwrap()
+            .contains(SUPPORTED_TYPES));
+
+        let request = Request::post("/api/orders")
+            .header(header::CONTENT_TYPE, "application/yaml")
+            .body(Body::from("order_uid: order-2"))
+            .unwrap();
+        let (status, response) = send(&app, request).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(response["message"]
+            .as_str()
+            .unwrap()
+            .contains(SUPPORTED_TYPES));
+    }
+
+    // Заказ с двумя товарами и суммами, сходящимися с товарами
+    fn stored_order(order_uid: &str) -> GetOrderDTO {
+        let mut order = order(order_uid);
+        let mut item = order["items"][0].clone();
+        item["chrt_id"] = json!(9934931);
+        item["rid"] = json!("ab4219087a764ae0btest2");
+        order["items"].as_array_mut().unwrap().push(item);
+        order["payment"]["goods_total"] = json!(634);
+        order["payment"]["amount"] = json!(2134);
+        serde_json::from_value(order).unwrap()
+    }
+
+    #[test]
+    fn round_trips_order_in_each_format() {
+        let timestamp_formats = [
+            TimestampFormat::Rfc3339,
+            TimestampFormat::Unix,
+            TimestampFormat::parse("%d.%m.%Y %H:%M:%S").unwrap(),
+            TimestampFormat::parse("%Y%m%d%H%M%S").unwrap(),
+        ];
+        let formats = [Format::Json, Format::MessagePack, Format::Cbor, Format::Xml];
+
+        for timestamp_format in timestamp_formats {
+            timestamp::with_format(timestamp_format.clone(), || {
+                let order = stored_order("order-1");
+                let expected = serde_json::to_value(&order).unwrap();
+                assert_eq!(expected["items"].as_array().unwrap().len(), 2);
+
+                for format in formats {
+                    let encoded = format.encode("order", &order).unwrap();
+                    let decoded: GetOrderDTO = format
+                        .decode(&encoded)
+                        .unwrap_or_else(|err| panic!("{format} {timestamp_format:?}: {err}"));
+                    assert_eq!(
+                        serde_json::to_value(&decoded).unwrap(),
+                        expected,
+                        "{format} {timestamp_format:?}"
+                    );
+                }
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn gets_order_in_requested_format() {
+        let app = app();
+        let body = serde_json::to_value(stored_order("order-1")).unwrap();
+        assert_eq!(post(&app, &body, None).await.0, StatusCode::CREATED);
+
+        for format in [Format::MessagePack, Format::Cbor, Format::Xml] {
+            let request = Request::get("/api/orders/order-1")
+                .header(header::ACCEPT, format.mime())
+                .body(Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{format}");
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                format.mime(),
+                "{format}"
+            );
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+
+            let order: GetOrderDTO = format.decode(&bytes).unwrap();
+            let mut order = serde_json::to_value(order).unwrap();
+            order.as_object_mut().unwrap().remove("date_created");
+            let mut expected = body.clone();
+            expected.as_object_mut().unwrap().remove("date_created");
+            expected["payment"]["payment_dt"] = json!("2021-11-26T06:22:07Z");
+            assert_eq!(order, expected, "{format}");
+        }
     }
 }
@@ -1,9 +1,17 @@
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDate, SubsecRound, Utc};
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
 
+use crate::money::{Currency, MinorUnits, Money, MoneyError};
+
+// Цены товаров принимаются в валюте платежа, поэтому тело заказа
+// читается через CreateOrderRecord
 #[derive(Serialize, Deserialize, Clone)]
+#[serde(from = "CreateOrderRecord")]
 pub struct CreateOrderDTO {
+    // Идентификатор заказа во внешней системе: UUID или произвольная строка.
+    // Если не передан, идентификатор генерируется базой данных
+    #[serde(default)]
+    pub order_uid: Option<String>,
     pub track_number: String,
     pub entry: String,
     pub delivery: DeliveryDTO,
@@ -18,9 +26,126 @@ pub struct CreateOrderDTO {
     pub oof_shard: String,
 }
 
+// Параметры запроса списка заказов GET /api/orders
+#[derive(Deserialize)]
+pub struct ListOrdersParams {
+    pub customer_id: Option<String>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+// Параметры выгрузки GET /api/export/orders.csv и /api/export/items.csv
+#[derive(Deserialize)]
+pub struct ExportCsvParams {
+    // Колонки через запятую, по умолчанию все колонки файла
+    pub columns: Option<String>,
+    pub customer_id: Option<String>,
+    // Даты создания заказов включительно, YYYY-MM-DD
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+// Параметры запроса списка GET /api/dead-letters
+#[derive(Deserialize)]
+pub struct ListDeadLettersParams {
+    pub source: Option<String>,
+    // Только ещё не повторённые успешно
+    #[serde(default)]
+    pub pending: bool,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+// Параметры журнала доставок вебхука
+#[derive(Debug, Deserialize)]
+pub struct ListWebhookDeliveriesParams {
+    // pending, delivered или failed
+    pub status: Option<String>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+// Максимальная длина идентификатора заказа, переданного клиентом
+const MAX_ORDER_UID_LENGTH: usize = 64;
+
+impl CreateOrderDTO {
+    // Проверяет идентификатор заказа, переданный клиентом.
+    // Допускаются UUID и строковые идентификаторы из латиницы, цифр, '-' и '_'
+    pub fn validate_order_uid(&self) -> Result<(), String> {
+        let Some(order_uid) = &self.order_uid else {
+            return Ok(());
+        };
+
+        if order_uid.is_empty() || order_uid.len() > MAX_ORDER_UID_LENGTH {
+            return Err(format!(
+                "order_uid must be between 1 and {MAX_ORDER_UID_LENGTH} characters long"
+            ));
+        }
+        if !order_uid
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(
+                "order_uid may contain only latin letters, digits, '-' and '_'".to_string(),
+            );
+        }
+
+        Ok(())
+    }
+
+    // Проверяет суммы заказа и возвращает итог платежа.
+    // Суммы не могут быть отрицательными, скидка задаётся в процентах,
+    // итоги платежа и товаров не должны переполнять i64,
+    // goods_total равен сумме total_price товаров
+    pub fn validate_amounts(&self) -> Result<Money, String> {
+        let payment = &self.payment;
+        let payment_amounts = [
+            ("amount", &payment.amount),
+            ("delivery_cost", &payment.delivery_cost),
+            ("goods_total", &payment.goods_total),
+            ("custom_fee", &payment.custom_fee),
+        ];
+        for (field, money) in payment_amounts {
+            if money.amount.0 < 0 {
+                return Err(format!("payment.{field} must not be negative"));
+            }
+        }
+
+        let mut items_total = Money::new(MinorUnits(0), payment.currency().clone());
+        for item in &self.items {
+            if item.price.amount.0 < 0 || item.total_price.amount.0 < 0 {
+                return Err(format!(
+                    "Prices of item {} must not be negative",
+                    item.chrt_id
+                ));
+            }
+            if !(0..=100).contains(&item.sale) {
+                return Err(format!(
+                    "Sale of item {} must be between 0 and 100 percent",
+                    item.chrt_id
+                ));
+            }
+
+            items_total = items_total
+                .checked_add(&item.total_price)
+                .map_err(|err| format!("Items total: {err}"))?;
+        }
+        if items_total != payment.goods_total {
+            return Err(format!(
+                "payment.goods_total {} does not match items total {items_total}",
+                payment.goods_total
+            ));
+        }
+
+        payment
+            .total()
+            .map_err(|err| format!("Payment total: {err}"))
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Order {
-    pub order_uid: Uuid,
+    pub order_uid: String,
     pub track_number: String,
     pub entry: String,
     pub locale: String,
@@ -28,7 +153,7 @@ pub struct Order {
     pub customer_id: String,
     pub delivery_service: String,
     pub sm_id: i32,
-    pub date_created: NaiveDateTime,
+    pub date_created: DateTime<Utc>,
     pub shardkey: String,
     pub oof_shard: String,
 }
@@ -52,6 +177,7 @@ impl From<tokio_postgres::Row> for Order {
 }
 
 #[derive(Serialize, Deserialize, Clone)]
+#[serde(from = "GetOrderRecord")]
 pub struct GetOrderDTO {
     pub order_uid: String,
     pub track_number: String,
@@ -64,7 +190,8 @@ pub struct GetOrderDTO {
     pub customer_id: String,
     pub delivery_service: String,
     pub sm_id: i32,
-    pub date_created: String,
+    #[serde(with = "crate::timestamp")]
+    pub date_created: DateTime<Utc>,
     pub shardkey: String,
     pub oof_shard: String,
 }
@@ -75,12 +202,8 @@ impl GetOrderDTO {
         delivery: DeliveryDTO,
         order_items: Vec<OrderItemDTO>,
     ) -> GetOrderDTO {
-        let custom_data: NaiveDateTime = row.get(9);
-        let formatted_date = custom_data.and_utc().to_rfc3339();
-        let order_uid: Uuid = row.get(0);
-
-        return GetOrderDTO {
-            order_uid: order_uid.to_string(),
+        GetOrderDTO {
+            order_uid: row.get(0),
             track_number: row.get(1),
             entry: row.get(2),
             delivery,
@@ -92,9 +215,26 @@ impl GetOrderDTO {
             delivery_service: row.get(6),
             shardkey: row.get(7),
             sm_id: row.get(8),
-            date_created: formatted_date,
+            date_created: row.get(9),
             oof_shard: row.get(10),
-        };
+        }
+    }
+
+    // Совпадает ли сохранённый заказ с телом запроса на создание.
+    // Используется для идемпотентного повтора создания заказа
+    pub fn matches(&self, body: &CreateOrderDTO) -> bool {
+        self.track_number == body.track_number
+            && self.entry == body.entry
+            && self.delivery == body.delivery
+            && self.payment.matches(&body.payment)
+            && self.items == body.items
+            && self.locale == body.locale
+            && self.internal_signature == body.internal_signature
+            && self.customer_id == body.customer_id
+            && self.delivery_service == body.delivery_service
+            && self.sm_id == body.sm_id
+            && self.shardkey == body.shardkey
+            && self.oof_shard == body.oof_shard
     }
 
     pub fn from_order(
@@ -103,8 +243,8 @@ impl GetOrderDTO {
         delivery: DeliveryDTO,
         order_items: Vec<OrderItemDTO>,
     ) -> GetOrderDTO {
-        return GetOrderDTO {
-            order_uid: order.order_uid.to_string(),
+        GetOrderDTO {
+            order_uid: order.order_uid,
             track_number: order.track_number,
             entry: order.entry,
             delivery,
@@ -116,43 +256,44 @@ impl GetOrderDTO {
             delivery_service: order.delivery_service,
             shardkey: order.shardkey,
             sm_id: order.sm_id,
-            date_created: order.date_created.to_string(),
+            date_created: order.date_created,
             oof_shard: order.oof_shard,
-        };
+        }
     }
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct OrderItemId {
-    item_id: String,
-}
-
-#[derive(Serialize, Deserialize, Clone)]
+// Цены товара в валюте платежа заказа. Валюта товара не передаётся,
+// цены передаются в минимальных единицах как в OrderItemRecord
+#[derive(Serialize, Clone, PartialEq)]
+#[serde(into = "OrderItemRecord")]
 pub struct OrderItemDTO {
     pub chrt_id: i64,
     pub track_number: String,
-    pub price: i32,
+    pub price: Money,
     pub rid: String,
     pub name: String,
+    // Скидка в процентах от price
     pub sale: i32,
     pub size: String,
-    pub total_price: i32,
+    // Цена с учётом скидки
+    pub total_price: Money,
     pub nm_id: i64,
     pub brand: String,
     pub status: i32,
 }
 
-impl From<tokio_postgres::Row> for OrderItemDTO {
-    fn from(value: tokio_postgres::Row) -> Self {
+impl OrderItemDTO {
+    // Товар из строки items, цены в валюте платежа заказа
+    pub fn from_row(value: &tokio_postgres::Row, currency: &Currency) -> Self {
         Self {
             chrt_id: value.get(0),
             track_number: value.get(1),
-            price: value.get(2),
+            price: Money::new(MinorUnits(value.get(2)), currency.clone()),
             rid: value.get(3),
             name: value.get(4),
             sale: value.get(5),
             size: value.get(6),
-            total_price: value.get(7),
+            total_price: Money::new(MinorUnits(value.get(7)), currency.clone()),
             nm_id: value.get(8),
             brand: value.get(9),
             status: value.get(10),
@@ -160,25 +301,7 @@ impl From<tokio_postgres::Row> for OrderItemDTO {
     }
 }
 
-impl From<&tokio_postgres::Row> for OrderItemDTO {
-    fn from(value: &tokio_postgres::Row) -> Self {
-        Self {
-            chrt_id: value.get(0),
-            track_number: value.get(1),
-            price: value.get(2),
-            rid: value.get(3),
-            name: value.get(4),
-            sale: value.get(5),
-            size: value.get(6),
-            total_price: value.get(7),
-            nm_id: value.get(8),
-            brand: value.get(9),
-            status: value.get(10),
-        }
-    }
-}
-
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct DeliveryDTO {
     pub name: String,
     pub phone: String,
@@ -203,33 +326,364 @@ impl From<tokio_postgres::Row> for DeliveryDTO {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+// Все суммы платежа в одной валюте. Передаётся как PaymentRecord:
+// код валюты отдельным полем, суммы в минимальных единицах
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(from = "PaymentRecord", into = "PaymentRecord")]
 pub struct PaymentDTO {
     pub transaction: String,
     pub request_id: String,
-    pub currency: String,
     pub provider: String,
-    pub amount: i32,
-    pub payment_dt: i64,
+    pub amount: Money,
+    pub payment_dt: DateTime<Utc>,
     pub bank: String,
-    pub delivery_cost: i32,
-    pub goods_total: i32,
-    pub custom_fee: i32,
+    pub delivery_cost: Money,
+    pub goods_total: Money,
+    // Таможенный сбор
+    pub custom_fee: Money,
+}
+
+impl PaymentDTO {
+    // Совпадает ли сохранённый платёж с платежом из запроса. База хранит
+    // payment_dt с точностью до микросекунд, поэтому время сравнивается с ней
+    pub fn matches(&self, other: &PaymentDTO) -> bool {
+        let truncated = |payment: &PaymentDTO| PaymentDTO {
+            payment_dt: payment.payment_dt.trunc_subsecs(6),
+            ..payment.clone()
+        };
+
+        truncated(self) == truncated(other)
+    }
+
+    // Валюта платежа и товаров заказа
+    pub fn currency(&self) -> &Currency {
+        &self.amount.currency
+    }
+
+    // Итог платежа: товары, доставка и таможенный сбор
+    pub fn total(&self) -> Result<Money, MoneyError> {
+        [&self.goods_total, &self.delivery_cost, &self.custom_fee]
+            .into_iter()
+            .try_fold(
+                Money::new(MinorUnits(0), self.currency().clone()),
+                |total, amount| total.checked_add(amount),
+            )
+    }
 }
 
 impl From<tokio_postgres::Row> for PaymentDTO {
     fn from(value: tokio_postgres::Row) -> Self {
+        let currency = Currency::from_stored(value.get(2));
+        let money = |index| Money::new(MinorUnits(value.get(index)), currency.clone());
+
         Self {
             transaction: value.get(0),
             request_id: value.get(1),
-            currency: value.get(2),
             provider: value.get(3),
-            amount: value.get(4),
+            amount: money(4),
             payment_dt: value.get(5),
             bank: value.get(6),
-            delivery_cost: value.get(7),
-            goods_total: value.get(8),
-            custom_fee: value.get(9),
+            delivery_cost: money(7),
+            goods_total: money(8),
+            custom_fee: money(9),
+        }
+    }
+}
+
+// Платёж в том виде, в котором он передаётся в JSON, MessagePack, CBOR и XML
+#[derive(Serialize, Deserialize)]
+struct PaymentRecord {
+    transaction: String,
+    request_id: String,
+    currency: Currency,
+    provider: String,
+    amount: MinorUnits,
+    // Принимается как Unix-время в секундах или строка RFC 3339
+    #[serde(with = "crate::timestamp")]
+    payment_dt: DateTime<Utc>,
+    bank: String,
+    delivery_cost: MinorUnits,
+    goods_total: MinorUnits,
+    custom_fee: MinorUnits,
+}
+
+impl From<PaymentRecord> for PaymentDTO {
+    fn from(value: PaymentRecord) -> Self {
+        let money = |amount| Money::new(amount, value.currency.clone());
+
+        PaymentDTO {
+            amount: money(value.amount),
+            delivery_cost: money(value.delivery_cost),
+            goods_total: money(value.goods_total),
+            custom_fee: money(value.custom_fee),
+            transaction: value.transaction,
+            request_id: value.request_id,
+            provider: value.provider,
+            payment_dt: value.payment_dt,
+            bank: value.bank,
+        }
+    }
+}
+
+impl From<PaymentDTO> for PaymentRecord {
+    fn from(value: PaymentDTO) -> Self {
+        PaymentRecord {
+            transaction: value.transaction,
+            request_id: value.request_id,
+            currency: value.amount.currency,
+            provider: value.provider,
+            amount: value.amount.amount,
+            payment_dt: value.payment_dt,
+            bank: value.bank,
+            delivery_cost: value.delivery_cost.amount,
+            goods_total: value.goods_total.amount,
+            custom_fee: value.custom_fee.amount,
+        }
+    }
+}
+
+// Товар в том виде, в котором он передаётся: цены без валюты
+#[derive(Serialize, Deserialize)]
+struct OrderItemRecord {
+    chrt_id: i64,
+    track_number: String,
+    price: MinorUnits,
+    rid: String,
+    name: String,
+    sale: i32,
+    size: String,
+    total_price: MinorUnits,
+    nm_id: i64,
+    brand: String,
+    status: i32,
+}
+
+impl OrderItemRecord {
+    fn into_item(self, currency: &Currency) -> OrderItemDTO {
+        OrderItemDTO {
+            chrt_id: self.chrt_id,
+            track_number: self.track_number,
+            price: Money::new(self.price, currency.clone()),
+            rid: self.rid,
+            name: self.name,
+            sale: self.sale,
+            size: self.size,
+            total_price: Money::new(self.total_price, currency.clone()),
+            nm_id: self.nm_id,
+            brand: self.brand,
+            status: self.status,
+        }
+    }
+}
+
+impl From<OrderItemDTO> for OrderItemRecord {
+    fn from(value: OrderItemDTO) -> Self {
+        OrderItemRecord {
+            chrt_id: value.chrt_id,
+            track_number: value.track_number,
+            price: value.price.amount,
+            rid: value.rid,
+            name: value.name,
+            sale: value.sale,
+            size: value.size,
+            total_price: value.total_price.amount,
+            nm_id: value.nm_id,
+            brand: value.brand,
+            status: value.status,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateOrderRecord {
+    #[serde(default)]
+    order_uid: Option<String>,
+    track_number: String,
+    entry: String,
+    delivery: DeliveryDTO,
+    payment: PaymentDTO,
+    items: Vec<OrderItemRecord>,
+    locale: String,
+    internal_signature: String,
+    customer_id: String,
+    delivery_service: String,
+    sm_id: i32,
+    shardkey: String,
+    oof_shard: String,
+}
+
+impl From<CreateOrderRecord> for CreateOrderDTO {
+    fn from(value: CreateOrderRecord) -> Self {
+        CreateOrderDTO {
+            items: value
+                .items
+                .into_iter()
+                .map(|item| item.into_item(value.payment.currency()))
+                .collect(),
+            order_uid: value.order_uid,
+            track_number: value.track_number,
+            entry: value.entry,
+            delivery: value.delivery,
+            payment: value.payment,
+            locale: value.locale,
+            internal_signature: value.internal_signature,
+            customer_id: value.customer_id,
+            delivery_service: value.delivery_service,
+            sm_id: value.sm_id,
+            shardkey: value.shardkey,
+            oof_shard: value.oof_shard,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GetOrderRecord {
+    order_uid: String,
+    track_number: String,
+    entry: String,
+    delivery: DeliveryDTO,
+    payment: PaymentDTO,
+    items: Vec<OrderItemRecord>,
+    locale: String,
+    internal_signature: String,
+    customer_id: String,
+    delivery_service: String,
+    sm_id: i32,
+    #[serde(with = "crate::timestamp")]
+    date_created: DateTime<Utc>,
+    shardkey: String,
+    oof_shard: String,
+}
+
+impl From<GetOrderRecord> for GetOrderDTO {
+    fn from(value: GetOrderRecord) -> Self {
+        GetOrderDTO {
+            items: value
+                .items
+                .into_iter()
+                .map(|item| item.into_item(value.payment.currency()))
+                .collect(),
+            order_uid: value.order_uid,
+            track_number: value.track_number,
+            entry: value.entry,
+            delivery: value.delivery,
+            payment: value.payment,
+            locale: value.locale,
+            internal_signature: value.internal_signature,
+            customer_id: value.customer_id,
+            delivery_service: value.delivery_service,
+            sm_id: value.sm_id,
+            date_created: value.date_created,
+            shardkey: value.shardkey,
+            oof_shard: value.oof_shard,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration};
+
+    use super::*;
+
+    fn order() -> CreateOrderDTO {
+        serde_json::from_str(include_str!("test/stubs/order.json")).unwrap()
+    }
+
+    #[test]
+    fn validates_amounts() {
+        let total = order().validate_amounts().unwrap();
+
+        assert_eq!(total.to_string(), "18.17 USD");
+    }
+
+    #[test]
+    fn keeps_amounts_in_payment_currency() {
+        let body = order();
+
+        assert_eq!(body.payment.amount.to_string(), "18.17 USD");
+        assert_eq!(body.items[0].total_price, body.payment.goods_total);
+
+        // В JSON суммы остаются числами, валюта указывается только в платеже
+        let value = serde_json::to_value(&body).unwrap();
+        assert_eq!(value["payment"]["currency"], "USD");
+        assert_eq!(value["payment"]["amount"], 1817);
+        assert_eq!(value["items"][0]["price"], 453);
+        assert!(value["items"][0].get("currency").is_none());
+    }
+
+    #[test]
+    fn rejects_item_in_other_currency() {
+        let mut body = order();
+        body.items[0].total_price.currency = Currency::new("EUR").unwrap();
+
+        assert_eq!(
+            body.validate_amounts().unwrap_err(),
+            "Items total: Currency mismatch: USD and EUR"
+        );
+    }
+
+    #[test]
+    fn rejects_goods_total_other_than_items_total() {
+        let mut body = order();
+        body.payment.goods_total.amount = MinorUnits(300);
+
+        assert_eq!(
+            body.validate_amounts().unwrap_err(),
+            "payment.goods_total 3.00 USD does not match items total 3.17 USD"
+        );
+    }
+
+    #[test]
+    fn rejects_negative_amounts_and_invalid_sale() {
+        let mut body = order();
+        body.payment.custom_fee.amount = MinorUnits(-1);
+        assert!(body.validate_amounts().is_err());
+
+        let mut body = order();
+        body.items[0].sale = 101;
+        assert!(body.validate_amounts().is_err());
+    }
+
+    #[test]
+    fn rejects_total_overflow() {
+        let mut body = order();
+        body.payment.goods_total.amount = MinorUnits(i64::MAX);
+        body.items[0].total_price.amount = MinorUnits(i64::MAX);
+
+        assert_eq!(
+            body.validate_amounts().unwrap_err(),
+            "Payment total: Amount overflow"
+        );
+
+        body.items.push(body.items[0].clone());
+        assert_eq!(
+            body.validate_amounts().unwrap_err(),
+            "Items total: Amount overflow"
+        );
+    }
+
+    #[test]
+    fn payment_matches_stored_microseconds() {
+        let mut body = order().payment;
+        body.payment_dt = DateTime::parse_from_rfc3339("2021-11-26T06:22:19.123456789Z")
+            .unwrap()
+            .to_utc();
+
+        // Postgres отбрасывает наносекунды
+        let mut stored = body.clone();
+        stored.payment_dt = body.payment_dt.trunc_subsecs(6);
+
+        assert!(stored.matches(&body));
+        assert!(body.matches(&stored));
+    }
+
+    #[test]
+    fn payment_differs_by_microsecond() {
+        let body = order().payment;
+        let mut stored = body.clone();
+        stored.payment_dt += Duration::microseconds(1);
+
+        assert!(!stored.matches(&body));
+    }
+}
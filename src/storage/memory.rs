@@ -0,0 +1,555 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use chrono::{NaiveDate, SubsecRound, Utc};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    dead_letters::{DeadLetter, DeadLetterCount},
+    errors::AppError,
+    idempotency::{self, IdempotencyClaim},
+    integrity::IntegrityIssue,
+    migrate::Migration,
+    outbox::{self, EventSink, OutboxEvent, ORDER_CREATED_EVENT},
+    parquet_export::ParquetFile,
+    query_plans::QueryPlan,
+    schema::{CreateOrderDTO, GetOrderDTO},
+    webhooks::{DeliveryAttempt, NewWebhook, PendingDelivery, Webhook, WebhookDelivery},
+};
+
+use super::{
+    CreateOrderOutcome, DeadLetterListQuery, OrderListQuery, OrderRepository, TransactionSnapshot,
+    WebhookDeliveryListQuery,
+};
+
+// Сохранённый ключ идемпотентности
+struct IdempotencyRecord {
+    request_hash: String,
+    response: Option<(StatusCode, serde_json::Value)>,
+    expires_at: Instant,
+}
+
+// Событие outbox и момент его доставки
+struct OutboxRecord {
+    event: OutboxEvent,
+    delivered_at: Option<Instant>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    orders: HashMap<String, GetOrderDTO>,
+    idempotency_keys: HashMap<String, IdempotencyRecord>,
+    outbox: Vec<OutboxRecord>,
+    next_event_id: i64,
+    dead_letters: Vec<DeadLetter>,
+    // Сколько следующих созданий заказа сохраняются, но отвечают ошибкой,
+    // как commit, ответ на который потерялся
+    #[cfg(test)]
+    lost_commits: u32,
+    // Сколько следующих созданий заказа отвечают временной ошибкой
+    // без сохранения, как недоступная база
+    #[cfg(test)]
+    failed_writes: u32,
+}
+
+// Хранилище заказов в памяти процесса.
+// Данные теряются при перезапуске, подходит для тестов и локального запуска
+#[derive(Default)]
+pub struct MemoryOrderRepository {
+    state: Mutex<MemoryState>,
+}
+
+impl MemoryOrderRepository {
+    pub fn new() -> Self {
+        MemoryOrderRepository {
+            state: Mutex::new(MemoryState::default()),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn lose_commits(&self, count: u32) {
+        self.state.lock().unwrap().lost_commits = count;
+    }
+
+    #[cfg(test)]
+    pub fn fail_writes(&self, count: u32) {
+        self.state.lock().unwrap().failed_writes = count;
+    }
+}
+
+#[async_trait]
+impl OrderRepository for MemoryOrderRepository {
+    async fn create_order(
+        &self,
+        body: &CreateOrderDTO,
+        idempotency_key: Option<&str>,
+    ) -> Result<CreateOrderOutcome, AppError> {
+        let mut state = self.state.lock().unwrap();
+        #[cfg(test)]
+        if state.failed_writes > 0 {
+            state.failed_writes -= 1;
+            return Err(AppError::ConnectionError(
+                "database unavailable".to_string(),
+            ));
+        }
+
+        let order_uid = body
+            .order_uid
+            .clone()
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        if let Some(existing_order) = state.orders.get(&order_uid) {
+            return Ok(CreateOrderOutcome::Exists(existing_order.clone()));
+        }
+        // Ключ, который уже не занят этим запросом, отменяет создание заказа
+        if let Some(key) = idempotency_key {
+            if state
+                .idempotency_keys
+                .get(key)
+                .is_none_or(|record| record.response.is_some())
+            {
+                return Err(AppError::IdempotencyKeyError(key.to_string()));
+            }
+        }
+
+        let order = GetOrderDTO {
+            order_uid: order_uid.clone(),
+            track_number: body.track_number.clone(),
+            entry: body.entry.clone(),
+            delivery: body.delivery.clone(),
+            payment: body.payment.clone(),
+            items: body.items.clone(),
+            locale: body.locale.clone(),
+            internal_signature: body.internal_signature.clone(),
+            customer_id: body.customer_id.clone(),
+            delivery_service: body.delivery_service.clone(),
+            sm_id: body.sm_id,
+            // Точность как у TIMESTAMPTZ в PostgreSQL
+            date_created: Utc::now().trunc_subsecs(6),
+            shardkey: body.shardkey.clone(),
+            oof_shard: body.oof_shard.clone(),
+        };
+        state.orders.insert(order_uid.clone(), order.clone());
+
+        state.next_event_id += 1;
+        let event = OutboxEvent {
+            event_id: state.next_event_id,
+            order_uid: order_uid.clone(),
+            event_type: ORDER_CREATED_EVENT.to_string(),
+            payload: json!(order),
+            created_at: order.date_created,
+        };
+        state.outbox.push(OutboxRecord {
+            event,
+            delivered_at: None,
+        });
+
+        // Ответ сохраняется вместе с заказом
+        if let Some(record) = idempotency_key.and_then(|key| state.idempotency_keys.get_mut(key)) {
+            record.response = Some(idempotency::created_response(&order_uid));
+        }
+
+        #[cfg(test)]
+        if state.lost_commits > 0 {
+            state.lost_commits -= 1;
+            return Err(AppError::ConnectionError(
+                "commit acknowledgement lost".to_string(),
+            ));
+        }
+
+        Ok(CreateOrderOutcome::Created(order))
+    }
+
+    async fn get_order(&self, order_uid: &str) -> Result<Option<GetOrderDTO>, AppError> {
+        Ok(self.state.lock().unwrap().orders.get(order_uid).cloned())
+    }
+
+    async fn get_orders(&self, order_uids: &[String]) -> Result<Vec<GetOrderDTO>, AppError> {
+        let state = self.state.lock().unwrap();
+
+        Ok(order_uids
+            .iter()
+            .filter_map(|order_uid| state.orders.get(order_uid).cloned())
+            .collect())
+    }
+
+    async fn list_orders(&self, query: &OrderListQuery) -> Result<Vec<GetOrderDTO>, AppError> {
+        let state = self.state.lock().unwrap();
+
+        let mut orders: Vec<&GetOrderDTO> = state
+            .orders
+            .values()
+            .filter(|order| {
+                query
+                    .customer_id
+                    .as_ref()
+                    .is_none_or(|customer_id| &order.customer_id == customer_id)
+            })
+            .filter(|order| {
+                query
+                    .after
+                    .as_ref()
+                    .is_none_or(|after| after.precedes(order))
+            })
+            .filter(|order| {
+                query
+                    .created_from
+                    .is_none_or(|from| order.date_created >= from)
+                    && query
+                        .created_before
+                        .is_none_or(|before| order.date_created < before)
+            })
+            .collect();
+        orders.sort_by(|a, b| {
+            b.date_created
+                .cmp(&a.date_created)
+                .then_with(|| a.order_uid.cmp(&b.order_uid))
+        });
+
+        Ok(orders
+            .into_iter()
+            .skip(query.offset as usize)
+            .take(query.limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        key: &str,
+        request_hash: &str,
+        ttl: Duration,
+    ) -> Result<IdempotencyClaim, AppError> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        match state.idempotency_keys.get(key) {
+            Some(record) if record.expires_at > now => {
+                if record.request_hash != request_hash {
+                    return Ok(IdempotencyClaim::Mismatch);
+                }
+                Ok(match &record.response {
+                    Some((status, body)) => IdempotencyClaim::Replay(*status, body.clone()),
+                    None => IdempotencyClaim::InProgress,
+                })
+            }
+            _ => {
+                state.idempotency_keys.insert(
+                    key.to_string(),
+                    IdempotencyRecord {
+                        request_hash: request_hash.to_string(),
+                        response: None,
+                        expires_at: now + ttl,
+                    },
+                );
+                Ok(IdempotencyClaim::Claimed)
+            }
+        }
+    }
+
+    async fn complete_idempotency_key(
+        &self,
+        key: &str,
+        status: StatusCode,
+        body: &serde_json::Value,
+    ) -> Result<(), AppError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(record) = state.idempotency_keys.get_mut(key) {
+            if record.response.is_none() {
+                record.response = Some((status, body.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn release_idempotency_key(&self, key: &str) -> Result<(), AppError> {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .idempotency_keys
+            .get(key)
+            .is_some_and(|record| record.response.is_none())
+        {
+            state.idempotency_keys.remove(key);
+        }
+
+        Ok(())
+    }
+
+    async fn cleanup_expired_idempotency_keys(&self) -> Result<u64, AppError> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let before = state.idempotency_keys.len();
+        state
+            .idempotency_keys
+            .retain(|_, record| record.expires_at > now);
+
+        Ok((before - state.idempotency_keys.len()) as u64)
+    }
+
+    // Схемы у хранилища в памяти нет
+    async fn migrate(&self, _migration: Migration) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    // Секционирования у хранилища в памяти нет, архивировать нечего
+    async fn create_partitions(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    async fn archive_orders(
+        &self,
+        _before: NaiveDate,
+        _archive_dir: &Path,
+    ) -> Result<Vec<NaiveDate>, AppError> {
+        Ok(Vec::new())
+    }
+
+    async fn restore_orders(&self, _month: NaiveDate, _archive_dir: &Path) -> Result<(), AppError> {
+        Err(AppError::UnsupportedError("restore orders".to_string()))
+    }
+
+    async fn export_parquet(
+        &self,
+        _from: NaiveDate,
+        _to: NaiveDate,
+        _output_dir: &Path,
+    ) -> Result<Vec<ParquetFile>, AppError> {
+        Err(AppError::UnsupportedError("export parquet".to_string()))
+    }
+
+    async fn check_query_plans(&self, _seed_rows: u32) -> Result<Vec<QueryPlan>, AppError> {
+        Err(AppError::UnsupportedError("check query plans".to_string()))
+    }
+
+    // Заказ хранится целиком, нарушить связи между его частями нельзя
+    async fn check_integrity(&self) -> Result<Vec<IntegrityIssue>, AppError> {
+        Ok(Vec::new())
+    }
+
+    async fn relay_outbox(&self, sink: &dyn EventSink, batch_size: u32) -> Result<usize, AppError> {
+        let events: Vec<OutboxEvent> = self
+            .state
+            .lock()
+            .unwrap()
+            .outbox
+            .iter()
+            .filter(|record| record.delivered_at.is_none())
+            .take(batch_size as usize)
+            .map(|record| record.event.clone())
+            .collect();
+
+        let results = outbox::publish_in_order(sink, &events).await;
+
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let mut delivered = 0;
+        for (event_id, result) in results {
+            if result.is_ok() {
+                if let Some(record) = state
+                    .outbox
+                    .iter_mut()
+                    .find(|record| record.event.event_id == event_id)
+                {
+                    record.delivered_at = Some(now);
+                    delivered += 1;
+                }
+            }
+        }
+
+        Ok(delivered)
+    }
+
+    async fn cleanup_delivered_events(&self, older_than: Duration) -> Result<u64, AppError> {
+        let mut state = self.state.lock().unwrap();
+        let before = state.outbox.len();
+        state.outbox.retain(|record| {
+            record
+                .delivered_at
+                .is_none_or(|delivered_at| delivered_at.elapsed() < older_than)
+        });
+
+        Ok((before - state.outbox.len()) as u64)
+    }
+
+    async fn events_after(&self, after_id: i64, limit: u32) -> Result<Vec<OutboxEvent>, AppError> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .outbox
+            .iter()
+            .filter(|record| record.event.event_id > after_id)
+            .take(limit as usize)
+            .map(|record| record.event.clone())
+            .collect())
+    }
+
+    async fn last_event_id(&self) -> Result<i64, AppError> {
+        Ok(self.state.lock().unwrap().next_event_id)
+    }
+
+    // Событие записывается вместе с заказом под одной блокировкой
+    async fn transaction_snapshot(&self) -> Result<Option<TransactionSnapshot>, AppError> {
+        Ok(None)
+    }
+
+    async fn add_dead_letter(
+        &self,
+        source: &str,
+        reference: Option<&str>,
+        payload: &str,
+        error: &str,
+    ) -> Result<i64, AppError> {
+        let mut state = self.state.lock().unwrap();
+        let dead_letter_id = state.dead_letters.len() as i64 + 1;
+        state.dead_letters.push(DeadLetter {
+            dead_letter_id,
+            source: source.to_string(),
+            reference: reference.map(str::to_string),
+            payload: payload.to_string(),
+            error: error.to_string(),
+            created_at: Utc::now(),
+            replayed_at: None,
+            replay_error: None,
+        });
+
+        Ok(dead_letter_id)
+    }
+
+    async fn list_dead_letters(
+        &self,
+        query: &DeadLetterListQuery,
+    ) -> Result<Vec<DeadLetter>, AppError> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state
+            .dead_letters
+            .iter()
+            .rev()
+            .filter(|letter| {
+                query
+                    .source
+                    .as_ref()
+                    .is_none_or(|source| &letter.source == source)
+            })
+            .filter(|letter| !query.pending || letter.replayed_at.is_none())
+            .skip(query.offset as usize)
+            .take(query.limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_dead_letter(&self, id: i64) -> Result<Option<DeadLetter>, AppError> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state
+            .dead_letters
+            .iter()
+            .find(|letter| letter.dead_letter_id == id)
+            .cloned())
+    }
+
+    async fn complete_dead_letter_replay(
+        &self,
+        id: i64,
+        error: Option<&str>,
+    ) -> Result<(), AppError> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(letter) = state
+            .dead_letters
+            .iter_mut()
+            .find(|letter| letter.dead_letter_id == id)
+        {
+            // Неудачный повтор после успешного не снимает отметку о повторе
+            if error.is_none() {
+                letter.replayed_at = Some(Utc::now());
+            }
+            letter.replay_error = error.map(str::to_string);
+        }
+
+        Ok(())
+    }
+
+    async fn count_dead_letters(&self) -> Result<Vec<DeadLetterCount>, AppError> {
+        let state = self.state.lock().unwrap();
+
+        // Источник -> (ожидают повтора, повторены)
+        let mut counts: BTreeMap<&str, (i64, i64)> = BTreeMap::new();
+        for letter in &state.dead_letters {
+            let count = counts.entry(&letter.source).or_default();
+            match letter.replayed_at {
+                Some(_) => count.1 += 1,
+                None => count.0 += 1,
+            }
+        }
+
+        Ok(counts
+            .into_iter()
+            .map(|(source, (pending, replayed))| DeadLetterCount {
+                source: source.to_string(),
+                pending,
+                replayed,
+            })
+            .collect())
+    }
+
+    // Подписки на вебхуки хранятся только в PostgreSQL
+    async fn create_webhook(&self, _webhook: &NewWebhook) -> Result<Webhook, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn list_webhooks(&self) -> Result<Vec<Webhook>, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn get_webhook(&self, _id: i64) -> Result<Option<Webhook>, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn delete_webhook(&self, _id: i64) -> Result<bool, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn list_webhook_deliveries(
+        &self,
+        _query: &WebhookDeliveryListQuery,
+    ) -> Result<Vec<WebhookDelivery>, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn redeliver_webhook(
+        &self,
+        _subscription_id: i64,
+        _delivery_id: i64,
+    ) -> Result<Option<WebhookDelivery>, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn claim_webhook_deliveries(
+        &self,
+        _limit: u32,
+        _lease: Duration,
+    ) -> Result<Vec<PendingDelivery>, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn complete_webhook_delivery(
+        &self,
+        _delivery_id: i64,
+        _attempt: &DeliveryAttempt,
+    ) -> Result<(), AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn cleanup_webhook_deliveries(&self, _older_than: Duration) -> Result<u64, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+}
@@ -0,0 +1,648 @@
+use std::{fmt, path::Path, str::FromStr, time::Duration};
+
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::{
+    dead_letters::{DeadLetter, DeadLetterCount},
+    errors::AppError,
+    idempotency::IdempotencyClaim,
+    integrity::IntegrityIssue,
+    migrate::Migration,
+    outbox::{EventSink, OutboxEvent},
+    parquet_export::ParquetFile,
+    query_plans::QueryPlan,
+    schema::{CreateOrderDTO, GetOrderDTO},
+    webhooks::{DeliveryAttempt, NewWebhook, PendingDelivery, Webhook, WebhookDelivery},
+};
+
+pub mod memory;
+pub mod postgres;
+pub mod sharded;
+pub mod sqlite;
+
+// Результат создания заказа
+pub enum CreateOrderOutcome {
+    // Заказ создан
+    Created(GetOrderDTO),
+    // Заказ с таким order_uid уже существует, возвращается сохранённый заказ
+    Exists(GetOrderDTO),
+}
+
+// Параметры выборки списка заказов
+#[derive(Debug, Clone)]
+pub struct OrderListQuery {
+    pub customer_id: Option<String>,
+    pub limit: u32,
+    pub offset: u32,
+    // Выборка начинается после этого заказа, offset отсчитывается от него
+    pub after: Option<OrderCursor>,
+    // Границы date_created: created_from включительно, created_before — нет
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+// Позиция заказа в списке: date_created DESC, order_uid
+#[derive(Debug, Clone)]
+pub struct OrderCursor {
+    pub date_created: DateTime<Utc>,
+    pub order_uid: String,
+}
+
+impl OrderCursor {
+    // Позиция сразу после заказа, для чтения следующей страницы
+    pub fn after(order: &GetOrderDTO) -> Self {
+        OrderCursor {
+            date_created: order.date_created,
+            order_uid: order.order_uid.clone(),
+        }
+    }
+
+    // Идёт ли заказ в списке после этой позиции
+    pub fn precedes(&self, order: &GetOrderDTO) -> bool {
+        order.date_created < self.date_created
+            || (order.date_created == self.date_created && order.order_uid > self.order_uid)
+    }
+}
+
+// Снимок транзакций базы: транзакции с номером меньше xmin завершены,
+// номера от xmax ещё не выданы
+#[derive(Debug, Clone, Copy)]
+pub struct TransactionSnapshot {
+    pub xmin: i64,
+    pub xmax: i64,
+}
+
+// Параметры выборки списка записей dead_letters
+#[derive(Debug, Clone)]
+pub struct DeadLetterListQuery {
+    pub source: Option<String>,
+    pub pending: bool,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+// Параметры страницы журнала доставок вебхуков
+#[derive(Debug, Clone)]
+pub struct WebhookDeliveryListQuery {
+    pub subscription_id: i64,
+    pub status: Option<String>,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+// Хранилище заказов и связанных с ними данных.
+// Обработчики работают только через этот типаж, поэтому реализацию можно
+// подменить, например хранилищем в памяти для тестов
+#[async_trait]
+pub trait OrderRepository: Send + Sync {
+    // Создаёт заказ вместе с delivery, payment и items атомарно.
+    // Если передан ключ идемпотентности, ответ на создание сохраняется вместе с заказом
+    async fn create_order(
+        &self,
+        order: &CreateOrderDTO,
+        idempotency_key: Option<&str>,
+    ) -> Result<CreateOrderOutcome, AppError>;
+
+    async fn get_order(&self, order_uid: &str) -> Result<Option<GetOrderDTO>, AppError>;
+
+    // Заказы по списку идентификаторов в порядке списка, ненайденные пропускаются
+    async fn get_orders(&self, order_uids: &[String]) -> Result<Vec<GetOrderDTO>, AppError>;
+
+    // Страница списка заказов, новые первыми
+    async fn list_orders(&self, query: &OrderListQuery) -> Result<Vec<GetOrderDTO>, AppError>;
+
+    // Атомарно занимает ключ идемпотентности
+    async fn claim_idempotency_key(
+        &self,
+        key: &str,
+        request_hash: &str,
+        ttl: Duration,
+    ) -> Result<IdempotencyClaim, AppError>;
+
+    // Сохраняет ответ для ключа идемпотентности
+    async fn complete_idempotency_key(
+        &self,
+        key: &str,
+        status: StatusCode,
+        body: &serde_json::Value,
+    ) -> Result<(), AppError>;
+
+    // Освобождает ключ идемпотентности без сохранённого ответа
+    async fn release_idempotency_key(&self, key: &str) -> Result<(), AppError>;
+
+    // Удаляет истёкшие ключи идемпотентности
+    async fn cleanup_expired_idempotency_keys(&self) -> Result<u64, AppError>;
+
+    // Применяет миграции схемы хранилища
+    async fn migrate(&self, migration: Migration) -> Result<(), AppError>;
+
+    // Создаёт секции таблиц заказов на текущий и следующий месяц
+    async fn create_partitions(&self) -> Result<(), AppError>;
+
+    // Переносит в архив заказы за месяцы раньше before,
+    // возвращает архивированные месяцы
+    async fn archive_orders(
+        &self,
+        before: NaiveDate,
+        archive_dir: &Path,
+    ) -> Result<Vec<NaiveDate>, AppError>;
+
+    // Возвращает в базу заказы за архивированный месяц
+    async fn restore_orders(&self, month: NaiveDate, archive_dir: &Path) -> Result<(), AppError>;
+
+    // Выгружает заказы, созданные с from по to включительно, в Parquet по дням
+    async fn export_parquet(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+        output_dir: &Path,
+    ) -> Result<Vec<ParquetFile>, AppError>;
+
+    // Планы запросов чтения на seed_rows сгенерированных заказах
+    async fn check_query_plans(&self, seed_rows: u32) -> Result<Vec<QueryPlan>, AppError>;
+
+    // Ищет дочерние строки без заказа, повторы доставки и оплаты
+    // и заказы без них
+    async fn check_integrity(&self) -> Result<Vec<IntegrityIssue>, AppError>;
+
+    // Отправляет до batch_size неотправленных событий outbox в sink,
+    // возвращает число доставленных
+    async fn relay_outbox(&self, sink: &dyn EventSink, batch_size: u32) -> Result<usize, AppError>;
+
+    // Удаляет события, доставленные раньше older_than назад
+    async fn cleanup_delivered_events(&self, older_than: Duration) -> Result<u64, AppError>;
+
+    // До limit событий outbox с номером больше after_id по порядку номеров,
+    // доставленные relay тоже. Источник потока событий для клиентов
+    async fn events_after(&self, after_id: i64, limit: u32) -> Result<Vec<OutboxEvent>, AppError>;
+
+    // Номер последнего записанного события outbox, 0 — событий нет
+    async fn last_event_id(&self) -> Result<i64, AppError>;
+
+    // Снимок транзакций для ожидания пропущенных номеров событий outbox.
+    // None — номер событию выдаётся при записи, которую никто не опередит,
+    // и пропуск в номерах окончателен
+    async fn transaction_snapshot(&self) -> Result<Option<TransactionSnapshot>, AppError>;
+
+    // Сохраняет заказ, который не удалось создать, возвращает id записи
+    async fn add_dead_letter(
+        &self,
+        source: &str,
+        reference: Option<&str>,
+        payload: &str,
+        error: &str,
+    ) -> Result<i64, AppError>;
+
+    // Страница записей, новые первыми
+    async fn list_dead_letters(
+        &self,
+        query: &DeadLetterListQuery,
+    ) -> Result<Vec<DeadLetter>, AppError>;
+
+    async fn get_dead_letter(&self, id: i64) -> Result<Option<DeadLetter>, AppError>;
+
+    // Отмечает результат повтора: без ошибки запись считается повторённой
+    async fn complete_dead_letter_replay(
+        &self,
+        id: i64,
+        error: Option<&str>,
+    ) -> Result<(), AppError>;
+
+    // Число повторённых и ожидающих записей по источникам
+    async fn count_dead_letters(&self) -> Result<Vec<DeadLetterCount>, AppError>;
+
+    // Создаёт подписку на вебхуки. Доставки по ней появляются
+    // для событий, записанных после создания
+    async fn create_webhook(&self, webhook: &NewWebhook) -> Result<Webhook, AppError>;
+
+    async fn list_webhooks(&self) -> Result<Vec<Webhook>, AppError>;
+
+    async fn get_webhook(&self, id: i64) -> Result<Option<Webhook>, AppError>;
+
+    // Удаляет подписку вместе с журналом доставок, false — подписки нет
+    async fn delete_webhook(&self, id: i64) -> Result<bool, AppError>;
+
+    // Страница журнала доставок подписки, новые первыми
+    async fn list_webhook_deliveries(
+        &self,
+        query: &WebhookDeliveryListQuery,
+    ) -> Result<Vec<WebhookDelivery>, AppError>;
+
+    // Ставит доставку подписки на немедленную отправку с новым счётом попыток,
+    // None — такой доставки у подписки нет
+    async fn redeliver_webhook(
+        &self,
+        subscription_id: i64,
+        delivery_id: i64,
+    ) -> Result<Option<WebhookDelivery>, AppError>;
+
+    // Берёт до limit доставок, срок которых подошёл. Следующая попытка
+    // откладывается на lease, если результат не будет записан
+    async fn claim_webhook_deliveries(
+        &self,
+        limit: u32,
+        lease: Duration,
+    ) -> Result<Vec<PendingDelivery>, AppError>;
+
+    // Записывает результат попытки доставки
+    async fn complete_webhook_delivery(
+        &self,
+        delivery_id: i64,
+        attempt: &DeliveryAttempt,
+    ) -> Result<(), AppError>;
+
+    // Удаляет доставленные и неудавшиеся доставки старше older_than
+    async fn cleanup_webhook_deliveries(&self, older_than: Duration) -> Result<u64, AppError>;
+}
+
+// Доступные реализации хранилища
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageBackend {
+    Postgres,
+    Sqlite,
+    Memory,
+}
+
+impl fmt::Display for StorageBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageBackend::Postgres => write!(f, "postgres"),
+            StorageBackend::Sqlite => write!(f, "sqlite"),
+            StorageBackend::Memory => write!(f, "memory"),
+        }
+    }
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "postgres" => Ok(StorageBackend::Postgres),
+            "sqlite" => Ok(StorageBackend::Sqlite),
+            "memory" => Ok(StorageBackend::Memory),
+            other => Err(format!("Unknown storage backend: {other}")),
+        }
+    }
+}
+
+// Общие тесты хранилищ: каждая проверка выполняется на всех реализациях,
+// PostgreSQL — при заданной TEST_POSTGRES
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::outbox::ORDER_CREATED_EVENT;
+
+    async fn backends() -> Vec<(&'static str, Arc<dyn OrderRepository>)> {
+        let sqlite = sqlite::SqliteOrderRepository::open(":memory:").unwrap();
+        sqlite.migrate(Migration::Up).await.unwrap();
+
+        let mut backends: Vec<(&'static str, Arc<dyn OrderRepository>)> = vec![
+            ("memory", Arc::new(memory::MemoryOrderRepository::new())),
+            ("sqlite", Arc::new(sqlite)),
+        ];
+        if let Some(postgres) = postgres::tests::repository().await {
+            backends.push(("postgres", Arc::new(postgres)));
+        }
+
+        backends
+    }
+
+    // Идентификаторы уникальны, чтобы тесты не мешали друг другу в общей базе
+    fn unique(prefix: &str) -> String {
+        format!("{prefix}-{}", Uuid::new_v4().simple())
+    }
+
+    fn order(order_uid: Option<String>, customer_id: &str) -> CreateOrderDTO {
+        let mut order: CreateOrderDTO =
+            serde_json::from_str(include_str!("../test/stubs/order.json")).unwrap();
+        order.order_uid = order_uid;
+        order.customer_id = customer_id.to_string();
+        order
+    }
+
+    async fn create(storage: &dyn OrderRepository, body: &CreateOrderDTO) -> GetOrderDTO {
+        match storage.create_order(body, None).await.unwrap() {
+            CreateOrderOutcome::Created(order) => order,
+            CreateOrderOutcome::Exists(order) => panic!("Order {} exists", order.order_uid),
+        }
+    }
+
+    fn order_uids(orders: &[GetOrderDTO]) -> Vec<&str> {
+        orders
+            .iter()
+            .map(|order| order.order_uid.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn creates_and_reads_orders() {
+        for (backend, storage) in backends().await {
+            let order_uid = unique("order");
+            let body = order(Some(order_uid.clone()), "customer");
+
+            let created = create(&*storage, &body).await;
+            assert_eq!(created.order_uid, order_uid, "{backend}");
+            assert!(created.matches(&body), "{backend}");
+
+            let stored = storage.get_order(&order_uid).await.unwrap().unwrap();
+            assert!(stored.matches(&body), "{backend}");
+            assert_eq!(
+                serde_json::to_value(&stored).unwrap(),
+                serde_json::to_value(&created).unwrap(),
+                "{backend}"
+            );
+
+            assert!(storage.get_order("missing").await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn generates_order_uid() {
+        for (backend, storage) in backends().await {
+            let created = create(&*storage, &order(None, "customer")).await;
+
+            assert!(!created.order_uid.is_empty(), "{backend}");
+            assert!(
+                storage
+                    .get_order(&created.order_uid)
+                    .await
+                    .unwrap()
+                    .is_some(),
+                "{backend}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn returns_existing_order() {
+        for (backend, storage) in backends().await {
+            let order_uid = unique("order");
+            create(&*storage, &order(Some(order_uid.clone()), "customer")).await;
+
+            let mut changed = order(Some(order_uid.clone()), "customer");
+            changed.track_number = "OTHER".to_string();
+            match storage.create_order(&changed, None).await.unwrap() {
+                CreateOrderOutcome::Exists(existing) => {
+                    assert_eq!(existing.order_uid, order_uid, "{backend}");
+                    assert!(!existing.matches(&changed), "{backend}");
+                }
+                CreateOrderOutcome::Created(_) => panic!("{backend}: order created twice"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn gets_orders_in_requested_order() {
+        for (backend, storage) in backends().await {
+            let first = unique("order");
+            let second = unique("order");
+            create(&*storage, &order(Some(first.clone()), "customer")).await;
+            create(&*storage, &order(Some(second.clone()), "customer")).await;
+
+            let orders = storage
+                .get_orders(&[second.clone(), "missing".to_string(), first.clone()])
+                .await
+                .unwrap();
+            assert_eq!(order_uids(&orders), [&second, &first], "{backend}");
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_customer_orders_by_pages() {
+        for (backend, storage) in backends().await {
+            let customer_id = unique("customer");
+            let mut created = Vec::new();
+            for _ in 0..3 {
+                created.push(create(&*storage, &order(None, &customer_id)).await);
+            }
+            create(&*storage, &order(None, &unique("customer"))).await;
+
+            let page = |limit, offset| OrderListQuery {
+                customer_id: Some(customer_id.clone()),
+                limit,
+                offset,
+                after: None,
+                created_from: None,
+                created_before: None,
+            };
+            let all = storage.list_orders(&page(10, 0)).await.unwrap();
+            assert_eq!(all.len(), 3, "{backend}");
+            assert!(
+                all.windows(2)
+                    .all(|pair| pair[0].date_created >= pair[1].date_created),
+                "{backend}: orders are not newest first"
+            );
+
+            let first_page = storage.list_orders(&page(2, 0)).await.unwrap();
+            let second_page = storage.list_orders(&page(2, 2)).await.unwrap();
+            let mut paged = order_uids(&first_page);
+            paged.extend(order_uids(&second_page));
+            assert_eq!(paged, order_uids(&all), "{backend}");
+
+            let after_first_page = OrderListQuery {
+                after: Some(OrderCursor::after(&first_page[1])),
+                ..page(2, 0)
+            };
+            let next_page = storage.list_orders(&after_first_page).await.unwrap();
+            assert_eq!(
+                order_uids(&next_page),
+                order_uids(&second_page),
+                "{backend}"
+            );
+
+            let mut expected: Vec<&str> = order_uids(&created);
+            let mut listed = order_uids(&all);
+            expected.sort();
+            listed.sort();
+            assert_eq!(listed, expected, "{backend}");
+
+            // Границы date_created: from включительно, before — нет
+            let newest = all[0].date_created;
+            let oldest = all[2].date_created;
+            let between = |created_from, created_before| OrderListQuery {
+                created_from,
+                created_before,
+                ..page(10, 0)
+            };
+            let bounded = storage
+                .list_orders(&between(
+                    Some(oldest),
+                    Some(newest + chrono::Duration::seconds(1)),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(order_uids(&bounded), order_uids(&all), "{backend}");
+            let bounded = storage
+                .list_orders(&between(Some(newest + chrono::Duration::seconds(1)), None))
+                .await
+                .unwrap();
+            assert!(bounded.is_empty(), "{backend}");
+            let bounded = storage
+                .list_orders(&between(None, Some(oldest)))
+                .await
+                .unwrap();
+            assert!(bounded.is_empty(), "{backend}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_order_for_key_not_held() {
+        let ttl = Duration::from_secs(60);
+        for (backend, storage) in backends().await {
+            let body = order(Some(unique("order")), &unique("customer"));
+            let key = unique("key");
+            let created = storage.create_order(&body, Some(&key)).await;
+            assert!(
+                matches!(created, Err(AppError::IdempotencyKeyError(_))),
+                "{backend}"
+            );
+
+            // Завершённый ключ тоже не даёт создать заказ второй раз
+            storage
+                .claim_idempotency_key(&key, "hash", ttl)
+                .await
+                .unwrap();
+            let response = serde_json::json!({"order_uid": "order"});
+            storage
+                .complete_idempotency_key(&key, StatusCode::CREATED, &response)
+                .await
+                .unwrap();
+            let created = storage.create_order(&body, Some(&key)).await;
+            assert!(
+                matches!(created, Err(AppError::IdempotencyKeyError(_))),
+                "{backend}"
+            );
+
+            let order_uid = body.order_uid.as_deref().unwrap();
+            assert!(
+                storage.get_order(order_uid).await.unwrap().is_none(),
+                "{backend}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn claims_and_replays_idempotency_keys() {
+        let ttl = Duration::from_secs(60);
+        for (backend, storage) in backends().await {
+            let key = unique("key");
+
+            let claim = storage.claim_idempotency_key(&key, "hash", ttl).await;
+            assert!(matches!(claim, Ok(IdempotencyClaim::Claimed)), "{backend}");
+            let claim = storage.claim_idempotency_key(&key, "hash", ttl).await;
+            assert!(
+                matches!(claim, Ok(IdempotencyClaim::InProgress)),
+                "{backend}"
+            );
+
+            let response = serde_json::json!({"order_uid": "order"});
+            storage
+                .complete_idempotency_key(&key, StatusCode::CREATED, &response)
+                .await
+                .unwrap();
+            match storage.claim_idempotency_key(&key, "hash", ttl).await {
+                Ok(IdempotencyClaim::Replay(status, body)) => {
+                    assert_eq!(status, StatusCode::CREATED, "{backend}");
+                    assert_eq!(body, response, "{backend}");
+                }
+                _ => panic!("{backend}: response is not replayed"),
+            }
+            let claim = storage.claim_idempotency_key(&key, "other", ttl).await;
+            assert!(matches!(claim, Ok(IdempotencyClaim::Mismatch)), "{backend}");
+
+            let released = unique("key");
+            storage
+                .claim_idempotency_key(&released, "hash", ttl)
+                .await
+                .unwrap();
+            storage.release_idempotency_key(&released).await.unwrap();
+            let claim = storage.claim_idempotency_key(&released, "hash", ttl).await;
+            assert!(matches!(claim, Ok(IdempotencyClaim::Claimed)), "{backend}");
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_order_events() {
+        for (backend, storage) in backends().await {
+            let last_event_id = storage.last_event_id().await.unwrap();
+            let created = create(&*storage, &order(None, "customer")).await;
+
+            let events = storage.events_after(last_event_id, 1000).await.unwrap();
+            let event = events
+                .iter()
+                .find(|event| event.order_uid == created.order_uid)
+                .unwrap_or_else(|| panic!("{backend}: no event for created order"));
+            assert_eq!(event.event_type, ORDER_CREATED_EVENT, "{backend}");
+            assert_eq!(event.payload["order_uid"], created.order_uid, "{backend}");
+            assert!(
+                events
+                    .windows(2)
+                    .all(|pair| pair[0].event_id < pair[1].event_id),
+                "{backend}"
+            );
+            assert!(
+                storage.last_event_id().await.unwrap() >= event.event_id,
+                "{backend}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn stores_and_replays_dead_letters() {
+        for (backend, storage) in backends().await {
+            let source = unique("source");
+            let id = storage
+                .add_dead_letter(&source, Some("ref"), "{}", "Invalid order")
+                .await
+                .unwrap();
+
+            let dead_letter = storage.get_dead_letter(id).await.unwrap().unwrap();
+            assert_eq!(dead_letter.source, source, "{backend}");
+            assert_eq!(dead_letter.reference.as_deref(), Some("ref"), "{backend}");
+            assert_eq!(dead_letter.error, "Invalid order", "{backend}");
+            assert!(dead_letter.replayed_at.is_none(), "{backend}");
+
+            let pending = DeadLetterListQuery {
+                source: Some(source.clone()),
+                pending: true,
+                limit: 10,
+                offset: 0,
+            };
+            assert_eq!(
+                storage.list_dead_letters(&pending).await.unwrap().len(),
+                1,
+                "{backend}"
+            );
+
+            storage
+                .complete_dead_letter_replay(id, Some("Still invalid"))
+                .await
+                .unwrap();
+            let dead_letter = storage.get_dead_letter(id).await.unwrap().unwrap();
+            assert_eq!(
+                dead_letter.replay_error.as_deref(),
+                Some("Still invalid"),
+                "{backend}"
+            );
+
+            storage.complete_dead_letter_replay(id, None).await.unwrap();
+            let dead_letter = storage.get_dead_letter(id).await.unwrap().unwrap();
+            assert!(dead_letter.replayed_at.is_some(), "{backend}");
+            assert!(
+                storage
+                    .list_dead_letters(&pending)
+                    .await
+                    .unwrap()
+                    .is_empty(),
+                "{backend}"
+            );
+        }
+    }
+}
@@ -0,0 +1,1842 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::Mutex as StdMutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use chrono::{NaiveDate, Utc};
+use futures::future::BoxFuture;
+use log::{error, info};
+use postgres_native_tls::MakeTlsConnector;
+use serde_json::json;
+use tokio::sync::MappedMutexGuard;
+use tokio_postgres::{types::ToSql, Error as PostgresError, GenericClient, Transaction};
+
+use crate::{
+    archive,
+    db::{Database, DatabaseNode, ReplicaSet, StatementCache},
+    dead_letters::{DeadLetter, DeadLetterCount},
+    errors::AppError,
+    idempotency::{self, IdempotencyClaim},
+    integrity::{self, IntegrityIssue},
+    migrate::{self, Migration},
+    outbox::{self, EventSink, OutboxEvent, ORDER_CREATED_EVENT},
+    parquet_export::{self, ParquetFile},
+    query_plans::{self, QueryPlan},
+    schema::{CreateOrderDTO, DeliveryDTO, GetOrderDTO, Order, OrderItemDTO, PaymentDTO},
+    webhooks::{
+        DeliveryAttempt, NewWebhook, PendingDelivery, Webhook, WebhookDelivery, DELIVERY_DELIVERED,
+        DELIVERY_FAILED, DELIVERY_PENDING,
+    },
+};
+
+use super::{
+    CreateOrderOutcome, DeadLetterListQuery, OrderListQuery, OrderRepository, TransactionSnapshot,
+    WebhookDeliveryListQuery,
+};
+
+// Запросы чтения. План каждого проверяет команда check-query-plans,
+// поэтому они вынесены в константы
+pub const GET_ORDER_QUERY: &str = "SELECT order_uid, track_number, entry, locale,
+        internal_signature, customer_id, delivery_service,
+        shardkey, sm_id, date_created, oof_shard
+    FROM orders WHERE order_uid = $1";
+
+pub const GET_DELIVERY_QUERY: &str = "SELECT name, phone, zip, city, address, region, email
+    FROM delivery WHERE order_uid = $1";
+
+pub const GET_PAYMENT_QUERY: &str = "SELECT transaction, request_id, currency,
+        provider, amount, payment_dt,
+        bank, delivery_cost, goods_total, custom_fee
+    FROM payment WHERE order_uid = $1";
+
+pub const GET_ITEMS_QUERY: &str = "SELECT chrt_id, track_number, price,
+        rid, name, sale, size,
+        total_price, nm_id, brand, status
+    FROM items WHERE order_uid = $1
+    ORDER BY item_id";
+
+// Те же запросы для нескольких заказов сразу. order_uid идёт последней
+// колонкой, чтобы строки разбирались так же, как строки запросов выше
+pub const GET_ORDERS_QUERY: &str = "SELECT order_uid, track_number, entry, locale,
+        internal_signature, customer_id, delivery_service,
+        shardkey, sm_id, date_created, oof_shard
+    FROM orders WHERE order_uid = ANY($1)";
+
+pub const GET_DELIVERIES_QUERY: &str = "SELECT name, phone, zip, city, address, region, email,
+        order_uid
+    FROM delivery WHERE order_uid = ANY($1)";
+
+pub const GET_PAYMENTS_QUERY: &str = "SELECT transaction, request_id, currency,
+        provider, amount, payment_dt,
+        bank, delivery_cost, goods_total, custom_fee, order_uid
+    FROM payment WHERE order_uid = ANY($1)";
+
+pub const GET_ORDERS_ITEMS_QUERY: &str = "SELECT chrt_id, track_number, price,
+        rid, name, sale, size,
+        total_price, nm_id, brand, status, order_uid
+    FROM items WHERE order_uid = ANY($1)
+    ORDER BY order_uid, item_id";
+
+// Границы date_created передаются последними параметрами, NULL — без границы.
+// Со значениями границ планировщик отсекает лишние месячные секции
+pub const LIST_ORDERS_QUERY: &str = "SELECT order_uid FROM orders
+    WHERE date_created >= COALESCE($3::TIMESTAMPTZ, '-infinity')
+      AND date_created < COALESCE($4::TIMESTAMPTZ, 'infinity')
+    ORDER BY date_created DESC, order_uid
+    LIMIT $1 OFFSET $2";
+
+pub const LIST_CUSTOMER_ORDERS_QUERY: &str = "SELECT order_uid FROM orders
+    WHERE customer_id = $1
+      AND date_created >= COALESCE($4::TIMESTAMPTZ, '-infinity')
+      AND date_created < COALESCE($5::TIMESTAMPTZ, 'infinity')
+    ORDER BY date_created DESC, order_uid
+    LIMIT $2 OFFSET $3";
+
+// Условие на date_created <= $t идёт по индексу, order_uid проверяется фильтром
+pub const LIST_ORDERS_AFTER_QUERY: &str = "SELECT order_uid FROM orders
+    WHERE date_created <= $1 AND (date_created < $1 OR order_uid > $2)
+      AND date_created >= COALESCE($5::TIMESTAMPTZ, '-infinity')
+      AND date_created < COALESCE($6::TIMESTAMPTZ, 'infinity')
+    ORDER BY date_created DESC, order_uid
+    LIMIT $3 OFFSET $4";
+
+pub const LIST_CUSTOMER_ORDERS_AFTER_QUERY: &str = "SELECT order_uid FROM orders
+    WHERE customer_id = $1
+      AND date_created <= $2 AND (date_created < $2 OR order_uid > $3)
+      AND date_created >= COALESCE($6::TIMESTAMPTZ, '-infinity')
+      AND date_created < COALESCE($7::TIMESTAMPTZ, 'infinity')
+    ORDER BY date_created DESC, order_uid
+    LIMIT $4 OFFSET $5";
+
+pub const FIND_SHARD_QUERY: &str = "SELECT shard FROM order_shards WHERE order_uid = $1";
+
+pub const FIND_SHARDS_QUERY: &str =
+    "SELECT order_uid, shard FROM order_shards WHERE order_uid = ANY($1)";
+
+pub const GET_IDEMPOTENCY_KEY_QUERY: &str = "SELECT request_hash, response_status, response_body
+    FROM idempotency_keys WHERE idempotency_key = $1";
+
+pub const CLEANUP_IDEMPOTENCY_KEYS_QUERY: &str =
+    "DELETE FROM idempotency_keys WHERE expires_at < CURRENT_TIMESTAMP";
+
+pub const PENDING_EVENTS_QUERY: &str = "SELECT event_id, order_uid, event_type, payload, created_at
+    FROM outbox WHERE delivered_at IS NULL
+    ORDER BY event_id
+    LIMIT $1";
+
+pub const EVENTS_AFTER_QUERY: &str = "SELECT event_id, order_uid, event_type, payload, created_at
+    FROM outbox WHERE event_id > $1
+    ORDER BY event_id
+    LIMIT $2";
+
+pub const LAST_EVENT_ID_QUERY: &str = "SELECT COALESCE(MAX(event_id), 0) FROM outbox";
+
+pub const CLEANUP_OUTBOX_QUERY: &str = "DELETE FROM outbox WHERE delivered_at < $1";
+
+const TRANSACTION_SNAPSHOT_QUERY: &str = "SELECT
+        pg_snapshot_xmin(snapshot)::TEXT::BIGINT,
+        pg_snapshot_xmax(snapshot)::TEXT::BIGINT
+    FROM pg_current_snapshot() AS snapshot";
+
+pub const LIST_DEAD_LETTERS_QUERY: &str = "SELECT dead_letter_id, source, reference, payload,
+        error, created_at, replayed_at, replay_error
+    FROM dead_letters
+    WHERE ($1::VARCHAR IS NULL OR source = $1)
+        AND (NOT $2 OR replayed_at IS NULL)
+    ORDER BY dead_letter_id DESC
+    LIMIT $3 OFFSET $4";
+
+pub const GET_DEAD_LETTER_QUERY: &str = "SELECT dead_letter_id, source, reference, payload,
+        error, created_at, replayed_at, replay_error
+    FROM dead_letters WHERE dead_letter_id = $1";
+
+pub const COUNT_DEAD_LETTERS_QUERY: &str = "SELECT source,
+        COUNT(*) FILTER (WHERE replayed_at IS NULL) AS pending,
+        COUNT(*) FILTER (WHERE replayed_at IS NOT NULL) AS replayed
+    FROM dead_letters
+    GROUP BY source
+    ORDER BY source";
+
+const WEBHOOK_COLUMNS: &str = "subscription_id, delivery_service, url, event_types, created_at";
+
+// Статусы доставок в запросах — значения DELIVERY_* из webhooks
+pub const LIST_WEBHOOK_DELIVERIES_QUERY: &str = "SELECT delivery_id, subscription_id, event_id,
+        order_uid, event_type, status, attempts, next_attempt_at, last_status_code, last_error,
+        created_at, last_attempt_at, delivered_at
+    FROM webhook_deliveries
+    WHERE subscription_id = $1
+        AND ($2::VARCHAR IS NULL OR status = $2)
+    ORDER BY delivery_id DESC
+    LIMIT $3 OFFSET $4";
+
+pub const REDELIVER_WEBHOOK_QUERY: &str = "UPDATE webhook_deliveries SET
+        status = 'pending',
+        attempts = 0,
+        next_attempt_at = CURRENT_TIMESTAMP,
+        delivered_at = NULL
+    WHERE delivery_id = $1 AND subscription_id = $2
+    RETURNING delivery_id, subscription_id, event_id, order_uid, event_type, status, attempts,
+        next_attempt_at, last_status_code, last_error, created_at, last_attempt_at, delivered_at";
+
+// Строки, взятые другим экземпляром, пропускаются (SKIP LOCKED)
+pub const CLAIM_WEBHOOK_DELIVERIES_QUERY: &str = "WITH due AS (
+        SELECT delivery_id FROM webhook_deliveries
+        WHERE status = 'pending'
+            AND next_attempt_at <= CURRENT_TIMESTAMP
+        ORDER BY next_attempt_at
+        LIMIT $1
+        FOR UPDATE SKIP LOCKED
+    )
+    UPDATE webhook_deliveries AS delivery
+    SET next_attempt_at = CURRENT_TIMESTAMP + $2 * INTERVAL '1 millisecond'
+    FROM due, webhook_subscriptions AS subscription
+    WHERE delivery.delivery_id = due.delivery_id
+        AND subscription.subscription_id = delivery.subscription_id
+    RETURNING delivery.delivery_id, delivery.event_id, delivery.order_uid,
+        delivery.event_type, delivery.payload, delivery.created_at,
+        delivery.attempts, subscription.url, subscription.secret";
+
+pub const CLEANUP_WEBHOOK_DELIVERIES_QUERY: &str = "DELETE FROM webhook_deliveries
+    WHERE status <> 'pending' AND created_at < $1";
+
+// Ключ advisory-блокировки relay: события одной базы отправляет один
+// экземпляр сервиса, иначе события заказа могли бы уйти не по порядку
+const OUTBOX_RELAY_LOCK: i64 = 0x6f75_7462_6f78;
+
+// Хранилище заказов в PostgreSQL.
+// Запись идёт в основной сервер, чтение заказов — в реплики, если они заданы.
+// Заказ, созданный этим экземпляром сервиса, в течение read_your_writes
+// читается из основного сервера, чтобы отставание реплики не скрыло его
+pub struct PostgresOrderRepository {
+    primary: DatabaseNode,
+    replicas: ReplicaSet,
+    read_your_writes: Duration,
+    recent_writes: StdMutex<HashMap<String, Instant>>,
+}
+
+impl PostgresOrderRepository {
+    // Подключается к основному серверу сразу, чтобы ошибка настройки
+    // обнаружилась при старте. К репликам подключение откладывается до чтения
+    pub async fn connect(
+        name: &str,
+        connection_string: String,
+        tls: MakeTlsConnector,
+        replicas: ReplicaSet,
+        read_your_writes: Duration,
+    ) -> Result<Self, AppError> {
+        let primary = DatabaseNode::new(name, connection_string, tls);
+        drop(primary.db().await?);
+
+        Ok(PostgresOrderRepository {
+            primary,
+            replicas,
+            read_your_writes,
+            recent_writes: StdMutex::new(HashMap::new()),
+        })
+    }
+
+    async fn db(&self) -> Result<MappedMutexGuard<'_, Database>, AppError> {
+        self.primary.db().await
+    }
+
+    // Выполняет чтение на реплике. При недоступности реплики запрос уходит
+    // в следующую, а если здоровых реплик нет или from_primary — в основной сервер
+    async fn read<T>(
+        &self,
+        from_primary: bool,
+        query: impl Fn(&mut Database) -> BoxFuture<'_, Result<T, AppError>>,
+    ) -> Result<T, AppError> {
+        if !from_primary {
+            for replica in self.replicas.healthy() {
+                let result = match replica.db().await {
+                    Ok(mut db) => query(&mut db).await,
+                    Err(err) => Err(err),
+                };
+                match result {
+                    Err(err) if err.is_retryable() => {
+                        error!("Read from {} failed: {err}", replica.name());
+                        self.replicas.mark_unhealthy(replica);
+                    }
+                    result => return result,
+                }
+            }
+        }
+
+        let mut db = self.db().await?;
+        query(&mut db).await
+    }
+
+    // Закрепляет заказ за шардом в справочнике и возвращает шард, за которым
+    // заказ закреплён. Если заказ уже есть в справочнике, шард не меняется
+    pub async fn assign_shard(&self, order_uid: &str, shard: usize) -> Result<usize, AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+
+        let assign_stmt = statements
+            .prepare(
+                client,
+                "INSERT INTO order_shards (order_uid, shard) VALUES ($1, $2)
+                 ON CONFLICT (order_uid) DO UPDATE SET order_uid = EXCLUDED.order_uid
+                 RETURNING shard",
+            )
+            .await?;
+        let row = client
+            .query_one(&assign_stmt, &[&order_uid, &(shard as i32)])
+            .await?;
+
+        Ok(row.get::<_, i32>("shard") as usize)
+    }
+
+    // Убирает закрепление заказа за шардом, если заказ в шард не записан
+    pub async fn unassign_shard(&self, order_uid: &str, shard: usize) -> Result<(), AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+
+        let unassign_stmt = statements
+            .prepare(
+                client,
+                "DELETE FROM order_shards WHERE order_uid = $1 AND shard = $2",
+            )
+            .await?;
+        client
+            .execute(&unassign_stmt, &[&order_uid, &(shard as i32)])
+            .await?;
+
+        Ok(())
+    }
+
+    // Шард, в котором хранится заказ, по справочнику
+    pub async fn find_shard(&self, order_uid: &str) -> Result<Option<usize>, AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+
+        let find_stmt = statements.prepare(client, FIND_SHARD_QUERY).await?;
+        let row = client.query_opt(&find_stmt, &[&order_uid]).await?;
+
+        Ok(row.map(|row| row.get::<_, i32>("shard") as usize))
+    }
+
+    // Шарды заказов по справочнику, заказы без шарда не попадают в ответ
+    pub async fn find_shards(
+        &self,
+        order_uids: &[String],
+    ) -> Result<HashMap<String, usize>, AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+
+        let find_stmt = statements.prepare(client, FIND_SHARDS_QUERY).await?;
+        let rows = client.query(&find_stmt, &[&order_uids]).await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| {
+                (
+                    row.get::<_, String>("order_uid"),
+                    row.get::<_, i32>("shard") as usize,
+                )
+            })
+            .collect())
+    }
+
+    fn remember_write(&self, order_uid: &str) {
+        if self.read_your_writes.is_zero() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut recent_writes = self.recent_writes.lock().unwrap();
+        recent_writes.retain(|_, expires_at| *expires_at > now);
+        recent_writes.insert(order_uid.to_string(), now + self.read_your_writes);
+    }
+
+    fn is_recent_write(&self, order_uid: &str) -> bool {
+        self.recent_writes
+            .lock()
+            .unwrap()
+            .get(order_uid)
+            .is_some_and(|expires_at| *expires_at > Instant::now())
+    }
+}
+
+#[async_trait]
+impl OrderRepository for PostgresOrderRepository {
+    async fn create_order(
+        &self,
+        body: &CreateOrderDTO,
+        idempotency_key: Option<&str>,
+    ) -> Result<CreateOrderOutcome, AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+
+        let mut transaction = client.transaction().await?;
+
+        // Создание order
+        let created_order =
+            match OrderService::create_one(&mut transaction, statements, body, &[]).await {
+                Ok(order) => order,
+                // Заказ с таким order_uid уже создан: повтор запроса или конфликт
+                Err(AppError::OrderExistsError(order_uid)) => {
+                    if let Err(rollback_err) = transaction.rollback().await {
+                        error!("Failed to rollback transaction: {:?}", rollback_err);
+                    }
+                    return match load_order(&mut db, &order_uid).await? {
+                        Some(order) => Ok(CreateOrderOutcome::Exists(order)),
+                        None => Err(AppError::OrderExistsError(order_uid)),
+                    };
+                }
+                Err(err) => {
+                    return Err(rollback(transaction, err, "Create order error").await);
+                }
+            };
+        let created_order_uid = created_order.order_uid.clone();
+        // Дочерние строки попадают в секцию того же месяца, что и заказ
+        let created_at = created_order.date_created;
+
+        // Создание delivery
+        let created_delivery = match DeliveryService::create_one(
+            &mut transaction,
+            statements,
+            &body.delivery,
+            &[&created_order_uid, &created_at],
+        )
+        .await
+        {
+            Ok(delivery) => delivery,
+            Err(err) => {
+                return Err(rollback(transaction, err, "Create delivery error").await);
+            }
+        };
+
+        // Создание payment
+        let created_payment = match PaymentService::create_one(
+            &mut transaction,
+            statements,
+            &body.payment,
+            &[&created_order_uid, &created_at],
+        )
+        .await
+        {
+            Ok(payment) => payment,
+            Err(err) => {
+                return Err(rollback(transaction, err, "Create payment error").await);
+            }
+        };
+
+        // Создание items
+        let created_order_items = match OrderItemsService::create_many(
+            &mut transaction,
+            statements,
+            &body.items,
+            &[&created_order_uid, &created_at],
+        )
+        .await
+        {
+            Ok(items) => items,
+            Err(err) => {
+                return Err(rollback(transaction, err, "Create items error").await);
+            }
+        };
+
+        let created = GetOrderDTO::from_order(
+            created_order,
+            created_payment,
+            created_delivery,
+            created_order_items,
+        );
+
+        // Событие о заказе попадает в outbox только вместе с самим заказом
+        if let Err(err) = insert_outbox_event(
+            &transaction,
+            statements,
+            &created_order_uid,
+            ORDER_CREATED_EVENT,
+            &json!(created),
+        )
+        .await
+        {
+            return Err(rollback(transaction, err.into(), "Outbox event error").await);
+        }
+
+        // Ответ сохраняется в той же транзакции, что и заказ. Ключ, который
+        // уже не занят этим запросом, отменяет создание заказа
+        if let Some(key) = idempotency_key {
+            let (status, response) = idempotency::created_response(&created_order_uid);
+            let err =
+                match complete_idempotency_key(&transaction, statements, key, status, &response)
+                    .await
+                {
+                    Ok(0) => Some(AppError::IdempotencyKeyError(key.to_string())),
+                    Ok(_) => None,
+                    Err(err) => Some(err.into()),
+                };
+            if let Some(err) = err {
+                return Err(rollback(transaction, err, "Idempotency key error").await);
+            }
+        }
+
+        // Commit транзакции
+        if let Err(err) = transaction.commit().await {
+            error!("Failed to commit transaction: {:?}", err);
+            return Err(err.into());
+        }
+        self.remember_write(&created_order_uid);
+
+        Ok(CreateOrderOutcome::Created(created))
+    }
+
+    async fn get_order(&self, order_uid: &str) -> Result<Option<GetOrderDTO>, AppError> {
+        let from_primary = self.is_recent_write(order_uid);
+        let order_uid = order_uid.to_string();
+        self.read(from_primary, |db| {
+            let order_uid = order_uid.clone();
+            Box::pin(async move { load_order(db, &order_uid).await })
+        })
+        .await
+    }
+
+    async fn get_orders(&self, order_uids: &[String]) -> Result<Vec<GetOrderDTO>, AppError> {
+        let from_primary = order_uids
+            .iter()
+            .any(|order_uid| self.is_recent_write(order_uid));
+        self.read(from_primary, |db| {
+            let order_uids = order_uids.to_vec();
+            Box::pin(async move { load_orders(db, &order_uids).await })
+        })
+        .await
+    }
+
+    async fn list_orders(&self, query: &OrderListQuery) -> Result<Vec<GetOrderDTO>, AppError> {
+        self.read(false, |db| {
+            let query = query.clone();
+            Box::pin(async move { list_orders(db, &query).await })
+        })
+        .await
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        key: &str,
+        request_hash: &str,
+        ttl: Duration,
+    ) -> Result<IdempotencyClaim, AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+        Ok(claim_idempotency_key(client, statements, key, request_hash, ttl).await?)
+    }
+
+    async fn complete_idempotency_key(
+        &self,
+        key: &str,
+        status: StatusCode,
+        body: &serde_json::Value,
+    ) -> Result<(), AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+        complete_idempotency_key(client, statements, key, status, body).await?;
+        Ok(())
+    }
+
+    async fn release_idempotency_key(&self, key: &str) -> Result<(), AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+        Ok(release_idempotency_key(client, statements, key).await?)
+    }
+
+    async fn cleanup_expired_idempotency_keys(&self) -> Result<u64, AppError> {
+        let db = self.db().await?;
+        Ok(db
+            .client
+            .execute(CLEANUP_IDEMPOTENCY_KEYS_QUERY, &[])
+            .await?)
+    }
+
+    async fn migrate(&self, migration: Migration) -> Result<(), AppError> {
+        let mut db = self.db().await?;
+        migrate::migrate(&mut db, migration).await?;
+
+        info!("Succefully migrated!");
+
+        Ok(())
+    }
+
+    async fn create_partitions(&self) -> Result<(), AppError> {
+        let mut db = self.db().await?;
+        archive::create_partitions(&mut db, Utc::now().date_naive()).await
+    }
+
+    async fn archive_orders(
+        &self,
+        before: NaiveDate,
+        archive_dir: &Path,
+    ) -> Result<Vec<NaiveDate>, AppError> {
+        archive::archive_before(&self.primary, before, archive_dir).await
+    }
+
+    async fn restore_orders(&self, month: NaiveDate, archive_dir: &Path) -> Result<(), AppError> {
+        archive::restore_month(&self.primary, month, archive_dir).await
+    }
+
+    // Выгрузка тяжёлая, поэтому читается с реплики, если она есть
+    async fn export_parquet(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+        output_dir: &Path,
+    ) -> Result<Vec<ParquetFile>, AppError> {
+        self.read(false, |db| {
+            let output_dir = output_dir.to_path_buf();
+            Box::pin(async move { parquet_export::export(db, from, to, &output_dir).await })
+        })
+        .await
+    }
+
+    async fn check_query_plans(&self, seed_rows: u32) -> Result<Vec<QueryPlan>, AppError> {
+        let mut db = self.db().await?;
+        query_plans::check(&mut db, seed_rows).await
+    }
+
+    async fn check_integrity(&self) -> Result<Vec<IntegrityIssue>, AppError> {
+        let db = self.db().await?;
+        integrity::check(&db).await
+    }
+
+    // Пока идёт отправка, соединение свободно для запросов. Блокировка
+    // принадлежит сессии и держится до записи результатов
+    async fn relay_outbox(&self, sink: &dyn EventSink, batch_size: u32) -> Result<usize, AppError> {
+        let events = {
+            let mut db = self.db().await?;
+            let locked: bool = db
+                .client
+                .query_one("SELECT pg_try_advisory_lock($1)", &[&OUTBOX_RELAY_LOCK])
+                .await?
+                .get(0);
+            if !locked {
+                return Ok(0);
+            }
+
+            match pending_events(&mut db, batch_size).await {
+                Ok(events) => events,
+                Err(err) => {
+                    unlock_outbox_relay(&db).await;
+                    return Err(err);
+                }
+            }
+        };
+        if events.is_empty() {
+            unlock_outbox_relay(&*self.db().await?).await;
+            return Ok(0);
+        }
+
+        let results = outbox::publish_in_order(sink, &events).await;
+
+        let mut db = self.db().await?;
+        let stored = store_delivery_results(&mut db, &results).await;
+        unlock_outbox_relay(&db).await;
+
+        stored
+    }
+
+    async fn cleanup_delivered_events(&self, older_than: Duration) -> Result<u64, AppError> {
+        let db = self.db().await?;
+        let cutoff = Utc::now() - older_than;
+        Ok(db.client.execute(CLEANUP_OUTBOX_QUERY, &[&cutoff]).await?)
+    }
+
+    // Читается с основного сервера: реплика может ещё не получить новые события
+    async fn events_after(&self, after_id: i64, limit: u32) -> Result<Vec<OutboxEvent>, AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+
+        let events_stmt = statements.prepare(client, EVENTS_AFTER_QUERY).await?;
+        let rows = client
+            .query(&events_stmt, &[&after_id, &(limit as i64)])
+            .await?;
+
+        Ok(rows.iter().map(outbox_event_from_row).collect())
+    }
+
+    async fn last_event_id(&self) -> Result<i64, AppError> {
+        let db = self.db().await?;
+        Ok(db.client.query_one(LAST_EVENT_ID_QUERY, &[]).await?.get(0))
+    }
+
+    // Номер события берётся из последовательности до фиксации, поэтому
+    // событие с меньшим номером может зафиксироваться позже. Событие пишется
+    // после заказа, и номер транзакции у него уже есть
+    async fn transaction_snapshot(&self) -> Result<Option<TransactionSnapshot>, AppError> {
+        let db = self.db().await?;
+        let row = db.client.query_one(TRANSACTION_SNAPSHOT_QUERY, &[]).await?;
+
+        Ok(Some(TransactionSnapshot {
+            xmin: row.get(0),
+            xmax: row.get(1),
+        }))
+    }
+
+    async fn add_dead_letter(
+        &self,
+        source: &str,
+        reference: Option<&str>,
+        payload: &str,
+        error: &str,
+    ) -> Result<i64, AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+
+        let insert_stmt = statements
+            .prepare(
+                client,
+                "INSERT INTO dead_letters (source, reference, payload, error)
+                 VALUES ($1, $2, $3, $4)
+                 RETURNING dead_letter_id",
+            )
+            .await?;
+        let row = client
+            .query_one(&insert_stmt, &[&source, &reference, &payload, &error])
+            .await?;
+
+        Ok(row.get("dead_letter_id"))
+    }
+
+    async fn list_dead_letters(
+        &self,
+        query: &DeadLetterListQuery,
+    ) -> Result<Vec<DeadLetter>, AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+
+        let list_stmt = statements.prepare(client, LIST_DEAD_LETTERS_QUERY).await?;
+        let rows = client
+            .query(
+                &list_stmt,
+                &[
+                    &query.source,
+                    &query.pending,
+                    &(query.limit as i64),
+                    &(query.offset as i64),
+                ],
+            )
+            .await?;
+
+        Ok(rows.iter().map(dead_letter_from_row).collect())
+    }
+
+    async fn get_dead_letter(&self, id: i64) -> Result<Option<DeadLetter>, AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+
+        let get_stmt = statements.prepare(client, GET_DEAD_LETTER_QUERY).await?;
+        let row = client.query_opt(&get_stmt, &[&id]).await?;
+
+        Ok(row.as_ref().map(dead_letter_from_row))
+    }
+
+    async fn complete_dead_letter_replay(
+        &self,
+        id: i64,
+        error: Option<&str>,
+    ) -> Result<(), AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+
+        // Неудачный повтор после успешного не снимает отметку о повторе
+        let complete_stmt = statements
+            .prepare(
+                client,
+                "UPDATE dead_letters SET
+                    replayed_at = CASE WHEN $2::VARCHAR IS NULL
+                        THEN CURRENT_TIMESTAMP ELSE replayed_at END,
+                    replay_error = $2
+                 WHERE dead_letter_id = $1",
+            )
+            .await?;
+        client.execute(&complete_stmt, &[&id, &error]).await?;
+
+        Ok(())
+    }
+
+    async fn count_dead_letters(&self) -> Result<Vec<DeadLetterCount>, AppError> {
+        let db = self.db().await?;
+        let rows = db.client.query(COUNT_DEAD_LETTERS_QUERY, &[]).await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| DeadLetterCount {
+                source: row.get("source"),
+                pending: row.get("pending"),
+                replayed: row.get("replayed"),
+            })
+            .collect())
+    }
+
+    async fn create_webhook(&self, webhook: &NewWebhook) -> Result<Webhook, AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+
+        let insert_stmt = statements
+            .prepare(
+                client,
+                &format!(
+                    "INSERT INTO webhook_subscriptions (delivery_service, url, event_types, secret)
+                     VALUES ($1, $2, $3, $4)
+                     RETURNING {WEBHOOK_COLUMNS}"
+                ),
+            )
+            .await?;
+        let row = client
+            .query_one(
+                &insert_stmt,
+                &[
+                    &webhook.delivery_service,
+                    &webhook.url,
+                    &webhook.event_types,
+                    &webhook.secret,
+                ],
+            )
+            .await?;
+
+        Ok(webhook_from_row(&row))
+    }
+
+    async fn list_webhooks(&self) -> Result<Vec<Webhook>, AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+
+        let list_stmt = statements
+            .prepare(
+                client,
+                &format!(
+                    "SELECT {WEBHOOK_COLUMNS} FROM webhook_subscriptions ORDER BY subscription_id"
+                ),
+            )
+            .await?;
+        let rows = client.query(&list_stmt, &[]).await?;
+
+        Ok(rows.iter().map(webhook_from_row).collect())
+    }
+
+    async fn get_webhook(&self, id: i64) -> Result<Option<Webhook>, AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+
+        let get_stmt = statements
+            .prepare(
+                client,
+                &format!(
+                    "SELECT {WEBHOOK_COLUMNS} FROM webhook_subscriptions WHERE subscription_id = $1"
+                ),
+            )
+            .await?;
+        let row = client.query_opt(&get_stmt, &[&id]).await?;
+
+        Ok(row.as_ref().map(webhook_from_row))
+    }
+
+    async fn delete_webhook(&self, id: i64) -> Result<bool, AppError> {
+        let db = self.db().await?;
+        let deleted = db
+            .client
+            .execute(
+                "DELETE FROM webhook_subscriptions WHERE subscription_id = $1",
+                &[&id],
+            )
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    async fn list_webhook_deliveries(
+        &self,
+        query: &WebhookDeliveryListQuery,
+    ) -> Result<Vec<WebhookDelivery>, AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+
+        let list_stmt = statements
+            .prepare(client, LIST_WEBHOOK_DELIVERIES_QUERY)
+            .await?;
+        let rows = client
+            .query(
+                &list_stmt,
+                &[
+                    &query.subscription_id,
+                    &query.status,
+                    &(query.limit as i64),
+                    &(query.offset as i64),
+                ],
+            )
+            .await?;
+
+        Ok(rows.iter().map(webhook_delivery_from_row).collect())
+    }
+
+    async fn redeliver_webhook(
+        &self,
+        subscription_id: i64,
+        delivery_id: i64,
+    ) -> Result<Option<WebhookDelivery>, AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+
+        let redeliver_stmt = statements.prepare(client, REDELIVER_WEBHOOK_QUERY).await?;
+        let row = client
+            .query_opt(&redeliver_stmt, &[&delivery_id, &subscription_id])
+            .await?;
+
+        Ok(row.as_ref().map(webhook_delivery_from_row))
+    }
+
+    async fn claim_webhook_deliveries(
+        &self,
+        limit: u32,
+        lease: Duration,
+    ) -> Result<Vec<PendingDelivery>, AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+
+        let claim_stmt = statements
+            .prepare(client, CLAIM_WEBHOOK_DELIVERIES_QUERY)
+            .await?;
+        let rows = client
+            .query(&claim_stmt, &[&(limit as i64), &(lease.as_millis() as f64)])
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| PendingDelivery {
+                delivery_id: row.get("delivery_id"),
+                url: row.get("url"),
+                secret: row.get("secret"),
+                attempts: row.get("attempts"),
+                event: OutboxEvent {
+                    event_id: row.get("event_id"),
+                    order_uid: row.get("order_uid"),
+                    event_type: row.get("event_type"),
+                    payload: row.get("payload"),
+                    created_at: row.get("created_at"),
+                },
+            })
+            .collect())
+    }
+
+    async fn complete_webhook_delivery(
+        &self,
+        delivery_id: i64,
+        attempt: &DeliveryAttempt,
+    ) -> Result<(), AppError> {
+        let mut db = self.db().await?;
+        let Database { client, statements } = &mut *db;
+
+        // Без ошибки доставка завершена, без срока повтора попытки исчерпаны
+        let complete_stmt = statements
+            .prepare(
+                client,
+                &format!(
+                    "UPDATE webhook_deliveries SET
+                        attempts = attempts + 1,
+                        last_status_code = $2,
+                        last_error = $3,
+                        last_attempt_at = CURRENT_TIMESTAMP,
+                        status = CASE
+                            WHEN $3::VARCHAR IS NULL THEN '{DELIVERY_DELIVERED}'
+                            WHEN $4::FLOAT8 IS NULL THEN '{DELIVERY_FAILED}'
+                            ELSE '{DELIVERY_PENDING}'
+                        END,
+                        delivered_at = CASE WHEN $3::VARCHAR IS NULL
+                            THEN CURRENT_TIMESTAMP ELSE delivered_at END,
+                        next_attempt_at = CURRENT_TIMESTAMP + $4 * INTERVAL '1 millisecond'
+                     WHERE delivery_id = $1"
+                ),
+            )
+            .await?;
+        let retry_in = attempt.retry_in.map(|retry_in| retry_in.as_millis() as f64);
+        client
+            .execute(
+                &complete_stmt,
+                &[
+                    &delivery_id,
+                    &attempt.status_code,
+                    &attempt.error,
+                    &retry_in,
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn cleanup_webhook_deliveries(&self, older_than: Duration) -> Result<u64, AppError> {
+        let db = self.db().await?;
+        let cutoff = Utc::now() - older_than;
+        Ok(db
+            .client
+            .execute(CLEANUP_WEBHOOK_DELIVERIES_QUERY, &[&cutoff])
+            .await?)
+    }
+}
+
+// Откатывает транзакцию после ошибки на одном из шагов создания заказа
+async fn rollback(transaction: Transaction<'_>, err: AppError, message: &str) -> AppError {
+    if let Err(rollback_err) = transaction.rollback().await {
+        error!("Failed to rollback transaction: {:?}", rollback_err);
+    }
+
+    error!("{}: {}", message, err);
+
+    err
+}
+
+// Загружает заказ вместе с payment, delivery и items из базы данных
+async fn load_order(db: &mut Database, id: &str) -> Result<Option<GetOrderDTO>, AppError> {
+    // Получение order
+    let Some(order_row) = OrderService::get_one_by_id(db, id).await? else {
+        return Ok(None);
+    };
+
+    // Получение payment
+    let payment_row = PaymentService::get_one_by_id(db, id)
+        .await?
+        .ok_or_else(|| {
+            AppError::IncompleteOrderError(format!("payment of order {id} not found"))
+        })?;
+
+    // Получение delivery
+    let delivery_row = DeliveryService::get_one_by_id(db, id)
+        .await?
+        .ok_or_else(|| {
+            AppError::IncompleteOrderError(format!("delivery of order {id} not found"))
+        })?;
+
+    // Получение items
+    let order_item_rows = OrderItemsService::get_many_by_id(db, id).await?;
+
+    let payment = PaymentDTO::from(payment_row);
+    let delivery = DeliveryDTO::from(delivery_row);
+    let order_items: Vec<OrderItemDTO> = order_item_rows
+        .iter()
+        .map(|row| OrderItemDTO::from_row(row, payment.currency()))
+        .collect();
+
+    Ok(Some(GetOrderDTO::from_row(
+        order_row,
+        payment,
+        delivery,
+        order_items,
+    )))
+}
+
+// Загружает страницу заказов, новые первыми
+async fn list_orders(
+    db: &mut Database,
+    query: &OrderListQuery,
+) -> Result<Vec<GetOrderDTO>, AppError> {
+    let Database { client, statements } = db;
+
+    let limit = query.limit as i64;
+    let offset = query.offset as i64;
+    let from = &query.created_from;
+    let before = &query.created_before;
+
+    // Отдельные запросы с фильтром и без, чтобы каждый шёл по своему индексу
+    let rows = match (&query.customer_id, &query.after) {
+        (Some(customer_id), None) => {
+            let list_stmt = statements
+                .prepare(client, LIST_CUSTOMER_ORDERS_QUERY)
+                .await?;
+            client
+                .query(&list_stmt, &[customer_id, &limit, &offset, from, before])
+                .await?
+        }
+        (None, None) => {
+            let list_stmt = statements.prepare(client, LIST_ORDERS_QUERY).await?;
+            client
+                .query(&list_stmt, &[&limit, &offset, from, before])
+                .await?
+        }
+        (Some(customer_id), Some(after)) => {
+            let list_stmt = statements
+                .prepare(client, LIST_CUSTOMER_ORDERS_AFTER_QUERY)
+                .await?;
+            client
+                .query(
+                    &list_stmt,
+                    &[
+                        customer_id,
+                        &after.date_created,
+                        &after.order_uid,
+                        &limit,
+                        &offset,
+                        from,
+                        before,
+                    ],
+                )
+                .await?
+        }
+        (None, Some(after)) => {
+            let list_stmt = statements.prepare(client, LIST_ORDERS_AFTER_QUERY).await?;
+            client
+                .query(
+                    &list_stmt,
+                    &[
+                        &after.date_created,
+                        &after.order_uid,
+                        &limit,
+                        &offset,
+                        from,
+                        before,
+                    ],
+                )
+                .await?
+        }
+    };
+
+    let order_uids: Vec<String> = rows.iter().map(|row| row.get("order_uid")).collect();
+    load_orders(db, &order_uids).await
+}
+
+// Загружает заказы по списку идентификаторов четырьмя запросами, а не
+// четырьмя на каждый заказ. Порядок — как в списке, ненайденные пропускаются
+async fn load_orders(
+    db: &mut Database,
+    order_uids: &[String],
+) -> Result<Vec<GetOrderDTO>, AppError> {
+    if order_uids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let Database { client, statements } = db;
+
+    let orders_stmt = statements.prepare(client, GET_ORDERS_QUERY).await?;
+    let mut order_rows: HashMap<String, tokio_postgres::Row> = client
+        .query(&orders_stmt, &[&order_uids])
+        .await?
+        .into_iter()
+        .map(|row| (row.get("order_uid"), row))
+        .collect();
+
+    let deliveries_stmt = statements.prepare(client, GET_DELIVERIES_QUERY).await?;
+    let mut deliveries: HashMap<String, DeliveryDTO> = client
+        .query(&deliveries_stmt, &[&order_uids])
+        .await?
+        .into_iter()
+        .map(|row| (row.get("order_uid"), DeliveryDTO::from(row)))
+        .collect();
+
+    let payments_stmt = statements.prepare(client, GET_PAYMENTS_QUERY).await?;
+    let mut payments: HashMap<String, PaymentDTO> = client
+        .query(&payments_stmt, &[&order_uids])
+        .await?
+        .into_iter()
+        .map(|row| (row.get("order_uid"), PaymentDTO::from(row)))
+        .collect();
+
+    let items_stmt = statements.prepare(client, GET_ORDERS_ITEMS_QUERY).await?;
+    let mut items: HashMap<String, Vec<tokio_postgres::Row>> = HashMap::new();
+    for row in client.query(&items_stmt, &[&order_uids]).await? {
+        items.entry(row.get("order_uid")).or_default().push(row);
+    }
+
+    let mut orders = Vec::with_capacity(order_rows.len());
+    for order_uid in order_uids {
+        let Some(order_row) = order_rows.remove(order_uid) else {
+            continue;
+        };
+        let payment = payments.remove(order_uid).ok_or_else(|| {
+            AppError::IncompleteOrderError(format!("payment of order {order_uid} not found"))
+        })?;
+        let delivery = deliveries.remove(order_uid).ok_or_else(|| {
+            AppError::IncompleteOrderError(format!("delivery of order {order_uid} not found"))
+        })?;
+        // Цены товаров в валюте платежа заказа
+        let order_items = items
+            .remove(order_uid)
+            .unwrap_or_default()
+            .iter()
+            .map(|row| OrderItemDTO::from_row(row, payment.currency()))
+            .collect();
+
+        orders.push(GetOrderDTO::from_row(
+            order_row,
+            payment,
+            delivery,
+            order_items,
+        ));
+    }
+
+    Ok(orders)
+}
+
+// Атомарно занимает ключ. Истёкшая запись перезаписывается,
+// при конкурентных запросах ключ достаётся только одному из них
+async fn claim_idempotency_key<C>(
+    client: &C,
+    statements: &mut StatementCache,
+    key: &str,
+    request_hash: &str,
+    ttl: Duration,
+) -> Result<IdempotencyClaim, PostgresError>
+where
+    C: GenericClient + Sync,
+{
+    let claim_stmt = statements
+        .prepare(
+            client,
+            "INSERT INTO idempotency_keys (idempotency_key, request_hash, expires_at)
+             VALUES ($1, $2, CURRENT_TIMESTAMP + make_interval(secs => $3))
+             ON CONFLICT (idempotency_key) DO UPDATE SET
+                request_hash = EXCLUDED.request_hash,
+                response_status = NULL,
+                response_body = NULL,
+                created_at = CURRENT_TIMESTAMP,
+                expires_at = EXCLUDED.expires_at
+             WHERE idempotency_keys.expires_at < CURRENT_TIMESTAMP
+             RETURNING idempotency_key",
+        )
+        .await?;
+
+    let ttl_secs = ttl.as_secs_f64();
+    if client
+        .query_opt(&claim_stmt, &[&key, &request_hash, &ttl_secs])
+        .await?
+        .is_some()
+    {
+        return Ok(IdempotencyClaim::Claimed);
+    }
+
+    let get_key_stmt = statements
+        .prepare(client, GET_IDEMPOTENCY_KEY_QUERY)
+        .await?;
+
+    let Some(row) = client.query_opt(&get_key_stmt, &[&key]).await? else {
+        // Запись удалили между запросами, считаем что ключ ещё занят
+        return Ok(IdempotencyClaim::InProgress);
+    };
+
+    let stored_hash: String = row.get(0);
+    if stored_hash != request_hash {
+        return Ok(IdempotencyClaim::Mismatch);
+    }
+
+    let response_status: Option<i32> = row.get(1);
+    let response_body: Option<serde_json::Value> = row.get(2);
+    match (response_status, response_body) {
+        (Some(status), Some(body)) => {
+            let status = StatusCode::from_u16(status as u16).unwrap_or(StatusCode::OK);
+            Ok(IdempotencyClaim::Replay(status, body))
+        }
+        _ => Ok(IdempotencyClaim::InProgress),
+    }
+}
+
+// Сохраняет ответ на запрос. Может вызываться внутри транзакции создания заказа,
+// повторный вызов для уже сохранённого ответа ничего не меняет
+// Возвращает число обновлённых строк: 0, если ключ не занят или уже завершён
+async fn complete_idempotency_key<C>(
+    client: &C,
+    statements: &mut StatementCache,
+    key: &str,
+    status: StatusCode,
+    body: &serde_json::Value,
+) -> Result<u64, PostgresError>
+where
+    C: GenericClient + Sync,
+{
+    let complete_stmt = statements
+        .prepare(
+            client,
+            "UPDATE idempotency_keys SET response_status = $2, response_body = $3
+             WHERE idempotency_key = $1 AND response_status IS NULL",
+        )
+        .await?;
+
+    client
+        .execute(&complete_stmt, &[&key, &(status.as_u16() as i32), body])
+        .await
+}
+
+// Освобождает ключ, если запрос завершился ошибкой сервера и его можно повторить
+async fn release_idempotency_key<C>(
+    client: &C,
+    statements: &mut StatementCache,
+    key: &str,
+) -> Result<(), PostgresError>
+where
+    C: GenericClient + Sync,
+{
+    let release_stmt = statements
+        .prepare(
+            client,
+            "DELETE FROM idempotency_keys
+             WHERE idempotency_key = $1 AND response_status IS NULL",
+        )
+        .await?;
+
+    client.execute(&release_stmt, &[&key]).await?;
+
+    Ok(())
+}
+
+// Записывает событие о заказе и доставки подписчикам вебхуков на этот тип
+// события, вызывается внутри транзакции создания заказа
+async fn insert_outbox_event<C>(
+    client: &C,
+    statements: &mut StatementCache,
+    order_uid: &str,
+    event_type: &str,
+    payload: &serde_json::Value,
+) -> Result<(), PostgresError>
+where
+    C: GenericClient + Sync,
+{
+    let insert_stmt = statements
+        .prepare(
+            client,
+            "WITH event AS (
+                INSERT INTO outbox (order_uid, event_type, payload) VALUES ($1, $2, $3)
+                RETURNING event_id, order_uid, event_type, payload
+            )
+            INSERT INTO webhook_deliveries (subscription_id, event_id, order_uid, event_type, payload)
+            SELECT subscription_id, event_id, order_uid, event_type, payload
+            FROM event, webhook_subscriptions
+            WHERE webhook_subscriptions.delivery_service = event.payload->>'delivery_service'
+                AND event.event_type = ANY(webhook_subscriptions.event_types)",
+        )
+        .await?;
+
+    client
+        .execute(&insert_stmt, &[&order_uid, &event_type, payload])
+        .await?;
+
+    Ok(())
+}
+
+// Неотправленные события в порядке записи
+async fn pending_events(db: &mut Database, limit: u32) -> Result<Vec<OutboxEvent>, AppError> {
+    let Database { client, statements } = db;
+
+    let pending_stmt = statements.prepare(client, PENDING_EVENTS_QUERY).await?;
+    let rows = client.query(&pending_stmt, &[&(limit as i64)]).await?;
+
+    Ok(rows.iter().map(outbox_event_from_row).collect())
+}
+
+fn outbox_event_from_row(row: &tokio_postgres::Row) -> OutboxEvent {
+    OutboxEvent {
+        event_id: row.get("event_id"),
+        order_uid: row.get("order_uid"),
+        event_type: row.get("event_type"),
+        payload: row.get("payload"),
+        created_at: row.get("created_at"),
+    }
+}
+
+// Отмечает доставленные события и записывает ошибки неудачных,
+// возвращает число доставленных
+async fn store_delivery_results(
+    db: &mut Database,
+    results: &[(i64, Result<(), String>)],
+) -> Result<usize, AppError> {
+    let Database { client, statements } = db;
+
+    let transaction = client.transaction().await?;
+    let delivered_stmt = statements
+        .prepare(
+            &transaction,
+            "UPDATE outbox SET delivered_at = CURRENT_TIMESTAMP,
+                attempts = attempts + 1, last_error = NULL
+             WHERE event_id = $1",
+        )
+        .await?;
+    let failed_stmt = statements
+        .prepare(
+            &transaction,
+            "UPDATE outbox SET attempts = attempts + 1, last_error = $2
+             WHERE event_id = $1",
+        )
+        .await?;
+
+    let mut delivered = 0;
+    for (event_id, result) in results {
+        match result {
+            Ok(()) => {
+                transaction.execute(&delivered_stmt, &[event_id]).await?;
+                delivered += 1;
+            }
+            Err(message) => {
+                transaction
+                    .execute(&failed_stmt, &[event_id, message])
+                    .await?;
+            }
+        }
+    }
+    transaction.commit().await?;
+
+    Ok(delivered)
+}
+
+fn webhook_from_row(row: &tokio_postgres::Row) -> Webhook {
+    Webhook {
+        subscription_id: row.get("subscription_id"),
+        delivery_service: row.get("delivery_service"),
+        url: row.get("url"),
+        event_types: row.get("event_types"),
+        created_at: row.get("created_at"),
+    }
+}
+
+fn webhook_delivery_from_row(row: &tokio_postgres::Row) -> WebhookDelivery {
+    WebhookDelivery {
+        delivery_id: row.get("delivery_id"),
+        subscription_id: row.get("subscription_id"),
+        event_id: row.get("event_id"),
+        order_uid: row.get("order_uid"),
+        event_type: row.get("event_type"),
+        status: row.get("status"),
+        attempts: row.get("attempts"),
+        next_attempt_at: row.get("next_attempt_at"),
+        last_status_code: row.get("last_status_code"),
+        last_error: row.get("last_error"),
+        created_at: row.get("created_at"),
+        last_attempt_at: row.get("last_attempt_at"),
+        delivered_at: row.get("delivered_at"),
+    }
+}
+
+fn dead_letter_from_row(row: &tokio_postgres::Row) -> DeadLetter {
+    DeadLetter {
+        dead_letter_id: row.get("dead_letter_id"),
+        source: row.get("source"),
+        reference: row.get("reference"),
+        payload: row.get("payload"),
+        error: row.get("error"),
+        created_at: row.get("created_at"),
+        replayed_at: row.get("replayed_at"),
+        replay_error: row.get("replay_error"),
+    }
+}
+
+async fn unlock_outbox_relay(db: &Database) {
+    if let Err(err) = db
+        .client
+        .execute("SELECT pg_advisory_unlock($1)", &[&OUTBOX_RELAY_LOCK])
+        .await
+    {
+        error!("Failed to release outbox relay lock: {err}");
+    }
+}
+
+// Типаж описывающий структуру запроса на получение элмента
+trait GetOneById {
+    async fn get_one_by_id(
+        db: &mut Database,
+        id: &str,
+    ) -> Result<Option<tokio_postgres::Row>, PostgresError>;
+}
+
+// Типаж описывающий структуру запроса на получение множества элементов
+trait GetManyById {
+    async fn get_many_by_id(
+        db: &mut Database,
+        id: &str,
+    ) -> Result<Vec<tokio_postgres::Row>, PostgresError>;
+}
+
+// Типаж описывающий структуру запроса на создание элемента
+trait CreateOne<T, R>
+where
+    R: From<tokio_postgres::Row>,
+{
+    async fn create_one(
+        transaction: &mut Transaction<'_>,
+        statements: &mut StatementCache,
+        body: &T,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<R, AppError>;
+}
+
+// Типаж описывающий структуру запроса на создание множества элемента
+trait CreateMany<T, R> {
+    async fn create_many(
+        transaction: &mut Transaction<'_>,
+        statements: &mut StatementCache,
+        body: &T,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<R>, AppError>;
+}
+
+struct PaymentService();
+impl GetOneById for PaymentService {
+    async fn get_one_by_id(
+        db: &mut Database,
+        id: &str,
+    ) -> Result<Option<tokio_postgres::Row>, PostgresError> {
+        let get_payment_stmt = db.statements.prepare(&db.client, GET_PAYMENT_QUERY).await?;
+
+        db.client.query_opt(&get_payment_stmt, &[&id]).await
+    }
+}
+impl CreateOne<PaymentDTO, PaymentDTO> for PaymentService {
+    async fn create_one(
+        transaction: &mut Transaction<'_>,
+        statements: &mut StatementCache,
+        body: &PaymentDTO,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<PaymentDTO, AppError> {
+        let create_payment_stmt = statements
+            .prepare(
+                transaction,
+                "INSERT INTO payment (
+                        order_uid, transaction, request_id,
+                        currency, provider, amount,
+                        payment_dt, bank, delivery_cost,
+                        goods_total, custom_fee, date_created
+                 ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) 
+                  RETURNING 
+                        transaction, request_id, currency, provider, amount,
+                        payment_dt, bank, delivery_cost,
+                        goods_total, custom_fee",
+            )
+            .await?;
+
+        let payment_row = transaction
+            .query_one(
+                &create_payment_stmt,
+                &[
+                    params[0],
+                    &body.transaction,
+                    &body.request_id,
+                    &body.currency().as_str(),
+                    &body.provider,
+                    &body.amount.amount.0,
+                    &body.payment_dt,
+                    &body.bank,
+                    &body.delivery_cost.amount.0,
+                    &body.goods_total.amount.0,
+                    &body.custom_fee.amount.0,
+                    params[1],
+                ],
+            )
+            .await?;
+
+        Ok(PaymentDTO::from(payment_row))
+    }
+}
+
+struct OrderService();
+impl GetOneById for OrderService {
+    async fn get_one_by_id(
+        db: &mut Database,
+        id: &str,
+    ) -> Result<Option<tokio_postgres::Row>, PostgresError> {
+        let get_order_stmt = db.statements.prepare(&db.client, GET_ORDER_QUERY).await?;
+
+        db.client.query_opt(&get_order_stmt, &[&id]).await
+    }
+}
+impl CreateOne<CreateOrderDTO, Order> for OrderService {
+    async fn create_one(
+        transaction: &mut Transaction<'_>,
+        statements: &mut StatementCache,
+        body: &CreateOrderDTO,
+        _params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Order, AppError> {
+        let create_order_stmt = statements
+            .prepare(
+                transaction,
+                "WITH reserved AS (
+              INSERT INTO order_uids (order_uid)
+              VALUES (COALESCE($1, public.uuid_generate_v4()::VARCHAR))
+              ON CONFLICT (order_uid) DO NOTHING
+              RETURNING order_uid, date_created
+            )
+            INSERT INTO orders (
+              order_uid, track_number, entry, locale,
+              internal_signature, customer_id, delivery_service,
+              shardkey, sm_id, oof_shard, date_created
+            ) SELECT
+              reserved.order_uid, $2, $3, $4, $5, $6, $7, $8, $9, $10,
+              reserved.date_created
+            FROM reserved
+            RETURNING
+              order_uid, track_number, entry, locale,
+              internal_signature, customer_id, delivery_service,
+              sm_id, date_created, shardkey, oof_shard",
+            )
+            .await?;
+
+        let create_order_row = transaction
+            .query_opt(
+                &create_order_stmt,
+                &[
+                    &body.order_uid,
+                    &body.track_number,
+                    &body.entry,
+                    &body.locale,
+                    &body.internal_signature,
+                    &body.customer_id,
+                    &body.delivery_service,
+                    &body.shardkey,
+                    &body.sm_id,
+                    &body.oof_shard,
+                ],
+            )
+            .await?;
+
+        // Идентификатор не зарезервирован, значит заказ с таким order_uid уже существует
+        match create_order_row {
+            Some(row) => Ok(Order::from(row)),
+            None => Err(AppError::OrderExistsError(
+                body.order_uid.clone().unwrap_or_default(),
+            )),
+        }
+    }
+}
+
+struct OrderItemsService();
+impl GetManyById for OrderItemsService {
+    async fn get_many_by_id(
+        db: &mut Database,
+        id: &str,
+    ) -> Result<Vec<tokio_postgres::Row>, PostgresError> {
+        let get_items_stmt = db.statements.prepare(&db.client, GET_ITEMS_QUERY).await?;
+
+        db.client.query(&get_items_stmt, &[&id]).await
+    }
+}
+impl CreateMany<Vec<OrderItemDTO>, OrderItemDTO> for OrderItemsService {
+    async fn create_many(
+        transaction: &mut Transaction<'_>,
+        statements: &mut StatementCache,
+        body: &Vec<OrderItemDTO>,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<OrderItemDTO>, AppError> {
+        // Элементы передаются массивами по колонкам, поэтому текст запроса
+        // не зависит от количества items и выражение готовится один раз
+        let create_items_stmt = statements
+            .prepare(
+                transaction,
+                "INSERT INTO items (order_uid, date_created,
+                    chrt_id, track_number, price,
+                    rid, name, sale, size, total_price,
+                    nm_id, brand, status
+                ) SELECT $1, $13, * FROM UNNEST(
+                    $2::BIGINT[], $3::VARCHAR[], $4::BIGINT[],
+                    $5::VARCHAR[], $6::VARCHAR[], $7::INTEGER[], $8::VARCHAR[], $9::BIGINT[],
+                    $10::BIGINT[], $11::VARCHAR[], $12::INTEGER[]
+                ) RETURNING
+                    chrt_id, track_number, price,
+                    rid, name, sale, size, total_price,
+                    nm_id, brand, status",
+            )
+            .await?;
+
+        let chrt_ids: Vec<i64> = body.iter().map(|item| item.chrt_id).collect();
+        let track_numbers: Vec<&str> = body.iter().map(|item| item.track_number.as_str()).collect();
+        let prices: Vec<i64> = body.iter().map(|item| item.price.amount.0).collect();
+        let rids: Vec<&str> = body.iter().map(|item| item.rid.as_str()).collect();
+        let names: Vec<&str> = body.iter().map(|item| item.name.as_str()).collect();
+        let sales: Vec<i32> = body.iter().map(|item| item.sale).collect();
+        let sizes: Vec<&str> = body.iter().map(|item| item.size.as_str()).collect();
+        let total_prices: Vec<i64> = body.iter().map(|item| item.total_price.amount.0).collect();
+        let nm_ids: Vec<i64> = body.iter().map(|item| item.nm_id).collect();
+        let brands: Vec<&str> = body.iter().map(|item| item.brand.as_str()).collect();
+        let statuses: Vec<i32> = body.iter().map(|item| item.status).collect();
+
+        let rows = transaction
+            .query(
+                &create_items_stmt,
+                &[
+                    params[0],
+                    &chrt_ids,
+                    &track_numbers,
+                    &prices,
+                    &rids,
+                    &names,
+                    &sales,
+                    &sizes,
+                    &total_prices,
+                    &nm_ids,
+                    &brands,
+                    &statuses,
+                    params[1],
+                ],
+            )
+            .await?;
+        // UNNEST возвращает строки в порядке массивов, валюта берётся у товаров запроса
+        let order_items: Vec<OrderItemDTO> = rows
+            .iter()
+            .zip(body)
+            .map(|(row, item)| OrderItemDTO::from_row(row, &item.price.currency))
+            .collect();
+
+        Ok(order_items)
+    }
+}
+
+struct DeliveryService();
+impl GetOneById for DeliveryService {
+    async fn get_one_by_id(
+        db: &mut Database,
+        id: &str,
+    ) -> Result<Option<tokio_postgres::Row>, PostgresError> {
+        let get_delivery_stmt = db
+            .statements
+            .prepare(&db.client, GET_DELIVERY_QUERY)
+            .await?;
+
+        db.client.query_opt(&get_delivery_stmt, &[&id]).await
+    }
+}
+impl CreateOne<DeliveryDTO, DeliveryDTO> for DeliveryService {
+    async fn create_one(
+        transaction: &mut Transaction<'_>,
+        statements: &mut StatementCache,
+        body: &DeliveryDTO,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<DeliveryDTO, AppError> {
+        let create_delivery_stmt = statements
+            .prepare(
+                transaction,
+                "INSERT INTO delivery (
+              order_uid, name, phone,
+              zip, city, address,
+              region, email, date_created
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING
+              name, phone,
+              zip, city, address,
+              region, email",
+            )
+            .await?;
+
+        let create_delivery_row = transaction
+            .query_one(
+                &create_delivery_stmt,
+                &[
+                    params[0],
+                    &body.name,
+                    &body.phone,
+                    &body.zip,
+                    &body.city,
+                    &body.address,
+                    &body.region,
+                    &body.email,
+                    params[1],
+                ],
+            )
+            .await?;
+
+        Ok(DeliveryDTO::from(create_delivery_row))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use chrono::DateTime;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::db;
+
+    pub async fn repository() -> Option<PostgresOrderRepository> {
+        let (connection_string, tls) = db::tests::connection()?;
+        let repository = PostgresOrderRepository::connect(
+            "test",
+            connection_string,
+            tls,
+            ReplicaSet::new(Vec::new(), Duration::ZERO),
+            Duration::ZERO,
+        )
+        .await
+        .unwrap();
+
+        Some(repository)
+    }
+
+    #[tokio::test]
+    async fn stored_order_matches_created() {
+        let Some(repository) = repository().await else {
+            return;
+        };
+
+        let mut body: CreateOrderDTO =
+            serde_json::from_str(include_str!("../test/stubs/order.json")).unwrap();
+        let order_uid = format!("test-{}", Uuid::new_v4().simple());
+        body.order_uid = Some(order_uid.clone());
+        body.payment.payment_dt = DateTime::parse_from_rfc3339("2021-11-26T06:22:19.123456789Z")
+            .unwrap()
+            .to_utc();
+
+        let created = match repository.create_order(&body, None).await.unwrap() {
+            CreateOrderOutcome::Created(order) => order,
+            CreateOrderOutcome::Exists(_) => panic!("Order {order_uid} already exists"),
+        };
+        let stored = repository.get_order(&order_uid).await.unwrap().unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&created).unwrap(),
+            serde_json::to_value(&stored).unwrap()
+        );
+        assert!(stored.matches(&body));
+    }
+
+    #[tokio::test]
+    async fn delivers_events_to_own_delivery_service() {
+        let Some(repository) = repository().await else {
+            return;
+        };
+
+        let delivery_service = format!("service-{}", Uuid::new_v4().simple());
+        let subscribe = |delivery_service: String| NewWebhook {
+            delivery_service,
+            url: "http://localhost/hooks".to_string(),
+            event_types: vec![ORDER_CREATED_EVENT.to_string()],
+            secret: "0123456789abcdef".to_string(),
+        };
+        let own = repository
+            .create_webhook(&subscribe(delivery_service.clone()))
+            .await
+            .unwrap();
+        let other = repository
+            .create_webhook(&subscribe(format!("other-{delivery_service}")))
+            .await
+            .unwrap();
+        assert_eq!(own.delivery_service, delivery_service);
+
+        let mut body: CreateOrderDTO =
+            serde_json::from_str(include_str!("../test/stubs/order.json")).unwrap();
+        body.order_uid = Some(format!("test-{}", Uuid::new_v4().simple()));
+        body.delivery_service = delivery_service;
+        repository.create_order(&body, None).await.unwrap();
+
+        let deliveries = |subscription_id| WebhookDeliveryListQuery {
+            subscription_id,
+            status: None,
+            limit: 10,
+            offset: 0,
+        };
+        let own_deliveries = repository
+            .list_webhook_deliveries(&deliveries(own.subscription_id))
+            .await
+            .unwrap();
+        let other_deliveries = repository
+            .list_webhook_deliveries(&deliveries(other.subscription_id))
+            .await
+            .unwrap();
+        repository
+            .delete_webhook(own.subscription_id)
+            .await
+            .unwrap();
+        repository
+            .delete_webhook(other.subscription_id)
+            .await
+            .unwrap();
+
+        assert_eq!(own_deliveries.len(), 1);
+        assert_eq!(Some(&own_deliveries[0].order_uid), body.order_uid.as_ref());
+        assert!(other_deliveries.is_empty());
+    }
+}
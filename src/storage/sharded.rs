@@ -0,0 +1,505 @@
+use std::{collections::HashMap, path::Path, time::Duration};
+
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use chrono::NaiveDate;
+use futures::future::try_join_all;
+use log::{error, info};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    dead_letters::{DeadLetter, DeadLetterCount},
+    errors::AppError,
+    idempotency::{self, IdempotencyClaim},
+    integrity::IntegrityIssue,
+    migrate::Migration,
+    outbox::{EventSink, OutboxEvent},
+    parquet_export::ParquetFile,
+    query_plans::QueryPlan,
+    routes::MAX_LIST_OFFSET,
+    schema::{CreateOrderDTO, GetOrderDTO},
+    webhooks::{DeliveryAttempt, NewWebhook, PendingDelivery, Webhook, WebhookDelivery},
+};
+
+use super::{
+    postgres::PostgresOrderRepository, CreateOrderOutcome, DeadLetterListQuery, OrderListQuery,
+    OrderRepository, TransactionSnapshot, WebhookDeliveryListQuery,
+};
+
+// Хранилище заказов, распределённое по нескольким базам PostgreSQL.
+// Шард выбирается по shardkey заказа. Справочник order_uid -> шард и ключи
+// идемпотентности хранятся в основной базе (POSTGRES_*), поэтому заказ
+// находится по order_uid без опроса всех шардов. Там же хранятся dead_letters.
+// Номер шарда — позиция в списке POSTGRES_SHARDS, новые шарды добавляются только в конец
+pub struct ShardedOrderRepository {
+    directory: PostgresOrderRepository,
+    shards: Vec<PostgresOrderRepository>,
+}
+
+impl ShardedOrderRepository {
+    pub fn new(directory: PostgresOrderRepository, shards: Vec<PostgresOrderRepository>) -> Self {
+        ShardedOrderRepository { directory, shards }
+    }
+
+    fn shard(&self, index: usize) -> Result<&PostgresOrderRepository, AppError> {
+        self.shards
+            .get(index)
+            .ok_or(AppError::ShardNotFoundError(index))
+    }
+}
+
+// Номер шарда для shardkey: числовой ключ берётся по модулю числа шардов,
+// остальные хешируются
+pub fn shard_index(shardkey: &str, shard_count: usize) -> usize {
+    let key = match shardkey.trim().parse::<u64>() {
+        Ok(key) => key,
+        Err(_) => {
+            let digest = Sha256::digest(shardkey.as_bytes());
+            u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes long"))
+        }
+    };
+
+    (key % shard_count as u64) as usize
+}
+
+#[async_trait]
+impl OrderRepository for ShardedOrderRepository {
+    async fn create_order(
+        &self,
+        body: &CreateOrderDTO,
+        // Ключ идемпотентности хранится в основной базе, а не в шарде,
+        // ответ для него сохраняет обработчик после создания заказа
+        idempotency_key: Option<&str>,
+    ) -> Result<CreateOrderOutcome, AppError> {
+        // Идентификатор нужен до записи в шард, чтобы закрепить заказ в справочнике.
+        // С ключом идемпотентности он выводится из ключа: если ответ не удалось
+        // сохранить, повтор после истечения ключа найдёт уже созданный заказ
+        let mut body = body.clone();
+        let order_uid = body
+            .order_uid
+            .get_or_insert_with(|| idempotency::new_order_uid(idempotency_key))
+            .clone();
+
+        // Повтор создания уходит в шард, за которым заказ уже закреплён
+        let shard = self
+            .directory
+            .assign_shard(&order_uid, shard_index(&body.shardkey, self.shards.len()))
+            .await?;
+        let created = self.shard(shard)?.create_order(&body, None).await;
+
+        // Запись справочника без заказа в шарде удаляется, иначе order_uid
+        // остаётся закреплённым за шардом, которого мог не выбрать повтор.
+        // Если шард недоступен и для проверки, запись остаётся, get_order
+        // для неё вернёт отсутствие заказа
+        if created.is_err() && matches!(self.shard(shard)?.get_order(&order_uid).await, Ok(None)) {
+            if let Err(err) = self.directory.unassign_shard(&order_uid, shard).await {
+                error!("Failed to remove order {order_uid} from shard directory: {err}");
+            }
+        }
+
+        created
+    }
+
+    async fn get_order(&self, order_uid: &str) -> Result<Option<GetOrderDTO>, AppError> {
+        match self.directory.find_shard(order_uid).await? {
+            Some(shard) => self.shard(shard)?.get_order(order_uid).await,
+            None => Ok(None),
+        }
+    }
+
+    // Заказы группируются по шардам из справочника, шарды читаются параллельно
+    async fn get_orders(&self, order_uids: &[String]) -> Result<Vec<GetOrderDTO>, AppError> {
+        let shards = self.directory.find_shards(order_uids).await?;
+
+        let mut shard_order_uids: HashMap<usize, Vec<String>> = HashMap::new();
+        for (order_uid, shard) in shards {
+            shard_order_uids.entry(shard).or_default().push(order_uid);
+        }
+
+        let mut loaded: HashMap<String, GetOrderDTO> = HashMap::new();
+        for shard_orders in
+            try_join_all(shard_order_uids.iter().map(|(shard, order_uids)| async {
+                self.shard(*shard)?.get_orders(order_uids).await
+            }))
+            .await?
+        {
+            loaded.extend(
+                shard_orders
+                    .into_iter()
+                    .map(|order| (order.order_uid.clone(), order)),
+            );
+        }
+
+        Ok(order_uids
+            .iter()
+            .filter_map(|order_uid| loaded.remove(order_uid))
+            .collect())
+    }
+
+    // Список собирается со всех шардов параллельно и сливается по дате создания.
+    // Каждый шард отдаёт offset + limit заказов, поэтому offset ограничен,
+    // а полный обход списка идёт по позиции after
+    async fn list_orders(&self, query: &OrderListQuery) -> Result<Vec<GetOrderDTO>, AppError> {
+        if query.offset > MAX_LIST_OFFSET {
+            return Err(AppError::UnsupportedError(format!(
+                "offset above {MAX_LIST_OFFSET} across shards, use after instead"
+            )));
+        }
+
+        let shard_query = OrderListQuery {
+            customer_id: query.customer_id.clone(),
+            limit: query.offset + query.limit,
+            offset: 0,
+            after: query.after.clone(),
+            created_from: query.created_from,
+            created_before: query.created_before,
+        };
+
+        let pages = try_join_all(
+            self.shards
+                .iter()
+                .map(|shard| shard.list_orders(&shard_query)),
+        )
+        .await?;
+
+        let mut orders: Vec<GetOrderDTO> = pages.into_iter().flatten().collect();
+        orders.sort_by(|a, b| {
+            b.date_created
+                .cmp(&a.date_created)
+                .then_with(|| a.order_uid.cmp(&b.order_uid))
+        });
+
+        Ok(orders
+            .into_iter()
+            .skip(query.offset as usize)
+            .take(query.limit as usize)
+            .collect())
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        key: &str,
+        request_hash: &str,
+        ttl: Duration,
+    ) -> Result<IdempotencyClaim, AppError> {
+        self.directory
+            .claim_idempotency_key(key, request_hash, ttl)
+            .await
+    }
+
+    async fn complete_idempotency_key(
+        &self,
+        key: &str,
+        status: StatusCode,
+        body: &serde_json::Value,
+    ) -> Result<(), AppError> {
+        self.directory
+            .complete_idempotency_key(key, status, body)
+            .await
+    }
+
+    async fn release_idempotency_key(&self, key: &str) -> Result<(), AppError> {
+        self.directory.release_idempotency_key(key).await
+    }
+
+    async fn cleanup_expired_idempotency_keys(&self) -> Result<u64, AppError> {
+        self.directory.cleanup_expired_idempotency_keys().await
+    }
+
+    async fn migrate(&self, migration: Migration) -> Result<(), AppError> {
+        self.directory.migrate(migration.clone()).await?;
+        for (index, shard) in self.shards.iter().enumerate() {
+            info!("Migrating shard {index}");
+            shard.migrate(migration.clone()).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn create_partitions(&self) -> Result<(), AppError> {
+        for shard in &self.shards {
+            shard.create_partitions().await?;
+        }
+
+        Ok(())
+    }
+
+    // Архив каждого шарда хранится в своём подкаталоге
+    async fn archive_orders(
+        &self,
+        before: NaiveDate,
+        archive_dir: &Path,
+    ) -> Result<Vec<NaiveDate>, AppError> {
+        let mut archived = Vec::new();
+        for (index, shard) in self.shards.iter().enumerate() {
+            let shard_dir = archive_dir.join(format!("shard-{index}"));
+            archived.extend(shard.archive_orders(before, &shard_dir).await?);
+        }
+        archived.sort();
+        archived.dedup();
+
+        Ok(archived)
+    }
+
+    async fn restore_orders(&self, month: NaiveDate, archive_dir: &Path) -> Result<(), AppError> {
+        for (index, shard) in self.shards.iter().enumerate() {
+            let shard_dir = archive_dir.join(format!("shard-{index}"));
+            shard.restore_orders(month, &shard_dir).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn export_parquet(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+        output_dir: &Path,
+    ) -> Result<Vec<ParquetFile>, AppError> {
+        let mut files = Vec::new();
+        for (index, shard) in self.shards.iter().enumerate() {
+            let shard_dir = output_dir.join(format!("shard-{index}"));
+            files.extend(shard.export_parquet(from, to, &shard_dir).await?);
+        }
+
+        Ok(files)
+    }
+
+    // Справочник проверяется вместе с шардами: в нём ищутся шард заказа
+    // и ключи идемпотентности
+    async fn check_query_plans(&self, seed_rows: u32) -> Result<Vec<QueryPlan>, AppError> {
+        let mut plans = self.directory.check_query_plans(seed_rows).await?;
+        for (index, shard) in self.shards.iter().enumerate() {
+            for mut plan in shard.check_query_plans(seed_rows).await? {
+                plan.name = format!("shard {index}: {}", plan.name);
+                plans.push(plan);
+            }
+        }
+
+        Ok(plans)
+    }
+
+    async fn check_integrity(&self) -> Result<Vec<IntegrityIssue>, AppError> {
+        let mut issues = Vec::new();
+        for (index, shard) in self.shards.iter().enumerate() {
+            for mut issue in shard.check_integrity().await? {
+                issue.check = format!("shard {index}: {}", issue.check);
+                issues.push(issue);
+            }
+        }
+
+        Ok(issues)
+    }
+
+    // Событие пишется в шард вместе с заказом, поэтому outbox у каждого шарда свой
+    async fn relay_outbox(&self, sink: &dyn EventSink, batch_size: u32) -> Result<usize, AppError> {
+        let mut delivered = 0;
+        for shard in &self.shards {
+            delivered += shard.relay_outbox(sink, batch_size).await?;
+        }
+
+        Ok(delivered)
+    }
+
+    async fn cleanup_delivered_events(&self, older_than: Duration) -> Result<u64, AppError> {
+        let mut removed = 0;
+        for shard in &self.shards {
+            removed += shard.cleanup_delivered_events(older_than).await?;
+        }
+
+        Ok(removed)
+    }
+
+    // Номера событий у каждого шарда свои, общего порядка для потока нет:
+    // один Last-Event-ID не задаёт место продолжения на всех шардах.
+    // Поток событий с шардами выключен, события доходят до клиентов через relay
+    async fn events_after(
+        &self,
+        _after_id: i64,
+        _limit: u32,
+    ) -> Result<Vec<OutboxEvent>, AppError> {
+        Err(AppError::UnsupportedError("order event stream".to_string()))
+    }
+
+    async fn last_event_id(&self) -> Result<i64, AppError> {
+        Err(AppError::UnsupportedError("order event stream".to_string()))
+    }
+
+    async fn transaction_snapshot(&self) -> Result<Option<TransactionSnapshot>, AppError> {
+        Err(AppError::UnsupportedError("order event stream".to_string()))
+    }
+
+    async fn add_dead_letter(
+        &self,
+        source: &str,
+        reference: Option<&str>,
+        payload: &str,
+        error: &str,
+    ) -> Result<i64, AppError> {
+        self.directory
+            .add_dead_letter(source, reference, payload, error)
+            .await
+    }
+
+    async fn list_dead_letters(
+        &self,
+        query: &DeadLetterListQuery,
+    ) -> Result<Vec<DeadLetter>, AppError> {
+        self.directory.list_dead_letters(query).await
+    }
+
+    async fn get_dead_letter(&self, id: i64) -> Result<Option<DeadLetter>, AppError> {
+        self.directory.get_dead_letter(id).await
+    }
+
+    async fn complete_dead_letter_replay(
+        &self,
+        id: i64,
+        error: Option<&str>,
+    ) -> Result<(), AppError> {
+        self.directory.complete_dead_letter_replay(id, error).await
+    }
+
+    async fn count_dead_letters(&self) -> Result<Vec<DeadLetterCount>, AppError> {
+        self.directory.count_dead_letters().await
+    }
+
+    // Доставки создаются в транзакции заказа, а она идёт в шарде,
+    // где подписок нет. С шардами сервис запускается только с WEBHOOKS_ENABLED=false
+    async fn create_webhook(&self, _webhook: &NewWebhook) -> Result<Webhook, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn list_webhooks(&self) -> Result<Vec<Webhook>, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn get_webhook(&self, _id: i64) -> Result<Option<Webhook>, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn delete_webhook(&self, _id: i64) -> Result<bool, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn list_webhook_deliveries(
+        &self,
+        _query: &WebhookDeliveryListQuery,
+    ) -> Result<Vec<WebhookDelivery>, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn redeliver_webhook(
+        &self,
+        _subscription_id: i64,
+        _delivery_id: i64,
+    ) -> Result<Option<WebhookDelivery>, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn claim_webhook_deliveries(
+        &self,
+        _limit: u32,
+        _lease: Duration,
+    ) -> Result<Vec<PendingDelivery>, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn complete_webhook_delivery(
+        &self,
+        _delivery_id: i64,
+        _attempt: &DeliveryAttempt,
+    ) -> Result<(), AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn cleanup_webhook_deliveries(&self, _older_than: Duration) -> Result<u64, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::storage::postgres;
+
+    // Справочник и единственный шард в одной тестовой базе
+    async fn repository() -> Option<ShardedOrderRepository> {
+        let directory = postgres::tests::repository().await?;
+        let shard = postgres::tests::repository().await?;
+        Some(ShardedOrderRepository::new(directory, vec![shard]))
+    }
+
+    fn order(order_uid: &str) -> CreateOrderDTO {
+        let mut order: CreateOrderDTO =
+            serde_json::from_str(include_str!("../test/stubs/order.json")).unwrap();
+        order.order_uid = Some(order_uid.to_string());
+        order
+    }
+
+    #[tokio::test]
+    async fn removes_directory_entry_when_shard_write_fails() {
+        let Some(repository) = repository().await else {
+            return;
+        };
+        let order_uid = format!("sharded-{}", Uuid::new_v4());
+
+        // Нулевой байт PostgreSQL не принимает в текстовых полях
+        let mut rejected = order(&order_uid);
+        rejected.delivery.name = "Test\0Testov".to_string();
+        assert!(repository.create_order(&rejected, None).await.is_err());
+        assert_eq!(
+            repository.directory.find_shard(&order_uid).await.unwrap(),
+            None
+        );
+
+        let created = repository.create_order(&order(&order_uid), None).await;
+        assert!(matches!(created, Ok(CreateOrderOutcome::Created(_))));
+        assert_eq!(
+            repository.directory.find_shard(&order_uid).await.unwrap(),
+            Some(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn derives_order_uid_from_idempotency_key() {
+        let Some(repository) = repository().await else {
+            return;
+        };
+        let key = format!("sharded-key-{}", Uuid::new_v4());
+        let mut body = order("");
+        body.order_uid = None;
+
+        let Ok(CreateOrderOutcome::Created(created)) =
+            repository.create_order(&body, Some(&key)).await
+        else {
+            panic!("Order is not created");
+        };
+        assert_eq!(created.order_uid, idempotency::new_order_uid(Some(&key)));
+        assert!(matches!(
+            repository.create_order(&body, Some(&key)).await,
+            Ok(CreateOrderOutcome::Exists(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_large_offset() {
+        let Some(repository) = repository().await else {
+            return;
+        };
+        let query = OrderListQuery {
+            customer_id: None,
+            limit: 1,
+            offset: MAX_LIST_OFFSET + 1,
+            after: None,
+            created_from: None,
+            created_before: None,
+        };
+        assert!(matches!(
+            repository.list_orders(&query).await,
+            Err(AppError::UnsupportedError(_))
+        ));
+    }
+}
@@ -0,0 +1,965 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use chrono::{NaiveDate, SubsecRound, Utc};
+use log::info;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    dead_letters::{DeadLetter, DeadLetterCount},
+    errors::AppError,
+    idempotency::{self, IdempotencyClaim},
+    integrity::{self, IntegrityIssue},
+    migrate::{self, Migration},
+    money::{Currency, MinorUnits, Money},
+    outbox::{self, EventSink, OutboxEvent, ORDER_CREATED_EVENT},
+    parquet_export::ParquetFile,
+    query_plans::QueryPlan,
+    schema::{CreateOrderDTO, DeliveryDTO, GetOrderDTO, Order, OrderItemDTO, PaymentDTO},
+    webhooks::{DeliveryAttempt, NewWebhook, PendingDelivery, Webhook, WebhookDelivery},
+};
+
+use super::{
+    CreateOrderOutcome, DeadLetterListQuery, OrderListQuery, OrderRepository, TransactionSnapshot,
+    WebhookDeliveryListQuery,
+};
+
+// Миграции SQLite в порядке применения
+const UP_MIGRATIONS: &[&str] = &[
+    "sqlite/init_migration.sql",
+    "sqlite/002_indexes.sql",
+    "sqlite/003_one_to_one.sql",
+    "sqlite/004_outbox.sql",
+    "sqlite/005_dead_letters.sql",
+];
+
+const DOWN_MIGRATION: &str = "sqlite/down_migration.sql";
+
+const DEAD_LETTER_COLUMNS: &str = "dead_letter_id, source, reference, payload, error,
+    created_at, replayed_at, replay_error";
+
+// Хранилище заказов в SQLite для локальной разработки и небольших инсталляций.
+// rusqlite синхронный, поэтому запросы выполняются в пуле блокирующих задач tokio
+pub struct SqliteOrderRepository {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteOrderRepository {
+    pub fn open(path: &str) -> Result<Self, AppError> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA foreign_keys = ON;
+             PRAGMA journal_mode = WAL;",
+        )?;
+
+        Ok(SqliteOrderRepository {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    async fn with_connection<T, F>(&self, f: F) -> Result<T, AppError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, AppError> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap();
+            f(&mut conn)
+        })
+        .await
+        .map_err(|err| AppError::IOError(err.into()))?
+    }
+}
+
+#[async_trait]
+impl OrderRepository for SqliteOrderRepository {
+    async fn create_order(
+        &self,
+        body: &CreateOrderDTO,
+        idempotency_key: Option<&str>,
+    ) -> Result<CreateOrderOutcome, AppError> {
+        let body = body.clone();
+        let idempotency_key = idempotency_key.map(str::to_string);
+
+        self.with_connection(move |conn| {
+            let transaction = conn.transaction()?;
+
+            let order_uid = body
+                .order_uid
+                .clone()
+                .unwrap_or_else(|| Uuid::new_v4().to_string());
+            let date_created = Utc::now().trunc_subsecs(6);
+
+            // Создание order
+            let inserted = transaction
+                .prepare_cached(
+                    "INSERT INTO orders (
+                        order_uid, track_number, entry, locale,
+                        internal_signature, customer_id, delivery_service,
+                        shardkey, sm_id, date_created, oof_shard
+                    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+                    ON CONFLICT (order_uid) DO NOTHING",
+                )?
+                .execute(params![
+                    order_uid,
+                    body.track_number,
+                    body.entry,
+                    body.locale,
+                    body.internal_signature,
+                    body.customer_id,
+                    body.delivery_service,
+                    body.shardkey,
+                    body.sm_id,
+                    date_created,
+                    body.oof_shard,
+                ])?;
+
+            // Заказ с таким order_uid уже создан: повтор запроса или конфликт
+            if inserted == 0 {
+                return match load_order(&transaction, &order_uid)? {
+                    Some(order) => Ok(CreateOrderOutcome::Exists(order)),
+                    None => Err(AppError::OrderExistsError(order_uid)),
+                };
+            }
+
+            // Создание delivery
+            let delivery = &body.delivery;
+            transaction
+                .prepare_cached(
+                    "INSERT INTO delivery (
+                        order_uid, name, phone, zip, city, address, region, email
+                    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                )?
+                .execute(params![
+                    order_uid,
+                    delivery.name,
+                    delivery.phone,
+                    delivery.zip,
+                    delivery.city,
+                    delivery.address,
+                    delivery.region,
+                    delivery.email,
+                ])?;
+
+            // Создание payment
+            let payment = &body.payment;
+            transaction
+                .prepare_cached(
+                    "INSERT INTO payment (
+                        order_uid, \"transaction\", request_id, currency, provider, amount,
+                        payment_dt, bank, delivery_cost, goods_total, custom_fee
+                    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                )?
+                .execute(params![
+                    order_uid,
+                    payment.transaction,
+                    payment.request_id,
+                    payment.currency().as_str(),
+                    payment.provider,
+                    payment.amount.amount.0,
+                    payment.payment_dt,
+                    payment.bank,
+                    payment.delivery_cost.amount.0,
+                    payment.goods_total.amount.0,
+                    payment.custom_fee.amount.0,
+                ])?;
+
+            // Создание items
+            {
+                let mut create_item_stmt = transaction.prepare_cached(
+                    "INSERT INTO items (
+                        order_uid, chrt_id, track_number, price, rid, name,
+                        sale, size, total_price, nm_id, brand, status
+                    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                )?;
+                for item in &body.items {
+                    create_item_stmt.execute(params![
+                        order_uid,
+                        item.chrt_id,
+                        item.track_number,
+                        item.price.amount.0,
+                        item.rid,
+                        item.name,
+                        item.sale,
+                        item.size,
+                        item.total_price.amount.0,
+                        item.nm_id,
+                        item.brand,
+                        item.status,
+                    ])?;
+                }
+            }
+
+            // Ответ сохраняется в той же транзакции, что и заказ. Ключ, который
+            // уже не занят этим запросом, отменяет создание заказа
+            if let Some(key) = &idempotency_key {
+                let (status, response) = idempotency::created_response(&order_uid);
+                if complete_idempotency_key(&transaction, key, status, &response)? == 0 {
+                    return Err(AppError::IdempotencyKeyError(key.clone()));
+                }
+            }
+
+            let created = GetOrderDTO {
+                order_uid,
+                track_number: body.track_number,
+                entry: body.entry,
+                delivery: body.delivery,
+                payment: body.payment,
+                items: body.items,
+                locale: body.locale,
+                internal_signature: body.internal_signature,
+                customer_id: body.customer_id,
+                delivery_service: body.delivery_service,
+                sm_id: body.sm_id,
+                date_created,
+                shardkey: body.shardkey,
+                oof_shard: body.oof_shard,
+            };
+
+            // Событие о заказе попадает в outbox только вместе с самим заказом
+            transaction
+                .prepare_cached(
+                    "INSERT INTO outbox (order_uid, event_type, payload, created_at)
+                     VALUES (?1, ?2, ?3, ?4)",
+                )?
+                .execute(params![
+                    created.order_uid,
+                    ORDER_CREATED_EVENT,
+                    json!(created),
+                    date_created,
+                ])?;
+
+            transaction.commit()?;
+
+            Ok(CreateOrderOutcome::Created(created))
+        })
+        .await
+    }
+
+    async fn get_order(&self, order_uid: &str) -> Result<Option<GetOrderDTO>, AppError> {
+        let order_uid = order_uid.to_string();
+        self.with_connection(move |conn| load_order(conn, &order_uid))
+            .await
+    }
+
+    // Файл базы локальный, поэтому заказы читаются по одному
+    async fn get_orders(&self, order_uids: &[String]) -> Result<Vec<GetOrderDTO>, AppError> {
+        let order_uids = order_uids.to_vec();
+        self.with_connection(move |conn| {
+            let mut orders = Vec::with_capacity(order_uids.len());
+            for order_uid in &order_uids {
+                if let Some(order) = load_order(conn, order_uid)? {
+                    orders.push(order);
+                }
+            }
+            Ok(orders)
+        })
+        .await
+    }
+
+    async fn list_orders(&self, query: &OrderListQuery) -> Result<Vec<GetOrderDTO>, AppError> {
+        let query = query.clone();
+        self.with_connection(move |conn| {
+            let order_uids = conn
+                .prepare_cached(
+                    "SELECT order_uid FROM orders
+                     WHERE (?1 IS NULL OR customer_id = ?1)
+                       AND (?4 IS NULL OR date_created < ?4
+                            OR (date_created = ?4 AND order_uid > ?5))
+                       AND (?6 IS NULL OR date_created >= ?6)
+                       AND (?7 IS NULL OR date_created < ?7)
+                     ORDER BY date_created DESC, order_uid
+                     LIMIT ?2 OFFSET ?3",
+                )?
+                .query_map(
+                    params![
+                        query.customer_id,
+                        query.limit,
+                        query.offset,
+                        query.after.as_ref().map(|after| after.date_created),
+                        query.after.as_ref().map(|after| &after.order_uid),
+                        query.created_from,
+                        query.created_before,
+                    ],
+                    |row| row.get::<_, String>(0),
+                )?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut orders = Vec::with_capacity(order_uids.len());
+            for order_uid in order_uids {
+                if let Some(order) = load_order(conn, &order_uid)? {
+                    orders.push(order);
+                }
+            }
+
+            Ok(orders)
+        })
+        .await
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        key: &str,
+        request_hash: &str,
+        ttl: Duration,
+    ) -> Result<IdempotencyClaim, AppError> {
+        let key = key.to_string();
+        let request_hash = request_hash.to_string();
+
+        self.with_connection(move |conn| {
+            let now = Utc::now();
+            let expires_at = now.timestamp() + ttl.as_secs() as i64;
+
+            // Истёкшая запись перезаписывается, живая остаётся нетронутой
+            let claimed = conn
+                .prepare_cached(
+                    "INSERT INTO idempotency_keys (
+                        idempotency_key, request_hash, created_at, expires_at
+                    ) VALUES (?1, ?2, ?3, ?4)
+                    ON CONFLICT (idempotency_key) DO UPDATE SET
+                        request_hash = excluded.request_hash,
+                        response_status = NULL,
+                        response_body = NULL,
+                        created_at = excluded.created_at,
+                        expires_at = excluded.expires_at
+                    WHERE idempotency_keys.expires_at < ?5",
+                )?
+                .execute(params![key, request_hash, now, expires_at, now.timestamp()])?;
+            if claimed > 0 {
+                return Ok(IdempotencyClaim::Claimed);
+            }
+
+            let stored = conn
+                .prepare_cached(
+                    "SELECT request_hash, response_status, response_body
+                     FROM idempotency_keys WHERE idempotency_key = ?1",
+                )?
+                .query_row(params![key], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Option<u16>>(1)?,
+                        row.get::<_, Option<serde_json::Value>>(2)?,
+                    ))
+                })
+                .optional()?;
+
+            Ok(match stored {
+                None => IdempotencyClaim::InProgress,
+                Some((stored_hash, _, _)) if stored_hash != request_hash => {
+                    IdempotencyClaim::Mismatch
+                }
+                Some((_, Some(status), Some(response))) => IdempotencyClaim::Replay(
+                    StatusCode::from_u16(status).unwrap_or(StatusCode::OK),
+                    response,
+                ),
+                Some(_) => IdempotencyClaim::InProgress,
+            })
+        })
+        .await
+    }
+
+    async fn complete_idempotency_key(
+        &self,
+        key: &str,
+        status: StatusCode,
+        body: &serde_json::Value,
+    ) -> Result<(), AppError> {
+        let key = key.to_string();
+        let body = body.clone();
+        self.with_connection(move |conn| {
+            complete_idempotency_key(conn, &key, status, &body)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn release_idempotency_key(&self, key: &str) -> Result<(), AppError> {
+        let key = key.to_string();
+        self.with_connection(move |conn| {
+            conn.prepare_cached(
+                "DELETE FROM idempotency_keys
+                 WHERE idempotency_key = ?1 AND response_status IS NULL",
+            )?
+            .execute(params![key])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn cleanup_expired_idempotency_keys(&self) -> Result<u64, AppError> {
+        self.with_connection(|conn| {
+            let removed = conn.execute(
+                "DELETE FROM idempotency_keys WHERE expires_at < ?1",
+                params![Utc::now().timestamp()],
+            )?;
+            Ok(removed as u64)
+        })
+        .await
+    }
+
+    async fn migrate(&self, migration: Migration) -> Result<(), AppError> {
+        self.with_connection(move |conn| migrate_sqlite(conn, migration))
+            .await?;
+
+        info!("Succefully migrated!");
+
+        Ok(())
+    }
+
+    // Секционирования в SQLite нет, архивировать нечего
+    async fn create_partitions(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    async fn archive_orders(
+        &self,
+        _before: NaiveDate,
+        _archive_dir: &Path,
+    ) -> Result<Vec<NaiveDate>, AppError> {
+        Ok(Vec::new())
+    }
+
+    async fn restore_orders(&self, _month: NaiveDate, _archive_dir: &Path) -> Result<(), AppError> {
+        Err(AppError::UnsupportedError("restore orders".to_string()))
+    }
+
+    async fn export_parquet(
+        &self,
+        _from: NaiveDate,
+        _to: NaiveDate,
+        _output_dir: &Path,
+    ) -> Result<Vec<ParquetFile>, AppError> {
+        Err(AppError::UnsupportedError("export parquet".to_string()))
+    }
+
+    async fn check_query_plans(&self, _seed_rows: u32) -> Result<Vec<QueryPlan>, AppError> {
+        Err(AppError::UnsupportedError("check query plans".to_string()))
+    }
+
+    async fn check_integrity(&self) -> Result<Vec<IntegrityIssue>, AppError> {
+        self.with_connection(|conn| {
+            let mut issues = Vec::new();
+
+            for (check, query) in integrity::CHECKS {
+                let count: i64 =
+                    conn.query_row(&integrity::count_query(query), [], |row| row.get(0))?;
+                if count == 0 {
+                    continue;
+                }
+
+                let examples = conn
+                    .prepare(&integrity::examples_query(query))?
+                    .query_map([], |row| row.get::<_, String>("order_uid"))?
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                issues.push(IntegrityIssue {
+                    check: check.to_string(),
+                    count,
+                    examples,
+                });
+            }
+
+            Ok(issues)
+        })
+        .await
+    }
+
+    async fn relay_outbox(&self, sink: &dyn EventSink, batch_size: u32) -> Result<usize, AppError> {
+        let events = self
+            .with_connection(move |conn| {
+                Ok(conn
+                    .prepare_cached(
+                        "SELECT event_id, order_uid, event_type, payload, created_at
+                         FROM outbox WHERE delivered_at IS NULL
+                         ORDER BY event_id
+                         LIMIT ?1",
+                    )?
+                    .query_map(params![batch_size], |row| {
+                        Ok(OutboxEvent {
+                            event_id: row.get("event_id")?,
+                            order_uid: row.get("order_uid")?,
+                            event_type: row.get("event_type")?,
+                            payload: row.get("payload")?,
+                            created_at: row.get("created_at")?,
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?)
+            })
+            .await?;
+        if events.is_empty() {
+            return Ok(0);
+        }
+
+        let results = outbox::publish_in_order(sink, &events).await;
+
+        self.with_connection(move |conn| {
+            let transaction = conn.transaction()?;
+            let mut delivered = 0;
+            for (event_id, result) in &results {
+                match result {
+                    Ok(()) => {
+                        transaction
+                            .prepare_cached(
+                                "UPDATE outbox SET delivered_at = ?2,
+                                    attempts = attempts + 1, last_error = NULL
+                                 WHERE event_id = ?1",
+                            )?
+                            .execute(params![event_id, Utc::now().timestamp()])?;
+                        delivered += 1;
+                    }
+                    Err(message) => {
+                        transaction
+                            .prepare_cached(
+                                "UPDATE outbox SET attempts = attempts + 1, last_error = ?2
+                                 WHERE event_id = ?1",
+                            )?
+                            .execute(params![event_id, message])?;
+                    }
+                }
+            }
+            transaction.commit()?;
+
+            Ok(delivered)
+        })
+        .await
+    }
+
+    async fn cleanup_delivered_events(&self, older_than: Duration) -> Result<u64, AppError> {
+        let cutoff = Utc::now().timestamp() - older_than.as_secs() as i64;
+        self.with_connection(move |conn| {
+            let removed = conn.execute(
+                "DELETE FROM outbox WHERE delivered_at < ?1",
+                params![cutoff],
+            )?;
+            Ok(removed as u64)
+        })
+        .await
+    }
+
+    async fn events_after(&self, after_id: i64, limit: u32) -> Result<Vec<OutboxEvent>, AppError> {
+        self.with_connection(move |conn| {
+            Ok(conn
+                .prepare_cached(
+                    "SELECT event_id, order_uid, event_type, payload, created_at
+                     FROM outbox WHERE event_id > ?1
+                     ORDER BY event_id
+                     LIMIT ?2",
+                )?
+                .query_map(params![after_id, limit], |row| {
+                    Ok(OutboxEvent {
+                        event_id: row.get("event_id")?,
+                        order_uid: row.get("order_uid")?,
+                        event_type: row.get("event_type")?,
+                        payload: row.get("payload")?,
+                        created_at: row.get("created_at")?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?)
+        })
+        .await
+    }
+
+    async fn last_event_id(&self) -> Result<i64, AppError> {
+        self.with_connection(|conn| {
+            Ok(conn
+                .prepare_cached("SELECT COALESCE(MAX(event_id), 0) FROM outbox")?
+                .query_row([], |row| row.get(0))?)
+        })
+        .await
+    }
+
+    // Пишет в базу одна транзакция за раз
+    async fn transaction_snapshot(&self) -> Result<Option<TransactionSnapshot>, AppError> {
+        Ok(None)
+    }
+
+    async fn add_dead_letter(
+        &self,
+        source: &str,
+        reference: Option<&str>,
+        payload: &str,
+        error: &str,
+    ) -> Result<i64, AppError> {
+        let source = source.to_string();
+        let reference = reference.map(str::to_string);
+        let payload = payload.to_string();
+        let error = error.to_string();
+        self.with_connection(move |conn| {
+            conn.prepare_cached(
+                "INSERT INTO dead_letters (source, reference, payload, error, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?
+            .execute(params![source, reference, payload, error, Utc::now()])?;
+            Ok(conn.last_insert_rowid())
+        })
+        .await
+    }
+
+    async fn list_dead_letters(
+        &self,
+        query: &DeadLetterListQuery,
+    ) -> Result<Vec<DeadLetter>, AppError> {
+        let query = query.clone();
+        self.with_connection(move |conn| {
+            Ok(conn
+                .prepare_cached(&format!(
+                    "SELECT {DEAD_LETTER_COLUMNS} FROM dead_letters
+                     WHERE (?1 IS NULL OR source = ?1)
+                        AND (NOT ?2 OR replayed_at IS NULL)
+                     ORDER BY dead_letter_id DESC
+                     LIMIT ?3 OFFSET ?4"
+                ))?
+                .query_map(
+                    params![query.source, query.pending, query.limit, query.offset],
+                    dead_letter_from_row,
+                )?
+                .collect::<Result<Vec<_>, _>>()?)
+        })
+        .await
+    }
+
+    async fn get_dead_letter(&self, id: i64) -> Result<Option<DeadLetter>, AppError> {
+        self.with_connection(move |conn| {
+            Ok(conn
+                .prepare_cached(&format!(
+                    "SELECT {DEAD_LETTER_COLUMNS} FROM dead_letters WHERE dead_letter_id = ?1"
+                ))?
+                .query_row(params![id], dead_letter_from_row)
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn complete_dead_letter_replay(
+        &self,
+        id: i64,
+        error: Option<&str>,
+    ) -> Result<(), AppError> {
+        let error = error.map(str::to_string);
+        self.with_connection(move |conn| {
+            // Неудачный повтор после успешного не снимает отметку о повторе
+            conn.prepare_cached(
+                "UPDATE dead_letters SET
+                    replayed_at = CASE WHEN ?2 IS NULL THEN ?3 ELSE replayed_at END,
+                    replay_error = ?2
+                 WHERE dead_letter_id = ?1",
+            )?
+            .execute(params![id, error, Utc::now()])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn count_dead_letters(&self) -> Result<Vec<DeadLetterCount>, AppError> {
+        self.with_connection(|conn| {
+            Ok(conn
+                .prepare_cached(
+                    "SELECT source,
+                        SUM(replayed_at IS NULL) AS pending,
+                        SUM(replayed_at IS NOT NULL) AS replayed
+                     FROM dead_letters
+                     GROUP BY source
+                     ORDER BY source",
+                )?
+                .query_map([], |row| {
+                    Ok(DeadLetterCount {
+                        source: row.get("source")?,
+                        pending: row.get("pending")?,
+                        replayed: row.get("replayed")?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?)
+        })
+        .await
+    }
+
+    // Подписки на вебхуки хранятся только в PostgreSQL
+    async fn create_webhook(&self, _webhook: &NewWebhook) -> Result<Webhook, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn list_webhooks(&self) -> Result<Vec<Webhook>, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn get_webhook(&self, _id: i64) -> Result<Option<Webhook>, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn delete_webhook(&self, _id: i64) -> Result<bool, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn list_webhook_deliveries(
+        &self,
+        _query: &WebhookDeliveryListQuery,
+    ) -> Result<Vec<WebhookDelivery>, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn redeliver_webhook(
+        &self,
+        _subscription_id: i64,
+        _delivery_id: i64,
+    ) -> Result<Option<WebhookDelivery>, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn claim_webhook_deliveries(
+        &self,
+        _limit: u32,
+        _lease: Duration,
+    ) -> Result<Vec<PendingDelivery>, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn complete_webhook_delivery(
+        &self,
+        _delivery_id: i64,
+        _attempt: &DeliveryAttempt,
+    ) -> Result<(), AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+
+    async fn cleanup_webhook_deliveries(&self, _older_than: Duration) -> Result<u64, AppError> {
+        Err(AppError::UnsupportedError("webhooks".to_string()))
+    }
+}
+
+// Применяет миграции SQLite, учитывая уже применённые в schema_migrations
+fn migrate_sqlite(conn: &mut Connection, migration: Migration) -> Result<(), AppError> {
+    match migration {
+        Migration::Up => {
+            conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS schema_migrations (
+                    name TEXT PRIMARY KEY,
+                    applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+                )",
+            )?;
+
+            for migration_name in UP_MIGRATIONS {
+                let applied = conn
+                    .query_row(
+                        "SELECT name FROM schema_migrations WHERE name = ?1",
+                        params![migration_name],
+                        |_| Ok(()),
+                    )
+                    .optional()?;
+                if applied.is_some() {
+                    continue;
+                }
+
+                let migration_script = migrate::load_migration_script_as_string(migration_name)?;
+
+                let transaction = conn.transaction()?;
+                transaction.execute_batch(&migration_script)?;
+                transaction.execute(
+                    "INSERT INTO schema_migrations (name) VALUES (?1)",
+                    params![migration_name],
+                )?;
+                transaction.commit()?;
+
+                info!("Applied migration {migration_name}");
+            }
+        }
+        Migration::Down => {
+            let migration_script = migrate::load_migration_script_as_string(DOWN_MIGRATION)?;
+            conn.execute_batch(&migration_script)?;
+        }
+        Migration::None => {}
+    }
+
+    Ok(())
+}
+
+// Возвращает число обновлённых строк: 0, если ключ не занят или уже завершён
+fn complete_idempotency_key(
+    conn: &Connection,
+    key: &str,
+    status: StatusCode,
+    body: &serde_json::Value,
+) -> Result<usize, AppError> {
+    Ok(conn
+        .prepare_cached(
+            "UPDATE idempotency_keys SET response_status = ?2, response_body = ?3
+             WHERE idempotency_key = ?1 AND response_status IS NULL",
+        )?
+        .execute(params![key, status.as_u16(), body])?)
+}
+
+// Загружает заказ вместе с payment, delivery и items
+fn load_order(conn: &Connection, id: &str) -> Result<Option<GetOrderDTO>, AppError> {
+    let order = conn
+        .prepare_cached(
+            "SELECT order_uid, track_number, entry, locale,
+                    internal_signature, customer_id, delivery_service,
+                    shardkey, sm_id, date_created, oof_shard
+             FROM orders WHERE order_uid = ?1",
+        )?
+        .query_row(params![id], order_from_row)
+        .optional()?;
+    let Some(order) = order else {
+        return Ok(None);
+    };
+
+    // Получение payment
+    let payment = conn
+        .prepare_cached(
+            "SELECT \"transaction\", request_id, currency,
+                    provider, amount, payment_dt,
+                    bank, delivery_cost, goods_total, custom_fee
+             FROM payment WHERE order_uid = ?1",
+        )?
+        .query_row(params![id], payment_from_row)
+        .optional()?
+        .ok_or_else(|| {
+            AppError::IncompleteOrderError(format!("payment of order {id} not found"))
+        })?;
+
+    // Получение delivery
+    let delivery = conn
+        .prepare_cached(
+            "SELECT name, phone, zip, city, address, region, email
+             FROM delivery WHERE order_uid = ?1",
+        )?
+        .query_row(params![id], delivery_from_row)
+        .optional()?
+        .ok_or_else(|| {
+            AppError::IncompleteOrderError(format!("delivery of order {id} not found"))
+        })?;
+
+    // Получение items
+    let items = conn
+        .prepare_cached(
+            "SELECT chrt_id, track_number, price,
+                    rid, name, sale, size,
+                    total_price, nm_id, brand, status
+             FROM items WHERE order_uid = ?1
+             ORDER BY item_id",
+        )?
+        .query_map(params![id], |row| {
+            order_item_from_row(row, payment.currency())
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Some(GetOrderDTO::from_order(
+        order, payment, delivery, items,
+    )))
+}
+
+fn dead_letter_from_row(row: &Row) -> rusqlite::Result<DeadLetter> {
+    Ok(DeadLetter {
+        dead_letter_id: row.get("dead_letter_id")?,
+        source: row.get("source")?,
+        reference: row.get("reference")?,
+        payload: row.get("payload")?,
+        error: row.get("error")?,
+        created_at: row.get("created_at")?,
+        replayed_at: row.get("replayed_at")?,
+        replay_error: row.get("replay_error")?,
+    })
+}
+
+fn order_from_row(row: &Row) -> rusqlite::Result<Order> {
+    Ok(Order {
+        order_uid: row.get(0)?,
+        track_number: row.get(1)?,
+        entry: row.get(2)?,
+        locale: row.get(3)?,
+        internal_signature: row.get(4)?,
+        customer_id: row.get(5)?,
+        delivery_service: row.get(6)?,
+        shardkey: row.get(7)?,
+        sm_id: row.get(8)?,
+        date_created: row.get(9)?,
+        oof_shard: row.get(10)?,
+    })
+}
+
+fn payment_from_row(row: &Row) -> rusqlite::Result<PaymentDTO> {
+    let currency = Currency::from_stored(row.get(2)?);
+    let money = |index| -> rusqlite::Result<Money> {
+        Ok(Money::new(MinorUnits(row.get(index)?), currency.clone()))
+    };
+
+    Ok(PaymentDTO {
+        transaction: row.get(0)?,
+        request_id: row.get(1)?,
+        provider: row.get(3)?,
+        amount: money(4)?,
+        payment_dt: row.get(5)?,
+        bank: row.get(6)?,
+        delivery_cost: money(7)?,
+        goods_total: money(8)?,
+        custom_fee: money(9)?,
+    })
+}
+
+fn delivery_from_row(row: &Row) -> rusqlite::Result<DeliveryDTO> {
+    Ok(DeliveryDTO {
+        name: row.get(0)?,
+        phone: row.get(1)?,
+        zip: row.get(2)?,
+        city: row.get(3)?,
+        address: row.get(4)?,
+        region: row.get(5)?,
+        email: row.get(6)?,
+    })
+}
+
+// Цены товара в валюте платежа заказа
+fn order_item_from_row(row: &Row, currency: &Currency) -> rusqlite::Result<OrderItemDTO> {
+    Ok(OrderItemDTO {
+        chrt_id: row.get(0)?,
+        track_number: row.get(1)?,
+        price: Money::new(MinorUnits(row.get(2)?), currency.clone()),
+        rid: row.get(3)?,
+        name: row.get(4)?,
+        sale: row.get(5)?,
+        size: row.get(6)?,
+        total_price: Money::new(MinorUnits(row.get(7)?), currency.clone()),
+        nm_id: row.get(8)?,
+        brand: row.get(9)?,
+        status: row.get(10)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Возможности, которых в SQLite нет (см. README, «Хранилище»).
+    // Остальное проверяется общими тестами хранилищ в storage::tests
+    #[tokio::test]
+    async fn reports_postgres_only_operations() {
+        let storage = SqliteOrderRepository::open(":memory:").unwrap();
+        storage.migrate(Migration::Up).await.unwrap();
+        let month = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let dir = Path::new("unused");
+
+        let unsupported = [
+            storage.restore_orders(month, dir).await.err(),
+            storage.export_parquet(month, month, dir).await.err(),
+            storage.check_query_plans(100).await.err(),
+            storage.list_webhooks().await.err(),
+            storage
+                .claim_webhook_deliveries(10, Duration::ZERO)
+                .await
+                .err(),
+        ];
+        for error in unsupported {
+            assert!(matches!(error, Some(AppError::UnsupportedError(_))));
+        }
+
+        // Секций нет, поэтому архивировать нечего
+        assert!(storage.archive_orders(month, dir).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,275 @@
+use std::sync::OnceLock;
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, NaiveDateTime, SecondsFormat, Utc,
+};
+use serde::{Deserialize, Deserializer, Serializer};
+
+// Формат, в котором метки времени отдаются клиентам
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimestampFormat {
+    // RFC 3339 в UTC, например 2021-11-26T06:22:19Z
+    Rfc3339,
+    // Unix-время в секундах
+    Unix,
+    // Произвольный шаблон strftime
+    Custom(String),
+}
+
+impl TimestampFormat {
+    // Разбирает значение настройки: rfc3339, unix или шаблон strftime
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "" | "rfc3339" => Ok(TimestampFormat::Rfc3339),
+            "unix" => Ok(TimestampFormat::Unix),
+            _ => {
+                if StrftimeItems::new(value).any(|item| matches!(item, Item::Error)) {
+                    return Err(format!("Invalid timestamp format: {value}"));
+                }
+
+                // Ответ в этом формате должен приниматься на входе без потерь,
+                // поэтому шаблон обязан содержать дату и время до секунд
+                let sample = from_unix(ROUND_TRIP_SAMPLE)?;
+                let formatted = sample.format(value).to_string();
+                if parse_custom(&formatted, value) != Some(sample) {
+                    return Err(format!(
+                        "Timestamp format {value} cannot be read back, it must contain date and time up to seconds"
+                    ));
+                }
+
+                Ok(TimestampFormat::Custom(value.to_string()))
+            }
+        }
+    }
+}
+
+// Метка, на которой проверяется, что шаблон можно разобрать обратно
+const ROUND_TRIP_SAMPLE: i64 = 1637907739;
+
+static TIMESTAMP_FORMAT: OnceLock<TimestampFormat> = OnceLock::new();
+
+// Задаёт формат вывода меток времени, вызывается один раз при старте
+pub fn init(format: TimestampFormat) {
+    let _ = TIMESTAMP_FORMAT.set(format);
+}
+
+fn current_format() -> &'static TimestampFormat {
+    #[cfg(test)]
+    if let Some(format) = TEST_FORMAT.with(std::cell::Cell::get) {
+        return format;
+    }
+
+    TIMESTAMP_FORMAT.get_or_init(|| TimestampFormat::Rfc3339)
+}
+
+// Формат для тестов в текущем потоке: глобальный задаётся один раз на процесс
+#[cfg(test)]
+thread_local! {
+    static TEST_FORMAT: std::cell::Cell<Option<&'static TimestampFormat>> =
+        const { std::cell::Cell::new(None) };
+}
+
+#[cfg(test)]
+pub fn with_format<T>(format: TimestampFormat, f: impl FnOnce() -> T) -> T {
+    let format: &'static TimestampFormat = Box::leak(Box::new(format));
+    TEST_FORMAT.with(|current| current.set(Some(format)));
+    let result = f();
+    TEST_FORMAT.with(|current| current.set(None));
+    result
+}
+
+// Метка времени на входе: Unix-время в секундах, строка RFC 3339 или строка
+// в настроенном формате вывода. В XML значение элемента без типа приходит
+// как текст элемента
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TimestampInput {
+    Unix(i64),
+    Text(String),
+    Element {
+        #[serde(rename = "$text")]
+        text: String,
+    },
+}
+
+pub fn parse(value: &str) -> Result<DateTime<Utc>, String> {
+    parse_with(value, current_format())
+}
+
+// Шаблон проверяется первым: вывод вида %Y%m%d%H%M%S тоже состоит из цифр
+fn parse_with(value: &str, format: &TimestampFormat) -> Result<DateTime<Utc>, String> {
+    if let TimestampFormat::Custom(pattern) = format {
+        if let Some(date) = parse_custom(value, pattern) {
+            return Ok(date);
+        }
+    }
+
+    if let Ok(unix) = value.parse::<i64>() {
+        return from_unix(unix);
+    }
+
+    DateTime::parse_from_rfc3339(value)
+        .map(|date| date.with_timezone(&Utc))
+        .map_err(|err| format!("Invalid timestamp {value}: {err}"))
+}
+
+// Шаблон без часового пояса выводится в UTC
+fn parse_custom(value: &str, pattern: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_str(value, pattern)
+        .map(|date| date.with_timezone(&Utc))
+        .or_else(|_| NaiveDateTime::parse_from_str(value, pattern).map(|date| date.and_utc()))
+        .ok()
+}
+
+fn from_unix(unix: i64) -> Result<DateTime<Utc>, String> {
+    DateTime::from_timestamp(unix, 0).ok_or_else(|| format!("Timestamp {unix} is out of range"))
+}
+
+// Сериализация и десериализация для #[serde(with = "crate::timestamp")]
+pub fn serialize<S>(date: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serialize_with(date, current_format(), serializer)
+}
+
+fn serialize_with<S>(
+    date: &DateTime<Utc>,
+    format: &TimestampFormat,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match format {
+        TimestampFormat::Rfc3339 => {
+            serializer.serialize_str(&date.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+        }
+        TimestampFormat::Unix => serializer.serialize_i64(date.timestamp()),
+        TimestampFormat::Custom(pattern) => {
+            serializer.serialize_str(&date.format(pattern).to_string())
+        }
+    }
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_with(deserializer, current_format())
+}
+
+fn deserialize_with<'de, D>(
+    deserializer: D,
+    format: &TimestampFormat,
+) -> Result<DateTime<Utc>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let parsed = match TimestampInput::deserialize(deserializer)? {
+        TimestampInput::Unix(unix) => from_unix(unix),
+        TimestampInput::Text(text) | TimestampInput::Element { text } => parse_with(&text, format),
+    };
+
+    parsed.map_err(serde::de::Error::custom)
+}
+
+// Сериализация необязательной метки, для #[serde(serialize_with = ...)]
+pub fn serialize_option<S>(date: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match date {
+        Some(date) => serialize(date, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn sample() -> DateTime<Utc> {
+        from_unix(1637907739).unwrap()
+    }
+
+    fn output(format: &TimestampFormat) -> Value {
+        serialize_with(&sample(), format, serde_json::value::Serializer).unwrap()
+    }
+
+    fn input(value: Value, format: &TimestampFormat) -> Result<DateTime<Utc>, String> {
+        deserialize_with(value, format).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn accepts_unix_seconds() {
+        let format = TimestampFormat::Rfc3339;
+
+        assert_eq!(input(json!(1637907739), &format), Ok(sample()));
+        assert_eq!(input(json!("1637907739"), &format), Ok(sample()));
+    }
+
+    #[test]
+    fn accepts_rfc3339() {
+        let format = TimestampFormat::Rfc3339;
+
+        assert_eq!(input(json!("2021-11-26T06:22:19Z"), &format), Ok(sample()));
+        assert_eq!(
+            input(json!("2021-11-26T09:22:19+03:00"), &format),
+            Ok(sample())
+        );
+        assert!(input(json!("26.11.2021"), &format).is_err());
+    }
+
+    #[test]
+    fn outputs_each_format() {
+        assert_eq!(
+            output(&TimestampFormat::Rfc3339),
+            json!("2021-11-26T06:22:19Z")
+        );
+        assert_eq!(output(&TimestampFormat::Unix), json!(1637907739));
+        assert_eq!(
+            output(&TimestampFormat::parse("%d.%m.%Y %H:%M:%S").unwrap()),
+            json!("26.11.2021 06:22:19")
+        );
+    }
+
+    #[test]
+    fn output_round_trips_in_each_format() {
+        let formats = [
+            TimestampFormat::Rfc3339,
+            TimestampFormat::Unix,
+            TimestampFormat::parse("%d.%m.%Y %H:%M:%S").unwrap(),
+            TimestampFormat::parse("%Y%m%d%H%M%S").unwrap(),
+            TimestampFormat::parse("%a, %d %b %Y %H:%M:%S %z").unwrap(),
+        ];
+
+        for format in formats {
+            assert_eq!(input(output(&format), &format), Ok(sample()), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn rfc3339_keeps_subseconds() {
+        let date = DateTime::parse_from_rfc3339("2021-11-26T06:22:19.123456Z")
+            .unwrap()
+            .to_utc();
+        let format = TimestampFormat::Rfc3339;
+        let value = serialize_with(&date, &format, serde_json::value::Serializer).unwrap();
+
+        assert_eq!(value, json!("2021-11-26T06:22:19.123456Z"));
+        assert_eq!(input(value, &format), Ok(date));
+    }
+
+    #[test]
+    fn rejects_formats_that_cannot_be_read_back() {
+        assert!(TimestampFormat::parse("%d.%m.%Y").is_err());
+        assert!(TimestampFormat::parse("%d.%m.%Y %H:%M").is_err());
+        assert!(TimestampFormat::parse("%Q").is_err());
+        assert_eq!(TimestampFormat::parse("unix"), Ok(TimestampFormat::Unix));
+        assert_eq!(TimestampFormat::parse(""), Ok(TimestampFormat::Rfc3339));
+    }
+}
@@ -0,0 +1,113 @@
+use std::{fmt, str::FromStr};
+
+use native_tls::{Certificate, Identity, TlsConnector};
+use postgres_native_tls::MakeTlsConnector;
+
+use crate::errors::AppError;
+
+// Режим TLS соединения с PostgreSQL, значения как у sslmode в libpq
+#[derive(Debug, Clone, PartialEq)]
+pub enum SslMode {
+    // Без шифрования
+    Disable,
+    // TLS, если сервер его поддерживает, сертификат не проверяется
+    Prefer,
+    // Только TLS, сертификат не проверяется
+    Require,
+    // Только TLS, сертификат сервера проверяется по цепочке доверия
+    VerifyCa,
+    // Как verify-ca, дополнительно проверяется имя хоста
+    VerifyFull,
+}
+
+impl SslMode {
+    // Значение sslmode для tokio-postgres: проверку сертификата выполняет
+    // сам коннектор, драйверу достаточно знать, обязателен ли TLS
+    pub fn connection_param(&self) -> &'static str {
+        match self {
+            SslMode::Disable => "disable",
+            SslMode::Prefer => "prefer",
+            SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => "require",
+        }
+    }
+}
+
+impl fmt::Display for SslMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SslMode::Disable => write!(f, "disable"),
+            SslMode::Prefer => write!(f, "prefer"),
+            SslMode::Require => write!(f, "require"),
+            SslMode::VerifyCa => write!(f, "verify-ca"),
+            SslMode::VerifyFull => write!(f, "verify-full"),
+        }
+    }
+}
+
+impl FromStr for SslMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "disable" => Ok(SslMode::Disable),
+            "prefer" => Ok(SslMode::Prefer),
+            "require" => Ok(SslMode::Require),
+            "verify-ca" => Ok(SslMode::VerifyCa),
+            "verify-full" => Ok(SslMode::VerifyFull),
+            other => Err(format!("Unknown ssl mode: {other}")),
+        }
+    }
+}
+
+// Настройки TLS соединения с PostgreSQL
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub mode: SslMode,
+    // PEM-файл с одним или несколькими корневыми сертификатами
+    pub root_cert: Option<String>,
+    // Клиентский сертификат и ключ в формате PEM, ключ в PKCS#8
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+}
+
+// Собирает коннектор для tokio-postgres по настройкам TLS
+pub fn make_connector(config: &TlsConfig) -> Result<MakeTlsConnector, AppError> {
+    let mut builder = TlsConnector::builder();
+
+    match config.mode {
+        SslMode::Disable | SslMode::Prefer | SslMode::Require => {
+            builder.danger_accept_invalid_certs(true);
+        }
+        SslMode::VerifyCa => {
+            builder.danger_accept_invalid_hostnames(true);
+        }
+        SslMode::VerifyFull => {}
+    }
+
+    if let Some(path) = &config.root_cert {
+        let bundle = std::fs::read(path)?;
+        for certificate in split_pem_bundle(&bundle) {
+            builder.add_root_certificate(Certificate::from_pem(&certificate)?);
+        }
+    }
+
+    if let (Some(cert_path), Some(key_path)) = (&config.client_cert, &config.client_key) {
+        let cert = std::fs::read(cert_path)?;
+        let key = std::fs::read(key_path)?;
+        builder.identity(Identity::from_pkcs8(&cert, &key)?);
+    }
+
+    Ok(MakeTlsConnector::new(builder.build()?))
+}
+
+// Certificate::from_pem читает только первый сертификат,
+// поэтому бандл разбивается на отдельные блоки
+fn split_pem_bundle(bundle: &[u8]) -> Vec<Vec<u8>> {
+    const END_MARKER: &str = "-----END CERTIFICATE-----";
+
+    String::from_utf8_lossy(bundle)
+        .split_inclusive(END_MARKER)
+        .filter(|block| block.contains(END_MARKER))
+        .map(|block| block.trim().as_bytes().to_vec())
+        .collect()
+}
@@ -0,0 +1,519 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use futures::{future::join_all, StreamExt};
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{
+    errors::AppError,
+    outbox::{OutboxEvent, ORDER_CREATED_EVENT},
+    retry::RetryPolicy,
+    storage::OrderRepository,
+};
+
+// События, на которые можно подписаться
+pub const WEBHOOK_EVENT_TYPES: &[&str] = &[ORDER_CREATED_EVENT];
+
+// Состояния доставки
+pub const DELIVERY_PENDING: &str = "pending";
+pub const DELIVERY_DELIVERED: &str = "delivered";
+pub const DELIVERY_FAILED: &str = "failed";
+
+// Заголовки доставки
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+
+const MIN_SECRET_LENGTH: usize = 16;
+
+// Сколько байт ответа получателя читается и сохраняется в журнале доставки
+const MAX_ERROR_LENGTH: usize = 500;
+
+// Настройки отправки вебхуков
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    // Выключенные вебхуки: API подписок не подключается, отправки нет
+    pub enabled: bool,
+    pub poll_interval: Duration,
+    pub batch_size: u32,
+    pub timeout: Duration,
+    // Число попыток доставки и паузы между ними
+    pub retry_policy: RetryPolicy,
+    // Сколько хранятся завершённые доставки
+    pub retention: Duration,
+}
+
+// Подписка. Секрет после создания не отдаётся
+#[derive(Debug, Clone, Serialize)]
+pub struct Webhook {
+    pub subscription_id: i64,
+    pub delivery_service: String,
+    pub url: String,
+    pub event_types: Vec<String>,
+    #[serde(serialize_with = "crate::timestamp::serialize")]
+    pub created_at: DateTime<Utc>,
+}
+
+// Тело запроса на создание подписки. Подписка получает события только
+// о заказах своей службы доставки
+#[derive(Debug, Deserialize)]
+pub struct NewWebhook {
+    pub delivery_service: String,
+    pub url: String,
+    pub event_types: Vec<String>,
+    pub secret: String,
+}
+
+impl NewWebhook {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.delivery_service.trim().is_empty() {
+            return Err("delivery_service must not be empty".to_string());
+        }
+
+        match reqwest::Url::parse(&self.url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            Ok(url) => return Err(format!("Unsupported webhook URL scheme: {}", url.scheme())),
+            Err(err) => return Err(format!("Invalid webhook URL {}: {err}", self.url)),
+        }
+
+        if self.event_types.is_empty() {
+            return Err("event_types must not be empty".to_string());
+        }
+        if let Some(event_type) = self
+            .event_types
+            .iter()
+            .find(|event_type| !WEBHOOK_EVENT_TYPES.contains(&event_type.as_str()))
+        {
+            return Err(format!(
+                "Unknown event type {event_type}, expected one of: {}",
+                WEBHOOK_EVENT_TYPES.join(", ")
+            ));
+        }
+
+        if self.secret.len() < MIN_SECRET_LENGTH {
+            return Err(format!(
+                "secret must be at least {MIN_SECRET_LENGTH} characters long"
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+// Запись журнала доставок подписки
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookDelivery {
+    pub delivery_id: i64,
+    pub subscription_id: i64,
+    pub event_id: i64,
+    pub order_uid: String,
+    pub event_type: String,
+    pub status: String,
+    pub attempts: i32,
+    #[serde(serialize_with = "crate::timestamp::serialize_option")]
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    #[serde(serialize_with = "crate::timestamp::serialize")]
+    pub created_at: DateTime<Utc>,
+    #[serde(serialize_with = "crate::timestamp::serialize_option")]
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    #[serde(serialize_with = "crate::timestamp::serialize_option")]
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+// Доставка, взятая в работу, с адресом и секретом подписки
+#[derive(Debug, Clone)]
+pub struct PendingDelivery {
+    pub delivery_id: i64,
+    pub url: String,
+    pub secret: String,
+    pub attempts: i32,
+    pub event: OutboxEvent,
+}
+
+// Результат попытки доставки
+#[derive(Debug)]
+pub struct DeliveryAttempt {
+    pub status_code: Option<i32>,
+    // None — доставлено
+    pub error: Option<String>,
+    // Через сколько повторить неудачную попытку, None — попытки исчерпаны
+    pub retry_in: Option<Duration>,
+}
+
+// Подпись тела: HMAC-SHA256 от "<timestamp>.<body>" в hex.
+// Метка времени в подписи не даёт повторить перехваченный запрос позже
+pub fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+// Перенаправления не выполняются: подписанное тело уходит только на адрес подписки
+fn client(timeout: Duration) -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+}
+
+// Отправляет доставки, срок которых подошёл, и удаляет старые завершённые
+pub fn spawn_dispatcher(storage: Arc<dyn OrderRepository>, config: WebhookConfig) {
+    tokio::spawn(async move {
+        let client = match client(config.timeout) {
+            Ok(client) => client,
+            Err(e) => {
+                error!("Webhook client error: {e}");
+                return;
+            }
+        };
+        let mut interval = tokio::time::interval(config.poll_interval);
+        let mut cleanup = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => loop {
+                    match dispatch(&storage, &client, &config).await {
+                        Ok(sent) if sent > 0 => {}
+                        Ok(_) => break,
+                        Err(AppError::UnsupportedError(operation)) => {
+                            warn!("Webhook dispatcher is disabled: {operation} are not supported");
+                            return;
+                        }
+                        Err(e) => {
+                            error!("Webhook dispatch error: {e}");
+                            break;
+                        }
+                    }
+                },
+                _ = cleanup.tick() => {
+                    match storage.cleanup_webhook_deliveries(config.retention).await {
+                        Ok(count) => info!("Removed {count} finished webhook deliveries"),
+                        Err(e) => error!("Webhook deliveries cleanup error: {e}"),
+                    }
+                }
+            }
+        }
+    });
+}
+
+// Берёт пачку доставок и отправляет их параллельно, возвращает размер пачки.
+// Взятые доставки другие экземпляры не получат, пока не истечёт аренда
+async fn dispatch(
+    storage: &Arc<dyn OrderRepository>,
+    client: &reqwest::Client,
+    config: &WebhookConfig,
+) -> Result<usize, AppError> {
+    let lease = config.timeout * 2;
+    let deliveries = storage
+        .claim_webhook_deliveries(config.batch_size, lease)
+        .await?;
+
+    let results = join_all(deliveries.iter().map(|delivery| async move {
+        let attempt = send(client, delivery).await;
+        (delivery, attempt)
+    }))
+    .await;
+
+    for (delivery, (status_code, error)) in &results {
+        let attempt_number = delivery.attempts as u32 + 1;
+        let retry_in = match error {
+            Some(error) if attempt_number < config.retry_policy.max_attempts => {
+                let backoff = config.retry_policy.backoff(attempt_number);
+                warn!(
+                    "Webhook delivery {} to {} failed on attempt {attempt_number}: {error}, retrying in {backoff:?}",
+                    delivery.delivery_id, delivery.url
+                );
+                Some(backoff)
+            }
+            Some(error) => {
+                warn!(
+                    "Webhook delivery {} to {} failed after {attempt_number} attempts: {error}",
+                    delivery.delivery_id, delivery.url
+                );
+                None
+            }
+            None => None,
+        };
+
+        let attempt = DeliveryAttempt {
+            status_code: *status_code,
+            error: error.clone(),
+            retry_in,
+        };
+        storage
+            .complete_webhook_delivery(delivery.delivery_id, &attempt)
+            .await?;
+    }
+
+    Ok(results.len())
+}
+
+// Успешен ответ 2xx. Возвращает код ответа и ошибку
+async fn send(
+    client: &reqwest::Client,
+    delivery: &PendingDelivery,
+) -> (Option<i32>, Option<String>) {
+    let body = match serde_json::to_vec(&delivery.event) {
+        Ok(body) => body,
+        Err(err) => return (None, Some(err.to_string())),
+    };
+    let timestamp = Utc::now().timestamp();
+
+    let response = client
+        .post(&delivery.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Webhook-Id", delivery.delivery_id.to_string())
+        .header("X-Event-Id", delivery.event.event_id.to_string())
+        .header("X-Event-Type", &delivery.event.event_type)
+        .header(TIMESTAMP_HEADER, timestamp.to_string())
+        .header(
+            SIGNATURE_HEADER,
+            signature(&delivery.secret, timestamp, &body),
+        )
+        .body(body)
+        .send()
+        .await;
+
+    match response {
+        Ok(response) => {
+            let status = response.status();
+            if status.is_success() {
+                return (Some(status.as_u16() as i32), None);
+            }
+            let text = error_body(response).await;
+            let error = match text.trim() {
+                "" => format!("HTTP {status}"),
+                text => format!("HTTP {status}: {text}"),
+            };
+            (Some(status.as_u16() as i32), Some(error))
+        }
+        Err(err) => (None, Some(err.to_string())),
+    }
+}
+
+// Начало тела ответа с ошибкой. Больше MAX_ERROR_LENGTH байт не читается,
+// чтобы большой ответ получателя не держал память и соединение
+async fn error_body(response: reqwest::Response) -> String {
+    let mut body = Vec::new();
+    let mut stream = response.bytes_stream();
+    while body.len() < MAX_ERROR_LENGTH {
+        match stream.next().await {
+            Some(Ok(chunk)) => body.extend_from_slice(&chunk),
+            _ => break,
+        }
+    }
+    body.truncate(MAX_ERROR_LENGTH);
+
+    String::from_utf8_lossy(&body).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    };
+
+    use axum::{
+        body::Bytes,
+        extract::Path,
+        http::{HeaderMap, StatusCode},
+        response::{IntoResponse, Redirect},
+        routing::post,
+        Router,
+    };
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        schema::CreateOrderDTO,
+        storage::{postgres, WebhookDeliveryListQuery},
+    };
+
+    const SECRET: &str = "0123456789abcdef";
+
+    type Received = Arc<Mutex<Vec<(String, HeaderMap, Bytes)>>>;
+
+    fn new_webhook() -> NewWebhook {
+        NewWebhook {
+            delivery_service: "meest".to_string(),
+            url: "https://example.com/hooks/orders".to_string(),
+            event_types: vec![ORDER_CREATED_EVENT.to_string()],
+            secret: SECRET.to_string(),
+        }
+    }
+
+    #[test]
+    fn signs_body_with_timestamp() {
+        assert_eq!(
+            signature(SECRET, 1700000000, br#"{"event_id":1}"#),
+            "sha256=0466a8191f5ccc2aa00bd24fd27cd5d3bbfcf800cba43e9159cb4b03bb1f19b1"
+        );
+    }
+
+    // Получатель: /ok отвечает 200, /flaky — 500 с длинным телом на первый
+    // запрос и 200 потом, /moved перенаправляет на /ok
+    async fn receiver() -> (String, Received) {
+        let received: Received = Arc::default();
+        let failed = Arc::new(AtomicBool::new(false));
+        let handler = {
+            let received = received.clone();
+            move |Path(name): Path<String>, headers: HeaderMap, body: Bytes| async move {
+                received.lock().unwrap().push((name.clone(), headers, body));
+                match name.as_str() {
+                    "flaky" if !failed.swap(true, Ordering::SeqCst) => {
+                        (StatusCode::INTERNAL_SERVER_ERROR, "x".repeat(10_000)).into_response()
+                    }
+                    "moved" => Redirect::temporary("/ok").into_response(),
+                    _ => StatusCode::OK.into_response(),
+                }
+            }
+        };
+        let app = Router::new().route("/:name", post(handler));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        (format!("http://{address}"), received)
+    }
+
+    async fn delivery(storage: &dyn OrderRepository, webhook: &Webhook) -> WebhookDelivery {
+        let query = WebhookDeliveryListQuery {
+            subscription_id: webhook.subscription_id,
+            status: None,
+            limit: 10,
+            offset: 0,
+        };
+        let mut deliveries = storage.list_webhook_deliveries(&query).await.unwrap();
+        assert_eq!(deliveries.len(), 1);
+        deliveries.remove(0)
+    }
+
+    #[tokio::test]
+    async fn dispatches_and_retries_deliveries() {
+        let Some(repository) = postgres::tests::repository().await else {
+            return;
+        };
+        let storage: Arc<dyn OrderRepository> = Arc::new(repository);
+        let (address, received) = receiver().await;
+
+        let delivery_service = format!("service-{}", Uuid::new_v4().simple());
+        let mut webhooks = Vec::new();
+        for name in ["ok", "flaky", "moved"] {
+            let webhook = NewWebhook {
+                delivery_service: delivery_service.clone(),
+                url: format!("{address}/{name}"),
+                ..new_webhook()
+            };
+            webhooks.push(storage.create_webhook(&webhook).await.unwrap());
+        }
+        let [ok, flaky, moved] = &webhooks[..] else {
+            unreachable!()
+        };
+
+        let mut order: CreateOrderDTO =
+            serde_json::from_str(include_str!("test/stubs/order.json")).unwrap();
+        order.order_uid = Some(format!("test-{}", Uuid::new_v4().simple()));
+        order.delivery_service = delivery_service;
+        storage.create_order(&order, None).await.unwrap();
+
+        let config = WebhookConfig {
+            enabled: true,
+            poll_interval: Duration::from_millis(10),
+            batch_size: 100,
+            timeout: Duration::from_secs(5),
+            retry_policy: RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::ZERO,
+                max_backoff: Duration::ZERO,
+            },
+            retention: Duration::from_secs(3600),
+        };
+        let client = client(config.timeout).unwrap();
+
+        dispatch(&storage, &client, &config).await.unwrap();
+        let delivered = delivery(&*storage, ok).await;
+        assert_eq!(delivered.status, DELIVERY_DELIVERED);
+        assert_eq!(delivered.attempts, 1);
+        assert_eq!(delivered.last_status_code, Some(200));
+
+        // Из длинного ответа сохраняется только начало
+        let failed = delivery(&*storage, flaky).await;
+        assert_eq!(failed.status, DELIVERY_PENDING);
+        assert_eq!(failed.last_status_code, Some(500));
+        let error = failed.last_error.unwrap();
+        assert!(
+            error.starts_with("HTTP 500 Internal Server Error: xxx"),
+            "{error}"
+        );
+        assert!(error.len() < 600, "{error}");
+
+        // Перенаправление не выполняется и считается неудачной попыткой
+        let redirected = delivery(&*storage, moved).await;
+        assert_eq!(redirected.status, DELIVERY_PENDING);
+        assert_eq!(redirected.last_status_code, Some(307));
+
+        dispatch(&storage, &client, &config).await.unwrap();
+        let retried = delivery(&*storage, flaky).await;
+        assert_eq!(retried.status, DELIVERY_DELIVERED);
+        assert_eq!(retried.attempts, 2);
+        assert_eq!(retried.last_status_code, Some(200));
+
+        for webhook in &webhooks {
+            storage
+                .delete_webhook(webhook.subscription_id)
+                .await
+                .unwrap();
+        }
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.iter().filter(|(name, ..)| name == "ok").count(), 1);
+        let flaky_requests: Vec<_> = received
+            .iter()
+            .filter(|(name, ..)| name == "flaky")
+            .collect();
+        assert_eq!(flaky_requests.len(), 2);
+        for (_, headers, body) in &flaky_requests {
+            let header = |name: &str| headers[name].to_str().unwrap().to_string();
+            assert_eq!(header("X-Webhook-Id"), failed.delivery_id.to_string());
+            assert_eq!(header("X-Event-Id"), failed.event_id.to_string());
+            let timestamp = header(TIMESTAMP_HEADER).parse().unwrap();
+            assert_eq!(header(SIGNATURE_HEADER), signature(SECRET, timestamp, body));
+        }
+    }
+
+    #[test]
+    fn validates_new_webhook() {
+        assert_eq!(new_webhook().validate(), Ok(()));
+
+        let invalid = [
+            NewWebhook {
+                delivery_service: " ".to_string(),
+                ..new_webhook()
+            },
+            NewWebhook {
+                url: "ftp://example.com".to_string(),
+                ..new_webhook()
+            },
+            NewWebhook {
+                event_types: vec!["order.deleted".to_string()],
+                ..new_webhook()
+            },
+            NewWebhook {
+                secret: "short".to_string(),
+                ..new_webhook()
+            },
+        ];
+        for webhook in invalid {
+            assert!(webhook.validate().is_err(), "{webhook:?}");
+        }
+    }
+}